@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Needs `protoc` on the PATH or in $PROTOC.
+    tonic_build::compile_protos("proto/kv.proto")?;
+    #[cfg(feature = "node")]
+    napi_build::setup();
+    Ok(())
+}
@@ -0,0 +1,133 @@
+#ifndef KV_H
+#define KV_H
+
+/* Generated by cbindgen from src/ffi.rs; run `make header` instead of editing. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define KV_OK 0
+
+/**
+ * `kv_get` found no value for the key.
+ */
+#define KV_NOT_FOUND 1
+
+/**
+ * A null pointer where one is not allowed, or a path that is not UTF-8.
+ */
+#define KV_ERR_INVALID_ARGUMENT -1
+
+/**
+ * Reading or writing the log failed.
+ */
+#define KV_ERR_IO -2
+
+/**
+ * The log holds a record that cannot be decoded.
+ */
+#define KV_ERR_CORRUPT -3
+
+/**
+ * The key or value is over the engine's size limit.
+ */
+#define KV_ERR_TOO_LARGE -4
+
+/**
+ * The engine panicked; the handle should not be used again.
+ */
+#define KV_ERR_PANIC -5
+
+/**
+ * An open engine, the handle of the C API that `include/kv.h` declares. Handles are safe to
+ * share between threads.
+ *
+ * Every function returns `KV_OK` or another status code, and after an error
+ * `kv_last_error_message` describes it.
+ */
+typedef struct KvEngine KvEngine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the log at `path`, creating it if it does not exist, and stores the handle in `*out`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string and `out` must be valid for writes.
+ */
+int kv_open(const char *path, struct KvEngine **out);
+
+/**
+ * Looks up a key. On `KV_OK`, `*value` and `*value_len` hold a copy of the value, which the
+ * caller releases with `kv_free`; on `KV_NOT_FOUND` they are set to null and 0.
+ *
+ * # Safety
+ *
+ * `engine` must come from `kv_open`, `key` must point to `key_len` readable bytes, and
+ * `value` and `value_len` must be valid for writes.
+ */
+int kv_get(const struct KvEngine *engine,
+           const uint8_t *key,
+           size_t key_len,
+           uint8_t **value,
+           size_t *value_len);
+
+/**
+ * Stores `value` under `key`.
+ *
+ * # Safety
+ *
+ * `engine` must come from `kv_open`, and `key` and `value` must point to `key_len` and
+ * `value_len` readable bytes.
+ */
+int kv_set(const struct KvEngine *engine,
+           const uint8_t *key,
+           size_t key_len,
+           const uint8_t *value,
+           size_t value_len);
+
+/**
+ * Deletes `key`. Deleting a missing key is not an error.
+ *
+ * # Safety
+ *
+ * `engine` must come from `kv_open` and `key` must point to `key_len` readable bytes.
+ */
+int kv_del(const struct KvEngine *engine, const uint8_t *key, size_t key_len);
+
+/**
+ * Syncs the log and frees the handle, which must not be used afterwards. Null is ignored.
+ *
+ * # Safety
+ *
+ * `engine` must come from `kv_open` and not have been closed already, and no other thread may
+ * be using it.
+ */
+int kv_close(struct KvEngine *engine);
+
+/**
+ * Releases a value returned by `kv_get`. Null is ignored.
+ *
+ * # Safety
+ *
+ * `value` and `value_len` must be exactly what `kv_get` returned, and released only once.
+ */
+void kv_free(uint8_t *value, size_t value_len);
+
+/**
+ * The message of the last error on the calling thread, or null if there was none. It stays
+ * valid until the next failing call on the same thread.
+ */
+const char *kv_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* KV_H */
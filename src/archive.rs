@@ -0,0 +1,171 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::codec::{self, Format};
+use crate::constants::{ARCHIVE_FILE_PREFIX, ARCHIVE_FILE_SUFFIX};
+use crate::engine::now_millis;
+
+const MILLIS_PER_HOUR: i64 = 60 * 60 * 1000;
+
+/// Copies of every record appended to a log, in files under a directory that start afresh each
+/// hour (UTC). Records are framed as in the log, sequence numbers included, and the files are
+/// only ever appended to or deleted whole, so they keep what compaction drops. Replaying them in
+/// name order with `Engine::replicate_from` rebuilds the store as of any of them.
+pub struct Archive {
+    dir: PathBuf,
+    retention: Option<Duration>,
+    /// The file records are appended to and its hour since the Unix epoch.
+    current: Option<(i64, File)>,
+    /// Sequence number of the last record archived.
+    last_seq: u64,
+    /// Where the next record to archive starts in the log.
+    pub(crate) pos: u64,
+}
+
+impl Archive {
+    /// Opens the archive in `dir`, creating the directory if needed, and finds the last record
+    /// archived. A record left half written by a crash is cut off.
+    pub fn open(dir: impl AsRef<Path>, retention: Option<Duration>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut last_seq = 0;
+        for (i, path) in files(&dir)?.iter().rev().enumerate() {
+            let (seq, whole) = scan(path)?;
+            if i == 0 {
+                OpenOptions::new().write(true).open(path)?.set_len(whole)?;
+            }
+            if seq > 0 {
+                last_seq = seq;
+                break;
+            }
+        }
+        let archive = Archive {
+            dir,
+            retention,
+            current: None,
+            last_seq,
+            pos: 0,
+        };
+        archive.prune()?;
+        Ok(archive)
+    }
+
+    /// Sequence number of the last record archived, or 0 if there is none.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Appends whole records, the last with sequence number `last_seq`, to this hour's file.
+    /// Nothing is left of them if the copy fails.
+    pub(crate) fn append(
+        &mut self,
+        mut records: impl Read,
+        last_seq: u64,
+        sync: bool,
+    ) -> io::Result<()> {
+        let hour = now_millis().div_euclid(MILLIS_PER_HOUR);
+        if self.current.as_ref().is_none_or(|(at, _)| *at != hour) {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(file_name(hour)))?;
+            self.current = Some((hour, file));
+            self.prune()?;
+        }
+        let (_, file) = self.current.as_mut().expect("opened above");
+        let start = file.metadata()?.len();
+        let mut copied = io::copy(&mut records, file).map(drop);
+        if sync {
+            copied = copied.and_then(|_| file.sync_data());
+        }
+        if let Err(e) = copied {
+            file.set_len(start)?;
+            return Err(e);
+        }
+        self.last_seq = self.last_seq.max(last_seq);
+        Ok(())
+    }
+
+    /// Deletes files last written longer ago than the retention period, never the current one.
+    fn prune(&self) -> io::Result<()> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let current = self.current.as_ref().map(|(hour, _)| file_name(*hour));
+        for path in files(&self.dir)? {
+            if current.as_deref() == path.file_name().and_then(|name| name.to_str()) {
+                continue;
+            }
+            let age = fs::metadata(&path)?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > retention {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The archive files in `dir`, oldest first.
+pub fn files(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let archived = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with(ARCHIVE_FILE_PREFIX) && name.ends_with(ARCHIVE_FILE_SUFFIX)
+            });
+        if archived {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The highest sequence number in an archive file, and how many of its bytes hold whole records.
+fn scan(path: &Path) -> io::Result<(u64, u64)> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let (mut pos, mut last_seq) = (0, 0);
+    while let Some((payload_len, prefix_len)) = codec::read_prefix(Format::V2, &mut reader)? {
+        if pos + prefix_len + payload_len > len {
+            break;
+        }
+        let head = codec::read_head(&mut (&mut reader).take(payload_len))?;
+        reader.seek_relative((payload_len - head.len) as i64)?;
+        last_seq = last_seq.max(head.seq);
+        pos += prefix_len + payload_len;
+    }
+    Ok((last_seq, pos))
+}
+
+/// `wal-YYYY-MM-DDTHH.log` for an hour since the Unix epoch, so names sort in time order.
+fn file_name(hour: i64) -> String {
+    // Days to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = hour.div_euclid(24) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}{:04}-{:02}-{:02}T{:02}{}",
+        ARCHIVE_FILE_PREFIX,
+        year,
+        month,
+        day,
+        hour.rem_euclid(24),
+        ARCHIVE_FILE_SUFFIX
+    )
+}
@@ -0,0 +1,148 @@
+use std::io;
+#[cfg(any(feature = "sled", feature = "rocksdb"))]
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::ImportReport;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Move data into and out of a breakout1 key-value store log"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import the string keys of a Redis RDB snapshot, AOF file or appendonlydir.
+    ImportRedis {
+        /// The dump.rdb, appendonly.aof or appendonlydir to read.
+        dump: PathBuf,
+        /// The log to write into, created if it does not exist.
+        db: PathBuf,
+    },
+    /// Copy a sled tree into a log.
+    #[cfg(feature = "sled")]
+    ImportSled {
+        /// The sled database directory.
+        sled: PathBuf,
+        db: PathBuf,
+        /// The tree to read; the default tree if left out.
+        #[arg(long)]
+        tree: Option<String>,
+    },
+    /// Copy a log's live keys into a sled tree.
+    #[cfg(feature = "sled")]
+    ExportSled {
+        db: PathBuf,
+        /// The sled database directory, created if it does not exist.
+        sled: PathBuf,
+        #[arg(long)]
+        tree: Option<String>,
+    },
+    /// Copy a RocksDB column family into a log.
+    #[cfg(feature = "rocksdb")]
+    ImportRocksdb {
+        /// The RocksDB directory.
+        rocksdb: PathBuf,
+        db: PathBuf,
+        /// The column family to read; the default one if left out.
+        #[arg(long)]
+        cf: Option<String>,
+    },
+    /// Copy a log's live keys into a RocksDB column family.
+    #[cfg(feature = "rocksdb")]
+    ExportRocksdb {
+        db: PathBuf,
+        /// The RocksDB directory, created if it does not exist.
+        rocksdb: PathBuf,
+        /// The column family to write, created if missing; the default one if left out.
+        #[arg(long)]
+        cf: Option<String>,
+    },
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args.command) {
+        eprintln!("kv-migrate: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(command: Command) -> io::Result<()> {
+    match command {
+        Command::ImportRedis { dump, db } => {
+            let engine = Engine::load(db)?;
+            print_report(&engine.import_redis(dump)?);
+            engine.sync()
+        }
+        #[cfg(feature = "sled")]
+        Command::ImportSled { sled, db, tree } => {
+            let source = open_sled_tree(&sled, tree.as_deref())?;
+            let engine = Engine::load(db)?;
+            print_report(&engine.import_sled(&source)?);
+            engine.sync()
+        }
+        #[cfg(feature = "sled")]
+        Command::ExportSled { db, sled, tree } => {
+            let target = open_sled_tree(&sled, tree.as_deref())?;
+            let engine = Engine::load(db)?;
+            println!("exported {} keys", engine.export_sled(&target)?);
+            Ok(())
+        }
+        #[cfg(feature = "rocksdb")]
+        Command::ImportRocksdb { rocksdb, db, cf } => {
+            let source = open_rocksdb(&rocksdb, cf.as_deref(), false)?;
+            let engine = Engine::load(db)?;
+            print_report(&engine.import_rocksdb(&source, cf.as_deref())?);
+            engine.sync()
+        }
+        #[cfg(feature = "rocksdb")]
+        Command::ExportRocksdb { db, rocksdb, cf } => {
+            let target = open_rocksdb(&rocksdb, cf.as_deref(), true)?;
+            let engine = Engine::load(db)?;
+            let count = engine.export_rocksdb(&target, cf.as_deref())?;
+            println!("exported {count} keys");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "sled")]
+fn open_sled_tree(path: &Path, tree: Option<&str>) -> io::Result<sled::Tree> {
+    let db = sled::open(path).map_err(io::Error::other)?;
+    match tree {
+        Some(name) => db.open_tree(name).map_err(io::Error::other),
+        None => Ok((*db).clone()),
+    }
+}
+
+/// Opens a RocksDB directory with all its column families. With `create`, the directory and
+/// `cf` are created if they are missing.
+#[cfg(feature = "rocksdb")]
+fn open_rocksdb(path: &Path, cf: Option<&str>, create: bool) -> io::Result<rocksdb::DB> {
+    let mut options = rocksdb::Options::default();
+    options.create_if_missing(create);
+    options.create_missing_column_families(create);
+    let mut families = rocksdb::DB::list_cf(&options, path).unwrap_or_default();
+    if let Some(cf) = cf
+        && !families.iter().any(|name| name == cf)
+    {
+        families.push(cf.to_string());
+    }
+    rocksdb::DB::open_cf(&options, path, families).map_err(io::Error::other)
+}
+
+fn print_report(report: &ImportReport) {
+    println!(
+        "imported {} keys; left out {} already expired and skipped {} of other types or databases",
+        report.keys, report.expired, report.skipped
+    );
+}
@@ -0,0 +1,1288 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::Engine as _;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use breakout1_kv_store::constants::DEFAULT_COMPACT_THRESHOLD;
+use breakout1_kv_store::types::{BatchOp, EngineStats};
+use breakout1_kv_store::{Engine, EngineOptions, SyncPolicy};
+use clap::{Parser, Subcommand, ValueEnum};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Command-line client for a breakout1 key-value store server and its log files"
+)]
+struct Args {
+    /// Base URL of the server.
+    #[arg(
+        long,
+        env = "KV_URL",
+        default_value = "http://127.0.0.1:8080",
+        global = true
+    )]
+    url: String,
+    /// API key, admin token or JWT, sent as `Authorization: Bearer`.
+    #[arg(long, env = "KV_TOKEN", hide_env_values = true, global = true)]
+    token: Option<String>,
+    /// API key sent as `X-Api-Key` instead.
+    #[arg(
+        long,
+        env = "KV_API_KEY",
+        hide_env_values = true,
+        global = true,
+        conflicts_with = "token"
+    )]
+    api_key: Option<String>,
+    /// How values are printed: as stored, or base64-encoded.
+    #[arg(long, value_enum, default_value_t = Output::Raw, global = true)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// The bytes as stored; `get` adds no trailing newline.
+    Raw,
+    Base64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the value of a key; exits with 1 if it does not exist.
+    Get { key: String },
+    /// Store a value, read from standard input when left out.
+    Set {
+        key: String,
+        value: Option<String>,
+        /// Seconds until the key expires.
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+    /// Delete a key.
+    Del { key: String },
+    /// Print the pairs under a prefix in key order, one `key<TAB>value` line each.
+    Scan {
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Most pairs to print; the server allows up to 1000.
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Print the number of live keys under a prefix.
+    Count {
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    /// Print the server's /stats.
+    Stats,
+    /// Compact the log now (admin).
+    Compact,
+    /// Write a snapshot into a directory on the server (admin).
+    Backup {
+        /// Directory on the server.
+        dir: PathBuf,
+    },
+    /// Work on a log file directly, with the server stopped.
+    File {
+        #[command(subcommand)]
+        command: FileCommand,
+    },
+    /// Start an interactive shell; type `help` in it for its commands.
+    Repl {
+        /// Work on this log with the embedded engine instead of the server.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Time a workload against an embedded engine or the server and report throughput and
+    /// latency percentiles.
+    Bench(BenchArgs),
+    /// Write the pairs in a JSON Lines or CSV file in batches, printing progress.
+    Load {
+        /// The file to read, or `-` for standard input.
+        input: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+        /// Leave keys that already exist alone.
+        #[arg(long)]
+        if_absent: bool,
+        /// Pairs per batch; each is written atomically.
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+        /// Load into this log with the embedded engine instead of the server.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Write the pairs under a prefix to a JSON Lines or CSV file, as of one moment. From the
+    /// server, this downloads a snapshot of the whole log (admin).
+    Export {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        #[arg(long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+        /// Where to write; standard output when left out.
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Read this log with the embedded engine instead of the server.
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// One `{"key", "value"}` object a line, with `key_base64` or `value_base64` in place of
+    /// bytes that are not UTF-8.
+    Jsonl,
+    /// `key,value` rows, quoted as in RFC 4180; a `key,value` header is skipped.
+    Csv,
+}
+
+#[derive(Subcommand)]
+enum FileCommand {
+    /// Print every live pair in key order, one `key<TAB>value` line each.
+    Dump { path: PathBuf },
+    /// Check that every record decodes and the index matches the log; exits with 1 if not.
+    Verify { path: PathBuf },
+    /// Rewrite the log with only its live keys.
+    Compact { path: PathBuf },
+    /// Print key and byte counts.
+    Stats { path: PathBuf },
+}
+
+#[derive(clap::Args)]
+struct BenchArgs {
+    /// Operations to run, shared between the threads.
+    #[arg(long, default_value_t = 1_000_000)]
+    ops: u64,
+    /// Bytes in each value written.
+    #[arg(long, default_value_t = 256)]
+    value_size: usize,
+    /// Distinct keys the operations pick from at random. Once they take up more than the compact
+    /// threshold, every write compacts.
+    #[arg(long, default_value_t = 1000)]
+    keys: u64,
+    #[arg(long, value_enum, default_value_t = Workload::Mixed)]
+    workload: Workload,
+    #[arg(long, default_value_t = 8)]
+    threads: usize,
+    /// Seed for picking keys and operations; the same seed repeats the same sequence.
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// Run against the server at `--url` instead of an embedded engine.
+    #[arg(long)]
+    http: bool,
+    /// Log for the embedded engine; a temporary file, removed afterwards, when left out.
+    #[arg(long, conflicts_with = "http")]
+    file: Option<PathBuf>,
+    /// When the embedded engine syncs writes to disk.
+    #[arg(long, value_enum, default_value_t = SyncMode::Never)]
+    sync: SyncMode,
+    /// Log size in bytes at which the embedded engine compacts.
+    #[arg(long, default_value_t = DEFAULT_COMPACT_THRESHOLD)]
+    compact_threshold: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Workload {
+    /// Gets of keys written before the clock starts.
+    Read,
+    /// Sets.
+    Write,
+    /// Half gets and half sets, on keys written before the clock starts.
+    Mixed,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SyncMode {
+    Never,
+    Always,
+}
+
+fn main() {
+    let args = Args::parse();
+    let credential = match (args.token, args.api_key) {
+        (Some(token), _) => Some(("Authorization", format!("Bearer {}", token))),
+        (None, Some(key)) => Some(("X-Api-Key", key)),
+        (None, None) => None,
+    };
+    let remote = Remote {
+        url: args.url.trim_end_matches('/').to_string(),
+        credential,
+        agent: ureq::AgentBuilder::new().build(),
+    };
+    if let Err(e) = run(&remote, args.command, args.output) {
+        eprintln!("kvctl: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(remote: &Remote, command: Command, output: Output) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    match command {
+        Command::Get { key } => {
+            let value = remote
+                .get(key.as_bytes())?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "key not found"))?;
+            match output {
+                Output::Raw => out.write_all(&value)?,
+                Output::Base64 => writeln!(out, "{}", STANDARD.encode(&value))?,
+            }
+        }
+        Command::Set { key, value, ttl } => {
+            let value = match value {
+                Some(value) => value.into_bytes(),
+                None => {
+                    let mut value = Vec::new();
+                    io::stdin().read_to_end(&mut value)?;
+                    value
+                }
+            };
+            remote.set(key.as_bytes(), &value, ttl)?;
+        }
+        Command::Del { key } => remote.del(key.as_bytes())?,
+        Command::Scan { prefix, limit } => {
+            for (key, value) in remote.scan(&prefix, limit)? {
+                write!(out, "{}\t", key)?;
+                match output {
+                    Output::Raw => out.write_all(&value)?,
+                    Output::Base64 => out.write_all(STANDARD.encode(&value).as_bytes())?,
+                }
+                writeln!(out)?;
+            }
+        }
+        Command::Count { prefix } => writeln!(out, "{}", remote.count(&prefix)?)?,
+        Command::Stats => print_json(&mut out, &remote.json("GET", "/stats", None)?)?,
+        Command::Compact => print_json(&mut out, &remote.json("POST", "/admin/compact", None)?)?,
+        Command::Backup { dir } => {
+            let body = json!({ "dir": dir });
+            print_json(&mut out, &remote.json("POST", "/admin/backup", Some(body))?)?
+        }
+        Command::File { command } => run_file(&mut out, command, output)?,
+        Command::Repl { file: Some(path) } => {
+            let engine = open_file(&path, false)?;
+            repl(&mut out, &Store::Local(&engine))?;
+        }
+        Command::Repl { file: None } => repl(&mut out, &Store::Remote(remote))?,
+        Command::Load {
+            input,
+            format,
+            if_absent,
+            batch_size,
+            file,
+        } => {
+            let reader: Box<dyn BufRead> = if input == Path::new("-") {
+                Box::new(io::stdin().lock())
+            } else {
+                Box::new(BufReader::new(File::open(&input)?))
+            };
+            let pairs = read_pairs(reader, format);
+            match file {
+                Some(path) => {
+                    let engine = open_file(&path, false)?;
+                    load(
+                        &mut out,
+                        &Store::Local(&engine),
+                        pairs,
+                        if_absent,
+                        batch_size,
+                    )?;
+                    engine.sync()?;
+                }
+                None => load(
+                    &mut out,
+                    &Store::Remote(remote),
+                    pairs,
+                    if_absent,
+                    batch_size,
+                )?,
+            }
+        }
+        Command::Export {
+            prefix,
+            format,
+            out: path,
+            file,
+        } => {
+            let mut writer: Box<dyn Write> = match &path {
+                Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+                None => Box::new(&mut out),
+            };
+            let count = match file {
+                Some(file) => export(&mut writer, &open_file(&file, true)?, &prefix, format)?,
+                None => {
+                    let snapshot =
+                        std::env::temp_dir().join(format!("kvctl-export-{}.db", process::id()));
+                    let result = remote
+                        .snapshot_to(&snapshot)
+                        .and_then(|_| open_file(&snapshot, true))
+                        .and_then(|engine| export(&mut writer, &engine, &prefix, format));
+                    let _ = std::fs::remove_file(&snapshot);
+                    result?
+                }
+            };
+            writer.flush()?;
+            eprintln!("exported {} pairs", count);
+        }
+        Command::Bench(args) if args.http => bench(&mut out, &Store::Remote(remote), &args)?,
+        Command::Bench(args) => {
+            let (path, temporary) = match &args.file {
+                Some(path) => (path.clone(), false),
+                None => {
+                    let name = format!("kvctl-bench-{}.db", process::id());
+                    (std::env::temp_dir().join(name), true)
+                }
+            };
+            let options = EngineOptions {
+                sync: match args.sync {
+                    SyncMode::Never => SyncPolicy::Never,
+                    SyncMode::Always => SyncPolicy::Always,
+                },
+                compact_threshold: args.compact_threshold,
+                ..EngineOptions::default()
+            };
+            let engine = Engine::load_with_options(&path, options)?;
+            let result = bench(&mut out, &Store::Local(&engine), &args);
+            drop(engine);
+            if temporary {
+                let _ = std::fs::remove_file(&path);
+            }
+            result?;
+        }
+    }
+    out.flush()
+}
+
+/// Keys read per page by `file dump` and `export`.
+const DUMP_PAGE: usize = 1000;
+
+fn run_file(out: &mut impl Write, command: FileCommand, output: Output) -> io::Result<()> {
+    match command {
+        FileCommand::Dump { path } => {
+            let engine = open_file(&path, true)?;
+            for_each_pair(&engine, b"", |key, value| match output {
+                Output::Raw => {
+                    out.write_all(key)?;
+                    out.write_all(b"\t")?;
+                    out.write_all(value)?;
+                    writeln!(out)
+                }
+                Output::Base64 => {
+                    writeln!(out, "{}\t{}", STANDARD.encode(key), STANDARD.encode(value))
+                }
+            })?;
+        }
+        FileCommand::Verify { path } => {
+            let report = open_file(&path, true)?.verify()?;
+            writeln!(
+                out,
+                "{} records in {} bytes, {} bytes unread at the end",
+                report.records, report.bytes, report.unread_bytes
+            )?;
+            for record in &report.corrupt {
+                writeln!(out, "corrupt record at {}: {}", record.pos, record.reason)?;
+            }
+            for entry in &report.orphaned {
+                let key = String::from_utf8_lossy(&entry.key);
+                writeln!(
+                    out,
+                    "index entry for {:?} disagrees with the log: {}",
+                    key, entry.reason
+                )?;
+            }
+            for key in &report.missing {
+                writeln!(
+                    out,
+                    "{:?} is missing from the index",
+                    String::from_utf8_lossy(key)
+                )?;
+            }
+            if !report.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the log failed verification",
+                ));
+            }
+        }
+        FileCommand::Compact { path } => {
+            let report = open_file(&path, false)?.compact()?;
+            writeln!(
+                out,
+                "kept {} keys; {} bytes before, {} after, in {:?}",
+                report.keys, report.bytes_before, report.bytes_after, report.duration
+            )?;
+        }
+        FileCommand::Stats { path } => print_stats(out, &open_file(&path, true)?.stats())?,
+    }
+    Ok(())
+}
+
+/// Calls `f` with every live pair under `prefix`, in key order, reading `DUMP_PAGE` keys at a time.
+fn for_each_pair(
+    engine: &Engine,
+    prefix: &[u8],
+    mut f: impl FnMut(&[u8], &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut after = None;
+    loop {
+        let keys = engine.keys(prefix, after.as_deref(), DUMP_PAGE);
+        for key in &keys {
+            // Skip keys that expired since the page was read.
+            if let Some(value) = engine.get(key)? {
+                f(key, &value)?;
+            }
+        }
+        if keys.len() < DUMP_PAGE {
+            return Ok(());
+        }
+        after = keys.into_iter().next_back();
+    }
+}
+
+fn print_stats(out: &mut impl Write, stats: &EngineStats) -> io::Result<()> {
+    writeln!(out, "keys: {}", stats.keys)?;
+    writeln!(out, "file_size: {}", stats.file_size)?;
+    writeln!(out, "live_bytes: {}", stats.live_bytes)?;
+    writeln!(out, "dead_bytes: {}", stats.dead_bytes)?;
+    writeln!(out, "index_bytes: {}", stats.index.bytes)?;
+    writeln!(out, "last_sequence: {}", stats.replication.applied_seq)
+}
+
+/// Loads a log with the embedded engine. Unlike `Engine::load`, a missing file is an error rather
+/// than a new empty log, and a `read_only` engine refuses writes. Nothing stops a running server
+/// from writing to the file at the same time, so stop it first.
+fn open_file(path: &Path, read_only: bool) -> io::Result<Engine> {
+    if !path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a file", path.display()),
+        ));
+    }
+    let engine = Engine::load(path)?;
+    engine.set_read_only(read_only);
+    Ok(engine)
+}
+
+fn print_json(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    writeln!(out, "{}", serde_json::to_string_pretty(value)?)
+}
+
+const REPL_COMMANDS: &[&str] = &[
+    "get", "set", "setex", "del", "scan", "count", "stats", "help", "exit", "quit",
+];
+
+const REPL_HELP: &str = "\
+get KEY                 print a value, as text or as a hex dump
+set KEY VALUE           store the rest of the line as the value
+setex KEY SECS VALUE    the same, expiring after SECS seconds
+del KEY                 delete a key
+scan [PREFIX [LIMIT]]   list pairs in key order, 20 by default
+count [PREFIX]          count live keys
+stats                   show the store's statistics
+exit                    leave (or press Ctrl-D)";
+
+/// An embedded engine or the server, for the commands that work on either.
+enum Store<'a> {
+    Local(&'a Engine),
+    Remote(&'a Remote),
+}
+
+impl Store<'_> {
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Store::Local(engine) => engine.get(key),
+            Store::Remote(remote) => remote.get(key),
+        }
+    }
+
+    fn set(&self, key: &[u8], value: &[u8], ttl_secs: Option<u64>) -> io::Result<()> {
+        match (self, ttl_secs) {
+            (Store::Local(engine), None) => engine.set(key, value).map(drop),
+            (Store::Local(engine), Some(secs)) => engine
+                .set_with_ttl(key, value, Duration::from_secs(secs))
+                .map(drop),
+            (Store::Remote(remote), _) => remote.set(key, value, ttl_secs),
+        }
+    }
+
+    fn del(&self, key: &[u8]) -> io::Result<()> {
+        match self {
+            Store::Local(engine) => engine.del(key),
+            Store::Remote(remote) => remote.del(key),
+        }
+    }
+
+    fn scan(&self, prefix: &str, limit: usize) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            Store::Local(engine) => engine.scan(prefix.as_bytes(), limit),
+            Store::Remote(remote) => Ok(remote
+                .scan(prefix, limit)?
+                .into_iter()
+                .map(|(key, value)| (key.into_bytes(), value))
+                .collect()),
+        }
+    }
+
+    fn count(&self, prefix: &str) -> io::Result<u64> {
+        match self {
+            Store::Local(engine) => Ok(engine.usage(prefix.as_bytes()).keys as u64),
+            Store::Remote(remote) => remote.count(prefix),
+        }
+    }
+
+    /// Writes `pairs` as one batch, or only those whose key is absent; returns which were written.
+    fn set_batch(&self, pairs: &[(Vec<u8>, Vec<u8>)], if_absent: bool) -> io::Result<Vec<bool>> {
+        match self {
+            Store::Local(engine) if if_absent => engine.set_many_if_absent(pairs),
+            Store::Local(engine) => {
+                let ops: Vec<BatchOp> = pairs
+                    .iter()
+                    .map(|(key, value)| BatchOp::Set {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect();
+                engine.write_batch(&ops)?;
+                Ok(vec![true; pairs.len()])
+            }
+            Store::Remote(remote) => {
+                let existed = remote.set_batch(pairs, if_absent)?;
+                Ok(existed
+                    .into_iter()
+                    .map(|existed| !if_absent || !existed)
+                    .collect())
+            }
+        }
+    }
+
+    fn print_stats(&self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Store::Local(engine) => print_stats(out, &engine.stats()),
+            Store::Remote(remote) => print_json(out, &remote.json("GET", "/stats", None)?),
+        }
+    }
+}
+
+/// Completes the command names at the start of a line.
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let word = &line[..pos];
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = REPL_COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|command| format!("{command} "))
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Reads commands until `exit` or end of input, keeping history in `~/.kvctl_history`. A failed
+/// command prints its error and the shell carries on.
+fn repl(out: &mut impl Write, store: &Store) -> io::Result<()> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new().map_err(io::Error::other)?;
+    editor.set_helper(Some(ReplHelper));
+    let history = std::env::var_os("HOME").map(|home| Path::new(&home).join(".kvctl_history"));
+    if let Some(history) = &history {
+        // There is none on the first run.
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("kv> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line).map_err(io::Error::other)?;
+        match repl_command(out, store, line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => writeln!(out, "error: {e}")?,
+        }
+        out.flush()?;
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(io::Error::other)?;
+    }
+    if let Store::Local(engine) = store {
+        engine.sync()?;
+    }
+    Ok(())
+}
+
+/// Runs one line of the shell; false to leave it.
+fn repl_command(out: &mut impl Write, store: &Store, line: &str) -> io::Result<bool> {
+    let (command, rest) = split_word(line);
+    let usage = || io::Error::new(io::ErrorKind::InvalidInput, "see `help` for usage");
+    match command {
+        "get" => {
+            let (key, _) = split_word(rest);
+            if key.is_empty() {
+                return Err(usage());
+            }
+            match store.get(key.as_bytes())? {
+                Some(value) => write_pretty(out, &value)?,
+                None => writeln!(out, "(not found)")?,
+            }
+        }
+        "set" => {
+            let (key, value) = split_word(rest);
+            if key.is_empty() {
+                return Err(usage());
+            }
+            store.set(key.as_bytes(), value.as_bytes(), None)?;
+            writeln!(out, "OK")?;
+        }
+        "setex" => {
+            let (key, rest) = split_word(rest);
+            let (secs, value) = split_word(rest);
+            let secs = secs.parse().map_err(|_| usage())?;
+            store.set(key.as_bytes(), value.as_bytes(), Some(secs))?;
+            writeln!(out, "OK")?;
+        }
+        "del" => {
+            let (key, _) = split_word(rest);
+            if key.is_empty() {
+                return Err(usage());
+            }
+            store.del(key.as_bytes())?;
+            writeln!(out, "OK")?;
+        }
+        "scan" => {
+            let (prefix, rest) = split_word(rest);
+            let (limit, _) = split_word(rest);
+            let limit = match limit {
+                "" => 20,
+                limit => limit.parse().map_err(|_| usage())?,
+            };
+            for (key, value) in store.scan(prefix, limit)? {
+                writeln!(out, "{} => {}", inline(&key), inline(&value))?;
+            }
+        }
+        "count" => writeln!(out, "{}", store.count(split_word(rest).0)?)?,
+        "stats" => store.print_stats(out)?,
+        "help" => writeln!(out, "{REPL_HELP}")?,
+        "exit" | "quit" => return Ok(false),
+        _ => writeln!(out, "unknown command {command:?}; try `help`")?,
+    }
+    Ok(true)
+}
+
+/// The first whitespace-separated word of `line`, and the rest with leading whitespace removed.
+fn split_word(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (line, ""),
+    }
+}
+
+/// Text that is safe to print as it is: UTF-8 without control characters other than newlines
+/// and tabs.
+fn as_text(value: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(value).ok()?;
+    let printable = text
+        .chars()
+        .all(|c| !c.is_control() || c == '\n' || c == '\t');
+    printable.then_some(text)
+}
+
+/// A value as text, or as a hex dump of 16 bytes a line with the printable ASCII alongside.
+fn write_pretty(out: &mut impl Write, value: &[u8]) -> io::Result<()> {
+    if let Some(text) = as_text(value) {
+        return writeln!(out, "{text}");
+    }
+    writeln!(out, "({} bytes)", value.len())?;
+    for (line, chunk) in value.chunks(16).enumerate() {
+        write!(out, "{:08x} ", line * 16)?;
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => write!(out, " {byte:02x}")?,
+                None => write!(out, "   ")?,
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                b' '..=b'~' => byte as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(out, "  |{ascii}|")?;
+    }
+    Ok(())
+}
+
+/// A key or value on one line: quoted text, or `0x` and hex.
+fn inline(value: &[u8]) -> String {
+    match as_text(value) {
+        Some(text) => format!("{text:?}"),
+        None => {
+            let hex: String = value.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("0x{hex}")
+        }
+    }
+}
+
+/// Key-value pairs read from a file.
+type Pairs = Box<dyn Iterator<Item = io::Result<(Vec<u8>, Vec<u8>)>>>;
+
+/// Writes `pairs` `batch_size` at a time, reporting the running total on standard error.
+fn load(
+    out: &mut impl Write,
+    store: &Store,
+    pairs: Pairs,
+    if_absent: bool,
+    batch_size: usize,
+) -> io::Result<()> {
+    let batch_size = batch_size.max(1);
+    let started = Instant::now();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut written = 0;
+    let mut skipped = 0;
+    let mut pairs = pairs.peekable();
+    while pairs.peek().is_some() {
+        batch.clear();
+        for pair in pairs.by_ref().take(batch_size) {
+            batch.push(pair?);
+        }
+        let results = store.set_batch(&batch, if_absent)?;
+        let batch_written = results.iter().filter(|&&written| written).count();
+        written += batch_written;
+        skipped += results.len() - batch_written;
+        eprint!("\r{} pairs written, {} left out", written, skipped);
+    }
+    if written + skipped > 0 {
+        eprintln!();
+    }
+    writeln!(
+        out,
+        "loaded {} pairs in {:.2?}; left out {} whose key existed",
+        written,
+        started.elapsed(),
+        skipped
+    )
+}
+
+/// The pairs in `reader`, decoded lazily so files of any size stream through.
+fn read_pairs(reader: Box<dyn BufRead>, format: Format) -> Pairs {
+    match format {
+        Format::Jsonl => Box::new(
+            reader
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+                .map(|(n, line)| {
+                    let record: Record =
+                        serde_json::from_str(&line?).map_err(|e| invalid_line(n + 1, e))?;
+                    record.into_pair().map_err(|e| invalid_line(n + 1, e))
+                }),
+        ),
+        Format::Csv => {
+            let mut reader = reader;
+            let mut line = 0;
+            let mut first = true;
+            Box::new(std::iter::from_fn(move || {
+                loop {
+                    let record_line = line + 1;
+                    let fields = match read_csv_record(&mut reader, &mut line) {
+                        Ok(Some(fields)) => fields,
+                        Ok(None) => return None,
+                        Err(e) => return Some(Err(invalid_line(record_line, e))),
+                    };
+                    let header = first && fields == ["key", "value"];
+                    first = false;
+                    match <[String; 2]>::try_from(fields) {
+                        _ if header => continue,
+                        Ok([key, value]) => {
+                            return Some(Ok((key.into_bytes(), value.into_bytes())));
+                        }
+                        Err(fields) if fields == [""] => continue,
+                        Err(fields) => {
+                            let e = format!("expected 2 fields, found {}", fields.len());
+                            return Some(Err(invalid_line(record_line, e)));
+                        }
+                    }
+                }
+            }))
+        }
+    }
+}
+
+fn invalid_line(line: usize, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {e}"))
+}
+
+/// Writes the live pairs under `prefix` in `format`; returns how many.
+fn export(out: &mut impl Write, engine: &Engine, prefix: &str, format: Format) -> io::Result<u64> {
+    let mut count = 0;
+    if let Format::Csv = format {
+        writeln!(out, "key,value")?;
+    }
+    for_each_pair(engine, prefix.as_bytes(), |key, value| {
+        count += 1;
+        match format {
+            Format::Jsonl => {
+                serde_json::to_writer(&mut *out, &Record::new(key, value))?;
+                writeln!(out)
+            }
+            Format::Csv => {
+                let text = |bytes| {
+                    std::str::from_utf8(bytes).map_err(|_| {
+                        let e = format!(
+                            "{} or its value is not UTF-8; use --format jsonl",
+                            inline(key)
+                        );
+                        io::Error::new(io::ErrorKind::InvalidData, e)
+                    })
+                };
+                writeln!(out, "{},{}", csv_field(text(key)?), csv_field(text(value)?))
+            }
+        }
+    })?;
+    Ok(count)
+}
+
+/// `field` quoted if it holds a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// One pair of a JSON Lines file. Keys and values are text when they are UTF-8, and base64 in the
+/// `_base64` field otherwise.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_base64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_base64: Option<String>,
+}
+
+impl Record {
+    fn new(key: &[u8], value: &[u8]) -> Self {
+        let (key, key_base64) = text_or_base64(key);
+        let (value, value_base64) = text_or_base64(value);
+        Record {
+            key,
+            key_base64,
+            value,
+            value_base64,
+        }
+    }
+
+    fn into_pair(self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let key = match (self.key, self.key_base64) {
+            (Some(key), _) => key.into_bytes(),
+            (None, Some(key)) => decode(&key)?,
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing `key` or `key_base64`",
+                ));
+            }
+        };
+        let value = match (self.value, self.value_base64) {
+            (Some(value), _) => value.into_bytes(),
+            (None, Some(value)) => decode(&value)?,
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing `value` or `value_base64`",
+                ));
+            }
+        };
+        Ok((key, value))
+    }
+}
+
+fn text_or_base64(bytes: &[u8]) -> (Option<String>, Option<String>) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (Some(text.to_string()), None),
+        Err(_) => (None, Some(STANDARD.encode(bytes))),
+    }
+}
+
+/// Reads one CSV record, which may run over several lines inside quotes, counting lines in
+/// `line`. `None` at the end of the input.
+fn read_csv_record(reader: &mut impl BufRead, line: &mut usize) -> io::Result<Option<Vec<String>>> {
+    let mut text = String::new();
+    loop {
+        if reader.read_line(&mut text)? == 0 {
+            if text.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unterminated quoted field",
+            ));
+        }
+        *line += 1;
+        if text.matches('"').count().is_multiple_of(2) {
+            break;
+        }
+    }
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    Ok(Some(fields))
+}
+
+/// Key `n` of a benchmark.
+fn bench_key(n: u64) -> Vec<u8> {
+    format!("bench:{n:010}").into_bytes()
+}
+
+/// Writes the keys a read or mixed workload picks from, runs the workload on `args.threads`
+/// threads and prints the results. Each thread draws its keys and operations from its own
+/// generator seeded from `args.seed`, so runs with the same arguments do the same work.
+fn bench(out: &mut impl Write, store: &Store, args: &BenchArgs) -> io::Result<()> {
+    let threads = args.threads.max(1) as u64;
+    let keys = args.keys.max(1);
+    let mut rng = Rng::new(args.seed);
+    let value: Vec<u8> = (0..args.value_size).map(|_| rng.next_u64() as u8).collect();
+
+    if args.workload != Workload::Write {
+        thread::scope(|scope| {
+            let loaders: Vec<_> = (0..threads)
+                .map(|t| {
+                    let value = &value;
+                    scope.spawn(move || {
+                        (t..keys)
+                            .step_by(threads as usize)
+                            .try_for_each(|n| store.set(&bench_key(n), value, None))
+                    })
+                })
+                .collect();
+            loaders
+                .into_iter()
+                .try_for_each(|loader| loader.join().unwrap())
+        })?;
+    }
+
+    let started = Instant::now();
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let value = &value;
+                let ops = args.ops / threads + u64::from(t < args.ops % threads);
+                let mut rng = Rng::new(args.seed.wrapping_add(t + 1));
+                scope.spawn(move || -> io::Result<Latencies> {
+                    let mut latencies = Latencies::default();
+                    for _ in 0..ops {
+                        let key = bench_key(rng.next_u64() % keys);
+                        let get = match args.workload {
+                            Workload::Read => true,
+                            Workload::Write => false,
+                            Workload::Mixed => rng.next_u64().is_multiple_of(2),
+                        };
+                        let op_started = Instant::now();
+                        if get {
+                            store.get(&key)?;
+                            latencies.gets.push(op_started.elapsed());
+                        } else {
+                            store.set(&key, value, None)?;
+                            latencies.sets.push(op_started.elapsed());
+                        }
+                    }
+                    Ok(latencies)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    let elapsed = started.elapsed();
+
+    let mut gets = Vec::new();
+    let mut sets = Vec::new();
+    for latencies in results {
+        gets.extend(latencies.gets);
+        sets.extend(latencies.sets);
+    }
+    let target = match store {
+        Store::Local(_) => "an embedded engine".to_string(),
+        Store::Remote(remote) => remote.url.clone(),
+    };
+    writeln!(
+        out,
+        "{} ops of {}-byte values over {} keys on {} threads against {}",
+        args.ops, args.value_size, keys, threads, target
+    )?;
+    writeln!(
+        out,
+        "took {:.2?}, {:.0} ops/s",
+        elapsed,
+        args.ops as f64 / elapsed.as_secs_f64()
+    )?;
+    writeln!(
+        out,
+        "{:<4} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "op", "count", "p50", "p90", "p99", "p99.9", "max"
+    )?;
+    for (name, latencies) in [("get", &mut gets), ("set", &mut sets)] {
+        if latencies.is_empty() {
+            continue;
+        }
+        latencies.sort_unstable();
+        let percentile = |p: f64| {
+            let i = ((latencies.len() - 1) as f64 * p).round() as usize;
+            format!("{:.1?}", latencies[i])
+        };
+        writeln!(
+            out,
+            "{:<4} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            name,
+            latencies.len(),
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(0.999),
+            percentile(1.0)
+        )?;
+    }
+    if let Store::Local(engine) = store
+        && let Some(compaction) = engine.stats().last_compaction
+    {
+        writeln!(
+            out,
+            "last compaction took {:.2?}: {} bytes to {}",
+            compaction.duration, compaction.bytes_before, compaction.bytes_after
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Latencies {
+    gets: Vec<Duration>,
+    sets: Vec<Duration>,
+}
+
+/// xorshift64*, enough to pick keys and operations without another dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+/// The HTTP API, with keys and values sent base64-encoded so any bytes work.
+struct Remote {
+    url: String,
+    /// Header and value carrying the credential.
+    credential: Option<(&'static str, String)>,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct ScanEntry {
+    key: String,
+    value: Option<String>,
+    value_base64: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: u64,
+}
+
+#[derive(Deserialize)]
+struct SetResult {
+    existed: bool,
+}
+
+impl Remote {
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self.agent.request(method, &format!("{}{}", self.url, path));
+        match &self.credential {
+            Some((header, value)) => request.set(header, value),
+            None => request,
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let path = format!("/get/{}", URL_SAFE_NO_PAD.encode(key));
+        let request = self.request("GET", &path).query("encoding", "base64");
+        match call(request.call()) {
+            Ok(response) => decode(&response.into_string()?).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set(&self, key: &[u8], value: &[u8], ttl_secs: Option<u64>) -> io::Result<()> {
+        let body = json!({
+            "key": STANDARD.encode(key),
+            "value": STANDARD.encode(value),
+            "ttl_secs": ttl_secs,
+        });
+        let request = self.request("POST", "/set").query("encoding", "base64");
+        call(request.send_json(body)).map(drop)
+    }
+
+    fn del(&self, key: &[u8]) -> io::Result<()> {
+        let path = format!("/del/{}", URL_SAFE_NO_PAD.encode(key));
+        let request = self.request("DELETE", &path).query("encoding", "base64");
+        call(request.call()).map(drop)
+    }
+
+    /// Writes `pairs` with one `/batch/set`; returns whether each key existed.
+    fn set_batch(&self, pairs: &[(Vec<u8>, Vec<u8>)], if_absent: bool) -> io::Result<Vec<bool>> {
+        let mut body = String::new();
+        for (key, value) in pairs {
+            let item = json!({ "key": STANDARD.encode(key), "value": STANDARD.encode(value) });
+            body.push_str(&item.to_string());
+            body.push('\n');
+        }
+        let request = self
+            .request("POST", "/batch/set")
+            .query("encoding", "base64")
+            .query("if_absent", &if_absent.to_string())
+            .set("Content-Type", "application/x-ndjson");
+        let results: Vec<SetResult> = call(request.send_string(&body))?.into_json()?;
+        Ok(results.into_iter().map(|result| result.existed).collect())
+    }
+
+    /// Downloads a consistent copy of the whole log into `path`. Needs admin credentials.
+    fn snapshot_to(&self, path: &Path) -> io::Result<u64> {
+        let response = call(self.request("GET", "/replication/snapshot").call())?;
+        io::copy(&mut response.into_reader(), &mut File::create(path)?)
+    }
+
+    /// `/scan` takes and returns keys as text, so keys that are not UTF-8 come back lossily.
+    fn scan(&self, prefix: &str, limit: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let request = self
+            .request("GET", "/scan")
+            .query("prefix", prefix)
+            .query("limit", &limit.to_string());
+        let entries: Vec<ScanEntry> = call(request.call())?.into_json()?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let value = match (entry.value, entry.value_base64) {
+                    (Some(value), _) => value.into_bytes(),
+                    (None, Some(value)) => decode(&value)?,
+                    (None, None) => Vec::new(),
+                };
+                Ok((entry.key, value))
+            })
+            .collect()
+    }
+
+    fn count(&self, prefix: &str) -> io::Result<u64> {
+        let request = self.request("GET", "/keys/count").query("prefix", prefix);
+        let count: Count = call(request.call())?.into_json()?;
+        Ok(count.count)
+    }
+
+    /// Sends a request, with `body` as JSON if given, and returns the JSON response.
+    fn json(&self, method: &str, path: &str, body: Option<Value>) -> io::Result<Value> {
+        let request = self.request(method, path);
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        call(response)?.into_json()
+    }
+}
+
+/// Turns an unsuccessful status into an error carrying the server's message.
+fn call(result: Result<ureq::Response, ureq::Error>) -> io::Result<ureq::Response> {
+    match result {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => {
+            let kind = match status {
+                404 => io::ErrorKind::NotFound,
+                401 | 403 => io::ErrorKind::PermissionDenied,
+                _ => io::ErrorKind::Other,
+            };
+            let message = response.into_string().unwrap_or_default();
+            Err(io::Error::new(
+                kind,
+                format!("{}: {}", status, message.trim()),
+            ))
+        }
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+fn decode(text: &str) -> io::Result<Vec<u8>> {
+    STANDARD
+        .decode(text.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
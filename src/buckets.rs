@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::engine::Engine;
+use crate::options::EngineOptions;
+
+/// Extension of bucket data files.
+const BUCKET_EXTENSION: &str = "db";
+/// Longest bucket name accepted.
+const MAX_NAME_LEN: usize = 64;
+
+/// Named engines kept side by side in one directory, one `<name>.db` log each, so every bucket
+/// is compacted, backed up and deleted on its own.
+pub struct Buckets {
+    dir: PathBuf,
+    options: EngineOptions,
+    engines: RwLock<BTreeMap<String, Arc<Engine>>>,
+}
+
+impl Buckets {
+    /// Opens every bucket in `dir`, creating the directory if needed. Each is loaded with
+    /// `options`.
+    pub fn open(dir: impl AsRef<Path>, options: EngineOptions) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut engines = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != BUCKET_EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if validate_name(name).is_err() {
+                continue;
+            }
+            let engine = Engine::load_with_options(&path, options.clone())?;
+            engines.insert(name.to_string(), Arc::new(engine));
+        }
+
+        Ok(Buckets {
+            dir,
+            options,
+            engines: RwLock::new(engines),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Engine>> {
+        self.engines.read().unwrap().get(name).cloned()
+    }
+
+    /// Bucket names in ascending order.
+    pub fn names(&self) -> Vec<String> {
+        self.engines.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Creates an empty bucket. Fails with `AlreadyExists` if there is one by that name and with
+    /// `InvalidInput` unless the name is 1 to 64 ASCII letters, digits, `-` or `_`.
+    pub fn create(&self, name: &str) -> io::Result<Arc<Engine>> {
+        validate_name(name)?;
+        let mut engines = self.engines.write().unwrap();
+        if engines.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("bucket {} already exists", name),
+            ));
+        }
+        let engine = Arc::new(Engine::load_with_options(
+            self.path(name),
+            self.options.clone(),
+        )?);
+        engines.insert(name.to_string(), engine.clone());
+        Ok(engine)
+    }
+
+    /// Deletes a bucket and its data file. Returns whether it existed. Requests already holding
+    /// the engine finish against the deleted file.
+    pub fn delete(&self, name: &str) -> io::Result<bool> {
+        let mut engines = self.engines.write().unwrap();
+        if engines.remove(name).is_none() {
+            return Ok(false);
+        }
+        fs::remove_file(self.path(name))?;
+        Ok(true)
+    }
+
+    /// Syncs every bucket to stable storage.
+    pub fn sync(&self) -> io::Result<()> {
+        for engine in self.engines.read().unwrap().values() {
+            engine.sync()?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name).with_extension(BUCKET_EXTENSION)
+    }
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bucket names are 1 to 64 ASCII letters, digits, - or _",
+        ))
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::wire::{self, Request, Response};
+
+/// Requests `Client::pipeline` writes before reading their responses, so neither side blocks on
+/// a full socket buffer while the other is still writing.
+const PIPELINE_BATCH: usize = 128;
+
+/// A blocking client for the binary protocol described on `wire::Request`.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    /// Sends an API key or token, needed first when the server requires credentials.
+    pub fn auth(&mut self, credential: &str) -> io::Result<()> {
+        self.call(Request::Auth(credential.as_bytes().to_vec()))
+            .map(drop)
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.call(Request::Get(key.to_vec()))
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.call(Request::Set {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        })
+        .map(drop)
+    }
+
+    pub fn del(&mut self, key: &[u8]) -> io::Result<()> {
+        self.call(Request::Del(key.to_vec())).map(drop)
+    }
+
+    /// Sends `requests` without waiting for each answer and returns the responses in order, so a
+    /// batch costs about one round trip.
+    pub fn pipeline(&mut self, requests: &[Request]) -> io::Result<Vec<Response>> {
+        let mut responses = Vec::with_capacity(requests.len());
+        for batch in requests.chunks(PIPELINE_BATCH) {
+            for request in batch {
+                self.writer.write_all(&request.encode())?;
+            }
+            self.writer.flush()?;
+            for _ in batch {
+                responses.push(self.read_response()?);
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Sends one request and turns its response into a value: `Some` payload for `Ok`, `None`
+    /// for `NotFound` and an error for `Error`.
+    fn call(&mut self, request: Request) -> io::Result<Option<Vec<u8>>> {
+        self.writer.write_all(&request.encode())?;
+        self.writer.flush()?;
+        match self.read_response()? {
+            Response::Ok(payload) => Ok(Some(payload)),
+            Response::NotFound => Ok(None),
+            Response::Error(message) => Err(io::Error::other(message)),
+        }
+    }
+
+    fn read_response(&mut self) -> io::Result<Response> {
+        let body = wire::read_frame(&mut self.reader, u32::MAX as usize)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "server closed the connection")
+        })?;
+        Response::decode(&body)
+    }
+}
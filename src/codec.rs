@@ -0,0 +1,448 @@
+use std::io::{self, Read};
+
+use wincode::{SchemaRead, SchemaWrite};
+
+use crate::constants::{
+    CHECKSUM_LEN, FLAG_CHECKSUM, FLAG_CHUNK, FLAG_CHUNKED, FLAG_EXPIRES, FLAG_HAS_VALUE,
+    FLAG_MERGE, FLAG_META, FLAG_SEQ, FORMAT_V2_MAGIC, LEN_PREFIX_SIZE,
+};
+use crate::types::{ChunkRole, DataFileEntry, Metadata};
+
+/// On-disk record layout of a log file.
+///
+/// v1: `[8 bytes: u64 LE length][wincode DataFileEntry]`, no file header.
+/// v2: file starts with `FORMAT_V2_MAGIC`, then `[varint length][payload]` where the payload is
+/// `[flags][varint zigzag tstamp][varint key len][key][optional fields][varint value len][value]`
+/// `[4 bytes: CRC-32 LE]`. The value part is only present when `FLAG_HAS_VALUE` is set;
+/// `FLAG_MERGE` marks the value as a merge operand. The CRC-32 covers everything before it in the
+/// payload and is there when `FLAG_CHECKSUM` is set, which it always is for records written now.
+/// Optional fields appear in flag order:
+/// - `FLAG_CHUNKED`: varint count of the `FLAG_CHUNK` piece records preceding this last piece.
+/// - `FLAG_SEQ`: varint sequence number. Always written; records without it read as sequence 0.
+/// - `FLAG_META`: varint entry count, then `[varint len][UTF-8 name][varint len][UTF-8 value]`
+///   per entry. Omitted when the metadata is empty.
+/// - `FLAG_EXPIRES`: varint zigzag expiry time in milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    V1,
+    V2,
+}
+
+impl Format {
+    /// Detects the format from the first bytes of a non-empty log file.
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(FORMAT_V2_MAGIC) {
+            Format::V2
+        } else {
+            Format::V1
+        }
+    }
+
+    /// Size of the file header preceding the first record.
+    pub fn header_len(self) -> u64 {
+        match self {
+            Format::V1 => 0,
+            Format::V2 => FORMAT_V2_MAGIC.len() as u64,
+        }
+    }
+}
+
+/// Shape of an entry as written by v1 logs.
+#[derive(SchemaWrite, SchemaRead)]
+struct V1Entry {
+    tstamp: i64,
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+/// Fields of a v2 record that precede the value bytes.
+#[derive(Debug, Clone)]
+pub struct RecordHead {
+    pub tstamp: i64,
+    pub seq: u64,
+    pub key: Vec<u8>,
+    pub merge: bool,
+    pub chunk: ChunkRole,
+    pub meta: Metadata,
+    pub expires_at: Option<i64>,
+    pub value_len: Option<u64>,
+    /// Whether a CRC-32 of the payload follows the value.
+    pub checksum: bool,
+    /// Bytes of payload taken up by the head, i.e. the offset of the value within the payload.
+    pub len: u64,
+}
+
+/// Encodes `entry` as a v2 record. Returns the framed bytes and the length of the prefix, so the
+/// payload starts at `prefix_len` within the returned buffer.
+pub fn encode(entry: &DataFileEntry) -> (Vec<u8>, u64) {
+    let value = entry.value.as_deref();
+    let head = encode_head(entry, value.map(|v| v.len() as u64));
+    let value = value.unwrap_or_default();
+
+    let mut frame =
+        Vec::with_capacity(MAX_VARINT_LEN + head.len() + value.len() + CHECKSUM_LEN as usize);
+    put_varint(&mut frame, (head.len() + value.len()) as u64 + CHECKSUM_LEN);
+    let prefix_len = frame.len() as u64;
+    frame.extend_from_slice(&head);
+    frame.extend_from_slice(value);
+    let checksum = crc32fast::hash(&frame[prefix_len as usize..]);
+    frame.extend_from_slice(&checksum.to_le_bytes());
+
+    (frame, prefix_len)
+}
+
+/// Encodes the length prefix and head of a record whose `value_len` value bytes will be written
+/// separately, straight after the returned buffer, and then the checksum a `Checksummed` reader
+/// over them gives. `entry.value` is ignored.
+pub fn encode_streamed(entry: &DataFileEntry, value_len: u64) -> (Vec<u8>, u64) {
+    let head = encode_head(entry, Some(value_len));
+
+    let mut frame = Vec::with_capacity(MAX_VARINT_LEN + head.len());
+    put_varint(&mut frame, head.len() as u64 + value_len + CHECKSUM_LEN);
+    let prefix_len = frame.len() as u64;
+    frame.extend_from_slice(&head);
+
+    (frame, prefix_len)
+}
+
+/// Passes the value of a streamed record through, keeping the CRC-32 that ends the record.
+pub struct Checksummed<R> {
+    reader: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> Checksummed<R> {
+    /// Wraps `reader`, the value of the record whose `frame` and `prefix_len` came from
+    /// `encode_streamed`.
+    pub fn new(frame: &[u8], prefix_len: u64, reader: R) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&frame[prefix_len as usize..]);
+        Checksummed { reader, hasher }
+    }
+
+    /// The bytes to write after the value, once all of it has been read.
+    pub fn checksum(&self) -> [u8; CHECKSUM_LEN as usize] {
+        self.hasher.clone().finalize().to_le_bytes()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Checks the CRC-32 that ends a v2 record `payload`. Records written before checksums were
+/// added have none, and pass.
+pub fn check(payload: &[u8]) -> io::Result<()> {
+    if payload
+        .first()
+        .is_none_or(|flags| flags & FLAG_CHECKSUM == 0)
+    {
+        return Ok(());
+    }
+    let Some(end) = payload.len().checked_sub(CHECKSUM_LEN as usize) else {
+        return Err(invalid("record truncated"));
+    };
+    let (data, checksum) = payload.split_at(end);
+    if crc32fast::hash(data).to_le_bytes() != checksum {
+        return Err(invalid("record fails its checksum"));
+    }
+    Ok(())
+}
+
+fn encode_head(entry: &DataFileEntry, value_len: Option<u64>) -> Vec<u8> {
+    let mut head = Vec::with_capacity(1 + 3 * MAX_VARINT_LEN + entry.key.len());
+
+    let mut flags = 0u8;
+    if value_len.is_some() {
+        flags |= FLAG_HAS_VALUE;
+    }
+    if entry.merge {
+        flags |= FLAG_MERGE;
+    }
+    match entry.chunk {
+        ChunkRole::Whole => {}
+        ChunkRole::Piece => flags |= FLAG_CHUNK,
+        ChunkRole::Last(_) => flags |= FLAG_CHUNKED,
+    }
+    flags |= FLAG_SEQ | FLAG_CHECKSUM;
+    if !entry.meta.is_empty() {
+        flags |= FLAG_META;
+    }
+    if entry.expires_at.is_some() {
+        flags |= FLAG_EXPIRES;
+    }
+    head.push(flags);
+    put_varint(&mut head, zigzag(entry.tstamp));
+    put_varint(&mut head, entry.key.len() as u64);
+    head.extend_from_slice(&entry.key);
+    if let ChunkRole::Last(pieces) = entry.chunk {
+        put_varint(&mut head, pieces);
+    }
+    put_varint(&mut head, entry.seq);
+    if !entry.meta.is_empty() {
+        put_varint(&mut head, entry.meta.len() as u64);
+        for (name, value) in &entry.meta {
+            put_varint(&mut head, name.len() as u64);
+            head.extend_from_slice(name.as_bytes());
+            put_varint(&mut head, value.len() as u64);
+            head.extend_from_slice(value.as_bytes());
+        }
+    }
+    if let Some(expires_at) = entry.expires_at {
+        put_varint(&mut head, zigzag(expires_at));
+    }
+    if let Some(value_len) = value_len {
+        put_varint(&mut head, value_len);
+    }
+
+    head
+}
+
+/// Reads the head of a v2 record payload, leaving `reader` positioned at the first value byte.
+pub fn read_head<R: Read>(reader: &mut R) -> io::Result<RecordHead> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let flags = check_flags(byte[0])?;
+    let mut len = 1;
+
+    let (tstamp, n) = read_varint(reader)?;
+    len += n;
+    let (key_len, n) = read_varint(reader)?;
+    len += n;
+    let mut key = vec![0u8; key_len as usize];
+    reader.read_exact(&mut key)?;
+    len += key_len;
+
+    let chunk = if flags & FLAG_CHUNK != 0 {
+        ChunkRole::Piece
+    } else if flags & FLAG_CHUNKED != 0 {
+        let (pieces, n) = read_varint(reader)?;
+        len += n;
+        ChunkRole::Last(pieces)
+    } else {
+        ChunkRole::Whole
+    };
+
+    let seq = if flags & FLAG_SEQ != 0 {
+        let (seq, n) = read_varint(reader)?;
+        len += n;
+        seq
+    } else {
+        0
+    };
+
+    let mut meta = Metadata::new();
+    if flags & FLAG_META != 0 {
+        let (count, n) = read_varint(reader)?;
+        len += n;
+        for _ in 0..count {
+            let (name, n) = read_string(reader)?;
+            len += n;
+            let (value, n) = read_string(reader)?;
+            len += n;
+            meta.insert(name, value);
+        }
+    }
+
+    let expires_at = if flags & FLAG_EXPIRES != 0 {
+        let (expires_at, n) = read_varint(reader)?;
+        len += n;
+        Some(unzigzag(expires_at))
+    } else {
+        None
+    };
+
+    let value_len = if flags & FLAG_HAS_VALUE != 0 {
+        let (value_len, n) = read_varint(reader)?;
+        len += n;
+        Some(value_len)
+    } else {
+        None
+    };
+
+    Ok(RecordHead {
+        tstamp: unzigzag(tstamp),
+        seq,
+        key,
+        merge: flags & FLAG_MERGE != 0,
+        chunk,
+        meta,
+        expires_at,
+        value_len,
+        checksum: flags & FLAG_CHECKSUM != 0,
+        len,
+    })
+}
+
+/// Reads a varint-length-prefixed UTF-8 string, returning it and its encoded length.
+fn read_string<R: Read>(reader: &mut R) -> io::Result<(String, u64)> {
+    let (len, n) = read_varint(reader)?;
+    let mut buf = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let s = String::from_utf8(buf).map_err(|_| invalid("metadata is not UTF-8"))?;
+    Ok((s, n + len))
+}
+
+fn check_flags(flags: u8) -> io::Result<u8> {
+    let known = FLAG_HAS_VALUE
+        | FLAG_MERGE
+        | FLAG_CHUNK
+        | FLAG_CHUNKED
+        | FLAG_SEQ
+        | FLAG_META
+        | FLAG_EXPIRES
+        | FLAG_CHECKSUM;
+    if flags & !known != 0 {
+        return Err(invalid("unknown record flags"));
+    }
+    let needs_value = FLAG_MERGE | FLAG_CHUNK | FLAG_CHUNKED;
+    if flags & needs_value != 0 && flags & FLAG_HAS_VALUE == 0 {
+        return Err(invalid("record flags require a value"));
+    }
+    if flags & FLAG_CHUNK != 0 && flags & FLAG_CHUNKED != 0 {
+        return Err(invalid("record is both a piece and a last piece"));
+    }
+    Ok(flags)
+}
+
+/// Decodes a record payload (without its length prefix) written in `format`.
+pub fn decode(format: Format, data: &[u8]) -> io::Result<DataFileEntry> {
+    match format {
+        Format::V1 => {
+            let entry: V1Entry = wincode::deserialize(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Ok(DataFileEntry {
+                tstamp: entry.tstamp,
+                key: entry.key,
+                value: entry.value,
+                ..DataFileEntry::default()
+            })
+        }
+        Format::V2 => decode_v2(data),
+    }
+}
+
+fn decode_v2(data: &[u8]) -> io::Result<DataFileEntry> {
+    check(data)?;
+    let mut cur = data;
+    let head = read_head(&mut cur).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("record truncated"),
+        _ => e,
+    })?;
+
+    let value = match head.value_len {
+        Some(value_len) => Some(take(&mut cur, value_len as usize)?.to_vec()),
+        None => None,
+    };
+    if head.checksum {
+        take(&mut cur, CHECKSUM_LEN as usize)?;
+    }
+
+    if !cur.is_empty() {
+        return Err(invalid("trailing bytes after record"));
+    }
+
+    Ok(DataFileEntry {
+        tstamp: head.tstamp,
+        seq: head.seq,
+        key: head.key,
+        value,
+        merge: head.merge,
+        chunk: head.chunk,
+        meta: head.meta,
+        expires_at: head.expires_at,
+    })
+}
+
+/// Reads a record length prefix. Returns `(payload_len, prefix_len)`, or `None` on a clean or
+/// torn end of file.
+pub fn read_prefix<R: Read>(format: Format, reader: &mut R) -> io::Result<Option<(u64, u64)>> {
+    match format {
+        Format::V1 => {
+            let mut len_buf = [0u8; LEN_PREFIX_SIZE as usize];
+            match reader.read_exact(&mut len_buf) {
+                Ok(_) => Ok(Some((u64::from_le_bytes(len_buf), LEN_PREFIX_SIZE))),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        Format::V2 => match read_varint(reader) {
+            Ok(prefix) => Ok(Some(prefix)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Appends `payload` to `buf` framed as a v2 record, as it is laid out in the log.
+pub fn put_frame(buf: &mut Vec<u8>, payload: &[u8]) {
+    put_varint(buf, payload.len() as u64);
+    buf.extend_from_slice(payload);
+}
+
+/// A heartbeat for a replication stream, telling replicas the primary's newest sequence number
+/// while there are no records to send: a zero length where a record's would be, then the number
+/// as a varint. Records are never empty, so the two cannot be confused.
+pub fn encode_heartbeat(seq: u64) -> Vec<u8> {
+    let mut buf = vec![0];
+    put_varint(&mut buf, seq);
+    buf
+}
+
+/// Size of the length prefix framing a v2 payload of `payload_len` bytes.
+pub fn prefix_len(payload_len: u64) -> u64 {
+    let bits = 64 - payload_len.leading_zeros().min(63) as u64;
+    bits.div_ceil(7)
+}
+
+/// Reads a varint, returning its value and encoded length.
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<(u64, u64)> {
+    let mut value = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok((value, i as u64 + 1));
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+const MAX_VARINT_LEN: usize = 10;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn take<'a>(cur: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if cur.len() < n {
+        return Err(invalid("record truncated"));
+    }
+    let (head, tail) = cur.split_at(n);
+    *cur = tail;
+    Ok(head)
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
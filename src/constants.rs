@@ -1,2 +1,57 @@
+use std::time::Duration;
+
 pub const DEFAULT_COMPACT_THRESHOLD: u64 = 1024 * 1024;
 pub const LEN_PREFIX_SIZE: u64 = 8;
+pub const FORMAT_V2_MAGIC: &[u8] = b"KVLOG\0v2";
+pub const FLAG_HAS_VALUE: u8 = 0x01;
+pub const FLAG_MERGE: u8 = 0x02;
+pub const FLAG_CHUNK: u8 = 0x04;
+pub const FLAG_CHUNKED: u8 = 0x08;
+pub const FLAG_SEQ: u8 = 0x10;
+pub const FLAG_META: u8 = 0x20;
+pub const FLAG_EXPIRES: u8 = 0x40;
+pub const FLAG_CHECKSUM: u8 = 0x80;
+/// Bytes of the CRC-32 that ends a record with `FLAG_CHECKSUM`.
+pub const CHECKSUM_LEN: u64 = 4;
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+pub const REBUILD_BATCH: usize = 64 * 1024;
+pub const HOT_KEY_SKETCH_DEPTH: usize = 4;
+pub const HOT_KEY_SKETCH_WIDTH: usize = 4096;
+pub const HOT_KEY_CANDIDATES: usize = 128;
+pub const SLOW_LOG_CAPACITY: usize = 128;
+pub const WATCH_BUFFER: usize = 1024;
+pub const BACKUP_HEAD_LEN: u64 = 4096;
+pub const IMPORT_BATCH: usize = 1024;
+pub const REPLICATION_BATCH: usize = 1024 * 1024;
+/// Metadata entry holding the version, `{seq}:{node}`, of a write applied by `apply_remote`.
+pub const ORIGIN_META: &str = "kv-origin";
+/// Prefix of the keys `Engine::set_idempotent` remembers request ids under.
+pub const IDEMPOTENCY_PREFIX: &[u8] = b"__idempotency:";
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// Prefix of the keys `Engine::acquire` keeps locks under.
+pub const LOCK_PREFIX: &[u8] = b"__lock:";
+/// Metadata entry holding the fencing token of a lock record.
+pub const LEASE_TOKEN_META: &str = "kv-lease-token";
+/// Prefix of the keys `Engine::tag` records tags under, as `{prefix}{tag}\0{key}`.
+pub const TAG_PREFIX: &[u8] = b"__tag:";
+/// Prefix of the keys deleted values are kept under with `EngineOptions::trash_retention`.
+pub const TRASH_PREFIX: &[u8] = b"__trash:";
+/// Metadata entry holding when a trashed value was deleted, in milliseconds since the epoch.
+pub const TRASHED_AT_META: &str = "kv-trashed-at";
+/// Metadata entry holding the expiry a trashed value had, restored with it by `undelete`.
+pub const TRASH_EXPIRES_META: &str = "kv-trash-expires-at";
+/// Prefix of the keys a cluster node keeps its Raft bookkeeping under in the store it serves.
+pub const RAFT_PREFIX: &[u8] = b"__raft:";
+/// Key holding the index of the last Raft entry applied to the store, as a big-endian u64.
+pub const RAFT_APPLIED_KEY: &[u8] = b"__raft:applied";
+/// Prefixes of the keys holding the engine's own records, which users may not write and
+/// listings leave out.
+pub const RESERVED_PREFIXES: &[&[u8]] = &[
+    IDEMPOTENCY_PREFIX,
+    LOCK_PREFIX,
+    TAG_PREFIX,
+    TRASH_PREFIX,
+    RAFT_PREFIX,
+];
+pub const ARCHIVE_FILE_PREFIX: &str = "wal-";
+pub const ARCHIVE_FILE_SUFFIX: &str = ".log";
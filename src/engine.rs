@@ -1,276 +1,3187 @@
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::{Path, PathBuf};
-use std::sync::{Mutex, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::constants::{DEFAULT_COMPACT_THRESHOLD, LEN_PREFIX_SIZE};
-use crate::types::{DataFileEntry, LogIndex};
+use tracing::field::Empty;
+use tracing::{Span, instrument};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::archive::Archive;
+use crate::codec::{self, Format};
+use crate::constants::{
+    BACKUP_HEAD_LEN, CHECKSUM_LEN, FORMAT_V2_MAGIC, IDEMPOTENCY_PREFIX, LEASE_TOKEN_META,
+    LOCK_PREFIX, ORIGIN_META, REBUILD_BATCH, REPLICATION_BATCH, RESERVED_PREFIXES, TAG_PREFIX,
+    TRASH_EXPIRES_META, TRASH_PREFIX, TRASHED_AT_META, WATCH_BUFFER,
+};
+use crate::hot_keys::{Access, HotKeyTracker};
+use crate::index::Index;
+use crate::options::{EngineOptions, SyncPolicy};
+use crate::slow_log::{OpTimer, SlowLog};
+use crate::storage::{FileStorage, LogFile, Storage};
+use crate::types::{
+    AsOf, BackupPoint, BatchOp, Change, ChangeKind, ChunkRole, CompactionReport, CorruptRecord,
+    DataFileEntry, DiskStats, EngineStats, HistoryEntry, HotKey, IndexStats, IndexedKey, Lease,
+    LoadProgress, LogIndex, Metadata, OrphanedEntry, PrefixUsage, RemoteEntry, ReplicationCursor,
+    ReplicationStats, SlowOp, SlowOpKind, ValueInfo, VerifyReport, Versioned,
+};
 
 pub struct Engine {
-    path: PathBuf,
-    file: Mutex<File>,
-    index: RwLock<HashMap<Vec<u8>, LogIndex>>,
+    storage: Box<dyn Storage>,
+    file: Mutex<Log>,
+    index: RwLock<Index>,
     file_size: Mutex<u64>,
-    compact_threshold: u64,
-    reader_pool: Mutex<Vec<File>>,
+    options: EngineOptions,
+    reader_pool: Mutex<Vec<Log>>,
+    hot_keys: Option<Mutex<HotKeyTracker>>,
+    last_seq: AtomicU64,
+    last_compaction: Mutex<Option<CompactionReport>>,
+    watchers: Mutex<Vec<Watcher>>,
+    read_only: AtomicBool,
+    /// Versions of the deletes made since the engine loaded or last compacted, so
+    /// `apply_remote` can tell a peer's older write to a deleted key from a newer one.
+    deletes: Mutex<HashMap<Box<[u8]>, Version>>,
+    /// Newest sequence number a primary has sent, in a record or a heartbeat.
+    primary_seq: AtomicU64,
+    replicated_records: AtomicU64,
+    replicated_bytes: AtomicU64,
+    /// Set by `archive_dir`; every record appended to the log is copied into it.
+    archive: Option<Mutex<Archive>>,
+    /// Writes refused by `min_free_space`.
+    low_space_rejections: AtomicU64,
+    /// Writes refused by `max_index_memory`.
+    index_full_rejections: AtomicU64,
+    /// Set by `slow_op_threshold`.
+    slow_log: Option<SlowLog>,
+}
+
+/// A handle on the log, from whichever `Storage` the engine was loaded with.
+type Log = Box<dyn LogFile>;
+
+/// The `(seq, node)` a write is ordered by in `apply_remote`.
+type Version = (u64, u64);
+
+/// One `Engine::watch` subscription.
+struct Watcher {
+    prefix: Box<[u8]>,
+    changes: SyncSender<Change>,
 }
 
 impl Engine {
     pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::load_with_threshold(path, DEFAULT_COMPACT_THRESHOLD)
+        Self::load_with_options(path, EngineOptions::default())
     }
 
     pub fn load_with_threshold(path: impl AsRef<Path>, compact_threshold: u64) -> io::Result<Self> {
-        let path = path.as_ref().to_path_buf();
-        let file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .truncate(false)
-            .open(&path)?;
+        Self::load_with_options(
+            path,
+            EngineOptions {
+                compact_threshold,
+                ..EngineOptions::default()
+            },
+        )
+    }
+
+    pub fn load_with_options(path: impl AsRef<Path>, options: EngineOptions) -> io::Result<Self> {
+        Self::load_with_storage(FileStorage::new(path), options)
+    }
+
+    /// Loads the log kept by `storage` rather than a file, such as a `MemoryStorage`.
+    pub fn load_with_storage(
+        storage: impl Storage + 'static,
+        options: EngineOptions,
+    ) -> io::Result<Self> {
+        let mut file = storage.open_log()?;
+
+        let format = if file.size()? == 0 {
+            file.write_all(FORMAT_V2_MAGIC)?;
+            file.flush()?;
+            Format::V2
+        } else {
+            let mut head = Vec::with_capacity(FORMAT_V2_MAGIC.len());
+            file.seek(SeekFrom::Start(0))?;
+            (&mut file)
+                .take(FORMAT_V2_MAGIC.len() as u64)
+                .read_to_end(&mut head)?;
+            Format::detect(&head)
+        };
 
         let mut readers = Vec::new();
         for _ in 0..4 {
-            if let Ok(r) = OpenOptions::new().read(true).open(&path) {
+            if let Ok(r) = storage.open_reader() {
                 readers.push(r);
             }
         }
 
-        let engine = Engine {
-            path,
+        let hot_keys = options
+            .track_hot_keys
+            .then(|| Mutex::new(HotKeyTracker::new()));
+        let slow_log = options.slow_op_threshold.map(SlowLog::new);
+
+        let mut engine = Engine {
+            storage: Box::new(storage),
             file: Mutex::new(file),
-            index: RwLock::new(HashMap::new()),
+            index: RwLock::new(Index::new()),
             file_size: Mutex::new(0),
-            compact_threshold,
+            options,
             reader_pool: Mutex::new(readers),
+            hot_keys,
+            last_seq: AtomicU64::new(0),
+            last_compaction: Mutex::new(None),
+            watchers: Mutex::new(Vec::new()),
+            read_only: AtomicBool::new(false),
+            deletes: Mutex::new(HashMap::new()),
+            primary_seq: AtomicU64::new(0),
+            replicated_records: AtomicU64::new(0),
+            replicated_bytes: AtomicU64::new(0),
+            archive: None,
+            low_space_rejections: AtomicU64::new(0),
+            index_full_rejections: AtomicU64::new(0),
+            slow_log,
         };
 
-        engine.rebuild_index()?;
+        engine.rebuild_index(format)?;
+
+        // Legacy logs are upgraded in place so every later append and read only deals with v2.
+        if format == Format::V1 {
+            engine.rewrite(Format::V1)?;
+        }
+
+        if let Some(dir) = engine.options.archive_dir.clone() {
+            let mut archive = Archive::open(dir, engine.options.archive_retention)?;
+            // Archiving picks up after the last record archived, or at the end of the log if
+            // compaction has dropped records that never were.
+            archive.pos = match engine.replication_cursor(archive.last_seq())? {
+                Some(cursor) => cursor.pos,
+                None => *engine.file_size.lock().unwrap(),
+            };
+            engine.archive = Some(Mutex::new(archive));
+            engine.archive_pending()?;
+        }
 
         Ok(engine)
     }
 
-    fn rebuild_index(&self) -> io::Result<()> {
+    /// Rebuilds the index by scanning the log framing sequentially in batches, decoding each
+    /// batch across `rebuild_threads` workers and applying the results in log order.
+    #[instrument(skip_all, fields(bytes = Empty, keys = Empty))]
+    fn rebuild_index(&self, format: Format) -> io::Result<()> {
+        let mut timer = self.timer(SlowOpKind::Rebuild);
         let mut file = self.file.lock().unwrap();
-        file.seek(SeekFrom::Start(0))?;
+        let file_len = file.size()?;
+        let mut pos = format.header_len();
+        file.seek(SeekFrom::Start(pos))?;
+
+        let mut reader = BufReader::new(&mut *file);
+        let mut index = self.index.write().unwrap();
+        let mut batch: Vec<LogIndex> = Vec::with_capacity(REBUILD_BATCH);
+        let mut entries = 0u64;
+        // Pieces of chunked values whose last piece has not been seen yet.
+        let mut pending: HashMap<Vec<u8>, Vec<LogIndex>> = HashMap::new();
+        let mut last_seq = 0;
+        timer.phase("lock");
 
         loop {
-            let mut len_buf = [0u8; LEN_PREFIX_SIZE as usize];
-            match file.read_exact(&mut len_buf) {
-                Ok(_) => {}
-                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e),
-            }
+            batch.clear();
+            while batch.len() < REBUILD_BATCH {
+                let Some((entry_len, prefix_len)) = codec::read_prefix(format, &mut reader)? else {
+                    break;
+                };
+
+                let data_pos = pos + prefix_len;
+                if data_pos + entry_len > file_len {
+                    break;
+                }
 
-            let entry_len = u64::from_le_bytes(len_buf);
-            let data_pos = file.stream_position()?;
+                reader.seek_relative(entry_len as i64)?;
+                pos = data_pos + entry_len;
+                batch.push(LogIndex {
+                    pos: data_pos,
+                    len: entry_len,
+                });
+            }
 
-            let mut data = vec![0u8; entry_len as usize];
-            match file.read_exact(&mut data) {
-                Ok(_) => {}
-                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e),
+            timer.phase("scan");
+            let records = self.decode_batch(format, &batch)?;
+            timer.phase("decode");
+            for (log_index, ScannedRecord { key, kind, seq }) in batch.iter().zip(records) {
+                last_seq = last_seq.max(seq);
+                index_record(&mut index, &mut pending, log_index, key, kind)?;
             }
+            timer.phase("index");
 
-            let entry: DataFileEntry = wincode::deserialize(&data)
-                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            entries += batch.len() as u64;
+            let done = batch.len() < REBUILD_BATCH;
+            if let Some(hook) = &self.options.on_load_progress {
+                hook(&LoadProgress {
+                    bytes_scanned: pos,
+                    total_bytes: file_len,
+                    entries,
+                    done,
+                });
+            }
 
-            let mut index = self.index.write().unwrap();
-            match entry.value {
-                Some(_) => {
-                    index.insert(
-                        entry.key,
-                        LogIndex {
-                            pos: data_pos,
-                            len: entry_len,
-                        },
-                    );
-                }
-                None => {
-                    index.remove(&entry.key);
-                }
+            if done {
+                break;
             }
         }
 
-        *self.file_size.lock().unwrap() = file.stream_position()?;
+        index.shrink_to_fit();
+        Span::current()
+            .record("bytes", pos)
+            .record("keys", index.len());
+        *self.file_size.lock().unwrap() = pos;
+        self.last_seq.store(last_seq, Ordering::SeqCst);
+        self.record_slow(timer, None, None);
 
         Ok(())
     }
 
-    pub fn set(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
-        let tstamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_millis() as i64)
-            .unwrap_or(0);
-
-        let entry = DataFileEntry {
-            tstamp,
-            key: key.to_vec(),
-            value: Some(value.to_vec()),
-        };
+    fn decode_batch(&self, format: Format, batch: &[LogIndex]) -> io::Result<Vec<ScannedRecord>> {
+        let threads = self.options.rebuild_threads.max(1);
+        if threads == 1 || batch.len() < threads * 2 {
+            return read_keys(self.storage.open_reader()?, format, batch);
+        }
 
-        let data = wincode::serialize(&entry).map_err(|e| io::Error::other(e.to_string()))?;
+        let chunk = batch.len().div_ceil(threads);
 
-        let entry_len = data.len() as u64;
+        thread::scope(|s| {
+            let handles: Vec<_> = batch
+                .chunks(chunk)
+                .map(|part| {
+                    let log = self.storage.open_reader()?;
+                    Ok(s.spawn(move || read_keys(log, format, part)))
+                })
+                .collect::<io::Result<_>>()?;
 
-        let mut file = self.file.lock().unwrap();
-        file.write_all(&entry_len.to_le_bytes())?;
+            let mut keys = Vec::with_capacity(batch.len());
+            for handle in handles {
+                keys.extend(handle.join().expect("rebuild worker panicked")?);
+            }
+            Ok(keys)
+        })
+    }
 
-        let data_pos = file.stream_position()?;
-        file.write_all(&data)?;
-        file.flush()?;
+    /// Hands out the next sequence number. Only called with the write lock held, so sequence
+    /// order matches log order.
+    fn next_seq(&self) -> u64 {
+        self.last_seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
 
-        *self.file_size.lock().unwrap() += LEN_PREFIX_SIZE + entry_len;
+    /// Sequence number of the most recent write, or 0 if nothing has been written yet.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_seq.load(Ordering::SeqCst)
+    }
 
-        self.index.write().unwrap().insert(
-            key.to_vec(),
-            LogIndex {
-                pos: data_pos,
-                len: entry_len,
-            },
-        );
+    /// Subscribes to the writes to keys under `prefix` from now on, in sequence order. Keys that
+    /// expire send nothing, since expiry is not a write. A subscriber more than `WATCH_BUFFER`
+    /// changes behind is dropped, so its receiver disconnects once drained.
+    pub fn watch(&self, prefix: &[u8]) -> Receiver<Change> {
+        let (changes, receiver) = mpsc::sync_channel(WATCH_BUFFER);
+        self.watchers.lock().unwrap().push(Watcher {
+            prefix: prefix.into(),
+            changes,
+        });
+        receiver
+    }
 
-        let should_compact = *self.file_size.lock().unwrap() >= self.compact_threshold;
+    /// The changes under `prefix` after sequence number `after` that the log still holds, in
+    /// sequence order, and a `watch` subscription picking up exactly where they end. Compaction
+    /// drops overwritten values and deletes, so history from before the last compaction shows
+    /// each surviving key once, as a set at the sequence number of its newest write.
+    pub fn watch_since(
+        &self,
+        prefix: &[u8],
+        after: u64,
+    ) -> io::Result<(Vec<Change>, Receiver<Change>)> {
+        // No write is in flight while the file is locked, so everything up to `end` was written
+        // before the subscription and everything after reaches it.
+        let file = self.file.lock().unwrap();
+        let end = *self.file_size.lock().unwrap();
+        let log = self.storage.open_reader()?;
+        let subscription = self.watch(prefix);
         drop(file);
 
-        if should_compact {
-            self.compact()?;
-        }
+        let mut changes = Vec::new();
+        scan_heads(log, end, |head, _| {
+            let kind = match (head.value_len, head.merge, head.chunk) {
+                (None, _, _) => ChangeKind::Del,
+                (Some(_), true, _) => ChangeKind::Merge,
+                (Some(_), false, ChunkRole::Piece) => return,
+                (Some(_), false, _) => ChangeKind::Set,
+            };
+            if head.seq > after && head.key.starts_with(prefix) {
+                changes.push(Change {
+                    seq: head.seq,
+                    key: head.key,
+                    kind,
+                });
+            }
+        })?;
+        // Compaction writes records in key order.
+        changes.sort_by_key(|change| change.seq);
+        Ok((changes, subscription))
+    }
 
-        Ok(())
+    /// The writes to `key` still in the log, newest first and at most `limit`. Compaction keeps
+    /// only the live version of each key, so history reaches back to the last compaction.
+    pub fn history(&self, key: &[u8], limit: usize) -> io::Result<Vec<HistoryEntry>> {
+        let (mut log, records) = self.key_records(key)?;
+        records
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|record| {
+                Ok(HistoryEntry {
+                    seq: record.head.seq,
+                    tstamp: record.head.tstamp,
+                    kind: record.kind(),
+                    value: record.read_value(&mut log)?,
+                })
+            })
+            .collect()
     }
 
-    pub fn del(&self, key: &[u8]) -> io::Result<()> {
-        let tstamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_millis() as i64)
+    /// The value `key` had at a point in the past, rebuilt from the log, or `None` if it was
+    /// unset or expired then. Only points since the last compaction can be answered exactly.
+    pub fn get_as_of(&self, key: &[u8], as_of: AsOf) -> io::Result<Option<Versioned>> {
+        let (mut log, mut records) = self.key_records(key)?;
+        records.retain(|record| match as_of {
+            AsOf::Seq(seq) => record.head.seq <= seq,
+            AsOf::Time(tstamp) => record.head.tstamp <= tstamp,
+        });
+        // Merge operands fold onto the newest set or delete before them.
+        let start = records
+            .iter()
+            .rposition(|record| record.kind() != ChangeKind::Merge)
             .unwrap_or(0);
 
-        let entry = DataFileEntry {
-            tstamp,
-            key: key.to_vec(),
-            value: None,
+        let mut versioned: Option<Versioned> = None;
+        for record in &records[start..] {
+            let value = record.read_value(&mut log)?;
+            versioned = match (record.kind(), value) {
+                (ChangeKind::Del, _) | (_, None) => None,
+                (ChangeKind::Set, Some(value)) => Some(Versioned {
+                    value,
+                    meta: record.head.meta.clone(),
+                    seq: record.head.seq,
+                }),
+                (ChangeKind::Merge, Some(operand)) => {
+                    let existing = versioned.as_ref().map(|v| v.value.as_slice());
+                    Some(Versioned {
+                        value: self.apply_merge(key, existing, &operand)?,
+                        meta: versioned.map(|v| v.meta).unwrap_or_default(),
+                        seq: record.head.seq,
+                    })
+                }
+            };
+        }
+
+        let expired = match (as_of, records.get(start)) {
+            (AsOf::Time(tstamp), Some(record)) => {
+                record.head.expires_at.is_some_and(|at| at <= tstamp)
+            }
+            _ => false,
         };
+        Ok(versioned.filter(|_| !expired))
+    }
 
-        let data = wincode::serialize(&entry).map_err(|e| io::Error::other(e.to_string()))?;
+    /// Every record for `key` in the log, in log order, with the log opened at a consistent end.
+    fn key_records(&self, key: &[u8]) -> io::Result<(Log, Vec<KeyRecord>)> {
+        let (mut log, end) = {
+            let _file = self.file.lock().unwrap();
+            (self.storage.open_reader()?, *self.file_size.lock().unwrap())
+        };
 
-        let entry_len = data.len() as u64;
+        let mut records = Vec::new();
+        let mut pieces = Vec::new();
+        scan_heads(&mut log, end, |head, log_index| {
+            if head.key != key {
+                return;
+            }
+            let chunks = match (head.value_len, head.merge, head.chunk) {
+                (Some(_), false, ChunkRole::Piece) => {
+                    pieces.push(log_index);
+                    return;
+                }
+                (Some(_), false, ChunkRole::Last(count)) => {
+                    pieces.split_off(pieces.len().saturating_sub(count as usize))
+                }
+                _ => Vec::new(),
+            };
+            records.push(KeyRecord {
+                head,
+                log_index,
+                pieces: chunks,
+            });
+            pieces.clear();
+        })?;
+        Ok((log, records))
+    }
 
-        let mut file = self.file.lock().unwrap();
-        file.write_all(&entry_len.to_le_bytes())?;
+    /// Sends a write to the subscribers watching its key. Called with the write lock held, so
+    /// they see changes in sequence order.
+    fn notify(&self, seq: u64, key: &[u8], kind: ChangeKind) {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| {
+            if !key.starts_with(&watcher.prefix) {
+                return true;
+            }
+            let change = Change {
+                seq,
+                key: key.to_vec(),
+                kind,
+            };
+            // Full means the subscriber fell behind; disconnected means it went away.
+            watcher.changes.try_send(change).is_ok()
+        });
+    }
+
+    fn append_entry(&self, file: &mut Log, entry: &mut DataFileEntry) -> io::Result<LogIndex> {
+        self.check_writable()?;
+        entry.seq = self.next_seq();
+        let (frame, prefix_len) = codec::encode(entry);
+        let entry_len = frame.len() as u64 - prefix_len;
 
-        file.write_all(&data)?;
+        file.write_all(&frame)?;
         file.flush()?;
+        self.sync_if_needed(file.as_ref())?;
 
-        *self.file_size.lock().unwrap() += LEN_PREFIX_SIZE + entry_len;
-        self.index.write().unwrap().remove(key);
+        let end = file.stream_position()?;
+        *self.file_size.lock().unwrap() += frame.len() as u64;
+        Span::current().record("bytes_written", frame.len());
+
+        Ok(LogIndex {
+            pos: end - entry_len,
+            len: entry_len,
+        })
+    }
+
+    /// Makes writes fail with `ReadOnlyFilesystem`, or allows them again. A replica is read-only
+    /// so that only `apply_replicated` adds to its log; compaction still runs.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::ReadOnlyFilesystem,
+                "the engine is read-only",
+            ));
+        }
+        self.check_free_space()
+    }
 
+    /// Fails with `StorageFull`, counted as a refused write, while free space is below
+    /// `min_free_space`; passes if the storage cannot say how much is free. Every write checks
+    /// it, and callers can too, to turn a write away before its data arrives.
+    pub fn check_free_space(&self) -> io::Result<()> {
+        let Some(min) = self.options.min_free_space else {
+            return Ok(());
+        };
+        if matches!(self.storage.free_space(), Ok(Some(free)) if free < min) {
+            self.low_space_rejections.fetch_add(1, Ordering::SeqCst);
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!("fewer than {} bytes of disk space are free", min),
+            ));
+        }
         Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    /// Fails with `OutOfMemory`, counted as a refused write, if writing `keys` would add keys
+    /// that take the index past `max_index_memory`. Keys already in the index always pass.
+    fn check_index_room<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>) -> io::Result<()> {
+        let Some(max) = self.options.max_index_memory else {
+            return Ok(());
+        };
         let index = self.index.read().unwrap();
+        let added: usize = keys
+            .into_iter()
+            .filter(|key| !index.contains_key(key))
+            .map(Index::new_key_bytes)
+            .sum();
+        if added > 0 && (index.memory_bytes() + added) as u64 > max {
+            self.index_full_rejections.fetch_add(1, Ordering::SeqCst);
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!("the index has reached its limit of {} bytes", max),
+            ));
+        }
+        Ok(())
+    }
 
-        let log_index = match index.get(key) {
-            Some(idx) => idx.clone(),
-            None => return Ok(None),
-        };
+    fn sync_if_needed(&self, file: &dyn LogFile) -> io::Result<()> {
+        match self.options.sync {
+            SyncPolicy::Never => Ok(()),
+            SyncPolicy::Always => file.sync_data(),
+        }
+    }
 
+    /// Copies the records appended since the last call into the archive, if there is one. Called
+    /// with the write lock held, so the log ends on a whole record.
+    fn archive_pending(&self) -> io::Result<()> {
+        let Some(archive) = &self.archive else {
+            return Ok(());
+        };
+        let mut archive = archive.lock().unwrap();
+        let end = *self.file_size.lock().unwrap();
+        if archive.pos >= end {
+            return Ok(());
+        }
         let mut reader = {
             let mut pool = self.reader_pool.lock().unwrap();
             match pool.pop() {
                 Some(r) => r,
-                None => OpenOptions::new().read(true).open(&self.path)?,
+                None => self.storage.open_reader()?,
             }
         };
+        reader.seek(SeekFrom::Start(archive.pos))?;
+        let records = (&mut reader).take(end - archive.pos);
+        let sync = self.options.sync == SyncPolicy::Always;
+        archive.append(records, self.last_sequence(), sync)?;
+        archive.pos = end;
 
-        reader.seek(SeekFrom::Start(log_index.pos))?;
+        let mut pool = self.reader_pool.lock().unwrap();
+        if pool.len() < 8 {
+            pool.push(reader);
+        }
+        Ok(())
+    }
 
-        let mut data = vec![0u8; log_index.len as usize];
-        reader.read_exact(&mut data)?;
+    /// Releases the write lock and compacts if the log has grown past the threshold.
+    fn maybe_compact(&self, file: MutexGuard<'_, Log>) -> io::Result<()> {
+        self.archive_pending()?;
+        let should_compact = *self.file_size.lock().unwrap() >= self.options.compact_threshold;
+        drop(file);
 
-        {
-            let mut pool = self.reader_pool.lock().unwrap();
-            if pool.len() < 8 {
-                pool.push(reader);
+        if should_compact {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Chunk size to split a value of `len` bytes with, if it needs splitting at all.
+    fn chunk_size_for(&self, len: u64) -> Option<u64> {
+        self.options
+            .chunk_size
+            .filter(|&size| size > 0 && len > size)
+    }
+
+    /// Stores `value` for `key`, returning the write's sequence number: the key's new version for
+    /// `compare_and_set`, and the `seq` of its change in `watch` and `watch_since`.
+    pub fn set(&self, key: &[u8], value: &[u8]) -> io::Result<u64> {
+        self.set_with_metadata(key, value, &Metadata::new())
+    }
+
+    /// Like `set`, also storing `meta` in the value's record. It is replaced on every write.
+    #[instrument(
+        name = "set",
+        level = "debug",
+        skip_all,
+        fields(key_len = key.len(), value_len = value.len(), bytes_written = Empty)
+    )]
+    pub fn set_with_metadata(&self, key: &[u8], value: &[u8], meta: &Metadata) -> io::Result<u64> {
+        self.set_value(key, value, meta, None)
+    }
+
+    /// Like `set`, with the value expiring `ttl` from now. Expired keys read as missing.
+    pub fn set_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> io::Result<u64> {
+        self.set_with_metadata_and_ttl(key, value, &Metadata::new(), ttl)
+    }
+
+    /// Like `set_with_metadata`, with the value expiring `ttl` from now.
+    pub fn set_with_metadata_and_ttl(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        meta: &Metadata,
+        ttl: Duration,
+    ) -> io::Result<u64> {
+        self.set_value(key, value, meta, Some(expiry(ttl)))
+    }
+
+    /// The timed body of the `set` family.
+    fn set_value(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        meta: &Metadata,
+        expires_at: Option<i64>,
+    ) -> io::Result<u64> {
+        self.track(key, Access::Write);
+        let mut timer = self.timer(SlowOpKind::Set);
+        let mut file = self.file.lock().unwrap();
+        timer.phase("lock");
+        let seq = self.write_value(&mut file, key, value, meta, expires_at)?;
+        timer.phase("write");
+        self.maybe_compact(file)?;
+        timer.phase("compact");
+        self.finish(timer, key, Some(value.len() as u64));
+        Ok(seq)
+    }
+
+    /// Makes an existing key expire `ttl` from now, keeping its value and metadata. This rewrites
+    /// the value, so it costs as much as a `set`. Returns false if the key does not exist.
+    pub fn expire(&self, key: &[u8], ttl: Duration) -> io::Result<bool> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let Some(current) = self.read_entry(&self.index.read().unwrap(), key)? else {
+            return Ok(false);
+        };
+        self.write_value(
+            &mut file,
+            key,
+            &current.value,
+            &current.meta,
+            Some(expiry(ttl)),
+        )?;
+        self.maybe_compact(file)?;
+        Ok(true)
+    }
+
+    /// Adds `by` to the integer stored at `key` as decimal text and returns the result, treating
+    /// a missing key as 0. Keeps the key's metadata and expiry. Fails with `InvalidData` if the
+    /// value is not an integer or the result overflows.
+    pub fn incr(&self, key: &[u8], by: i64) -> io::Result<i64> {
+        self.incr_with_floor(key, by, i64::MIN)
+    }
+
+    /// Like `incr`, but a result below `floor` is stored as `floor` in the same write, as
+    /// memcached's `decr` stops at 0.
+    pub fn incr_with_floor(&self, key: &[u8], by: i64, floor: i64) -> io::Result<i64> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let (current, meta, expires_at) = {
+            let index = self.index.read().unwrap();
+            match self.read_entry(&index, key)? {
+                Some(entry) => (
+                    parse_counter(&entry.value)?,
+                    entry.meta,
+                    index.expires_at(key),
+                ),
+                None => (0, Metadata::new(), None),
+            }
+        };
+        let value = match current.checked_add(by) {
+            Some(value) => value.max(floor),
+            // Below the smallest integer, so below any other floor too.
+            None if by < 0 && floor > i64::MIN => floor,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "counter overflow",
+                ));
             }
+        };
+
+        self.write_value(
+            &mut file,
+            key,
+            value.to_string().as_bytes(),
+            &meta,
+            expires_at,
+        )?;
+        self.maybe_compact(file)?;
+        Ok(value)
+    }
+
+    /// Appends `data` to the value of `key`, creating it if missing, and returns the new length.
+    /// Keeps the key's metadata and expiry. The whole value is rewritten, so each call costs as
+    /// much as a `set` of the result.
+    pub fn append(&self, key: &[u8], data: &[u8]) -> io::Result<u64> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let (mut value, meta, expires_at) = {
+            let index = self.index.read().unwrap();
+            match self.read_entry(&index, key)? {
+                Some(entry) => (entry.value, entry.meta, index.expires_at(key)),
+                None => (Vec::new(), Metadata::new(), None),
+            }
+        };
+        value.extend_from_slice(data);
+
+        self.write_value(&mut file, key, &value, &meta, expires_at)?;
+        self.maybe_compact(file)?;
+        Ok(value.len() as u64)
+    }
+
+    /// Time left before `key` expires: `None` if the key does not exist, `Some(None)` if it never
+    /// expires.
+    pub fn ttl(&self, key: &[u8]) -> Option<Option<Duration>> {
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        if !is_live(&index, key, now) {
+            return None;
+        }
+        Some(
+            index
+                .expires_at(key)
+                .map(|at| Duration::from_millis((at - now) as u64)),
+        )
+    }
+
+    fn check_value_size(&self, len: u64) -> io::Result<()> {
+        match self.options.max_value_size {
+            Some(max) if len > max => Err(value_too_large(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes `value` for `key` with the write lock held, as one record or streamed into chunks,
+    /// and points the index at it. Returns the write's sequence number.
+    fn write_value(
+        &self,
+        file: &mut Log,
+        key: &[u8],
+        value: &[u8],
+        meta: &Metadata,
+        expires_at: Option<i64>,
+    ) -> io::Result<u64> {
+        let len = value.len() as u64;
+        self.check_value_size(len)?;
+        if self.chunk_size_for(len).is_some() {
+            return self.write_value_streamed(
+                file,
+                key,
+                value,
+                Some(len),
+                meta.clone(),
+                expires_at,
+            );
         }
+        self.check_index_room([key])?;
 
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: key.to_vec(),
+            value: Some(value.to_vec()),
+            meta: meta.clone(),
+            expires_at,
+            ..DataFileEntry::default()
+        };
+        let log_index = self.append_entry(file, &mut entry)?;
+        let mut index = self.index.write().unwrap();
+        index.insert(key, log_index);
+        index.set_expiry(key, expires_at);
         drop(index);
+        self.notify(entry.seq, key, ChangeKind::Set);
 
-        let entry: DataFileEntry = wincode::deserialize(&data)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(entry.seq)
+    }
+
+    /// Sequence number of the newest write to `key`, or `None` if it does not exist. This is
+    /// the version compared by `compare_and_set`.
+    pub fn version(&self, key: &[u8]) -> io::Result<Option<u64>> {
+        let index = self.index.read().unwrap();
+        self.current_version(&index, key)
+    }
 
-        Ok(entry.value)
+    fn current_version(&self, index: &Index, key: &[u8]) -> io::Result<Option<u64>> {
+        if !is_live(index, key, now_millis()) {
+            return Ok(None);
+        }
+        match index.operands(key).last().or_else(|| index.get(key)) {
+            Some(latest) => Ok(Some(
+                read_head_at(&mut self.storage.open_reader()?, latest)?.seq,
+            )),
+            None => Ok(None),
+        }
     }
 
-    pub fn compact(&self) -> io::Result<()> {
+    /// Sets `key` only if its current version is `expected`, where `None` means the key must not
+    /// exist. Returns the new sequence number, or the current version on a mismatch.
+    pub fn compare_and_set(
+        &self,
+        key: &[u8],
+        expected: Option<u64>,
+        value: &[u8],
+        meta: &Metadata,
+    ) -> io::Result<Result<u64, Option<u64>>> {
+        self.track(key, Access::Write);
         let mut file = self.file.lock().unwrap();
 
-        let tmp_path = self.path.with_extension("tmp");
+        let current = self.current_version(&self.index.read().unwrap(), key)?;
+        if current != expected {
+            return Ok(Err(current));
+        }
+
+        let seq = self.write_value(&mut file, key, value, meta, None)?;
+        self.maybe_compact(file)?;
+        Ok(Ok(seq))
+    }
 
-        let mut tmp_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&tmp_path)?;
+    /// Deletes `key` only if its current version is `expected`. Returns the current version on a
+    /// mismatch, including `None` when the key does not exist.
+    pub fn compare_and_del(
+        &self,
+        key: &[u8],
+        expected: u64,
+    ) -> io::Result<Result<(), Option<u64>>> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
 
-        let entries: Vec<(Vec<u8>, LogIndex)> = self
-            .index
-            .read()
-            .unwrap()
+        let current = self.current_version(&self.index.read().unwrap(), key)?;
+        if current != Some(expected) {
+            return Ok(Err(current));
+        }
+
+        self.trash(&mut file, key)?;
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: key.to_vec(),
+            ..DataFileEntry::default()
+        };
+        self.append_entry(&mut file, &mut entry)?;
+        self.index.write().unwrap().remove(key);
+        self.remember_delete(key, (entry.seq, self.options.node_id));
+        self.notify(entry.seq, key, ChangeKind::Del);
+        self.archive_pending()?;
+
+        Ok(Ok(()))
+    }
+
+    /// Sets `key` only if it does not exist. Returns whether the value was written.
+    pub fn set_nx(&self, key: &[u8], value: &[u8]) -> io::Result<bool> {
+        Ok(self
+            .compare_and_set(key, None, value, &Metadata::new())?
+            .is_ok())
+    }
+
+    /// Applies `ops` in order with a single append, and a single fsync under
+    /// `SyncPolicy::Always`; with `trash_retention` set the trash copies of deleted values are in
+    /// the same append. Readers see none or all of the batch; a crash part way through the write
+    /// can keep a prefix of it. Returns, for each op, whether its key existed just before.
+    pub fn write_batch(&self, ops: &[BatchOp]) -> io::Result<Vec<bool>> {
+        self.check_writable()?;
+        for op in ops {
+            if let BatchOp::Set { value, .. } = op {
+                self.check_value_size(value.len() as u64)?;
+            }
+        }
+        let file = self.file.lock().unwrap();
+        self.append_batch(file, ops)
+    }
+
+    /// Like `write_batch` with only sets, leaving out the pairs whose key already exists or comes
+    /// earlier in `pairs`. Keys are checked under the write lock, so no other write can land in
+    /// between. Returns, for each pair, whether it was written.
+    pub fn set_many_if_absent(&self, pairs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<Vec<bool>> {
+        self.check_writable()?;
+        for (_, value) in pairs {
+            self.check_value_size(value.len() as u64)?;
+        }
+        let file = self.file.lock().unwrap();
+        let now = now_millis();
+        let index = self.index.read().unwrap();
+        let mut seen = HashSet::new();
+        let written: Vec<bool> = pairs
+            .iter()
+            .map(|(key, _)| !is_live(&index, key, now) && seen.insert(key.as_slice()))
+            .collect();
+        drop(index);
+
+        let ops: Vec<BatchOp> = pairs
             .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .zip(&written)
+            .filter(|(_, written)| **written)
+            .map(|((key, value), _)| BatchOp::Set {
+                key: key.clone(),
+                value: value.clone(),
+            })
             .collect();
+        if !ops.is_empty() {
+            self.append_batch(file, &ops)?;
+        }
+        Ok(written)
+    }
 
-        let mut new_index: HashMap<Vec<u8>, LogIndex> = HashMap::new();
-        let mut new_file_size: u64 = 0;
+    /// The rest of `write_batch`, once the ops are checked and the write lock is held.
+    fn append_batch(
+        &self,
+        mut file: MutexGuard<'_, Log>,
+        ops: &[BatchOp],
+    ) -> io::Result<Vec<bool>> {
+        let tstamp = now_millis();
+        let trash = self.batch_trash(ops, tstamp)?;
+        let set_keys = ops.iter().filter_map(|op| match op {
+            BatchOp::Set { key, .. } => Some(key.as_slice()),
+            BatchOp::Del { .. } => None,
+        });
+        let trash_keys = trash.iter().flatten().map(|entry| entry.key.as_slice());
+        self.check_index_room(set_keys.chain(trash_keys))?;
+        let start = file.seek(SeekFrom::End(0))?;
 
-        for (key, log_index) in entries {
-            file.seek(SeekFrom::Start(log_index.pos))?;
-            let mut data = vec![0u8; log_index.len as usize];
-            file.read_exact(&mut data)?;
+        // Each trash record goes just before the tombstone of the key it keeps.
+        let mut buf = Vec::new();
+        let mut positions = Vec::with_capacity(ops.len());
+        let mut seqs = Vec::with_capacity(ops.len());
+        let mut trashed = Vec::with_capacity(ops.len());
+        for (op, trash) in ops.iter().zip(trash) {
+            trashed.push(trash.map(|mut entry| {
+                entry.seq = self.next_seq();
+                let log_index = push_frame(&mut buf, start, &entry);
+                (entry, log_index)
+            }));
+            let (key, value) = match op {
+                BatchOp::Set { key, value } => (key, Some(value.clone())),
+                BatchOp::Del { key } => (key, None),
+            };
+            self.track(key, Access::Write);
+            let seq = self.next_seq();
+            seqs.push(seq);
+            let entry = DataFileEntry {
+                tstamp,
+                seq,
+                key: key.clone(),
+                value,
+                ..DataFileEntry::default()
+            };
+            positions.push(push_frame(&mut buf, start, &entry));
+        }
 
-            let entry_len = data.len() as u64;
-            tmp_file.write_all(&entry_len.to_le_bytes())?;
-            let new_pos = tmp_file.stream_position()?;
-            tmp_file.write_all(&data)?;
+        if let Err(e) = file.write_all(&buf).and_then(|_| file.flush()) {
+            file.set_len(start)?;
+            return Err(e);
+        }
+        self.sync_if_needed(file.as_ref())?;
+        *self.file_size.lock().unwrap() += buf.len() as u64;
 
-            new_file_size += LEN_PREFIX_SIZE + entry_len;
-            new_index.insert(
-                key,
-                LogIndex {
-                    pos: new_pos,
-                    len: entry_len,
+        let mut index = self.index.write().unwrap();
+        let mut existed = Vec::with_capacity(ops.len());
+        for ((op, log_index), trashed) in ops.iter().zip(positions).zip(&trashed) {
+            if let Some((entry, trash_index)) = trashed {
+                index.insert(entry.key.as_slice(), trash_index.clone());
+                index.set_expiry(&entry.key, entry.expires_at);
+            }
+            match op {
+                BatchOp::Set { key, .. } => {
+                    existed.push(is_live(&index, key, tstamp));
+                    index.insert(key.as_slice(), log_index);
+                }
+                BatchOp::Del { key } => {
+                    existed.push(is_live(&index, key, tstamp));
+                    index.remove(key);
+                }
+            }
+        }
+        drop(index);
+        for ((op, seq), trashed) in ops.iter().zip(seqs).zip(&trashed) {
+            if let Some((entry, _)) = trashed {
+                self.notify(entry.seq, &entry.key, ChangeKind::Set);
+            }
+            match op {
+                BatchOp::Set { key, .. } => self.notify(seq, key, ChangeKind::Set),
+                BatchOp::Del { key } => {
+                    self.remember_delete(key, (seq, self.options.node_id));
+                    self.notify(seq, key, ChangeKind::Del);
+                }
+            }
+        }
+
+        self.maybe_compact(file)?;
+        Ok(existed)
+    }
+
+    /// The trash record of each op in a batch with `EngineOptions::trash_retention` set. As in
+    /// `del`, a delete puts the value it removes in the trash: for a key set earlier in the batch
+    /// that is the batch's value, and for one deleted earlier, nothing.
+    fn batch_trash(&self, ops: &[BatchOp], tstamp: i64) -> io::Result<Vec<Option<DataFileEntry>>> {
+        if self.options.trash_retention.is_none() {
+            return Ok(ops.iter().map(|_| None).collect());
+        }
+        let index = self.index.read().unwrap();
+        let mut written: HashMap<&[u8], Option<&[u8]>> = HashMap::new();
+        let mut trash = Vec::with_capacity(ops.len());
+        for op in ops {
+            trash.push(match op {
+                BatchOp::Set { key, value } => {
+                    written.insert(key.as_slice(), Some(value.as_slice()));
+                    None
+                }
+                BatchOp::Del { key } => match written.insert(key.as_slice(), None) {
+                    None => self.read_entry(&index, key)?.and_then(|current| {
+                        let expires_at = index.expires_at(key);
+                        self.trash_entry(key, current.value, current.meta, expires_at, tstamp)
+                    }),
+                    Some(Some(value)) => {
+                        self.trash_entry(key, value.to_vec(), Metadata::new(), None, tstamp)
+                    }
+                    Some(None) => None,
                 },
-            );
+            });
         }
+        Ok(trash)
+    }
 
-        tmp_file.flush()?;
-        drop(tmp_file);
+    /// Appends `operand` for `key` without reading the current value. Operands are folded into
+    /// the value with the configured merge operator on `get` and during compaction.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> io::Result<()> {
+        if self.options.merge_operator.is_none() {
+            return Err(no_merge_operator());
+        }
+        self.check_value_size(operand.len() as u64)?;
+        self.track(key, Access::Write);
 
-        self.reader_pool.lock().unwrap().clear();
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: key.to_vec(),
+            value: Some(operand.to_vec()),
+            merge: true,
+            ..DataFileEntry::default()
+        };
 
-        let mut index = self.index.write().unwrap();
+        let mut file = self.file.lock().unwrap();
+        self.check_index_room([key])?;
+        let log_index = self.append_entry(&mut file, &mut entry)?;
 
-        std::fs::rename(&tmp_path, &self.path)?;
-        *file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(&self.path)?;
-        *index = new_index;
-        *self.file_size.lock().unwrap() = new_file_size;
+        self.index.write().unwrap().push_operand(key, log_index);
+        self.notify(entry.seq, key, ChangeKind::Merge);
 
-        let mut pool = self.reader_pool.lock().unwrap();
-        for _ in 0..4 {
-            if let Ok(r) = OpenOptions::new().read(true).open(&self.path) {
-                pool.push(r);
-            }
+        self.maybe_compact(file)
+    }
+
+    /// Stores a value of exactly `len` bytes read from `reader` without buffering it in memory.
+    /// If `reader` fails or ends early the partial record is truncated away and the error returned.
+    pub fn set_from_reader(&self, key: &[u8], reader: impl Read, len: u64) -> io::Result<u64> {
+        self.set_from_reader_with_metadata(key, reader, Some(len), &Metadata::new())
+    }
+
+    /// Like `set_from_reader`, also storing `meta`. `len` may be `None` when the length is not
+    /// known up front; the value is then read one chunk at a time, so at most
+    /// `EngineOptions::chunk_size` bytes of it are held in memory, and reading stops once it
+    /// passes `EngineOptions::max_value_size`.
+    pub fn set_from_reader_with_metadata(
+        &self,
+        key: &[u8],
+        reader: impl Read,
+        len: Option<u64>,
+        meta: &Metadata,
+    ) -> io::Result<u64> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+        let seq = self.write_value_streamed(&mut file, key, reader, len, meta.clone(), None)?;
+        self.maybe_compact(file)?;
+        Ok(seq)
+    }
+
+    /// Like `set`, but at most once per `request_id`: a retry with an id seen within
+    /// `EngineOptions::idempotency_window` writes nothing and returns `Err` with the sequence
+    /// number of the write the id was first sent with. Ids are remembered as keys under
+    /// `IDEMPOTENCY_PREFIX` that expire after the window, so they survive restarts and reach
+    /// replicas. The id is written after the value, so a crash in between lets a retry write the
+    /// value again rather than lose it. Sending an id again for another key fails with
+    /// `AlreadyExists`.
+    pub fn set_idempotent(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        request_id: &str,
+    ) -> io::Result<Result<u64, u64>> {
+        let len = Some(value.len() as u64);
+        self.set_once(key, value, len, &Metadata::new(), None, request_id)
+    }
+
+    /// Like `set_idempotent`, with the value expiring `ttl` from now.
+    pub fn set_idempotent_with_ttl(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+        request_id: &str,
+    ) -> io::Result<Result<u64, u64>> {
+        let len = Some(value.len() as u64);
+        self.set_once(
+            key,
+            value,
+            len,
+            &Metadata::new(),
+            Some(expiry(ttl)),
+            request_id,
+        )
+    }
+
+    /// `set_idempotent` for a value streamed from `reader` as in `set_from_reader_with_metadata`.
+    /// For a request id already seen nothing is read from `reader`.
+    pub fn set_idempotent_from_reader(
+        &self,
+        key: &[u8],
+        reader: impl Read,
+        len: Option<u64>,
+        meta: &Metadata,
+        request_id: &str,
+    ) -> io::Result<Result<u64, u64>> {
+        self.set_once(key, reader, len, meta, None, request_id)
+    }
+
+    fn set_once(
+        &self,
+        key: &[u8],
+        reader: impl Read,
+        len: Option<u64>,
+        meta: &Metadata,
+        expires_at: Option<i64>,
+        request_id: &str,
+    ) -> io::Result<Result<u64, u64>> {
+        self.track(key, Access::Write);
+        let marker = [IDEMPOTENCY_PREFIX, request_id.as_bytes()].concat();
+        let mut file = self.file.lock().unwrap();
+        let index = self.index.read().unwrap();
+        if let Some(first) = self.read_value(&index, &marker)? {
+            return match parse_request_record(&first) {
+                Some((seq, sent_for)) if sent_for == key => Ok(Err(seq)),
+                Some(_) => Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("request id {} was already sent for another key", request_id),
+                )),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unreadable record of request id {}", request_id),
+                )),
+            };
         }
+        drop(index);
 
-        Ok(())
+        let seq =
+            self.write_value_streamed(&mut file, key, reader, len, meta.clone(), expires_at)?;
+        let window = Some(expiry(self.options.idempotency_window));
+        let recorded = [seq.to_string().as_bytes(), b" ", key].concat();
+        self.write_value(&mut file, &marker, &recorded, &Metadata::new(), window)?;
+        self.maybe_compact(file)?;
+        Ok(Ok(seq))
+    }
+
+    /// Takes the lock `name` for `owner` until `ttl` from now and returns its fencing token, a
+    /// number larger than any token handed out before: the guarded resource can refuse writes
+    /// carrying a smaller one, from a holder whose lease lapsed. If `owner` already holds the
+    /// lock, its lease is extended and keeps its token; if someone else does, returns their
+    /// `Lease`. Locks are keys under `LOCK_PREFIX` that expire like any other, so they survive
+    /// restarts and reach replicas.
+    pub fn acquire(
+        &self,
+        name: &[u8],
+        owner: &str,
+        ttl: Duration,
+    ) -> io::Result<Result<u64, Lease>> {
+        let key = [LOCK_PREFIX, name].concat();
+        self.track(&key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let token = match self.read_lease(&self.index.read().unwrap(), &key)? {
+            Some(lease) if lease.owner != owner => return Ok(Err(lease)),
+            Some(lease) => lease.token,
+            // With the write lock held, the record below is the next write.
+            None => self.last_sequence() + 1,
+        };
+        self.write_lease(&mut file, &key, owner, token, ttl)?;
+        self.maybe_compact(file)?;
+        Ok(Ok(token))
     }
+
+    /// Extends the lease on `name` taken with `token` to `ttl` from now. Returns false if the
+    /// lock has expired or been taken since.
+    pub fn renew(&self, name: &[u8], token: u64, ttl: Duration) -> io::Result<bool> {
+        let key = [LOCK_PREFIX, name].concat();
+        self.track(&key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let owner = match self.read_lease(&self.index.read().unwrap(), &key)? {
+            Some(lease) if lease.token == token => lease.owner,
+            _ => return Ok(false),
+        };
+        self.write_lease(&mut file, &key, &owner, token, ttl)?;
+        self.maybe_compact(file)?;
+        Ok(true)
+    }
+
+    /// Gives up the lock `name` taken with `token`, so others can take it before the lease
+    /// runs out. Returns false if the lock has expired or been taken since.
+    pub fn release(&self, name: &[u8], token: u64) -> io::Result<bool> {
+        let key = [LOCK_PREFIX, name].concat();
+        self.track(&key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let held = self.read_lease(&self.index.read().unwrap(), &key)?;
+        if held.is_none_or(|lease| lease.token != token) {
+            return Ok(false);
+        }
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: key.clone(),
+            ..DataFileEntry::default()
+        };
+        self.append_entry(&mut file, &mut entry)?;
+        self.index.write().unwrap().remove(&key);
+        self.notify(entry.seq, &key, ChangeKind::Del);
+        self.archive_pending()?;
+        Ok(true)
+    }
+
+    /// The current holder of the lock `name`, or `None` if it is free.
+    pub fn lease(&self, name: &[u8]) -> io::Result<Option<Lease>> {
+        let key = [LOCK_PREFIX, name].concat();
+        self.track(&key, Access::Read);
+        self.read_lease(&self.index.read().unwrap(), &key)
+    }
+
+    fn read_lease(&self, index: &Index, key: &[u8]) -> io::Result<Option<Lease>> {
+        let Some(record) = self.read_entry(index, key)? else {
+            return Ok(None);
+        };
+        Ok(Some(Lease {
+            owner: String::from_utf8_lossy(&record.value).into_owned(),
+            token: record
+                .meta
+                .get(LEASE_TOKEN_META)
+                .and_then(|token| token.parse().ok())
+                .unwrap_or(record.seq),
+            expires_at: index.expires_at(key).unwrap_or(i64::MAX),
+        }))
+    }
+
+    fn write_lease(
+        &self,
+        file: &mut Log,
+        key: &[u8],
+        owner: &str,
+        token: u64,
+        ttl: Duration,
+    ) -> io::Result<u64> {
+        let meta = Metadata::from([(LEASE_TOKEN_META.to_string(), token.to_string())]);
+        self.write_value(file, key, owner.as_bytes(), &meta, Some(expiry(ttl)))
+    }
+
+    /// `compare_and_set` for a value streamed from `reader` as in `set_from_reader_with_metadata`.
+    /// On a version mismatch nothing is read from `reader`.
+    pub fn compare_and_set_from_reader(
+        &self,
+        key: &[u8],
+        expected: Option<u64>,
+        reader: impl Read,
+        len: Option<u64>,
+        meta: &Metadata,
+    ) -> io::Result<Result<u64, Option<u64>>> {
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let current = self.current_version(&self.index.read().unwrap(), key)?;
+        if current != expected {
+            return Ok(Err(current));
+        }
+
+        let seq = self.write_value_streamed(&mut file, key, reader, len, meta.clone(), None)?;
+        self.maybe_compact(file)?;
+        Ok(Ok(seq))
+    }
+
+    /// Streams a value of `len` bytes (or up to the end of `reader` if `None`) into the log with
+    /// the write lock held and points the index at it. Returns the write's sequence number.
+    fn write_value_streamed(
+        &self,
+        file: &mut Log,
+        key: &[u8],
+        reader: impl Read,
+        len: Option<u64>,
+        meta: Metadata,
+        expires_at: Option<i64>,
+    ) -> io::Result<u64> {
+        self.check_writable()?;
+        if let Some(len) = len {
+            self.check_value_size(len)?;
+        }
+        self.check_index_room([key])?;
+        let entry = DataFileEntry {
+            tstamp: now_millis(),
+            seq: self.next_seq(),
+            key: key.to_vec(),
+            meta,
+            expires_at,
+            ..DataFileEntry::default()
+        };
+        let seq = entry.seq;
+        let start = file.seek(SeekFrom::End(0))?;
+
+        let written = match len {
+            Some(len) => self.append_streamed(file, start, entry, reader, len),
+            None => self.append_unsized(file, start, entry, reader),
+        };
+        let (log_index, chunks, end) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                file.set_len(start)?;
+                return Err(e);
+            }
+        };
+        self.sync_if_needed(file.as_ref())?;
+        *self.file_size.lock().unwrap() += end - start;
+        Span::current().record("bytes_written", end - start);
+
+        let mut index = self.index.write().unwrap();
+        index.insert_chunked(key, log_index, chunks);
+        index.set_expiry(key, expires_at);
+        drop(index);
+        self.notify(seq, key, ChangeKind::Set);
+
+        Ok(seq)
+    }
+
+    /// Writes a streamed value at `start`, split into pieces if it exceeds the chunk size.
+    /// Returns the index entries of the last record and of the leading pieces, and the new end
+    /// of the log.
+    fn append_streamed(
+        &self,
+        file: &mut Log,
+        start: u64,
+        entry: DataFileEntry,
+        mut reader: impl Read,
+        len: u64,
+    ) -> io::Result<(LogIndex, Vec<LogIndex>, u64)> {
+        let mut pos = start;
+        let mut remaining = len;
+        let mut chunks = Vec::new();
+
+        if let Some(chunk_size) = self.chunk_size_for(len) {
+            let piece = DataFileEntry {
+                chunk: ChunkRole::Piece,
+                meta: Metadata::new(),
+                expires_at: None,
+                ..entry.clone()
+            };
+            while remaining > chunk_size {
+                let (head, prefix_len) = codec::encode_streamed(&piece, chunk_size);
+                let written = write_streamed(file, &head, prefix_len, &mut reader, chunk_size)?;
+                chunks.push(LogIndex {
+                    pos: pos + prefix_len,
+                    len: written - prefix_len,
+                });
+                pos += written;
+                remaining -= chunk_size;
+            }
+        }
+
+        let last = DataFileEntry {
+            chunk: match chunks.len() {
+                0 => ChunkRole::Whole,
+                n => ChunkRole::Last(n as u64),
+            },
+            ..entry
+        };
+        let (head, prefix_len) = codec::encode_streamed(&last, remaining);
+        let written = write_streamed(file, &head, prefix_len, &mut reader, remaining)?;
+        let log_index = LogIndex {
+            pos: pos + prefix_len,
+            len: written - prefix_len,
+        };
+        pos += written;
+
+        Ok((log_index, chunks, pos))
+    }
+
+    /// Writes a value of unknown length at `start`, reading it a chunk at a time and splitting it
+    /// into pieces once it outgrows one. Returns the same as `append_streamed`; a value over the
+    /// maximum size is an error once one byte too many has been read.
+    fn append_unsized(
+        &self,
+        file: &mut Log,
+        start: u64,
+        entry: DataFileEntry,
+        reader: impl Read,
+    ) -> io::Result<(LogIndex, Vec<LogIndex>, u64)> {
+        let chunk_size = self.options.chunk_size.filter(|&size| size > 0);
+        let max = self.options.max_value_size;
+        let mut reader = reader.take(max.map_or(u64::MAX, |max| max + 1));
+        let mut pos = start;
+        let mut chunks = Vec::new();
+        let mut value = Vec::new();
+        let mut total = 0;
+
+        loop {
+            // Reading one byte past a chunk tells whether another piece follows.
+            let want = chunk_size.map_or(u64::MAX, |size| size + 1) - value.len() as u64;
+            (&mut reader).take(want).read_to_end(&mut value)?;
+            let Some(size) = chunk_size.filter(|&size| value.len() as u64 > size) else {
+                break;
+            };
+
+            let rest = value.split_off(size as usize);
+            total += size;
+            let piece = DataFileEntry {
+                chunk: ChunkRole::Piece,
+                value: Some(std::mem::replace(&mut value, rest)),
+                meta: Metadata::new(),
+                expires_at: None,
+                ..entry.clone()
+            };
+            let (frame, prefix_len) = codec::encode(&piece);
+            file.write_all(&frame)?;
+            chunks.push(LogIndex {
+                pos: pos + prefix_len,
+                len: frame.len() as u64 - prefix_len,
+            });
+            pos += frame.len() as u64;
+        }
+
+        total += value.len() as u64;
+        self.check_value_size(total)?;
+
+        let last = DataFileEntry {
+            chunk: match chunks.len() {
+                0 => ChunkRole::Whole,
+                n => ChunkRole::Last(n as u64),
+            },
+            value: Some(value),
+            ..entry
+        };
+        let (frame, prefix_len) = codec::encode(&last);
+        file.write_all(&frame)?;
+        file.flush()?;
+        let log_index = LogIndex {
+            pos: pos + prefix_len,
+            len: frame.len() as u64 - prefix_len,
+        };
+        pos += frame.len() as u64;
+
+        Ok((log_index, chunks, pos))
+    }
+
+    /// Opens the value of `key` for reading without loading it into memory, returning its
+    /// `ValueInfo` and a reader over exactly `info.len` bytes. The reader has its own handle on
+    /// the log, so it yields the value as of this call even if the key is overwritten or the log
+    /// compacted while it is read. Values with pending merge operands are folded in memory.
+    pub fn open_value(&self, key: &[u8]) -> io::Result<Option<(ValueInfo, ValueReader)>> {
+        self.track(key, Access::Read);
+        let index = self.index.read().unwrap();
+
+        if !index.operands(key).is_empty() {
+            return Ok(self.read_entry(&index, key)?.map(|v| {
+                let info = ValueInfo {
+                    len: v.value.len() as u64,
+                    meta: v.meta,
+                    seq: v.seq,
+                };
+                (info, ValueReader::memory(v.value))
+            }));
+        }
+        if !is_live(&index, key, now_millis()) {
+            return Ok(None);
+        }
+        let Some(base) = index.get(key).cloned() else {
+            return Ok(None);
+        };
+        let mut records = index.chunks(key).to_vec();
+
+        let mut file = self.storage.open_reader()?;
+        drop(index);
+
+        let head = read_head_at(&mut file, &base)?;
+        let mut len = head.value_len.unwrap_or_default();
+        for piece in &records {
+            len += read_head_at(&mut file, piece)?
+                .value_len
+                .unwrap_or_default();
+        }
+        records.push(base);
+
+        let info = ValueInfo {
+            len,
+            meta: head.meta,
+            seq: head.seq,
+        };
+        Ok(Some((info, ValueReader::log(file, records))))
+    }
+
+    /// Copies the value of `key` into `writer` without buffering it in memory. Returns the number
+    /// of bytes written, or `None` if the key does not exist.
+    pub fn get_to_writer(&self, key: &[u8], mut writer: impl Write) -> io::Result<Option<u64>> {
+        match self.open_value(key)? {
+            Some((_, mut reader)) => io::copy(&mut reader, &mut writer).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn apply_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operand: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let merge_operator = self
+            .options
+            .merge_operator
+            .as_ref()
+            .ok_or_else(no_merge_operator)?;
+        Ok(merge_operator(key, existing, operand))
+    }
+
+    #[instrument(level = "debug", skip_all, fields(key_len = key.len(), bytes_written = Empty))]
+    pub fn del(&self, key: &[u8]) -> io::Result<()> {
+        self.track(key, Access::Write);
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: key.to_vec(),
+            value: None,
+            ..DataFileEntry::default()
+        };
+
+        let mut timer = self.timer(SlowOpKind::Del);
+        let mut file = self.file.lock().unwrap();
+        timer.phase("lock");
+        self.trash(&mut file, key)?;
+        self.append_entry(&mut file, &mut entry)?;
+        timer.phase("write");
+
+        self.index.write().unwrap().remove(key);
+        self.remember_delete(key, (entry.seq, self.options.node_id));
+        self.notify(entry.seq, key, ChangeKind::Del);
+        timer.phase("index");
+        self.archive_pending()?;
+        timer.phase("archive");
+        self.finish(timer, key, None);
+
+        Ok(())
+    }
+
+    /// With `EngineOptions::trash_retention` set, copies the live value of `key` under
+    /// `TRASH_PREFIX` before it is deleted, to expire when the retention runs out. The engine's
+    /// own records, values already in the trash included, are deleted for good.
+    fn trash(&self, file: &mut Log, key: &[u8]) -> io::Result<()> {
+        if self.options.trash_retention.is_none() || is_reserved(key) {
+            return Ok(());
+        }
+        let (current, expires_at) = {
+            let index = self.index.read().unwrap();
+            match self.read_entry(&index, key)? {
+                Some(current) => (current, index.expires_at(key)),
+                None => return Ok(()),
+            }
+        };
+        let entry = self.trash_entry(key, current.value, current.meta, expires_at, now_millis());
+        if let Some(DataFileEntry {
+            key: trash_key,
+            value: Some(value),
+            meta,
+            expires_at,
+            ..
+        }) = entry
+        {
+            self.write_value(file, &trash_key, &value, &meta, expires_at)?;
+        }
+        Ok(())
+    }
+
+    /// The record that keeps a value of `key` with `meta`, which was to expire at `expires_at`,
+    /// in the trash, or `None` without `trash_retention` or for the engine's own records.
+    fn trash_entry(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+        mut meta: Metadata,
+        expires_at: Option<i64>,
+        tstamp: i64,
+    ) -> Option<DataFileEntry> {
+        let retention = self.options.trash_retention?;
+        if is_reserved(key) {
+            return None;
+        }
+        meta.insert(TRASHED_AT_META.to_string(), tstamp.to_string());
+        if let Some(at) = expires_at {
+            meta.insert(TRASH_EXPIRES_META.to_string(), at.to_string());
+        }
+        Some(DataFileEntry {
+            tstamp,
+            key: [TRASH_PREFIX, key].concat(),
+            value: Some(value),
+            meta,
+            expires_at: Some(expiry(retention)),
+            ..DataFileEntry::default()
+        })
+    }
+
+    /// Restores `key` as it was when it was last deleted, with its metadata and any expiry it
+    /// had, if that was within `EngineOptions::trash_retention`. Returns false if there is nothing
+    /// to restore or the key has been written again since.
+    pub fn undelete(&self, key: &[u8]) -> io::Result<bool> {
+        self.track(key, Access::Write);
+        let trash_key = [TRASH_PREFIX, key].concat();
+        let mut file = self.file.lock().unwrap();
+
+        let trashed = {
+            let index = self.index.read().unwrap();
+            if is_live(&index, key, now_millis()) {
+                return Ok(false);
+            }
+            self.read_entry(&index, &trash_key)?
+        };
+        let Some(Versioned {
+            value, mut meta, ..
+        }) = trashed
+        else {
+            return Ok(false);
+        };
+        meta.remove(TRASHED_AT_META);
+        let expires_at = meta
+            .remove(TRASH_EXPIRES_META)
+            .and_then(|at| at.parse().ok());
+        self.write_value(&mut file, key, &value, &meta, expires_at)?;
+
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: trash_key.clone(),
+            ..DataFileEntry::default()
+        };
+        self.append_entry(&mut file, &mut entry)?;
+        self.index.write().unwrap().remove(&trash_key);
+        self.notify(entry.seq, &trash_key, ChangeKind::Del);
+        self.maybe_compact(file)?;
+        Ok(true)
+    }
+
+    fn remember_delete(&self, key: &[u8], version: Version) {
+        self.deletes.lock().unwrap().insert(key.into(), version);
+    }
+
+    /// Deletes every live key starting with `prefix` in one atomic batch of tombstones and
+    /// returns how many there were. Keys written while it runs may survive. The engine's own
+    /// records under `RESERVED_PREFIXES` are left alone, so an empty prefix clears the user's keys.
+    pub fn delete_prefix(&self, prefix: &[u8]) -> io::Result<usize> {
+        let ops: Vec<_> = {
+            let index = self.index.read().unwrap();
+            let now = now_millis();
+            index
+                .keys(Bound::Included(prefix), Bound::Unbounded)
+                .take_while(|key| key.starts_with(prefix))
+                .filter(|key| is_listed(&index, key, now))
+                .map(|key| BatchOp::Del { key: key.to_vec() })
+                .collect()
+        };
+        if ops.is_empty() {
+            return Ok(0);
+        }
+        let existed = self.write_batch(&ops)?;
+        Ok(existed.into_iter().filter(|&existed| existed).count())
+    }
+
+    /// Adds `key` to the group `tag`, returning false if it was already in it. A key can carry
+    /// any number of tags. Tags belong to the key's name rather than its value, so they stay
+    /// through overwrites and deletes until `untag`. They are kept as keys under `TAG_PREFIX`,
+    /// ordered by tag, so listing a tag reads only its own keys. Tags may not contain `\0`.
+    pub fn tag(&self, key: &[u8], tag: &str) -> io::Result<bool> {
+        let member = [tag_prefix(tag)?.as_slice(), key].concat();
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        if is_live(&self.index.read().unwrap(), &member, now_millis()) {
+            return Ok(false);
+        }
+        self.write_value(&mut file, &member, b"", &Metadata::new(), None)?;
+        self.maybe_compact(file)?;
+        Ok(true)
+    }
+
+    /// Takes `key` out of the group `tag`, returning false if it was not in it.
+    pub fn untag(&self, key: &[u8], tag: &str) -> io::Result<bool> {
+        let member = [tag_prefix(tag)?.as_slice(), key].concat();
+        self.track(key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        if !is_live(&self.index.read().unwrap(), &member, now_millis()) {
+            return Ok(false);
+        }
+        let mut entry = DataFileEntry {
+            tstamp: now_millis(),
+            key: member.clone(),
+            ..DataFileEntry::default()
+        };
+        self.append_entry(&mut file, &mut entry)?;
+        self.index.write().unwrap().remove(&member);
+        self.notify(entry.seq, &member, ChangeKind::Del);
+        self.archive_pending()?;
+        Ok(true)
+    }
+
+    /// The live keys tagged `tag`, in ascending order.
+    pub fn keys_by_tag(&self, tag: &str) -> io::Result<Vec<Vec<u8>>> {
+        let prefix = tag_prefix(tag)?;
+        let index = self.index.read().unwrap();
+        Ok(tagged_keys(&index, &prefix, now_millis())
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+
+    /// The live keys tagged `tag` with their values, in ascending key order and read as one
+    /// consistent view like `scan`.
+    pub fn get_by_tag(&self, tag: &str) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let prefix = tag_prefix(tag)?;
+        let index = self.index.read().unwrap();
+        self.read_pairs(&index, tagged_keys(&index, &prefix, now_millis()))
+    }
+
+    /// Deletes every live key tagged `tag` in one atomic batch of tombstones and returns how many
+    /// there were. The tags stay, so keys written again later are still in the group.
+    pub fn delete_by_tag(&self, tag: &str) -> io::Result<usize> {
+        let ops: Vec<_> = self
+            .keys_by_tag(tag)?
+            .into_iter()
+            .map(|key| BatchOp::Del { key })
+            .collect();
+        if ops.is_empty() {
+            return Ok(0);
+        }
+        let existed = self.write_batch(&ops)?;
+        Ok(existed.into_iter().filter(|&existed| existed).count())
+    }
+
+    #[instrument(level = "debug", skip_all, fields(key_len = key.len(), value_len = Empty))]
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.track(key, Access::Read);
+        let mut timer = self.timer(SlowOpKind::Get);
+        let index = self.index.read().unwrap();
+        timer.phase("lock");
+        let value = self.read_value(&index, key)?;
+        timer.phase("read");
+        if let Some(value) = &value {
+            Span::current().record("value_len", value.len());
+        }
+        let value_len = value.as_ref().map(|value| value.len() as u64);
+        self.finish(timer, key, value_len);
+        Ok(value)
+    }
+
+    /// Looks up several keys under one index lock, so the values form a consistent view.
+    /// Returns them in the order asked, with `None` for missing keys.
+    pub fn get_many(&self, keys: &[&[u8]]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let index = self.index.read().unwrap();
+        keys.iter()
+            .map(|key| {
+                self.track(key, Access::Read);
+                self.read_value(&index, key)
+            })
+            .collect()
+    }
+
+    /// Up to `limit` live key/value pairs whose key starts with `prefix`, in key order, leaving
+    /// out the engine's own records under `RESERVED_PREFIXES`. The pairs form a consistent view:
+    /// writes wait until the scan is done.
+    pub fn scan(&self, prefix: &[u8], limit: usize) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        let keys = index
+            .keys(Bound::Included(prefix), Bound::Unbounded)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| is_listed(&index, key, now))
+            .take(limit);
+        self.read_pairs(&index, keys)
+    }
+
+    /// Up to `limit` live key/value pairs with keys between `start` and `end`, in ascending key
+    /// order or descending if `reverse` is set (starting from `end`). Like `scan`, the pairs form
+    /// a consistent view without the engine's own records.
+    pub fn range(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        limit: usize,
+        reverse: bool,
+    ) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        let keys = index
+            .keys(start, end)
+            .filter(|key| is_listed(&index, key, now));
+        if reverse {
+            self.read_pairs(&index, keys.rev().take(limit))
+        } else {
+            self.read_pairs(&index, keys.take(limit))
+        }
+    }
+
+    fn read_pairs<'a>(
+        &self,
+        index: &Index,
+        keys: impl Iterator<Item = &'a [u8]>,
+    ) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut pairs = Vec::new();
+        for key in keys {
+            self.track(key, Access::Read);
+            if let Some(value) = self.read_value(index, key)? {
+                pairs.push((key.to_vec(), value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Whether `key` exists and has not expired, without touching the log.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        is_live(&self.index.read().unwrap(), key, now_millis())
+    }
+
+    /// Length, metadata and version of the value of `key`, read from record heads so the value
+    /// itself stays on disk (unless it has pending merge operands, which must be folded).
+    pub fn value_info(&self, key: &[u8]) -> io::Result<Option<ValueInfo>> {
+        Ok(self.open_value(key)?.map(|(info, _)| info))
+    }
+
+    /// The metadata stored with `key` by `set_with_metadata`, read like `value_info` without the
+    /// value. A key written without metadata has an empty map.
+    pub fn metadata(&self, key: &[u8]) -> io::Result<Option<Metadata>> {
+        Ok(self.value_info(key)?.map(|info| info.meta))
+    }
+
+    /// Returns the value of `key` together with the metadata stored by `set_with_metadata`.
+    pub fn get_with_metadata(&self, key: &[u8]) -> io::Result<Option<(Vec<u8>, Metadata)>> {
+        Ok(self.get_versioned(key)?.map(|v| (v.value, v.meta)))
+    }
+
+    /// Returns the value of `key` with the sequence number of its newest write, read together so
+    /// the number can be passed to `compare_and_set`.
+    pub fn get_with_seq(&self, key: &[u8]) -> io::Result<Option<(Vec<u8>, u64)>> {
+        Ok(self.get_versioned(key)?.map(|v| (v.value, v.seq)))
+    }
+
+    /// Returns the value of `key` with its metadata and version, read together so the version
+    /// matches the value for `compare_and_set`.
+    pub fn get_versioned(&self, key: &[u8]) -> io::Result<Option<Versioned>> {
+        self.track(key, Access::Read);
+        let index = self.index.read().unwrap();
+        self.read_entry(&index, key)
+    }
+
+    fn read_value(&self, index: &Index, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.read_entry(index, key)?.map(|v| v.value))
+    }
+
+    /// Reads and assembles the value, metadata and version of `key`; the caller holds the index
+    /// lock so compaction cannot swap the file underneath.
+    fn read_entry(&self, index: &Index, key: &[u8]) -> io::Result<Option<Versioned>> {
+        if !is_live(index, key, now_millis()) {
+            return Ok(None);
+        }
+        let base = index.get(key).cloned();
+        let chunks = index.chunks(key);
+        let operands = index.operands(key);
+        if base.is_none() && operands.is_empty() {
+            return Ok(None);
+        }
+
+        let mut reader = {
+            let mut pool = self.reader_pool.lock().unwrap();
+            match pool.pop() {
+                Some(r) => r,
+                None => self.storage.open_reader()?,
+            }
+        };
+
+        let mut chunk_data = Vec::with_capacity(chunks.len());
+        for log_index in chunks {
+            chunk_data.push((log_index, read_payload(&mut reader, log_index)?));
+        }
+        let base = match base {
+            Some(log_index) => Some((read_payload(&mut reader, &log_index)?, log_index)),
+            None => None,
+        };
+        let mut operand_data = Vec::with_capacity(operands.len());
+        for log_index in operands {
+            operand_data.push((log_index, read_payload(&mut reader, log_index)?));
+        }
+
+        {
+            let mut pool = self.reader_pool.lock().unwrap();
+            if pool.len() < 8 {
+                pool.push(reader);
+            }
+        }
+
+        let mut meta = Metadata::new();
+        let mut seq = 0;
+        let mut value = match base {
+            Some((data, log_index)) => {
+                let entry = self.decode_at(Format::V2, &data, &log_index)?;
+                meta = entry.meta;
+                seq = entry.seq;
+                let last = entry.value.unwrap_or_default();
+                if chunk_data.is_empty() {
+                    Some(last)
+                } else {
+                    let mut value = Vec::new();
+                    for (log_index, data) in chunk_data {
+                        let piece = self.decode_at(Format::V2, &data, log_index)?;
+                        value.extend(piece.value.unwrap_or_default());
+                    }
+                    value.extend(last);
+                    Some(value)
+                }
+            }
+            None => None,
+        };
+        for (log_index, data) in operand_data {
+            let operand = self.decode_at(Format::V2, &data, log_index)?;
+            seq = operand.seq;
+            value = Some(self.apply_merge(
+                key,
+                value.as_deref(),
+                &operand.value.unwrap_or_default(),
+            )?);
+        }
+
+        Ok(value.map(|value| Versioned { value, meta, seq }))
+    }
+
+    /// Up to `limit` live keys starting with `prefix`, in ascending order, the engine's own
+    /// records left out. Pass the last key of the previous page as `after` to continue from it.
+    pub fn keys(&self, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Vec<Vec<u8>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        index
+            .keys(start, Bound::Unbounded)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| is_listed(&index, key, now))
+            .take(limit)
+            .map(|key| key.to_vec())
+            .collect()
+    }
+
+    /// What the index holds for up to `limit` keys starting with `prefix`, expired keys included,
+    /// with the sequence number read from each key's record. Pass the last key of the previous
+    /// page as `after` to continue from it. Meant for finding out why a key reads as it does.
+    pub fn index_entries(
+        &self,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> io::Result<Vec<IndexedKey>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+        let index = self.index.read().unwrap();
+        let mut reader = {
+            let mut pool = self.reader_pool.lock().unwrap();
+            match pool.pop() {
+                Some(r) => r,
+                None => self.storage.open_reader()?,
+            }
+        };
+
+        let entries = index
+            .keys(start, Bound::Unbounded)
+            .take_while(|key| key.starts_with(prefix))
+            .take(limit)
+            .map(|key| {
+                let record = index.get(key).cloned();
+                let seq = match &record {
+                    Some(log_index) => Some(read_head_at(&mut reader, log_index)?.seq),
+                    None => None,
+                };
+                Ok(IndexedKey {
+                    key: key.to_vec(),
+                    record,
+                    seq,
+                    pieces: index.chunks(key).to_vec(),
+                    operands: index.operands(key).to_vec(),
+                    expires_at: index.expires_at(key),
+                })
+            })
+            .collect();
+
+        let mut pool = self.reader_pool.lock().unwrap();
+        if pool.len() < 8 {
+            pool.push(reader);
+        }
+        entries
+    }
+
+    /// How many live keys start with `prefix`, the engine's own records aside, and how many bytes
+    /// of the log they use, counted from the index without reading any values.
+    pub fn usage(&self, prefix: &[u8]) -> PrefixUsage {
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        let framed = |log_index: &LogIndex| log_index.len + codec::prefix_len(log_index.len);
+        index
+            .keys(Bound::Included(prefix), Bound::Unbounded)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| is_listed(&index, key, now))
+            .fold(PrefixUsage::default(), |usage, key| PrefixUsage {
+                keys: usage.keys + 1,
+                bytes: usage.bytes
+                    + index.get(key).map_or(0, framed)
+                    + index
+                        .chunks(key)
+                        .iter()
+                        .chain(index.operands(key))
+                        .map(framed)
+                        .sum::<u64>(),
+            })
+    }
+
+    /// The `n` keys with the most traffic since the engine was loaded, busiest first. Counts are
+    /// approximate and may overestimate. Empty unless `EngineOptions::track_hot_keys` is set.
+    pub fn hot_keys(&self, n: usize) -> Vec<HotKey> {
+        match &self.hot_keys {
+            Some(tracker) => tracker.lock().unwrap().top(n),
+            None => Vec::new(),
+        }
+    }
+
+    fn track(&self, key: &[u8], access: Access) {
+        if let Some(tracker) = &self.hot_keys {
+            tracker.lock().unwrap().record(key, access);
+        }
+    }
+
+    /// The most recent operations that took at least `EngineOptions::slow_op_threshold`, up to
+    /// `SLOW_LOG_CAPACITY`, oldest first. Empty unless the threshold is set.
+    pub fn slow_ops(&self) -> Vec<SlowOp> {
+        match &self.slow_log {
+            Some(slow_log) => slow_log.ops(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Empties the list `slow_ops` returns.
+    pub fn clear_slow_ops(&self) {
+        if let Some(slow_log) = &self.slow_log {
+            slow_log.clear();
+        }
+    }
+
+    fn timer(&self, kind: SlowOpKind) -> OpTimer {
+        OpTimer::start(
+            kind,
+            self.slow_log.is_some() || self.options.observer.is_some(),
+        )
+    }
+
+    /// Tells the observer about a finished get, set or delete and keeps it if it was slow.
+    fn finish(&self, timer: OpTimer, key: &[u8], value_len: Option<u64>) {
+        if let Some(observer) = &self.options.observer {
+            let elapsed = timer.elapsed();
+            match timer.kind() {
+                SlowOpKind::Get => observer.on_get(key, value_len, elapsed),
+                SlowOpKind::Set => observer.on_set(key, value_len.unwrap_or_default(), elapsed),
+                SlowOpKind::Del => observer.on_del(key, elapsed),
+                SlowOpKind::Compact | SlowOpKind::Rebuild => {}
+            }
+        }
+        self.record_slow(timer, Some(key.len()), value_len);
+    }
+
+    /// Decodes the record at `log_index`, telling the observer if it does not decode.
+    fn decode_at(
+        &self,
+        format: Format,
+        payload: &[u8],
+        log_index: &LogIndex,
+    ) -> io::Result<DataFileEntry> {
+        codec::decode(format, payload).inspect_err(|e| {
+            if e.kind() == io::ErrorKind::InvalidData {
+                self.report_corruption(log_index.pos, &e.to_string());
+            }
+        })
+    }
+
+    fn report_corruption(&self, pos: u64, reason: &str) {
+        if let Some(observer) = &self.options.observer {
+            observer.on_corruption(pos, reason);
+        }
+    }
+
+    fn record_slow(&self, timer: OpTimer, key_len: Option<usize>, value_len: Option<u64>) {
+        if let Some(slow_log) = &self.slow_log {
+            slow_log.record(timer, key_len, value_len);
+        }
+    }
+
+    /// Key count, log size and how much of it is dead. Walks the index to total the live bytes,
+    /// so it takes time proportional to the number of keys.
+    pub fn stats(&self) -> EngineStats {
+        let file_size = *self.file_size.lock().unwrap();
+        let index = self.index.read().unwrap();
+
+        let framed = |log_index: &LogIndex| log_index.len + codec::prefix_len(log_index.len);
+        let live_bytes = FORMAT_V2_MAGIC.len() as u64
+            + index
+                .entries()
+                .map(|e| {
+                    e.base.map_or(0, framed)
+                        + e.chunks.iter().chain(e.operands).map(framed).sum::<u64>()
+                })
+                .sum::<u64>();
+
+        EngineStats {
+            keys: index.len(),
+            file_size,
+            live_bytes,
+            dead_bytes: file_size.saturating_sub(live_bytes),
+            last_compaction: *self.last_compaction.lock().unwrap(),
+            replication: self.replication_stats(),
+            disk: self.disk_stats(),
+            index: self.index_stats(),
+        }
+    }
+
+    /// Estimated memory used by the index and the writes refused for want of it.
+    pub fn index_stats(&self) -> IndexStats {
+        IndexStats {
+            bytes: self.index.read().unwrap().memory_bytes() as u64,
+            max_bytes: self.options.max_index_memory,
+            rejected_writes: self.index_full_rejections.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Free space for the log and the writes refused for want of it. Takes no locks, but asks
+    /// the file system each time.
+    pub fn disk_stats(&self) -> DiskStats {
+        DiskStats {
+            free_bytes: self.storage.free_space().ok().flatten(),
+            min_free_bytes: self.options.min_free_space,
+            rejected_writes: self.low_space_rejections.load(Ordering::SeqCst),
+        }
+    }
+
+    /// What has been applied from a primary since the engine loaded. Unlike `stats` it takes
+    /// no locks, so it suits frequent polling.
+    pub fn replication_stats(&self) -> ReplicationStats {
+        let applied_seq = self.last_sequence();
+        ReplicationStats {
+            applied_seq,
+            primary_seq: self.primary_seq.load(Ordering::SeqCst).max(applied_seq),
+            records_applied: self.replicated_records.load(Ordering::SeqCst),
+            bytes_applied: self.replicated_bytes.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Copies a consistent snapshot of the log into `writer` and returns its size. Only opening
+    /// the file and taking its length happen under the write lock: later appends land past that
+    /// length and compaction swaps a new log into place, so neither disturbs the copy.
+    pub fn backup_to_writer(&self, mut writer: impl Write) -> io::Result<u64> {
+        let (_, mut snapshot) = self.snapshot()?;
+        io::copy(&mut snapshot, &mut writer)
+    }
+
+    /// The length of the log and a reader over a consistent snapshot of it, for copying it
+    /// elsewhere like `backup_to_writer` does, such as to bootstrap a replica over the network.
+    pub fn snapshot(&self) -> io::Result<(u64, impl Read + Send + use<>)> {
+        let _file = self.file.lock().unwrap();
+        let snapshot = self.storage.open_reader()?;
+        let len = snapshot.size()?;
+        Ok((len, snapshot.take(len)))
+    }
+
+    /// Copies what was appended to the log after `since`, a point returned by an earlier call,
+    /// into `writer`, or the whole log without one, and returns the point this copy ends at.
+    /// Concatenating a whole copy and the increments after it, in order, gives a loadable log.
+    /// Returns `None` without copying anything if the log was compacted after `since`, since
+    /// only a whole copy can follow that.
+    pub fn backup_since(
+        &self,
+        since: Option<&BackupPoint>,
+        mut writer: impl Write,
+    ) -> io::Result<Option<BackupPoint>> {
+        let (mut snapshot, len) = {
+            let _file = self.file.lock().unwrap();
+            let snapshot = self.storage.open_reader()?;
+            let len = snapshot.size()?;
+            (snapshot, len)
+        };
+        let mut head = Vec::new();
+        (&mut snapshot)
+            .take(len.min(BACKUP_HEAD_LEN))
+            .read_to_end(&mut head)?;
+        let from = match since {
+            None => 0,
+            Some(point) if point.len <= len && head.starts_with(&point.head) => point.len,
+            Some(_) => return Ok(None),
+        };
+        snapshot.seek(SeekFrom::Start(from))?;
+        io::copy(&mut snapshot.take(len - from), &mut writer)?;
+        Ok(Some(BackupPoint { len, head }))
+    }
+
+    /// Writes a snapshot of the log to `path` and syncs it. The result loads like any log.
+    pub fn backup(&self, path: impl AsRef<Path>) -> io::Result<u64> {
+        let mut out = File::create(path)?;
+        let len = self.backup_to_writer(&mut out)?;
+        out.sync_all()?;
+        Ok(len)
+    }
+
+    /// Where a replica that has applied every write up to sequence number `after` picks up in
+    /// this log, or `None` if it cannot: compaction has since dropped writes it is missing, or
+    /// it is ahead of this log. Such a replica has to start over from a backup.
+    pub fn replication_cursor(&self, after: u64) -> io::Result<Option<ReplicationCursor>> {
+        let (mut log, end) = {
+            let _file = self.file.lock().unwrap();
+            (self.storage.open_reader()?, *self.file_size.lock().unwrap())
+        };
+        if after > self.last_sequence() {
+            return Ok(None);
+        }
+        let mut head = Vec::new();
+        (&mut log)
+            .take(end.min(BACKUP_HEAD_LEN))
+            .read_to_end(&mut head)?;
+        // Everything compaction kept has a sequence number up to its marker's; later records
+        // were appended since, in sequence order.
+        let compacted = compaction_marker(&head)?;
+        if after < compacted {
+            return Ok(None);
+        }
+        let mut pos = end;
+        scan_heads(&mut log, end, |record, log_index| {
+            if record.seq > after && pos == end {
+                pos = log_index.pos - codec::prefix_len(log_index.len);
+            }
+        })?;
+        Ok(Some(ReplicationCursor {
+            seq: after,
+            pos,
+            head,
+        }))
+    }
+
+    /// Reads the records after `cursor` as they are framed in the log, roughly `max_bytes` of
+    /// them, and moves the cursor past them. A value written in chunks is never split between
+    /// reads. Returns `None` if compaction has dropped records the cursor had yet to reach.
+    pub fn read_replication(
+        &self,
+        cursor: &mut ReplicationCursor,
+        max_bytes: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let (mut log, end) = {
+            let _file = self.file.lock().unwrap();
+            (self.storage.open_reader()?, *self.file_size.lock().unwrap())
+        };
+        let mut head = Vec::new();
+        (&mut log)
+            .take(end.min(BACKUP_HEAD_LEN))
+            .read_to_end(&mut head)?;
+        if cursor.pos > end || !head.starts_with(&cursor.head) {
+            match self.replication_cursor(cursor.seq)? {
+                Some(moved) => *cursor = moved,
+                None => return Ok(None),
+            }
+            return self.read_replication(cursor, max_bytes);
+        }
+
+        let mut reader = BufReader::new(log);
+        reader.seek(SeekFrom::Start(cursor.pos))?;
+        let mut frames = Vec::new();
+        let mut whole = (cursor.pos, cursor.seq);
+        let mut pos = cursor.pos;
+        while pos < end && (frames.len() < max_bytes || whole.0 != pos) {
+            let Some((len, prefix_len)) = codec::read_prefix(Format::V2, &mut reader)? else {
+                break;
+            };
+            let mut payload = vec![0u8; len as usize];
+            reader.read_exact(&mut payload)?;
+            let record = codec::read_head(&mut payload.as_slice())?;
+            codec::put_frame(&mut frames, &payload);
+            pos += prefix_len + len;
+            if record.chunk != ChunkRole::Piece {
+                whole = (pos, record.seq);
+            }
+        }
+        // Pieces of a value still being written wait for the next read.
+        frames.truncate((frames.len() as u64 - (pos - whole.0)) as usize);
+        (cursor.pos, cursor.seq) = whole;
+        cursor.head = head;
+        Ok(Some(frames))
+    }
+
+    /// Appends records read from a primary's log by `read_replication`, keeping their sequence
+    /// numbers, and indexes them. Records this log already has are skipped, so a replica can
+    /// resume from its own `last_sequence`, which it keeps across restarts like any log. Returns
+    /// the sequence number of the last record applied.
+    pub fn apply_replicated(&self, frames: &[u8]) -> io::Result<u64> {
+        let mut records = Vec::new();
+        let mut reader = frames;
+        let mut offset = 0;
+        while let Some((len, prefix_len)) = codec::read_prefix(Format::V2, &mut reader)? {
+            let payload = reader
+                .get(..len as usize)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "record truncated"))?;
+            codec::check(payload)?;
+            let head = codec::read_head(&mut &payload[..])?;
+            records.push((offset, prefix_len, len, head));
+            reader = &reader[len as usize..];
+            offset += (prefix_len + len) as usize;
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let last = self.last_sequence();
+        let first = records.partition_point(|(.., head)| head.seq <= last);
+        let Some(&(from, ..)) = records.get(first) else {
+            return Ok(last);
+        };
+        self.check_free_space()?;
+        if records
+            .last()
+            .is_some_and(|(.., head)| head.chunk == ChunkRole::Piece)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "replicated records end part way through a chunked value",
+            ));
+        }
+        let start = file.seek(SeekFrom::End(0))?;
+        if let Err(e) = file.write_all(&frames[from..]).and_then(|_| file.flush()) {
+            file.set_len(start)?;
+            return Err(e);
+        }
+        self.sync_if_needed(file.as_ref())?;
+        *self.file_size.lock().unwrap() += (frames.len() - from) as u64;
+        self.replicated_records
+            .fetch_add((records.len() - first) as u64, Ordering::SeqCst);
+        self.replicated_bytes
+            .fetch_add((frames.len() - from) as u64, Ordering::SeqCst);
+
+        let mut index = self.index.write().unwrap();
+        let mut pieces = Vec::new();
+        let mut changes = Vec::new();
+        for (offset, prefix_len, len, head) in records.drain(first..) {
+            let log_index = LogIndex {
+                pos: start + (offset - from) as u64 + prefix_len,
+                len,
+            };
+            self.last_seq.fetch_max(head.seq, Ordering::SeqCst);
+            let kind = match (head.value_len, head.merge, head.chunk) {
+                (None, _, _) => {
+                    pieces.clear();
+                    index.remove(&head.key);
+                    ChangeKind::Del
+                }
+                (Some(_), true, _) => {
+                    index.push_operand(head.key.as_slice(), log_index);
+                    ChangeKind::Merge
+                }
+                (Some(_), false, ChunkRole::Piece) => {
+                    pieces.push(log_index);
+                    continue;
+                }
+                (Some(_), false, chunk) => {
+                    let count = match chunk {
+                        ChunkRole::Last(count) => count as usize,
+                        _ => 0,
+                    };
+                    let chunks = pieces.split_off(pieces.len().saturating_sub(count));
+                    pieces.clear();
+                    index.insert_chunked(head.key.as_slice(), log_index, chunks);
+                    index.set_expiry(&head.key, head.expires_at);
+                    ChangeKind::Set
+                }
+            };
+            changes.push((head.seq, head.key, kind));
+        }
+        drop(index);
+        for (seq, key, kind) in changes {
+            self.notify(seq, &key, kind);
+        }
+
+        let last = self.last_sequence();
+        self.maybe_compact(file)?;
+        Ok(last)
+    }
+
+    /// Applies a stream of records from `read_replication`, such as a primary's replication
+    /// endpoint, until it ends, and returns the sequence number of the last record applied.
+    /// Records are applied as they arrive, a batch at a time. The stream may carry heartbeats
+    /// from `codec::encode_heartbeat` between records; each is passed to `on_heartbeat` once the
+    /// records before it are applied.
+    pub fn replicate_from(
+        &self,
+        reader: impl Read,
+        mut on_heartbeat: impl FnMut(u64),
+    ) -> io::Result<u64> {
+        let mut reader = BufReader::new(reader);
+        let mut frames = Vec::new();
+        let mut whole = 0;
+        while let Some((len, _)) = codec::read_prefix(Format::V2, &mut reader)? {
+            if len == 0 {
+                let (primary_seq, _) = codec::read_prefix(Format::V2, &mut reader)?
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                self.apply_replicated(&frames[..whole])?;
+                frames.drain(..whole);
+                whole = 0;
+                self.primary_seq.fetch_max(primary_seq, Ordering::SeqCst);
+                on_heartbeat(primary_seq);
+                continue;
+            }
+            let mut payload = vec![0u8; len as usize];
+            reader.read_exact(&mut payload)?;
+            let record = codec::read_head(&mut payload.as_slice())?;
+            codec::put_frame(&mut frames, &payload);
+            if record.chunk != ChunkRole::Piece {
+                whole = frames.len();
+            }
+            // Apply once nothing more has arrived, keeping back a value still in pieces.
+            if reader.buffer().is_empty() || whole >= REPLICATION_BATCH {
+                self.apply_replicated(&frames[..whole])?;
+                frames.drain(..whole);
+                whole = 0;
+            }
+        }
+        self.apply_replicated(&frames[..whole])
+    }
+
+    /// Applies a write made on a peer, unless the key's current state wins the conflict, and
+    /// returns whether it was applied. Without a `conflict_resolver`, the write with the higher
+    /// `(seq, node)` wins, where writes made here count as `node_id`'s and applied ones keep their
+    /// peer's version, so engines that exchange all their writes end up with the same data in
+    /// whatever order they arrive. Later writes here are numbered after `seq`, so they win over
+    /// what was applied before them. Applied writes are stored with their version in the
+    /// `ORIGIN_META` metadata entry and replace any other metadata and expiry. Deletes are only
+    /// remembered until the engine restarts or compacts; a peer's write to a key deleted before
+    /// then is weighed against nothing and applied.
+    pub fn apply_remote(&self, entry: &RemoteEntry) -> io::Result<bool> {
+        self.check_writable()?;
+        self.track(&entry.key, Access::Write);
+        let mut file = self.file.lock().unwrap();
+
+        let current = match self.read_entry(&self.index.read().unwrap(), &entry.key)? {
+            Some(versioned) => {
+                let (seq, node) =
+                    parse_origin(&versioned.meta).unwrap_or((versioned.seq, self.options.node_id));
+                RemoteEntry {
+                    key: entry.key.clone(),
+                    value: Some(versioned.value),
+                    seq,
+                    node,
+                }
+            }
+            None => {
+                let deletes = self.deletes.lock().unwrap();
+                let (seq, node) = deletes.get(entry.key.as_slice()).copied().unwrap_or((0, 0));
+                RemoteEntry {
+                    key: entry.key.clone(),
+                    value: None,
+                    seq,
+                    node,
+                }
+            }
+        };
+        let replace = match &self.options.conflict_resolver {
+            Some(resolver) => resolver(&current, entry),
+            None => (entry.seq, entry.node) > (current.seq, current.node),
+        };
+        self.last_seq.fetch_max(entry.seq, Ordering::SeqCst);
+        if !replace {
+            return Ok(false);
+        }
+
+        let meta = Metadata::from([(
+            ORIGIN_META.to_string(),
+            format!("{}:{}", entry.seq, entry.node),
+        )]);
+        match &entry.value {
+            Some(value) => {
+                self.write_value(&mut file, &entry.key, value, &meta, None)?;
+            }
+            None => {
+                let mut tombstone = DataFileEntry {
+                    tstamp: now_millis(),
+                    key: entry.key.clone(),
+                    value: None,
+                    meta,
+                    ..DataFileEntry::default()
+                };
+                self.append_entry(&mut file, &mut tombstone)?;
+                self.index.write().unwrap().remove(&entry.key);
+                self.remember_delete(&entry.key, (entry.seq, entry.node));
+                self.notify(tombstone.seq, &entry.key, ChangeKind::Del);
+            }
+        }
+        self.maybe_compact(file)?;
+        Ok(true)
+    }
+
+    /// Reads every record in the log in full, checking that each one decodes and matches its
+    /// checksum, then checks the index against the one loading the log again would build.
+    /// Records written before checksums were added are only checked for decoding. Writes wait
+    /// until it finishes, and a second index is built alongside the live one.
+    pub fn verify(&self) -> io::Result<VerifyReport> {
+        let file = self.file.lock().unwrap();
+        let file_len = file.size()?;
+        let mut reader = BufReader::new(self.storage.open_reader()?);
+        let mut pos = Format::V2.header_len();
+        reader.seek(SeekFrom::Start(pos))?;
+
+        let mut report = VerifyReport::default();
+        let mut rebuilt = Index::new();
+        let mut pending = HashMap::new();
+        loop {
+            let (len, prefix_len) = match codec::read_prefix(Format::V2, &mut reader) {
+                Ok(Some(prefix)) => prefix,
+                Ok(None) => break,
+                // Without its length there is no finding where the next record starts.
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    self.report_corruption(pos, &e.to_string());
+                    report.corrupt.push(CorruptRecord {
+                        pos,
+                        reason: e.to_string(),
+                    });
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            let log_index = LogIndex {
+                pos: pos + prefix_len,
+                len,
+            };
+            if log_index.pos.saturating_add(len) > file_len {
+                break;
+            }
+            let mut payload = vec![0u8; len as usize];
+            reader.read_exact(&mut payload)?;
+            pos = log_index.pos + len;
+            report.records += 1;
+
+            let indexed = codec::decode(Format::V2, &payload).and_then(|entry| {
+                let kind = RecordKind::new(
+                    entry.value.is_some(),
+                    entry.merge,
+                    entry.chunk,
+                    entry.expires_at,
+                );
+                index_record(&mut rebuilt, &mut pending, &log_index, entry.key, kind)
+            });
+            if let Err(e) = indexed {
+                self.report_corruption(log_index.pos, &e.to_string());
+                report.corrupt.push(CorruptRecord {
+                    pos: log_index.pos,
+                    reason: e.to_string(),
+                });
+            }
+        }
+        report.bytes = pos;
+        report.unread_bytes = file_len - pos;
+
+        let index = self.index.read().unwrap();
+        for entry in index.entries() {
+            let reason = if !rebuilt.contains_key(entry.key) {
+                "not in the log"
+            } else if entry.base != rebuilt.get(entry.key) {
+                "points at a record other than the key's latest"
+            } else if entry.chunks != rebuilt.chunks(entry.key) {
+                "value pieces differ from the log"
+            } else if entry.operands != rebuilt.operands(entry.key) {
+                "merge operands differ from the log"
+            } else if entry.expires_at != rebuilt.expires_at(entry.key) {
+                "expiry differs from the log"
+            } else {
+                continue;
+            };
+            report.orphaned.push(OrphanedEntry {
+                key: entry.key.to_vec(),
+                reason: reason.to_string(),
+            });
+        }
+        report.missing = rebuilt
+            .entries()
+            .filter(|entry| !index.contains_key(entry.key))
+            .map(|entry| entry.key.to_vec())
+            .collect();
+
+        Ok(report)
+    }
+
+    /// Forces everything written so far to stable storage. Waits for any write or compaction in
+    /// progress, so once it returns the log on disk is complete.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.lock().unwrap().sync_all()
+    }
+
+    #[instrument(skip_all, fields(bytes_before = Empty, bytes_after = Empty, keys = Empty))]
+    pub fn compact(&self) -> io::Result<CompactionReport> {
+        let report = self.rewrite(Format::V2)?;
+        Span::current()
+            .record("bytes_before", report.bytes_before)
+            .record("bytes_after", report.bytes_after)
+            .record("keys", report.keys);
+        Ok(report)
+    }
+
+    /// Rewrites the live entries of a log currently laid out in `source` into a fresh v2 log.
+    fn rewrite(&self, source: Format) -> io::Result<CompactionReport> {
+        let mut timer = self.timer(SlowOpKind::Compact);
+        let mut file = self.file.lock().unwrap();
+        timer.phase("lock");
+        // The rewritten log has none of the records the archive might still be missing.
+        self.archive_pending()?;
+        let started = Instant::now();
+        let bytes_before = *self.file_size.lock().unwrap();
+        if let Some(observer) = &self.options.observer {
+            observer.on_compact_start(bytes_before);
+        }
+
+        let mut tmp_file = BufWriter::new(self.storage.create_temp()?);
+
+        let now = now_millis();
+        let entries: Vec<_> = self
+            .index
+            .read()
+            .unwrap()
+            .entries()
+            .filter(|e| e.expires_at.is_none_or(|at| at > now))
+            .map(|e| {
+                (
+                    e.key.to_vec(),
+                    e.base.cloned(),
+                    e.chunks.to_vec(),
+                    e.operands.to_vec(),
+                )
+            })
+            .collect();
+
+        let mut new_index = Index::new();
+        let keys = entries.len();
+
+        tmp_file.write_all(FORMAT_V2_MAGIC)?;
+        let mut new_file_size = FORMAT_V2_MAGIC.len() as u64;
+
+        // Dropping tombstones can drop the newest sequence number, so a leading tombstone for the
+        // empty key carries it into the new log. Live entries written after it are unaffected.
+        let (marker, _) = codec::encode(&DataFileEntry {
+            tstamp: now_millis(),
+            seq: self.last_sequence(),
+            ..DataFileEntry::default()
+        });
+        tmp_file.write_all(&marker)?;
+        new_file_size += marker.len() as u64;
+
+        for (key, base, chunks, operands) in entries {
+            let mut entry = match base {
+                Some(log_index) => {
+                    self.decode_at(source, &read_payload(&mut file, &log_index)?, &log_index)?
+                }
+                None => DataFileEntry {
+                    key: key.clone(),
+                    ..DataFileEntry::default()
+                },
+            };
+
+            let mut new_chunks = Vec::with_capacity(chunks.len());
+            if operands.is_empty() {
+                // Pieces move one record at a time, so a chunked value is never held whole.
+                for log_index in &chunks {
+                    let piece =
+                        self.decode_at(source, &read_payload(&mut file, log_index)?, log_index)?;
+                    let (frame, prefix_len) = codec::encode(&piece);
+                    tmp_file.write_all(&frame)?;
+
+                    new_chunks.push(LogIndex {
+                        pos: new_file_size + prefix_len,
+                        len: frame.len() as u64 - prefix_len,
+                    });
+                    new_file_size += frame.len() as u64;
+                }
+            } else if !chunks.is_empty() {
+                let mut value = Vec::new();
+                for log_index in &chunks {
+                    let piece =
+                        self.decode_at(source, &read_payload(&mut file, log_index)?, log_index)?;
+                    value.extend(piece.value.unwrap_or_default());
+                }
+                value.extend(entry.value.take().unwrap_or_default());
+                entry.value = Some(value);
+                entry.chunk = ChunkRole::Whole;
+            }
+
+            for log_index in &operands {
+                let operand =
+                    self.decode_at(source, &read_payload(&mut file, log_index)?, log_index)?;
+                let merged = self.apply_merge(
+                    &key,
+                    entry.value.as_deref(),
+                    &operand.value.unwrap_or_default(),
+                )?;
+                entry.value = Some(merged);
+                entry.tstamp = operand.tstamp;
+                entry.seq = operand.seq;
+            }
+
+            let (frame, prefix_len) = codec::encode(&entry);
+            tmp_file.write_all(&frame)?;
+
+            new_index.insert_chunked(
+                key.clone(),
+                LogIndex {
+                    pos: new_file_size + prefix_len,
+                    len: frame.len() as u64 - prefix_len,
+                },
+                new_chunks,
+            );
+            new_index.set_expiry(&key, entry.expires_at);
+            new_file_size += frame.len() as u64;
+        }
+
+        tmp_file.flush()?;
+        timer.phase("copy");
+        self.sync_if_needed(tmp_file.get_ref().as_ref())?;
+        drop(tmp_file);
+        timer.phase("sync");
+
+        self.reader_pool.lock().unwrap().clear();
+
+        let mut index = self.index.write().unwrap();
+
+        self.storage.replace_log()?;
+        *file = self.storage.open_log()?;
+        *index = new_index;
+        *self.file_size.lock().unwrap() = new_file_size;
+        if let Some(archive) = &self.archive {
+            archive.lock().unwrap().pos = new_file_size;
+        }
+
+        let mut pool = self.reader_pool.lock().unwrap();
+        for _ in 0..4 {
+            if let Ok(r) = self.storage.open_reader() {
+                pool.push(r);
+            }
+        }
+
+        let report = CompactionReport {
+            finished_at: now_millis(),
+            duration: started.elapsed(),
+            bytes_before,
+            bytes_after: new_file_size,
+            keys,
+        };
+        *self.last_compaction.lock().unwrap() = Some(report);
+        // Compaction drops the tombstones these versions belong to.
+        self.deletes.lock().unwrap().clear();
+        timer.phase("swap");
+        self.record_slow(timer, None, None);
+        if let Some(observer) = &self.options.observer {
+            observer.on_compact_end(&report);
+        }
+
+        Ok(report)
+    }
+}
+
+/// Reads one value out of the log record by record; returned by `Engine::open_value`.
+pub struct ValueReader {
+    source: ValueSource,
+}
+
+enum ValueSource {
+    Log {
+        file: Log,
+        /// Records still to read, last first.
+        records: Vec<LogIndex>,
+        /// Value bytes left in the current record.
+        remaining: u64,
+    },
+    Memory(io::Cursor<Vec<u8>>),
+}
+
+impl ValueReader {
+    fn log(file: Log, mut records: Vec<LogIndex>) -> Self {
+        records.reverse();
+        ValueReader {
+            source: ValueSource::Log {
+                file,
+                records,
+                remaining: 0,
+            },
+        }
+    }
+
+    fn memory(value: Vec<u8>) -> Self {
+        ValueReader {
+            source: ValueSource::Memory(io::Cursor::new(value)),
+        }
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (file, records, remaining) = match &mut self.source {
+            ValueSource::Memory(cursor) => return cursor.read(buf),
+            ValueSource::Log {
+                file,
+                records,
+                remaining,
+            } => (file, records, remaining),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while *remaining == 0 {
+            let Some(log_index) = records.pop() else {
+                return Ok(0);
+            };
+            let head = read_head_at(file, &log_index)?;
+            *remaining = head
+                .value_len
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "indexed tombstone"))?;
+            file.seek(SeekFrom::Start(log_index.pos + head.len))?;
+        }
+
+        let n = file.take(*remaining).read(buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "value truncated",
+            ));
+        }
+        *remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+struct ScannedRecord {
+    key: Vec<u8>,
+    kind: RecordKind,
+    seq: u64,
+}
+
+enum RecordKind {
+    /// A full value, or the last piece of one preceded by `pieces` chunk records.
+    Value {
+        pieces: u64,
+        expires_at: Option<i64>,
+    },
+    Piece,
+    Merge,
+    Tombstone,
+}
+
+impl RecordKind {
+    fn new(has_value: bool, merge: bool, chunk: ChunkRole, expires_at: Option<i64>) -> Self {
+        match (has_value, merge, chunk) {
+            (false, _, _) => RecordKind::Tombstone,
+            (true, true, _) => RecordKind::Merge,
+            (true, false, ChunkRole::Piece) => RecordKind::Piece,
+            (true, false, ChunkRole::Last(pieces)) => RecordKind::Value { pieces, expires_at },
+            (true, false, ChunkRole::Whole) => RecordKind::Value {
+                pieces: 0,
+                expires_at,
+            },
+        }
+    }
+}
+
+/// Reads the records at `records` (which must be in log order) and returns what the index needs
+/// to know about each of them.
+fn read_keys(log: Log, format: Format, records: &[LogIndex]) -> io::Result<Vec<ScannedRecord>> {
+    let mut reader = BufReader::new(log);
+    let mut keys = Vec::with_capacity(records.len());
+    let mut cur = None;
+
+    for record in records {
+        match cur {
+            Some(cur) => reader.seek_relative((record.pos - cur) as i64)?,
+            None => {
+                reader.seek(SeekFrom::Start(record.pos))?;
+            }
+        }
+
+        cur = Some(record.pos + record.len);
+
+        // v2 heads carry everything the index needs; the value bytes are only read to check the
+        // record's checksum.
+        let (key, seq, has_value, merge, chunk, expires_at) = match format {
+            Format::V1 => {
+                let mut data = vec![0u8; record.len as usize];
+                reader.read_exact(&mut data)?;
+                let entry = codec::decode(format, &data)?;
+                (
+                    entry.key,
+                    entry.seq,
+                    entry.value.is_some(),
+                    entry.merge,
+                    entry.chunk,
+                    entry.expires_at,
+                )
+            }
+            Format::V2 => {
+                let mut data = vec![0u8; record.len as usize];
+                reader.read_exact(&mut data)?;
+                codec::check(&data).map_err(|e| {
+                    io::Error::new(e.kind(), format!("record at offset {}: {}", record.pos, e))
+                })?;
+                let head = codec::read_head(&mut data.as_slice())?;
+                (
+                    head.key,
+                    head.seq,
+                    head.value_len.is_some(),
+                    head.merge,
+                    head.chunk,
+                    head.expires_at,
+                )
+            }
+        };
+
+        let kind = RecordKind::new(has_value, merge, chunk, expires_at);
+        keys.push(ScannedRecord { key, kind, seq });
+    }
+
+    Ok(keys)
+}
+
+/// Applies a record found scanning the log to `index`, as loading does. `pending` holds the
+/// pieces of chunked values whose last piece is yet to be seen.
+fn index_record(
+    index: &mut Index,
+    pending: &mut HashMap<Vec<u8>, Vec<LogIndex>>,
+    log_index: &LogIndex,
+    key: Vec<u8>,
+    kind: RecordKind,
+) -> io::Result<()> {
+    match kind {
+        RecordKind::Value { pieces, expires_at } => {
+            let mut chunks = pending.remove(&key).unwrap_or_default();
+            let pieces = pieces as usize;
+            if chunks.len() < pieces {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunked value is missing pieces",
+                ));
+            }
+            let chunks = chunks.split_off(chunks.len() - pieces);
+            index.insert_chunked(key.clone(), log_index.clone(), chunks);
+            index.set_expiry(&key, expires_at);
+        }
+        RecordKind::Piece => pending.entry(key).or_default().push(log_index.clone()),
+        RecordKind::Merge => index.push_operand(key, log_index.clone()),
+        RecordKind::Tombstone => {
+            pending.remove(&key);
+            index.remove(&key);
+        }
+    }
+    Ok(())
+}
+
+/// Encodes `entry` onto `buf`, which is to be appended at `start`, and returns where its payload
+/// will be.
+fn push_frame(buf: &mut Vec<u8>, start: u64, entry: &DataFileEntry) -> LogIndex {
+    let (frame, prefix_len) = codec::encode(entry);
+    let log_index = LogIndex {
+        pos: start + buf.len() as u64 + prefix_len,
+        len: frame.len() as u64 - prefix_len,
+    };
+    buf.extend_from_slice(&frame);
+    log_index
+}
+
+/// Writes the `head` from `codec::encode_streamed`, `len` value bytes from `reader` and the
+/// checksum after them. Returns the length of the whole frame.
+fn write_streamed(
+    file: &mut Log,
+    head: &[u8],
+    prefix_len: u64,
+    reader: impl Read,
+    len: u64,
+) -> io::Result<u64> {
+    file.write_all(head)?;
+    let mut value = codec::Checksummed::new(head, prefix_len, reader.take(len));
+    let copied = io::copy(&mut value, file)?;
+    if copied < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "reader ended before the declared length",
+        ));
+    }
+    file.write_all(&value.checksum())?;
+    file.flush()?;
+    Ok(head.len() as u64 + len + CHECKSUM_LEN)
+}
+
+/// Reads just the head of the record at `log_index`. The value stays on disk.
+fn read_head_at(file: &mut Log, log_index: &LogIndex) -> io::Result<codec::RecordHead> {
+    file.seek(SeekFrom::Start(log_index.pos))?;
+    codec::read_head(&mut BufReader::new(&mut *file).take(log_index.len))
+}
+
+/// Calls `visit` with the head and payload position of each record of a v2 log up to `end`, in
+/// log order, without reading values.
+fn scan_heads(
+    log: impl Read + Seek,
+    end: u64,
+    mut visit: impl FnMut(codec::RecordHead, LogIndex),
+) -> io::Result<()> {
+    let mut reader = BufReader::new(log);
+    let mut pos = Format::V2.header_len();
+    reader.seek(SeekFrom::Start(pos))?;
+    while pos < end {
+        let Some((len, prefix_len)) = codec::read_prefix(Format::V2, &mut reader)? else {
+            break;
+        };
+        let head = codec::read_head(&mut (&mut reader).take(len))?;
+        let rest = len
+            .checked_sub(head.len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "record overrun"))?;
+        reader.seek_relative(rest as i64)?;
+        // A compacted log opens with a tombstone for the empty key carrying the sequence number
+        // forward; it is not a write.
+        let marker =
+            pos == Format::V2.header_len() && head.key.is_empty() && head.value_len.is_none();
+        let log_index = LogIndex {
+            pos: pos + prefix_len,
+            len,
+        };
+        pos += prefix_len + len;
+        if !marker {
+            visit(head, log_index);
+        }
+    }
+    Ok(())
+}
+
+/// Sequence number carried by the marker a compacted log opens with, found in the log's first
+/// bytes, or 0 if the log was never compacted.
+fn compaction_marker(head: &[u8]) -> io::Result<u64> {
+    let Some(mut records) = head.strip_prefix(FORMAT_V2_MAGIC) else {
+        return Ok(0);
+    };
+    if codec::read_prefix(Format::V2, &mut records)?.is_none() {
+        return Ok(0);
+    }
+    let record = codec::read_head(&mut records)?;
+    let marker = record.key.is_empty() && record.value_len.is_none();
+    Ok(if marker { record.seq } else { 0 })
+}
+
+/// A record for one key found by `Engine::key_records`.
+struct KeyRecord {
+    head: codec::RecordHead,
+    log_index: LogIndex,
+    /// The earlier records of a value written in chunks.
+    pieces: Vec<LogIndex>,
+}
+
+impl KeyRecord {
+    fn kind(&self) -> ChangeKind {
+        match (self.head.value_len, self.head.merge) {
+            (None, _) => ChangeKind::Del,
+            (Some(_), true) => ChangeKind::Merge,
+            (Some(_), false) => ChangeKind::Set,
+        }
+    }
+
+    /// The value set or merge operand, or `None` for a delete.
+    fn read_value(&self, log: &mut Log) -> io::Result<Option<Vec<u8>>> {
+        if self.head.value_len.is_none() {
+            return Ok(None);
+        }
+        let mut value = Vec::new();
+        for log_index in self.pieces.iter().chain([&self.log_index]) {
+            let entry = codec::decode(Format::V2, &read_payload(log, log_index)?)?;
+            value.extend(entry.value.unwrap_or_default());
+        }
+        Ok(Some(value))
+    }
+}
+
+fn read_payload(reader: &mut Log, log_index: &LogIndex) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(log_index.pos))?;
+    let mut data = vec![0u8; log_index.len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn no_merge_operator() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "no merge operator configured")
+}
+
+fn value_too_large(max: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        format!("value is larger than the {}-byte limit", max),
+    )
+}
+
+/// Whether `key` holds one of the engine's own records, under `RESERVED_PREFIXES`. Servers refuse
+/// to write such keys for their clients, who could otherwise forge or drop the records.
+pub fn is_reserved(key: &[u8]) -> bool {
+    RESERVED_PREFIXES
+        .iter()
+        .any(|prefix| key.starts_with(prefix))
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Expiry time for a value written now that should live for `ttl`.
+fn expiry(ttl: Duration) -> i64 {
+    now_millis().saturating_add(ttl.as_millis().min(i64::MAX as u128) as i64)
+}
+
+fn parse_counter(value: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "value is not an integer"))
+}
+
+/// The `(seq, node)` version `apply_remote` stored with a write, if it came from a peer.
+fn parse_origin(meta: &Metadata) -> Option<Version> {
+    let (seq, node) = meta.get(ORIGIN_META)?.split_once(':')?;
+    Some((seq.parse().ok()?, node.parse().ok()?))
+}
+
+/// The start of the keys recording membership of `tag`, to be followed by the tagged key.
+/// The sequence number and key of a request id's record, written by `set_once` as `{seq} {key}`.
+fn parse_request_record(record: &[u8]) -> Option<(u64, &[u8])> {
+    let space = record.iter().position(|&b| b == b' ')?;
+    let seq = std::str::from_utf8(&record[..space]).ok()?.parse().ok()?;
+    Some((seq, &record[space + 1..]))
+}
+
+fn tag_prefix(tag: &str) -> io::Result<Vec<u8>> {
+    if tag.is_empty() || tag.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a tag must be non-empty and free of NUL bytes",
+        ));
+    }
+    Ok([TAG_PREFIX, tag.as_bytes(), b"\0"].concat())
+}
+
+/// The live keys whose membership records start with `prefix`, from `tag_prefix`.
+fn tagged_keys<'a>(index: &'a Index, prefix: &'a [u8], now: i64) -> impl Iterator<Item = &'a [u8]> {
+    index
+        .keys(Bound::Included(prefix), Bound::Unbounded)
+        .take_while(move |member| member.starts_with(prefix))
+        .filter(move |member| is_live(index, member, now))
+        .map(|member| &member[prefix.len()..])
+        .filter(move |key| is_live(index, key, now))
+}
+
+/// Whether `key` is in the index and has not expired by `now`.
+fn is_live(index: &Index, key: &[u8], now: i64) -> bool {
+    index.contains_key(key) && index.expires_at(key).is_none_or(|at| at > now)
+}
+
+/// Whether `key` is live and one of the user's, so listings and prefix deletes include it.
+fn is_listed(index: &Index, key: &[u8], now: i64) -> bool {
+    !is_reserved(key) && is_live(index, key, now)
 }
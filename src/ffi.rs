@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::{io, ptr, slice};
+
+use crate::Engine;
+
+pub const KV_OK: c_int = 0;
+/// `kv_get` found no value for the key.
+pub const KV_NOT_FOUND: c_int = 1;
+/// A null pointer where one is not allowed, or a path that is not UTF-8.
+pub const KV_ERR_INVALID_ARGUMENT: c_int = -1;
+/// Reading or writing the log failed.
+pub const KV_ERR_IO: c_int = -2;
+/// The log holds a record that cannot be decoded.
+pub const KV_ERR_CORRUPT: c_int = -3;
+/// The key or value is over the engine's size limit.
+pub const KV_ERR_TOO_LARGE: c_int = -4;
+/// The engine panicked; the handle should not be used again.
+pub const KV_ERR_PANIC: c_int = -5;
+
+/// An open engine, the handle of the C API that `include/kv.h` declares. Handles are safe to
+/// share between threads.
+///
+/// Every function returns `KV_OK` or another status code, and after an error
+/// `kv_last_error_message` describes it.
+pub struct KvEngine(Engine);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs `work`, turning its error or panic into a status code and recording the message.
+fn guard(work: impl FnOnce() -> io::Result<c_int>) -> c_int {
+    let (code, message) = match panic::catch_unwind(AssertUnwindSafe(work)) {
+        Ok(Ok(code)) => return code,
+        Ok(Err(e)) => {
+            let code = match e.kind() {
+                io::ErrorKind::InvalidInput => KV_ERR_INVALID_ARGUMENT,
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => KV_ERR_CORRUPT,
+                io::ErrorKind::FileTooLarge => KV_ERR_TOO_LARGE,
+                _ => KV_ERR_IO,
+            };
+            (code, e.to_string())
+        }
+        Err(_) => (KV_ERR_PANIC, "the engine panicked".to_string()),
+    };
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+/// The `len` bytes at `data`, which may be null only when `len` is 0.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> io::Result<&'a [u8]> {
+    match (data.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(invalid("null buffer with a non-zero length")),
+        (false, len) => Ok(unsafe { slice::from_raw_parts(data, len) }),
+    }
+}
+
+unsafe fn handle<'a>(engine: *const KvEngine) -> io::Result<&'a Engine> {
+    match unsafe { engine.as_ref() } {
+        Some(engine) => Ok(&engine.0),
+        None => Err(invalid("null engine")),
+    }
+}
+
+/// Opens the log at `path`, creating it if it does not exist, and stores the handle in `*out`.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_open(path: *const c_char, out: *mut *mut KvEngine) -> c_int {
+    guard(|| {
+        if path.is_null() || out.is_null() {
+            return Err(invalid("null path or output pointer"));
+        }
+        let path = unsafe { CStr::from_ptr(path) }
+            .to_str()
+            .map_err(|_| invalid("path is not UTF-8"))?;
+        let engine = Engine::load(path)?;
+        unsafe { *out = Box::into_raw(Box::new(KvEngine(engine))) };
+        Ok(KV_OK)
+    })
+}
+
+/// Looks up a key. On `KV_OK`, `*value` and `*value_len` hold a copy of the value, which the
+/// caller releases with `kv_free`; on `KV_NOT_FOUND` they are set to null and 0.
+///
+/// # Safety
+///
+/// `engine` must come from `kv_open`, `key` must point to `key_len` readable bytes, and
+/// `value` and `value_len` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_get(
+    engine: *const KvEngine,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        if value.is_null() || value_len.is_null() {
+            return Err(invalid("null output pointer"));
+        }
+        let found = unsafe { handle(engine)?.get(bytes(key, key_len)?)? };
+        let (data, len, code) = match found {
+            Some(found) => {
+                let len = found.len();
+                (Box::into_raw(found.into_boxed_slice()).cast(), len, KV_OK)
+            }
+            None => (ptr::null_mut(), 0, KV_NOT_FOUND),
+        };
+        unsafe {
+            *value = data;
+            *value_len = len;
+        }
+        Ok(code)
+    })
+}
+
+/// Stores `value` under `key`.
+///
+/// # Safety
+///
+/// `engine` must come from `kv_open`, and `key` and `value` must point to `key_len` and
+/// `value_len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_set(
+    engine: *const KvEngine,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    guard(|| unsafe {
+        handle(engine)?.set(bytes(key, key_len)?, bytes(value, value_len)?)?;
+        Ok(KV_OK)
+    })
+}
+
+/// Deletes `key`. Deleting a missing key is not an error.
+///
+/// # Safety
+///
+/// `engine` must come from `kv_open` and `key` must point to `key_len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_del(engine: *const KvEngine, key: *const u8, key_len: usize) -> c_int {
+    guard(|| unsafe {
+        handle(engine)?.del(bytes(key, key_len)?)?;
+        Ok(KV_OK)
+    })
+}
+
+/// Syncs the log and frees the handle, which must not be used afterwards. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` must come from `kv_open` and not have been closed already, and no other thread may
+/// be using it.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_close(engine: *mut KvEngine) -> c_int {
+    guard(|| {
+        if engine.is_null() {
+            return Ok(KV_OK);
+        }
+        let engine = unsafe { Box::from_raw(engine) };
+        engine.0.sync()?;
+        Ok(KV_OK)
+    })
+}
+
+/// Releases a value returned by `kv_get`. Null is ignored.
+///
+/// # Safety
+///
+/// `value` and `value_len` must be exactly what `kv_get` returned, and released only once.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kv_free(value: *mut u8, value_len: usize) {
+    if !value.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(value, value_len)) });
+    }
+}
+
+/// The message of the last error on the calling thread, or null if there was none. It stays
+/// valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn kv_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::constants::{HOT_KEY_CANDIDATES, HOT_KEY_SKETCH_DEPTH, HOT_KEY_SKETCH_WIDTH};
+use crate::types::HotKey;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Approximate per-key traffic counter.
+///
+/// Reads and writes are counted in two count-min sketches, so memory stays fixed no matter how
+/// many distinct keys are touched and counts can only be overestimated. A bounded candidate set
+/// remembers which keys currently have the highest estimates so they can be listed.
+#[derive(Debug)]
+pub struct HotKeyTracker {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    candidates: HashMap<Box<[u8]>, u64>,
+}
+
+impl Default for HotKeyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HotKeyTracker {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0; HOT_KEY_SKETCH_DEPTH * HOT_KEY_SKETCH_WIDTH],
+            writes: vec![0; HOT_KEY_SKETCH_DEPTH * HOT_KEY_SKETCH_WIDTH],
+            candidates: HashMap::with_capacity(HOT_KEY_CANDIDATES),
+        }
+    }
+
+    pub fn record(&mut self, key: &[u8], access: Access) {
+        let slots = slots(key);
+        let table = match access {
+            Access::Read => &mut self.reads,
+            Access::Write => &mut self.writes,
+        };
+        for slot in slots {
+            table[slot] = table[slot].saturating_add(1);
+        }
+
+        let total = self.estimate(&slots, Access::Read) + self.estimate(&slots, Access::Write);
+        if let Some(count) = self.candidates.get_mut(key) {
+            *count = total;
+            return;
+        }
+
+        if self.candidates.len() < HOT_KEY_CANDIDATES {
+            self.candidates.insert(key.into(), total);
+            return;
+        }
+
+        let coldest = self
+            .candidates
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(k, count)| (k.clone(), *count));
+        if let Some((coldest, count)) = coldest
+            && total > count
+        {
+            self.candidates.remove(&coldest);
+            self.candidates.insert(key.into(), total);
+        }
+    }
+
+    /// The `n` keys with the most estimated traffic, busiest first.
+    pub fn top(&self, n: usize) -> Vec<HotKey> {
+        let mut hot: Vec<HotKey> = self
+            .candidates
+            .keys()
+            .map(|key| {
+                let slots = slots(key);
+                HotKey {
+                    key: key.to_vec(),
+                    reads: self.estimate(&slots, Access::Read),
+                    writes: self.estimate(&slots, Access::Write),
+                }
+            })
+            .collect();
+
+        hot.sort_by(|a, b| {
+            (b.reads + b.writes)
+                .cmp(&(a.reads + a.writes))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        hot.truncate(n);
+        hot
+    }
+
+    fn estimate(&self, slots: &[usize; HOT_KEY_SKETCH_DEPTH], access: Access) -> u64 {
+        let table = match access {
+            Access::Read => &self.reads,
+            Access::Write => &self.writes,
+        };
+        slots
+            .iter()
+            .map(|&slot| u64::from(table[slot]))
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+fn slots(key: &[u8]) -> [usize; HOT_KEY_SKETCH_DEPTH] {
+    let mut slots = [0; HOT_KEY_SKETCH_DEPTH];
+    for (row, slot) in slots.iter_mut().enumerate() {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        *slot = row * HOT_KEY_SKETCH_WIDTH + (hasher.finish() as usize % HOT_KEY_SKETCH_WIDTH);
+    }
+    slots
+}
@@ -0,0 +1,325 @@
+use std::io;
+use std::time::Duration;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use futures_util::{Stream, StreamExt, stream};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Change, ChangeKind};
+
+const DEFAULT_RETRIES: u32 = 3;
+/// Wait before the first retry; it doubles for each one after.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// An async client for the HTTP API. Keys and values travel base64-encoded, so any bytes work.
+///
+/// Requests that fail to connect, time out, or are answered with `429` or `503` (loading,
+/// maintenance, rate limiting) are retried with exponential backoff. Other failures map onto
+/// `io::ErrorKind`: `InvalidInput` for `400`, `PermissionDenied` for `401` and `403`,
+/// `FileTooLarge` for `413`, `QuotaExceeded` for `429`, `ResourceBusy` for `503`,
+/// `StorageFull` for `507`, and `Other` for the rest, with the server's message.
+#[derive(Debug, Clone)]
+pub struct KvClient {
+    http: reqwest::Client,
+    base_url: String,
+    credential: Option<String>,
+    retries: u32,
+}
+
+#[derive(Serialize)]
+struct SetBody {
+    key: String,
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SetResult {
+    existed: bool,
+}
+
+#[derive(Deserialize)]
+struct DelResult {
+    deleted: usize,
+}
+
+#[derive(Deserialize)]
+struct ScanEntry {
+    key: String,
+    value: Option<String>,
+    value_base64: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChangeEvent {
+    seq: u64,
+    op: String,
+    key: String,
+}
+
+impl KvClient {
+    /// A client for the server at `base_url`, such as `http://127.0.0.1:8080`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        KvClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            credential: None,
+            retries: DEFAULT_RETRIES,
+        }
+    }
+
+    /// Sends an API key, admin token or JWT with every request.
+    pub fn with_credential(mut self, credential: impl Into<String>) -> Self {
+        self.credential = Some(credential.into());
+        self
+    }
+
+    /// How many times a failed request is retried [default: 3].
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub async fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let url = format!("{}/get/{}", self.base_url, URL_SAFE_NO_PAD.encode(key));
+        let response = self
+            .send(|| self.http.get(&url).query(&[("encoding", "base64")]))
+            .await;
+        match response {
+            Ok(response) => decode(&response.text().await.map_err(io::Error::other)?).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn set(&self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.put(key, value, None).await
+    }
+
+    /// Stores `value` under `key` until `ttl` has passed, to the second.
+    pub async fn set_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> io::Result<()> {
+        self.put(key, value, Some(ttl.as_secs())).await
+    }
+
+    pub async fn del(&self, key: &[u8]) -> io::Result<()> {
+        let url = format!("{}/del/{}", self.base_url, URL_SAFE_NO_PAD.encode(key));
+        self.send(|| self.http.delete(&url).query(&[("encoding", "base64")]))
+            .await
+            .map(drop)
+    }
+
+    /// The values of `keys` in order, read as one consistent view; `None` for misses.
+    pub async fn batch_get(&self, keys: &[&[u8]]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let body: Vec<String> = keys.iter().map(|key| STANDARD.encode(key)).collect();
+        let values: Vec<Option<String>> = self.json(|| self.post("/batch/get").json(&body)).await?;
+        values
+            .into_iter()
+            .map(|value| value.as_deref().map(decode).transpose())
+            .collect()
+    }
+
+    /// Writes every pair in one engine batch and returns whether each key existed.
+    pub async fn batch_set(&self, pairs: &[(&[u8], &[u8])]) -> io::Result<Vec<bool>> {
+        let body: Vec<SetBody> = pairs
+            .iter()
+            .map(|(key, value)| SetBody {
+                key: STANDARD.encode(key),
+                value: STANDARD.encode(value),
+                ttl_secs: None,
+            })
+            .collect();
+        let results: Vec<SetResult> = self.json(|| self.post("/batch/set").json(&body)).await?;
+        Ok(results.into_iter().map(|result| result.existed).collect())
+    }
+
+    /// Deletes `keys` atomically and returns how many of them existed.
+    pub async fn batch_del(&self, keys: &[&[u8]]) -> io::Result<usize> {
+        let body: Vec<String> = keys.iter().map(|key| STANDARD.encode(key)).collect();
+        let result: DelResult = self.json(|| self.post("/batch/del").json(&body)).await?;
+        Ok(result.deleted)
+    }
+
+    /// Up to `limit` pairs under `prefix` in key order. `/scan` takes and returns keys as text,
+    /// so keys that are not UTF-8 come back lossily.
+    pub async fn scan(&self, prefix: &str, limit: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let url = format!("{}/scan", self.base_url);
+        let limit = limit.to_string();
+        let entries: Vec<ScanEntry> = self
+            .json(|| {
+                self.http
+                    .get(&url)
+                    .query(&[("prefix", prefix), ("limit", limit.as_str())])
+            })
+            .await?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let value = match (entry.value, entry.value_base64) {
+                    (Some(value), _) => value.into_bytes(),
+                    (None, Some(value)) => decode(&value)?,
+                    (None, None) => Vec::new(),
+                };
+                Ok((entry.key, value))
+            })
+            .collect()
+    }
+
+    /// The changes under `prefix` after `since_seq` that the log still holds, then new ones as
+    /// they happen, from `/changes`. The stream ends if the client falls too far behind; resume
+    /// with the last sequence number seen.
+    pub async fn watch(
+        &self,
+        prefix: &[u8],
+        since_seq: u64,
+    ) -> io::Result<impl Stream<Item = io::Result<Change>> + use<>> {
+        let url = format!("{}/changes", self.base_url);
+        let query = [
+            ("prefix", STANDARD.encode(prefix)),
+            ("since_seq", since_seq.to_string()),
+            ("encoding", "base64".to_string()),
+        ];
+        let response = self.send(|| self.http.get(&url).query(&query)).await?;
+        let events = (Box::pin(response.bytes_stream()), Vec::new());
+        Ok(stream::try_unfold(events, next_change))
+    }
+
+    async fn put(&self, key: &[u8], value: &[u8], ttl_secs: Option<u64>) -> io::Result<()> {
+        let body = SetBody {
+            key: STANDARD.encode(key),
+            value: STANDARD.encode(value),
+            ttl_secs,
+        };
+        self.send(|| self.post("/set").json(&body)).await.map(drop)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http
+            .post(format!("{}{}", self.base_url, path))
+            .query(&[("encoding", "base64")])
+    }
+
+    async fn json<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+    ) -> io::Result<T> {
+        let response = self.send(request).await?;
+        response
+            .json()
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Sends the request `request` builds, retrying as described on `KvClient`, and turns an
+    /// unsuccessful status into an error.
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> io::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let mut builder = request();
+            if let Some(credential) = &self.credential {
+                builder = builder.bearer_auth(credential);
+            }
+            let (error, retry) = match builder.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let message = response.text().await.unwrap_or_default();
+                    let retry = matches!(
+                        status,
+                        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                    );
+                    (status_error(status, message), retry)
+                }
+                Err(e) => {
+                    let kind = if e.is_timeout() {
+                        io::ErrorKind::TimedOut
+                    } else if e.is_connect() {
+                        io::ErrorKind::ConnectionRefused
+                    } else {
+                        io::ErrorKind::Other
+                    };
+                    let retry = kind != io::ErrorKind::Other;
+                    (io::Error::new(kind, e), retry)
+                }
+            };
+            if !retry || attempt >= self.retries {
+                return Err(error);
+            }
+            tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn status_error(status: StatusCode, message: String) -> io::Error {
+    let kind = match status {
+        StatusCode::BAD_REQUEST => io::ErrorKind::InvalidInput,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => io::ErrorKind::PermissionDenied,
+        StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+        StatusCode::PAYLOAD_TOO_LARGE => io::ErrorKind::FileTooLarge,
+        StatusCode::TOO_MANY_REQUESTS => io::ErrorKind::QuotaExceeded,
+        StatusCode::SERVICE_UNAVAILABLE => io::ErrorKind::ResourceBusy,
+        StatusCode::INSUFFICIENT_STORAGE => io::ErrorKind::StorageFull,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {}", status, message))
+}
+
+fn decode(text: &str) -> io::Result<Vec<u8>> {
+    STANDARD
+        .decode(text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads Server-Sent Events from `bytes` until one carries a change.
+async fn next_change<S, B>(
+    (mut bytes, mut buffer): (S, Vec<u8>),
+) -> io::Result<Option<(Change, (S, Vec<u8>))>>
+where
+    S: Stream<Item = reqwest::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    loop {
+        if let Some(end) = buffer.windows(2).position(|pair| pair == b"\n\n") {
+            let event: Vec<u8> = buffer.drain(..end + 2).collect();
+            if let Some(change) = parse_event(&event)? {
+                return Ok(Some((change, (bytes, buffer))));
+            }
+            continue;
+        }
+        match bytes.next().await {
+            Some(chunk) => buffer.extend_from_slice(chunk.map_err(io::Error::other)?.as_ref()),
+            None => return Ok(None),
+        }
+    }
+}
+
+/// The change in one Server-Sent Event, or `None` for an event without data.
+fn parse_event(event: &[u8]) -> io::Result<Option<Change>> {
+    let Some(data) = event
+        .split(|&b| b == b'\n')
+        .find_map(|line| line.strip_prefix(b"data: "))
+    else {
+        return Ok(None);
+    };
+    let event: ChangeEvent =
+        serde_json::from_slice(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let kind = match event.op.as_str() {
+        "set" => ChangeKind::Set,
+        "del" => ChangeKind::Del,
+        "merge" => ChangeKind::Merge,
+        op => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown change op {}", op),
+            ));
+        }
+    };
+    Ok(Some(Change {
+        seq: event.seq,
+        key: decode(&event.key)?,
+        kind,
+    }))
+}
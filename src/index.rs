@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::ops::Bound;
+
+use crate::types::LogIndex;
+
+/// In-memory key directory mapping each live key to its record in the log.
+///
+/// Keys are kept sorted so they can be listed in pages and scanned by prefix or range. They are
+/// held as boxed slices rather than `Vec<u8>`, dropping the spare capacity word and any
+/// over-allocation left behind by the decoder, which keeps the per-key cost at roughly 60 bytes
+/// of tree space plus one allocation for the key.
+///
+/// Merge operands appended since a key's last full value, and the leading pieces of values split
+/// into chunks, are kept in separate tables so keys that use neither pay nothing for them. A key
+/// may have operands without a base value, in which case its entry in the tree is `None`.
+/// Expiry times live in a third table; expired keys stay in the index until compaction, and the
+/// engine hides them from reads.
+///
+/// The index keeps a running estimate of the heap it uses, from the sizes above rather than the
+/// allocator, so it can be capped with `EngineOptions::max_index_memory`.
+#[derive(Debug, Default)]
+pub struct Index {
+    map: BTreeMap<Box<[u8]>, Option<LogIndex>>,
+    operands: HashMap<Box<[u8]>, Vec<LogIndex>>,
+    chunks: HashMap<Box<[u8]>, Vec<LogIndex>>,
+    expiries: HashMap<Box<[u8]>, i64>,
+    bytes: usize,
+}
+
+/// Estimated bytes of tree space per key, besides the key itself.
+const TREE_ENTRY_BYTES: usize = 60;
+/// Estimated bytes per entry in one of the side tables, besides the key and its records.
+const TABLE_ENTRY_BYTES: usize = 48;
+
+/// A live key as seen by compaction.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexEntry<'a> {
+    pub key: &'a [u8],
+    pub base: Option<&'a LogIndex>,
+    pub chunks: &'a [LogIndex],
+    pub operands: &'a [LogIndex],
+    pub expires_at: Option<i64>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&LogIndex> {
+        self.map.get(key)?.as_ref()
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Approximate bytes of memory the index uses.
+    pub fn memory_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Approximate bytes a new key adds to the index when it is first written with a value in
+    /// one record.
+    pub fn new_key_bytes(key: &[u8]) -> usize {
+        key.len() + TREE_ENTRY_BYTES
+    }
+
+    /// Approximate bytes `key` takes up across the tree and the side tables.
+    fn key_bytes(&self, key: &[u8]) -> usize {
+        let mut bytes = 0;
+        if self.map.contains_key(key) {
+            bytes += Self::new_key_bytes(key);
+        }
+        if let Some(operands) = self.operands.get(key) {
+            bytes += table_bytes(key, operands.len());
+        }
+        if let Some(chunks) = self.chunks.get(key) {
+            bytes += table_bytes(key, chunks.len());
+        }
+        if self.expiries.contains_key(key) {
+            bytes += table_bytes(key, 0);
+        }
+        bytes
+    }
+
+    /// Runs `change` on the entries of `key`, keeping the memory estimate up to date.
+    fn update<T>(&mut self, key: &[u8], change: impl FnOnce(&mut Self) -> T) -> T {
+        let before = self.key_bytes(key);
+        let result = change(self);
+        self.bytes = self.bytes - before + self.key_bytes(key);
+        result
+    }
+
+    /// Points `key` at a full value, discarding any pending merge operands.
+    pub fn insert(&mut self, key: impl Into<Box<[u8]>>, log_index: LogIndex) -> Option<LogIndex> {
+        self.insert_chunked(key, log_index, Vec::new())
+    }
+
+    /// Points `key` at a value whose leading `chunks` precede the record at `log_index`. Clears
+    /// any expiry; the caller sets the new one with `set_expiry`.
+    pub fn insert_chunked(
+        &mut self,
+        key: impl Into<Box<[u8]>>,
+        log_index: LogIndex,
+        chunks: Vec<LogIndex>,
+    ) -> Option<LogIndex> {
+        let key = key.into();
+        self.bytes -= self.key_bytes(&key);
+        self.bytes += Self::new_key_bytes(&key);
+        self.operands.remove(&key);
+        self.expiries.remove(&key);
+        if chunks.is_empty() {
+            self.chunks.remove(&key);
+        } else {
+            self.bytes += table_bytes(&key, chunks.len());
+            self.chunks.insert(key.clone(), chunks);
+        }
+        self.map.insert(key, Some(log_index)).flatten()
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<LogIndex> {
+        self.update(key, |index| {
+            index.operands.remove(key);
+            index.chunks.remove(key);
+            index.expiries.remove(key);
+            index.map.remove(key).flatten()
+        })
+    }
+
+    /// Milliseconds since the Unix epoch after which `key` is gone, if it expires.
+    pub fn expires_at(&self, key: &[u8]) -> Option<i64> {
+        self.expiries.get(key).copied()
+    }
+
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<i64>) {
+        self.update(key, |index| match expires_at {
+            Some(at) => {
+                index.expiries.insert(key.into(), at);
+            }
+            None => {
+                index.expiries.remove(key);
+            }
+        })
+    }
+
+    /// Leading pieces of a chunked value, in order. Empty for values stored in one record.
+    pub fn chunks(&self, key: &[u8]) -> &[LogIndex] {
+        self.chunks.get(key).map_or(&[], |chunks| chunks.as_slice())
+    }
+
+    pub fn push_operand(&mut self, key: impl Into<Box<[u8]>>, log_index: LogIndex) {
+        let key = key.into();
+        if !self.map.contains_key(&key) {
+            self.bytes += Self::new_key_bytes(&key);
+            self.map.insert(key.clone(), None);
+        }
+        if !self.operands.contains_key(&key) {
+            self.bytes += table_bytes(&key, 0);
+        }
+        self.operands.entry(key).or_default().push(log_index);
+        self.bytes += size_of::<LogIndex>();
+    }
+
+    /// Merge operands recorded for `key` since its last full value, oldest first.
+    pub fn operands(&self, key: &[u8]) -> &[LogIndex] {
+        self.operands.get(key).map_or(&[], |ops| ops.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Every live key in ascending order with its base value (if any), chunks and pending merge
+    /// operands.
+    pub fn entries(&self) -> impl Iterator<Item = IndexEntry<'_>> + '_ {
+        self.map.iter().map(|(k, v)| IndexEntry {
+            key: k,
+            base: v.as_ref(),
+            chunks: self.chunks(k),
+            operands: self.operands(k),
+            expires_at: self.expires_at(k),
+        })
+    }
+
+    /// Live keys between `start` and `end` in ascending order; reverse the iterator for
+    /// descending order. An empty or inverted range yields nothing.
+    pub fn keys<'a>(
+        &'a self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> impl DoubleEndedIterator<Item = &'a [u8]> + 'a {
+        let valid = match (start, end) {
+            (Bound::Included(s), Bound::Included(e)) => s <= e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e)) => s < e,
+            _ => true,
+        };
+        valid
+            .then(|| self.map.range::<[u8], _>((start, end)))
+            .into_iter()
+            .flatten()
+            .map(|(k, _)| &**k)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &LogIndex)> + '_ {
+        self.map
+            .iter()
+            .filter_map(|(k, v)| Some((&**k, v.as_ref()?)))
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.operands.shrink_to_fit();
+        self.chunks.shrink_to_fit();
+        self.expiries.shrink_to_fit();
+    }
+}
+
+/// Approximate bytes of an entry for `key` holding `records` in one of the side tables.
+fn table_bytes(key: &[u8], records: usize) -> usize {
+    key.len() + TABLE_ENTRY_BYTES + records * size_of::<LogIndex>()
+}
@@ -1,5 +1,37 @@
+pub mod archive;
+pub mod buckets;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client;
+pub mod codec;
 pub mod constants;
 pub mod engine;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod hot_keys;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod http_client;
+pub mod index;
+#[cfg(any(feature = "sled", feature = "rocksdb"))]
+mod migrate;
+#[cfg(feature = "node")]
+mod node;
+pub mod observer;
+pub mod options;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod raft;
+mod redis_import;
+pub mod slow_log;
+pub mod storage;
 pub mod types;
+pub mod wire;
 
+pub use buckets::Buckets;
+#[cfg(not(target_arch = "wasm32"))]
+pub use client::Client;
 pub use engine::Engine;
+#[cfg(not(target_arch = "wasm32"))]
+pub use http_client::KvClient;
+pub use observer::EngineObserver;
+pub use options::{EngineOptions, SyncPolicy};
@@ -1,55 +1,754 @@
+mod server;
+
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use actix_web::http::header;
+use actix_web::middleware::{self, Compress, Condition};
 use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
-use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::LoadProgress;
+use breakout1_kv_store::{Buckets, Engine, EngineOptions};
 use serde::Deserialize;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use server::access_log::{self, LogFormat};
+use server::acl::{Acl, Scope};
+use server::auth::Auth;
+use server::compress::{self, CompressionRules};
+use server::config::{Config, Task};
+use server::encoding::EncodingQuery;
+use server::jwt::JwtValidator;
+use server::limits;
+use server::ratelimit::{self, RateLimiter};
+use server::state::{AppState, Db};
+use server::systemd::Listener;
+use server::{
+    admin, append, audit, auth, batch, binary, buckets, cas, changes, cluster, counter, graphql,
+    grpc, health, history, idempotency, kafka, keys, kv, lock, maintenance, memcached, metrics,
+    mqtt, openapi, replica, replication, resp, router, s3_backup, scan, stats, systemd, tags,
+    timeout, ttl, watch,
+};
 
 #[derive(Deserialize)]
 pub struct SetRequest {
     key: String,
     value: String,
+    /// Seconds until the key expires; it never does if left out.
+    ttl_secs: Option<u64>,
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let db = web::Data::new(Engine::load("data.db")?);
+    let (task, config) = Config::load()?;
+    // `RUST_LOG=info,breakout1_kv_store=debug` adds a span per get/set/del; spans are logged
+    // when they close, with their busy and idle time.
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_span_events(FmtSpan::CLOSE);
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
 
-    HttpServer::new(move || {
+    let options = EngineOptions {
+        compact_threshold: config.compact_threshold,
+        sync: config.sync,
+        archive_dir: config.archive_dir.clone(),
+        archive_retention: config.archive_retention,
+        max_value_size: Some(config.max_value_size),
+        min_free_space: config.min_free_space,
+        max_index_memory: config.max_index_memory,
+        slow_op_threshold: config.slow_op_threshold,
+        idempotency_window: config.idempotency_window,
+        trash_retention: config.trash_retention,
+        on_load_progress: Some(Arc::new(log_load_progress)),
+        ..EngineOptions::default()
+    };
+    if let Task::Maintain(task) = task {
+        return maintenance::run(task, &config, options);
+    }
+    info!(
+        "effective configuration:\n{}",
+        config.effective().join("\n")
+    );
+    let auth = Auth {
+        admin_token: config.admin_token.clone(),
+        api_keys: config.api_keys.clone(),
+        jwt: JwtValidator::new(config.jwt.clone())?,
+        acl: Acl::new(config.acl.clone())?,
+    };
+    let require_auth = auth.required();
+    if require_auth && config.memcached_bind.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "memcached_bind needs credentials off: memcached clients cannot send them",
+        ));
+    }
+    let listeners = config.resp_bind.is_some()
+        || config.memcached_bind.is_some()
+        || config.grpc_bind.is_some()
+        || config.binary_bind.is_some();
+    if config.audit_log.is_some() && listeners {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "audit_log records writes over HTTP only: it cannot be combined with resp_bind, \
+             memcached_bind, grpc_bind or binary_bind",
+        ));
+    }
+    let router = config.router.router()?.map(web::Data::new);
+    let data_elsewhere = config.replica.enabled()
+        || config.bucket_dir.is_some()
+        || config.audit_log.is_some()
+        || config.resp_bind.is_some()
+        || config.memcached_bind.is_some()
+        || config.grpc_bind.is_some()
+        || config.binary_bind.is_some()
+        || config.mqtt.enabled()
+        || config.kafka.enabled();
+    if router.is_some() && data_elsewhere {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "route_to serves the HTTP API alone: it cannot be combined with replica_of, \
+             bucket_dir, audit_log, the other protocol listeners, mqtt or kafka",
+        ));
+    }
+    let cluster = config.cluster.node(&config.data_path)?.map(web::Data::new);
+    // Every write must go through the Raft log, and an evicted key could not be read back the
+    // same on every node.
+    if cluster.is_some()
+        && (router.is_some() || data_elsewhere || config.max_index_memory.is_some())
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "cluster_nodes serves the HTTP API alone: it cannot be combined with replica_of, \
+             route_to, bucket_dir, audit_log, the other protocol listeners, mqtt, kafka or \
+             max_index_memory",
+        ));
+    }
+    if cluster.is_some() && !auth.admin_enabled() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "cluster_nodes needs admin_token or a JWT admin scope: the nodes call each other's \
+             admin endpoints",
+        ));
+    }
+    // Read before the engine starts loading, so a bad certificate fails fast.
+    let tls = config.tls.server_config()?;
+    let state = web::Data::new(AppState::new(auth));
+    let max_body_size = config.max_body_size;
+    let compression = config.compression;
+    let cors = config.cors.clone();
+    let log_requests = config.access_log;
+    let docs = config.docs;
+    let s3_backups = config.s3.target()?.map(web::Data::new);
+    let graphql_schema = config.graphql.then(|| web::Data::new(graphql::schema()));
+    let rate_limited = config.rate_limit > 0;
+    let rate_limiter = web::Data::new(RateLimiter::new(config.rate_limit, config.rate_limit_burst));
+    let timeouts = web::Data::new(config.timeouts);
+    let compression_rules = web::Data::new(CompressionRules {
+        min_size: config.compression_min_size,
+    });
+    let audit_log = match &config.audit_log {
+        Some(path) => {
+            let options = EngineOptions {
+                on_load_progress: None,
+                archive_dir: None,
+                slow_op_threshold: None,
+                // Every entry is a new key; the cap is for the data the API serves.
+                max_index_memory: None,
+                ..options.clone()
+            };
+            Some(web::Data::new(audit::AuditLog::open(path, options)?))
+        }
+        None => None,
+    };
+    let audited = audit_log.is_some();
+    let disk_watermark = config.min_free_space.is_some();
+
+    // The index is rebuilt in the background so /health answers (and /ready reports loading)
+    // while a large log is scanned.
+    let loader = state.clone();
+    let data_path = config.data_path.clone();
+    let bucket_dir = config.bucket_dir.clone();
+    let replica_mode = config.replica.enabled();
+    let primary = replica_mode.then(|| replica::Primary::new(&config.replica));
+    let replica_status = primary
+        .as_ref()
+        .map(|primary| web::Data::new(replica::ReplicaStatus::new(primary, &config.replica)));
+    let follower = replica_status.clone();
+    let raft_node = cluster.clone();
+    let max_value_size = config.max_value_size;
+    // The Raft log holds whole requests and is only ever read back by the node itself.
+    let raft_options = EngineOptions {
+        on_load_progress: None,
+        archive_dir: None,
+        slow_op_threshold: None,
+        max_index_memory: None,
+        max_value_size: None,
+        trash_retention: None,
+        ..options.clone()
+    };
+    // Progress is only logged, and records only archived, for the main log.
+    let bucket_options = EngineOptions {
+        on_load_progress: None,
+        archive_dir: None,
+        ..options.clone()
+    };
+    // Readiness is reported to systemd once the index is rebuilt and the listeners are bound.
+    let (loaded_tx, loaded) = tokio::sync::oneshot::channel();
+    // A router holds no data of its own.
+    if router.is_none() {
+        thread::spawn(move || {
+            // Buckets go first: the server counts as ready once the engine is set.
+            if let Some(dir) = bucket_dir {
+                match Buckets::open(&dir, bucket_options) {
+                    Ok(buckets) => loader.set_buckets(buckets),
+                    Err(e) => {
+                        error!("failed to load buckets in {}: {}", dir.display(), e);
+                        process::exit(1);
+                    }
+                }
+            }
+            let loaded = match &primary {
+                Some(primary) => replica::load(primary, &data_path, options),
+                None => Engine::load_with_options(&data_path, options),
+            };
+            match loaded {
+                Ok(engine) => loader.set_engine(engine),
+                Err(e) => {
+                    error!("failed to load {}: {}", data_path.display(), e);
+                    process::exit(1);
+                }
+            }
+            if let (Some(node), Some(engine)) = (raft_node, loader.engine()) {
+                if let Err(e) = node.start(engine.clone(), raft_options, max_value_size) {
+                    error!("failed to join the cluster: {}", e);
+                    process::exit(1);
+                }
+            }
+            let _ = loaded_tx.send(());
+            // This thread has nothing left to do, so a replica follows its primary on it.
+            if let (Some(primary), Some(status), Some(engine)) =
+                (primary, follower, loader.engine())
+            {
+                replica::follow(primary, engine.clone(), &status);
+            }
+        });
+    } else {
+        let _ = loaded_tx.send(());
+    }
+
+    let app_state = state.clone();
+    // actix stops accepting connections on SIGINT/SIGTERM and lets in-flight requests finish
+    // (up to the shutdown timeout) before `run` resolves.
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(db.clone())
+            .wrap(middleware::from_fn(timeout::enforce))
+            // Inside `authenticate`, to see who made each write.
+            .wrap(Condition::new(audited, middleware::from_fn(audit::record)))
+            .wrap(Condition::new(
+                replica_mode,
+                middleware::from_fn(replica::reject_writes),
+            ))
+            .wrap(Condition::new(
+                disk_watermark,
+                middleware::from_fn(limits::reject_writes_when_full),
+            ))
+            // `compress::rules` runs inside `Compress` and opts responses out of it.
+            .wrap(Condition::new(
+                compression,
+                middleware::from_fn(compress::rules),
+            ))
+            .wrap(Condition::new(compression, Compress::default()))
+            .wrap(Condition::new(
+                require_auth,
+                middleware::from_fn(auth::authenticate),
+            ))
+            .wrap(Condition::new(
+                rate_limited,
+                middleware::from_fn(ratelimit::limit),
+            ))
+            .wrap(Condition::new(cors.enabled(), cors.middleware()))
+            .wrap(Condition::new(
+                log_requests,
+                middleware::from_fn(access_log::log),
+            ))
+            .app_data(app_state.clone())
+            .app_data(compression_rules.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(timeouts.clone())
+            .app_data(limits::json_config(max_body_size))
+            .app_data(web::PayloadConfig::new(max_body_size))
+            // A router's or cluster node's routes come first and catch every path.
+            .configure(|cfg| {
+                if let Some(router) = &router {
+                    router::configure(cfg, router.clone());
+                }
+                if let Some(node) = &cluster {
+                    cluster::configure(cfg, node.clone(), max_body_size);
+                }
+            })
             .route("/", web::get().to(home))
+            .route("/health", web::get().to(health::health))
+            .route("/ready", web::get().to(health::ready))
+            .route("/stats", web::get().to(stats::stats))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/admin/compact", web::post().to(admin::compact))
+            .route("/admin/verify", web::post().to(admin::verify))
+            .route("/admin/index", web::get().to(admin::index))
+            .route("/admin/slowlog", web::get().to(admin::slowlog))
+            .route("/admin/slowlog", web::delete().to(admin::clear_slowlog))
+            .route("/admin/backup", web::post().to(admin::backup))
+            .route("/admin/backup/s3", web::post().to(s3_backup::backup))
+            .route("/admin/maintenance", web::post().to(admin::maintenance))
+            .route("/admin/promote", web::post().to(replica::promote))
+            .route("/admin/audit", web::get().to(audit::list))
             .route("/set", web::post().to(set_handler))
             .route("/get/{key}", web::get().to(get_handler))
             .route("/del/{key}", web::delete().to(del_handler))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+            .route("/undelete/{key}", web::post().to(undelete_handler))
+            .route("/expire/{key}", web::post().to(ttl::expire))
+            .route("/ttl/{key}", web::get().to(ttl::ttl))
+            .route("/incr/{key}", web::post().to(counter::incr))
+            .route("/decr/{key}", web::post().to(counter::decr))
+            .route("/append/{key}", web::post().to(append::append))
+            .route("/cas/{key}", web::post().to(cas::cas))
+            .route("/lock/{name}", web::post().to(lock::acquire))
+            .route("/lock/{name}", web::put().to(lock::renew))
+            .route("/lock/{name}", web::delete().to(lock::release))
+            .route("/lock/{name}", web::get().to(lock::holder))
+            .route("/keys", web::get().to(keys::keys))
+            .route("/keys", web::delete().to(keys::delete_prefix))
+            .route("/keys/count", web::get().to(keys::count))
+            .route("/du", web::get().to(stats::du))
+            .route("/exists/{key}", web::get().to(keys::exists))
+            .route("/tags/{tag}", web::get().to(tags::keys))
+            .route("/tags/{tag}", web::delete().to(tags::delete))
+            .route("/tags/{tag}/{key}", web::put().to(tags::tag))
+            .route("/tags/{tag}/{key}", web::delete().to(tags::untag))
+            .route("/kv/{key}", web::put().to(kv::put))
+            .route("/kv/{key}", web::get().to(kv::get))
+            .route("/kv/{key}", web::head().to(kv::head))
+            .route("/kv/{key}", web::delete().to(kv::delete))
+            .route("/kv/{key}", web::patch().to(kv::patch))
+            .route("/history/{key}", web::get().to(history::history))
+            .route("/scan", web::get().to(scan::scan))
+            .route("/range", web::get().to(scan::range))
+            .route("/batch/set", web::post().to(batch::set))
+            .route("/batch/get", web::post().to(batch::get))
+            .route("/batch/del", web::post().to(batch::del))
+            .route("/watch", web::get().to(watch::watch))
+            .route("/changes", web::get().to(changes::changes))
+            .route("/replication/stream", web::get().to(replication::stream))
+            .route(
+                "/replication/snapshot",
+                web::get().to(replication::snapshot),
+            )
+            .route("/replication/status", web::get().to(replica::status))
+            .route("/b", web::get().to(buckets::list))
+            .route("/b/{bucket}", web::put().to(buckets::create))
+            .route("/b/{bucket}", web::delete().to(buckets::delete))
+            .service(web::scope("/b/{bucket}").configure(bucket_routes))
+            .route("/openapi.json", web::get().to(openapi::spec))
+            .configure(|cfg| {
+                if docs {
+                    cfg.route("/docs", web::get().to(openapi::docs));
+                }
+                if let Some(backups) = &s3_backups {
+                    cfg.app_data(backups.clone());
+                }
+                if let Some(status) = &replica_status {
+                    cfg.app_data(status.clone());
+                }
+                if let Some(audit_log) = &audit_log {
+                    cfg.app_data(audit_log.clone());
+                }
+                if let Some(schema) = &graphql_schema {
+                    cfg.app_data(schema.clone())
+                        .route("/graphql", web::post().to(graphql::graphql));
+                }
+            })
+    });
+    // Under socket activation systemd has bound the sockets, and `bind` and `unix_socket` are
+    // left alone so restarts do not refuse connections.
+    let activated = systemd::listeners()?;
+    let mut server = server;
+    if activated.is_empty() {
+        server = match (&config.bind, &tls) {
+            (Some(bind), Some(tls)) => server.bind_rustls_0_23(bind.as_str(), tls.clone())?,
+            (Some(bind), None) => server.bind(bind.as_str())?,
+            (None, _) => server,
+        };
+        // TLS only applies to TCP; access to the socket is governed by its file permissions.
+        if let Some(path) = &config.unix_socket {
+            #[cfg(unix)]
+            {
+                remove_stale_socket(path)?;
+                server = server.bind_uds(path)?;
+            }
+            #[cfg(not(unix))]
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "unix sockets are only supported on unix",
+            ));
+        }
+    } else {
+        info!("serving on {} sockets passed by systemd", activated.len());
+    }
+    for listener in activated {
+        server = match (listener, &tls) {
+            (Listener::Tcp(listener), Some(tls)) => {
+                server.listen_rustls_0_23(listener, tls.clone())?
+            }
+            (Listener::Tcp(listener), None) => server.listen(listener)?,
+            #[cfg(unix)]
+            (Listener::Unix(listener), _) => server.listen_uds(listener)?,
+        };
+    }
+    if let Some(addr) = &config.resp_bind {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        info!("serving the Redis protocol on {}", addr);
+        tokio::spawn(resp::serve(listener, state.clone(), config.max_body_size));
+    }
+    if let Some(addr) = &config.memcached_bind {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        info!("serving the memcached protocol on {}", addr);
+        tokio::spawn(memcached::serve(
+            listener,
+            state.clone(),
+            config.max_body_size,
+        ));
+    }
+    if let Some(addr) = &config.grpc_bind {
+        let incoming = grpc::bind(addr)?;
+        info!("serving gRPC on {}", addr);
+        tokio::spawn(grpc::serve(incoming, state.clone(), config.max_body_size));
+    }
+    if let Some(addr) = &config.binary_bind {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        info!("serving the binary protocol on {}", addr);
+        tokio::spawn(binary::serve(listener, state.clone(), config.max_body_size));
+    }
+    if config.mqtt.enabled() {
+        mqtt::spawn(config.mqtt.clone(), state.clone(), config.max_body_size)?;
+    }
+    if config.kafka.enabled() {
+        kafka::spawn(config.kafka.clone(), state.clone(), config.max_body_size)?;
+    }
+    tokio::spawn(async move {
+        if loaded.await.is_ok() {
+            systemd::notify("READY=1");
+        }
+    });
+    server
+        .shutdown_timeout(config.shutdown_timeout)
+        .run()
+        .await?;
+    systemd::notify("STOPPING=1");
+
+    if let Some(buckets) = state.buckets() {
+        buckets.sync()?;
+    }
+    match state.engine() {
+        Some(engine) => {
+            info!("shutting down, syncing data file");
+            engine.sync()
+        }
+        None => Ok(()),
+    }
+}
+
+/// The routes served within each bucket under `/b/{bucket}`; `Db` picks the bucket's engine.
+fn bucket_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/kv/{key}", web::put().to(kv::put))
+        .route("/kv/{key}", web::get().to(kv::get))
+        .route("/kv/{key}", web::head().to(kv::head))
+        .route("/kv/{key}", web::delete().to(kv::delete))
+        .route("/kv/{key}", web::patch().to(kv::patch))
+        .route("/undelete/{key}", web::post().to(undelete_handler))
+        .route("/history/{key}", web::get().to(history::history))
+        .route("/keys", web::get().to(keys::keys))
+        .route("/keys", web::delete().to(keys::delete_prefix))
+        .route("/keys/count", web::get().to(keys::count))
+        .route("/tags/{tag}", web::get().to(tags::keys))
+        .route("/tags/{tag}", web::delete().to(tags::delete))
+        .route("/tags/{tag}/{key}", web::put().to(tags::tag))
+        .route("/tags/{tag}/{key}", web::delete().to(tags::untag))
+        .route("/du", web::get().to(stats::du))
+        .route("/scan", web::get().to(scan::scan))
+        .route("/range", web::get().to(scan::range))
+        .route("/batch/set", web::post().to(batch::set))
+        .route("/batch/get", web::post().to(batch::get))
+        .route("/batch/del", web::post().to(batch::del))
+        .route("/admin/compact", web::post().to(admin::compact))
+        .route("/admin/verify", web::post().to(admin::verify))
+        .route("/admin/index", web::get().to(admin::index))
+        .route("/admin/slowlog", web::get().to(admin::slowlog))
+        .route("/admin/slowlog", web::delete().to(admin::clear_slowlog));
+}
+
+/// Removes a socket file left behind by an earlier run, which would make binding fail.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn log_load_progress(progress: &LoadProgress) {
+    let percent = match progress.total_bytes {
+        0 => 100,
+        total => progress.bytes_scanned * 100 / total,
+    };
+    if progress.done {
+        info!("loaded {} entries, ready", progress.entries);
+    } else {
+        info!(
+            "loading: {}% ({} entries scanned)",
+            percent, progress.entries
+        );
+    }
 }
 
 async fn home(_req: HttpRequest) -> impl Responder {
     "Welcome!".to_string()
 }
 
-async fn set_handler(req: web::Json<SetRequest>, engine: web::Data<Engine>) -> impl Responder {
-    let op = engine.set(req.key.as_bytes(), req.value.as_bytes());
+async fn set_handler(
+    http: HttpRequest,
+    req: web::Json<SetRequest>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> impl Responder {
+    let (key, value) = match (
+        query.encoding.decode(&req.key),
+        query.encoding.decode(&req.value),
+    ) {
+        (Ok(key), Ok(value)) => (key, value),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let request_id = match idempotency::request_id(&http) {
+        Ok(request_id) => request_id,
+        Err(response) => return response,
+    };
+    let op = match (request_id, req.ttl_secs) {
+        (Some(id), Some(secs)) => {
+            engine.set_idempotent_with_ttl(&key, &value, Duration::from_secs(secs), &id)
+        }
+        (Some(id), None) => engine.set_idempotent(&key, &value, &id),
+        (None, Some(secs)) => engine
+            .set_with_ttl(&key, &value, Duration::from_secs(secs))
+            .map(Ok),
+        (None, None) => engine.set(&key, &value).map(Ok),
+    };
     match op {
-        Ok(_) => HttpResponse::Ok().body("OK"),
-        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        Ok(Ok(seq)) => HttpResponse::Ok()
+            .insert_header((header::ETAG, kv::etag(seq)))
+            .body("OK"),
+        Ok(Err(seq)) => HttpResponse::Ok()
+            .insert_header((header::ETAG, kv::etag(seq)))
+            .insert_header(idempotency::REPLAYED)
+            .body("OK"),
+        Err(e) => idempotency::write_error(e),
     }
 }
 
-async fn get_handler(req: web::Path<String>, engine: web::Data<Engine>) -> impl Responder {
-    let op = engine.get(req.as_bytes());
+async fn get_handler(
+    req: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> impl Responder {
+    let key = match query.encoding.decode(&req) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&key) {
+        return response;
+    }
+    let op = engine.get_with_seq(&key);
     match op {
-        Ok(Some(val)) => HttpResponse::Ok().body(val),
+        Ok(Some((val, seq))) => HttpResponse::Ok()
+            .insert_header((header::ETAG, kv::etag(seq)))
+            .body(query.encoding.encode(&val)),
         Ok(None) => HttpResponse::NotFound().body("Key is not found"),
         Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
-async fn del_handler(req: web::Path<String>, engine: web::Data<Engine>) -> impl Responder {
-    let op = engine.del(req.as_bytes());
+async fn del_handler(
+    req: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> impl Responder {
+    let key = match query.encoding.decode(&req) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let op = engine.del(&key);
     match op {
         Ok(_) => HttpResponse::Ok().body("OK"),
+        Err(e) => limits::write_error(e),
+    }
+}
+
+/// Restores a key deleted within `trash_retention`. `404` if there is nothing to restore.
+async fn undelete_handler(
+    req: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> impl Responder {
+    let key = match query.encoding.decode(&req) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    match web::block(move || engine.undelete(&key)).await {
+        Ok(Ok(true)) => HttpResponse::Ok().body("OK"),
+        Ok(Ok(false)) => HttpResponse::NotFound().body("Key is not in the trash"),
+        Ok(Err(e)) => limits::write_error(e),
         Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{StatusCode, header};
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, test, web};
+    use breakout1_kv_store::Engine;
+    use tempfile::NamedTempFile;
+
+    use super::server::acl::{Acl, AclRule};
+    use super::server::auth::{self, Auth};
+    use super::server::kv;
+    use super::server::state::AppState;
+    use super::{get_handler, set_handler};
+
+    /// State with one API key that may only read keys under `tenantA:`, which holds one key.
+    fn read_scoped_state(file: &NamedTempFile) -> web::Data<AppState> {
+        let acl = Acl::new(vec![AclRule {
+            api_key: Some("reader".to_string()),
+            subject: None,
+            read: vec!["tenantA:".to_string()],
+            write: Vec::new(),
+        }])
+        .unwrap();
+        let state = web::Data::new(AppState::new(Auth {
+            admin_token: None,
+            api_keys: vec!["reader".to_string()],
+            jwt: None,
+            acl,
+        }));
+        let engine = Engine::load(file.path()).unwrap();
+        engine.set(b"tenantA:name", b"alice").unwrap();
+        state.set_engine(engine);
+        state
+    }
+
+    #[actix_web::test]
+    async fn test_read_scoped_credential_can_get_but_not_set() {
+        let file = NamedTempFile::new().unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(auth::authenticate))
+                .app_data(read_scoped_state(&file))
+                .route("/set", web::post().to(set_handler))
+                .route("/get/{key}", web::get().to(get_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/set")
+            .insert_header(("X-Api-Key", "reader"))
+            .set_json(serde_json::json!({"key": "tenantA:name", "value": "bob"}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/get/tenantA:name")
+            .insert_header(("X-Api-Key", "reader"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "alice");
+
+        let req = test::TestRequest::get()
+            .uri("/get/tenantB:name")
+            .insert_header(("X-Api-Key", "reader"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_put_with_stale_if_match_is_refused() {
+        let file = NamedTempFile::new().unwrap();
+        let state = web::Data::new(AppState::new(Auth {
+            admin_token: None,
+            api_keys: Vec::new(),
+            jwt: None,
+            acl: Acl::new(Vec::new()).unwrap(),
+        }));
+        state.set_engine(Engine::load(file.path()).unwrap());
+        state.engine().unwrap().set(b"name", b"alice").unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .route("/kv/{key}", web::put().to(kv::put))
+                .route("/kv/{key}", web::get().to(kv::get)),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/kv/name").to_request());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stale = resp.headers().get(header::ETAG).unwrap().clone();
+
+        let req = test::TestRequest::put()
+            .uri("/kv/name")
+            .insert_header((header::IF_MATCH, stale.clone()))
+            .set_payload("bob")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let current = resp.headers().get(header::ETAG).unwrap().clone();
+        assert_ne!(current, stale);
+
+        let req = test::TestRequest::put()
+            .uri("/kv/name")
+            .insert_header((header::IF_MATCH, stale))
+            .set_payload("carol")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(resp.headers().get(header::ETAG), Some(&current));
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/kv/name").to_request());
+        assert_eq!(test::read_body(resp).await, "bob");
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+
+use crate::Engine;
+use crate::constants::IMPORT_BATCH;
+use crate::types::{BatchOp, ImportReport};
+
+type Pair = (Vec<u8>, Vec<u8>);
+
+impl Engine {
+    /// Copies every entry of a sled tree into the store, overwriting keys it already has.
+    #[cfg(feature = "sled")]
+    pub fn import_sled(&self, tree: &sled::Tree) -> io::Result<ImportReport> {
+        self.import_pairs(tree.iter().map(|entry| {
+            entry
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .map_err(io::Error::other)
+        }))
+    }
+
+    /// Copies every live key into a sled tree and flushes it, returning how many there were.
+    /// TTLs and metadata are left behind.
+    #[cfg(feature = "sled")]
+    pub fn export_sled(&self, tree: &sled::Tree) -> io::Result<usize> {
+        let count = self.export_pairs(|pairs| {
+            let mut batch = sled::Batch::default();
+            for (key, value) in pairs {
+                batch.insert(key.as_slice(), value.as_slice());
+            }
+            tree.apply_batch(batch).map_err(io::Error::other)
+        })?;
+        tree.flush().map_err(io::Error::other)?;
+        Ok(count)
+    }
+
+    /// Copies every entry of a RocksDB column family, or of the default one if `cf` is `None`,
+    /// into the store, overwriting keys it already has.
+    #[cfg(feature = "rocksdb")]
+    pub fn import_rocksdb(&self, db: &rocksdb::DB, cf: Option<&str>) -> io::Result<ImportReport> {
+        let entries = match cf {
+            Some(name) => db.iterator_cf(column_family(db, name)?, rocksdb::IteratorMode::Start),
+            None => db.iterator(rocksdb::IteratorMode::Start),
+        };
+        self.import_pairs(entries.map(|entry| {
+            entry
+                .map(|(key, value)| (key.into_vec(), value.into_vec()))
+                .map_err(io::Error::other)
+        }))
+    }
+
+    /// Copies every live key into a RocksDB column family, or the default one if `cf` is
+    /// `None`, returning how many there were. TTLs and metadata are left behind.
+    #[cfg(feature = "rocksdb")]
+    pub fn export_rocksdb(&self, db: &rocksdb::DB, cf: Option<&str>) -> io::Result<usize> {
+        let cf = cf.map(|name| column_family(db, name)).transpose()?;
+        self.export_pairs(|pairs| {
+            let mut batch = rocksdb::WriteBatch::default();
+            for (key, value) in pairs {
+                match cf {
+                    Some(cf) => batch.put_cf(cf, key, value),
+                    None => batch.put(key, value),
+                }
+            }
+            db.write(batch).map_err(io::Error::other)
+        })
+    }
+
+    /// Writes `pairs` with `write_batch`, `IMPORT_BATCH` at a time, so each batch is atomic
+    /// but the import as a whole is not.
+    fn import_pairs(
+        &self,
+        pairs: impl Iterator<Item = io::Result<Pair>>,
+    ) -> io::Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut batch = Vec::with_capacity(IMPORT_BATCH);
+        for pair in pairs {
+            let (key, value) = pair?;
+            batch.push(BatchOp::Set { key, value });
+            if batch.len() == IMPORT_BATCH {
+                self.write_batch(&batch)?;
+                report.keys += batch.len();
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.write_batch(&batch)?;
+            report.keys += batch.len();
+        }
+        Ok(report)
+    }
+
+    /// Hands the live keys and their values to `write` in key order, `IMPORT_BATCH` at a time.
+    /// Each page is read under one index lock; writes made between pages may or may not be
+    /// seen.
+    fn export_pairs(&self, mut write: impl FnMut(&[Pair]) -> io::Result<()>) -> io::Result<usize> {
+        let mut after = None;
+        let mut count = 0;
+        loop {
+            let keys = self.keys(b"", after.as_deref(), IMPORT_BATCH);
+            if keys.is_empty() {
+                return Ok(count);
+            }
+            let values = self.get_many(&keys.iter().map(Vec::as_slice).collect::<Vec<_>>())?;
+            after = keys.last().cloned();
+            let pairs: Vec<Pair> = keys
+                .into_iter()
+                .zip(values)
+                .filter_map(|(key, value)| Some((key, value?)))
+                .collect();
+            write(&pairs)?;
+            count += pairs.len();
+        }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+fn column_family<'a>(db: &'a rocksdb::DB, name: &str) -> io::Result<&'a rocksdb::ColumnFamily> {
+    db.cf_handle(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no column family named {name}"),
+        )
+    })
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::sync::Arc;
+
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+
+use crate::Engine;
+
+/// An engine opened from Node.js. Keys and values are `Buffer`s, and every call returns a
+/// promise, with the engine's work done off the JavaScript thread.
+#[napi(js_name = "Engine")]
+pub struct JsEngine {
+    engine: Option<Arc<Engine>>,
+}
+
+#[napi(object)]
+pub struct Entry {
+    pub key: Buffer,
+    pub value: Buffer,
+}
+
+/// Opens the log at `path`, creating it if it does not exist.
+#[napi]
+pub async fn open(path: String) -> Result<JsEngine> {
+    let engine = blocking(move || Engine::load(path)).await?;
+    Ok(JsEngine {
+        engine: Some(Arc::new(engine)),
+    })
+}
+
+#[napi]
+impl JsEngine {
+    /// The value of `key`, or `null` if it has none.
+    #[napi]
+    pub async fn get(&self, key: Buffer) -> Result<Option<Buffer>> {
+        let engine = self.engine()?;
+        let value = blocking(move || engine.get(&key)).await?;
+        Ok(value.map(Buffer::from))
+    }
+
+    #[napi]
+    pub async fn set(&self, key: Buffer, value: Buffer) -> Result<()> {
+        let engine = self.engine()?;
+        blocking(move || engine.set(&key, &value).map(drop)).await
+    }
+
+    #[napi]
+    pub async fn delete(&self, key: Buffer) -> Result<()> {
+        let engine = self.engine()?;
+        blocking(move || engine.del(&key)).await
+    }
+
+    /// Entries under `prefix` in key order, all of them unless `limit` is given.
+    #[napi]
+    pub async fn scan_prefix(&self, prefix: Buffer, limit: Option<u32>) -> Result<Vec<Entry>> {
+        let engine = self.engine()?;
+        let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+        let pairs = blocking(move || engine.scan(&prefix, limit)).await?;
+        Ok(pairs
+            .into_iter()
+            .map(|(key, value)| Entry {
+                key: key.into(),
+                value: value.into(),
+            })
+            .collect())
+    }
+
+    /// Syncs the log and closes the engine; its file is released once calls already made have
+    /// finished. Calls made afterwards reject. Unlike the others, this call is synchronous.
+    #[napi]
+    pub fn close(&mut self) -> Result<()> {
+        match self.engine.take() {
+            Some(engine) => engine.sync().map_err(|e| Error::from_reason(e.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl JsEngine {
+    fn engine(&self) -> Result<Arc<Engine>> {
+        self.engine
+            .clone()
+            .ok_or_else(|| Error::from_reason("engine is closed"))
+    }
+}
+
+/// Runs engine work on the blocking pool, so the event loop keeps going.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Result<T> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result.map_err(|e| Error::from_reason(e.to_string())),
+        Err(e) => Err(Error::from_reason(e.to_string())),
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use crate::types::CompactionReport;
+
+/// Callbacks an embedder can set in `EngineOptions::observer` to feed the engine's activity into
+/// their own metrics or logging, whatever the telemetry stack. Every method does nothing by
+/// default, so an implementation only overrides the ones it needs.
+///
+/// Calls are made on the thread doing the work, some with the write lock held, so they should
+/// return quickly. The per-key hooks cover `get`, the `set` family and `del`, the same
+/// operations the slow-operation log times; other writes, such as batches, counters and merges,
+/// can be followed with `Engine::watch`.
+#[allow(unused_variables)]
+pub trait EngineObserver: Send + Sync {
+    /// After a successful `set`, `set_with_metadata` or `set_with_ttl`.
+    fn on_set(&self, key: &[u8], value_len: u64, elapsed: Duration) {}
+
+    /// After a successful `get`; `value_len` is `None` when the key was missing.
+    fn on_get(&self, key: &[u8], value_len: Option<u64>, elapsed: Duration) {}
+
+    /// After a successful `del`, whether or not the key existed.
+    fn on_del(&self, key: &[u8], elapsed: Duration) {}
+
+    /// When a compaction has the write lock and is about to rewrite a log of `bytes_before` bytes.
+    fn on_compact_start(&self, bytes_before: u64) {}
+
+    /// When a compaction has swapped in the rewritten log. Not called if it fails.
+    fn on_compact_end(&self, report: &CompactionReport) {}
+
+    /// When a record at offset `pos` in the log does not decode, found by a read, a compaction
+    /// or `Engine::verify`.
+    fn on_corruption(&self, pos: u64, reason: &str) {}
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::constants::{DEFAULT_CHUNK_SIZE, DEFAULT_COMPACT_THRESHOLD, DEFAULT_IDEMPOTENCY_WINDOW};
+use crate::observer::EngineObserver;
+use crate::types::{LoadProgress, RemoteEntry};
+
+pub type ProgressHook = Arc<dyn Fn(&LoadProgress) + Send + Sync>;
+
+/// Folds one merge operand into a key's existing value: `(key, existing, operand) -> new value`.
+pub type MergeOperator = Arc<dyn Fn(&[u8], Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Decides whether a write from a peer replaces a key's current state in `Engine::apply_remote`:
+/// `(current, incoming) -> replace`. `current` has the key's value (`None` if unset or deleted)
+/// and version. It must give the same answer on every engine, or their copies diverge.
+pub type ConflictResolver = Arc<dyn Fn(&RemoteEntry, &RemoteEntry) -> bool + Send + Sync>;
+
+/// When appended records are forced to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave writing back to the operating system. A crash of the machine (not just the process)
+    /// can lose recent writes.
+    #[default]
+    Never,
+    /// `fsync` the log before every write returns.
+    Always,
+}
+
+/// Tuning knobs accepted by `Engine::load_with_options`.
+#[derive(Clone)]
+pub struct EngineOptions {
+    /// Log size in bytes at which `set` triggers an automatic compaction.
+    pub compact_threshold: u64,
+    /// Number of threads decoding records while the index is rebuilt on load.
+    pub rebuild_threads: usize,
+    /// Called after every batch of records scanned while rebuilding the index. The last call
+    /// has `done` set.
+    pub on_load_progress: Option<ProgressHook>,
+    /// Required for `Engine::merge`; applied on read and when compaction folds operands.
+    pub merge_operator: Option<MergeOperator>,
+    /// Values larger than this are split into records of at most this many value bytes. `None`
+    /// always writes a value as a single record.
+    pub chunk_size: Option<u64>,
+    /// Writes of larger values (or merge operands) fail with `io::ErrorKind::FileTooLarge`.
+    /// `None` accepts any size.
+    pub max_value_size: Option<u64>,
+    /// Count reads and writes per key (approximately) so `Engine::hot_keys` can report them.
+    pub track_hot_keys: bool,
+    /// Whether writes wait for the log to reach stable storage.
+    pub sync: SyncPolicy,
+    /// Identifies this engine's own writes to the peers it syncs with through `apply_remote`.
+    /// Each peer needs a different one.
+    pub node_id: u64,
+    /// Settles conflicts in `apply_remote`. `None` keeps the write with the higher sequence
+    /// number, then the higher node id.
+    pub conflict_resolver: Option<ConflictResolver>,
+    /// Directory to copy every record appended to the log into, in hourly files that compaction
+    /// leaves alone. See `archive::Archive`.
+    pub archive_dir: Option<PathBuf>,
+    /// Archive files last written longer ago than this are deleted. `None` keeps them all.
+    pub archive_retention: Option<Duration>,
+    /// Writes fail with `io::ErrorKind::StorageFull` while the file system holding the log has
+    /// fewer bytes free than this, so a full disk never tears the end of the log. Reads and
+    /// compaction carry on. `None`, or storage without a file system, never checks.
+    pub min_free_space: Option<u64>,
+    /// Writes that would add a key fail with `io::ErrorKind::OutOfMemory` once the index's
+    /// estimate of its memory (see `IndexStats`) would pass this many bytes. Overwrites, deletes
+    /// and reads of existing keys carry on, as does replication from a primary. `None` lets the
+    /// index grow without limit.
+    pub max_index_memory: Option<u64>,
+    /// Gets, sets, deletes, compactions and index rebuilds taking at least this long are logged
+    /// as tracing events and kept for `Engine::slow_ops`. `None` times nothing.
+    pub slow_op_threshold: Option<Duration>,
+    /// How long `Engine::set_idempotent` remembers a request id, so a retry within it is a no-op.
+    pub idempotency_window: Duration,
+    /// Keep the value of a key removed with `Engine::del` or `Engine::compare_and_del` for this
+    /// long, so `Engine::undelete` can bring it back. `None` deletes for good.
+    pub trash_retention: Option<Duration>,
+    /// Told about gets, sets, deletes, compactions and corrupt records as they happen.
+    pub observer: Option<Arc<dyn EngineObserver>>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            compact_threshold: DEFAULT_COMPACT_THRESHOLD,
+            rebuild_threads: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            on_load_progress: None,
+            merge_operator: None,
+            chunk_size: Some(DEFAULT_CHUNK_SIZE),
+            max_value_size: None,
+            track_hot_keys: false,
+            sync: SyncPolicy::Never,
+            node_id: 0,
+            conflict_resolver: None,
+            archive_dir: None,
+            archive_retention: None,
+            min_free_space: None,
+            max_index_memory: None,
+            slow_op_threshold: None,
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+            trash_retention: None,
+            observer: None,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::Engine;
+
+/// An engine opened from Python. Keys and values are `bytes`. Closing it, by `close()` or at
+/// the end of a `with` block, syncs the log and releases its file.
+#[pyclass(name = "Engine", module = "breakout1_kv_store")]
+struct PyEngine {
+    engine: Option<Engine>,
+}
+
+/// Opens the log at `path`, creating it if it does not exist.
+#[pyfunction]
+fn open(py: Python<'_>, path: PathBuf) -> PyResult<PyEngine> {
+    let engine = py.allow_threads(|| Engine::load(path))?;
+    Ok(PyEngine {
+        engine: Some(engine),
+    })
+}
+
+#[pymethods]
+impl PyEngine {
+    fn get<'py>(&self, py: Python<'py>, key: &[u8]) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let engine = self.engine()?;
+        let value = py.allow_threads(|| engine.get(key))?;
+        Ok(value.map(|value| PyBytes::new(py, &value)))
+    }
+
+    fn set(&self, py: Python<'_>, key: &[u8], value: &[u8]) -> PyResult<()> {
+        let engine = self.engine()?;
+        Ok(py.allow_threads(|| engine.set(key, value).map(drop))?)
+    }
+
+    fn delete(&self, py: Python<'_>, key: &[u8]) -> PyResult<()> {
+        let engine = self.engine()?;
+        Ok(py.allow_threads(|| engine.del(key))?)
+    }
+
+    /// `(key, value)` pairs under `prefix` in key order, all of them unless `limit` is given.
+    #[pyo3(signature = (prefix, limit = None))]
+    fn scan_prefix<'py>(
+        &self,
+        py: Python<'py>,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> PyResult<Vec<(Bound<'py, PyBytes>, Bound<'py, PyBytes>)>> {
+        let engine = self.engine()?;
+        let pairs = py.allow_threads(|| engine.scan(prefix, limit.unwrap_or(usize::MAX)))?;
+        Ok(pairs
+            .iter()
+            .map(|(key, value)| (PyBytes::new(py, key), PyBytes::new(py, value)))
+            .collect())
+    }
+
+    /// Syncs and closes the log. Closing twice is allowed; any other call afterwards raises
+    /// `ValueError`, as on a closed Python file.
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        match self.engine.take() {
+            Some(engine) => Ok(py.allow_threads(|| engine.sync())?),
+            None => Ok(()),
+        }
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: &Bound<'_, PyAny>,
+        _exc_value: &Bound<'_, PyAny>,
+        _traceback: &Bound<'_, PyAny>,
+    ) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+}
+
+impl PyEngine {
+    fn engine(&self) -> PyResult<&Engine> {
+        self.engine
+            .as_ref()
+            .ok_or_else(|| PyValueError::new_err("engine is closed"))
+    }
+}
+
+/// The `breakout1_kv_store` Python module, built with `maturin` and the `python` feature.
+#[pymodule]
+fn breakout1_kv_store(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEngine>()?;
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,1021 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::constants::RAFT_APPLIED_KEY;
+use crate::engine::Engine;
+use crate::options::{EngineOptions, SyncPolicy};
+use crate::types::BatchOp;
+
+/// Prefix of the Raft log's entry keys, followed by the entry's index as a big-endian u64.
+const ENTRY_PREFIX: &[u8] = b"entry:";
+/// First key after every entry key.
+const ENTRY_END: &[u8] = b"entry;";
+/// Key holding the current term and the vote cast in it.
+const STATE_KEY: &[u8] = b"state";
+/// Key holding the index and term of the last entry discarded from the log.
+const DISCARDED_KEY: &[u8] = b"discarded";
+/// Entries read from the log's store at a time while it is opened.
+const LOAD_BATCH: usize = 1024;
+/// Entries discarded from the log at a time, so applying entries never stalls on it.
+const DISCARD_BATCH: u64 = 1024;
+/// Bytes of encoded entries an append request stops adding entries at.
+pub const MAX_APPEND_BYTES: usize = 4 * 1024 * 1024;
+
+const OP_SET: u8 = 0;
+const OP_DEL: u8 = 1;
+
+/// Identifies a node of a cluster. Ids are nonzero and differ between the nodes.
+pub type NodeId = u64;
+
+/// One entry of the replicated log: the batch a leader accepted in `term`. The leader of a new
+/// term starts it with an entry without ops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub term: u64,
+    pub ops: Vec<BatchOp>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// How long a node waits for its leader, and how often the leader tells it it is still there.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// A follower hearing nothing from a leader for between this and twice this stands for
+    /// election.
+    pub election_timeout: Duration,
+    /// Longest a leader leaves a follower without a request. Well under `election_timeout`.
+    pub heartbeat: Duration,
+}
+
+/// Asks the other nodes to elect `candidate` for `term`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate: NodeId,
+    pub last_index: u64,
+    pub last_term: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteResponse {
+    pub term: u64,
+    pub granted: bool,
+}
+
+/// A leader's entries for a follower, following the entry at `prev_index`. Sent without entries
+/// as a heartbeat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppendRequest {
+    pub term: u64,
+    pub leader: NodeId,
+    pub prev_index: u64,
+    pub prev_term: u64,
+    pub entries: Vec<Entry>,
+    /// The leader's commit index.
+    pub commit: u64,
+    /// Entries up to here are held by every node, so the follower may discard those it has
+    /// applied.
+    pub compact_to: u64,
+    /// The leader's read round when it sent the request, echoed in the response.
+    pub round: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppendResponse {
+    pub term: u64,
+    pub success: bool,
+    /// The follower's last entry matching the leader's log when `success` is set, otherwise the
+    /// last index the leader should try next.
+    pub last_index: u64,
+    pub round: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Vote(VoteRequest),
+    Append(AppendRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Vote(VoteResponse),
+    Append(AppendResponse),
+}
+
+/// A linearizable read in progress, from `Raft::read_index`. The read may go ahead once
+/// `Raft::confirmed` holds and the store has applied `index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadTicket {
+    pub term: u64,
+    pub index: u64,
+    pub round: u64,
+}
+
+/// The Raft log of one node, kept in a store of its own with every write synced. Entries are
+/// keyed by index; those every node holds and this one has applied are discarded.
+pub struct RaftLog {
+    engine: Engine,
+    /// Index and term of the last entry discarded, `(0, 0)` before any.
+    discarded: (u64, u64),
+    /// Terms of the entries after `discarded`, in order.
+    terms: VecDeque<u64>,
+}
+
+impl RaftLog {
+    /// Opens the log at `path`. `options` are used with `SyncPolicy::Always`, since a node must
+    /// not forget a vote or an entry it has acknowledged.
+    pub fn open(path: impl AsRef<Path>, options: EngineOptions) -> io::Result<Self> {
+        let engine = Engine::load_with_options(
+            path,
+            EngineOptions {
+                sync: SyncPolicy::Always,
+                ..options
+            },
+        )?;
+        let discarded = match engine.get(DISCARDED_KEY)? {
+            Some(value) => {
+                let mut value = Decoder(&value);
+                let discarded = (value.u64()?, value.u64()?);
+                value.finish()?;
+                discarded
+            }
+            None => (0, 0),
+        };
+        let mut terms = VecDeque::new();
+        let mut after: Option<Vec<u8>> = None;
+        loop {
+            let start = match &after {
+                Some(key) => Bound::Excluded(key.as_slice()),
+                None => Bound::Included(ENTRY_PREFIX),
+            };
+            let page = engine.range(start, Bound::Excluded(ENTRY_END), LOAD_BATCH, false)?;
+            let exhausted = page.len() < LOAD_BATCH;
+            for (key, value) in page {
+                let expected = discarded.0 + terms.len() as u64 + 1;
+                if key != entry_key(expected) {
+                    return Err(invalid("the Raft log has a gap"));
+                }
+                terms.push_back(Decoder(&value).u64()?);
+                after = Some(key);
+            }
+            if exhausted {
+                break;
+            }
+        }
+        Ok(RaftLog {
+            engine,
+            discarded,
+            terms,
+        })
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.discarded.0 + self.terms.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.terms.back().copied().unwrap_or(self.discarded.1)
+    }
+
+    /// Index of the last entry discarded; entries after it are still held.
+    pub fn first_index(&self) -> u64 {
+        self.discarded.0
+    }
+
+    /// Term of the entry at `index`, or `None` if it is discarded or not yet written.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.discarded.0 {
+            return Some(self.discarded.1);
+        }
+        let offset = index.checked_sub(self.discarded.0 + 1)?;
+        self.terms.get(offset as usize).copied()
+    }
+
+    /// The current term and the node voted for in it.
+    fn state(&self) -> io::Result<(u64, Option<NodeId>)> {
+        match self.engine.get(STATE_KEY)? {
+            Some(value) => {
+                let mut value = Decoder(&value);
+                let state = (value.u64()?, value.u64()?);
+                value.finish()?;
+                Ok((state.0, (state.1 != 0).then_some(state.1)))
+            }
+            None => Ok((0, None)),
+        }
+    }
+
+    fn save_state(&self, term: u64, voted_for: Option<NodeId>) -> io::Result<()> {
+        let mut value = Vec::with_capacity(16);
+        value.extend_from_slice(&term.to_be_bytes());
+        value.extend_from_slice(&voted_for.unwrap_or(0).to_be_bytes());
+        self.engine.set(STATE_KEY, &value)?;
+        Ok(())
+    }
+
+    /// Writes `entries` from index `from` on, dropping whatever the log held from there.
+    fn append(&mut self, from: u64, entries: &[Entry]) -> io::Result<()> {
+        let end = from + entries.len() as u64;
+        let mut ops: Vec<BatchOp> = entries
+            .iter()
+            .zip(from..)
+            .map(|(entry, index)| BatchOp::Set {
+                key: entry_key(index),
+                value: entry.encode(),
+            })
+            .collect();
+        ops.extend((end..=self.last_index()).map(|index| BatchOp::Del {
+            key: entry_key(index),
+        }));
+        self.engine.write_batch(&ops)?;
+        self.terms.truncate((from - self.discarded.0 - 1) as usize);
+        self.terms.extend(entries.iter().map(|entry| entry.term));
+        Ok(())
+    }
+
+    /// The entries from `from` to `to`, stopping early once they pass `max_bytes`.
+    fn entries(&self, from: u64, to: u64, max_bytes: usize) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        for index in from..=to {
+            if !entries.is_empty() && bytes >= max_bytes {
+                break;
+            }
+            let value = self
+                .engine
+                .get(&entry_key(index))?
+                .ok_or_else(|| invalid("an entry is missing from the Raft log"))?;
+            bytes += value.len();
+            entries.push(Entry::decode(&value)?);
+        }
+        Ok(entries)
+    }
+
+    /// Drops the entries up to `index`.
+    fn discard_to(&mut self, index: u64) -> io::Result<()> {
+        let Some(term) = self.term_at(index).filter(|_| index > self.discarded.0) else {
+            return Ok(());
+        };
+        let mut discarded = Vec::with_capacity(16);
+        discarded.extend_from_slice(&index.to_be_bytes());
+        discarded.extend_from_slice(&term.to_be_bytes());
+        let mut ops: Vec<BatchOp> = (self.discarded.0 + 1..=index)
+            .map(|index| BatchOp::Del {
+                key: entry_key(index),
+            })
+            .collect();
+        ops.push(BatchOp::Set {
+            key: DISCARDED_KEY.to_vec(),
+            value: discarded,
+        });
+        self.engine.write_batch(&ops)?;
+        self.terms.drain(..(index - self.discarded.0) as usize);
+        self.discarded = (index, term);
+        Ok(())
+    }
+}
+
+/// What a leader knows of one follower.
+struct Progress {
+    /// Index of the next entry to send.
+    next: u64,
+    /// Last index known to match the leader's log.
+    matched: u64,
+    /// Highest read round the follower has answered a request of.
+    acked_round: u64,
+    sent_at: Option<Instant>,
+}
+
+/// One node's side of the Raft consensus protocol, without any I/O but its own log. The caller
+/// moves requests and responses between the nodes, calls `tick` as time passes, and applies
+/// `committed` entries to the store with `apply`, reporting them with `applied_to`.
+///
+/// Writes are proposed to the leader, which commits them once a majority of the nodes hold them.
+/// Reads are linearizable through `read_index`: the leader notes its commit index, confirms with
+/// a majority that it still leads, and the read waits until the store has applied that index.
+pub struct Raft {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    log: RaftLog,
+    timing: Timing,
+    term: u64,
+    voted_for: Option<NodeId>,
+    role: Role,
+    leader: Option<NodeId>,
+    commit: u64,
+    applied: u64,
+    /// When a follower or candidate stands for election next.
+    deadline: Instant,
+    rng: u64,
+    votes: HashSet<NodeId>,
+    /// Peers sent a vote request this election.
+    asked: HashSet<NodeId>,
+    progress: HashMap<NodeId, Progress>,
+    /// Index of the entry without ops this node appended on becoming leader.
+    term_start: u64,
+    /// Read round, raised by every `read_index`.
+    round: u64,
+    /// The leader's `compact_to`, on a follower.
+    compact_to: u64,
+}
+
+impl Raft {
+    /// A follower among `peers`, the ids of the other nodes. `applied` is the last index the
+    /// store has applied, from `applied_index`.
+    pub fn open(
+        id: NodeId,
+        peers: Vec<NodeId>,
+        log: RaftLog,
+        timing: Timing,
+        applied: u64,
+        now: Instant,
+    ) -> io::Result<Self> {
+        if id == 0 || peers.contains(&0) || peers.contains(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "node ids must be nonzero and differ",
+            ));
+        }
+        if peers.len() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a cluster needs at least 3 nodes",
+            ));
+        }
+        if applied < log.first_index() || applied > log.last_index() {
+            return Err(invalid(
+                "the store and the Raft log do not match: one of them was lost or replaced",
+            ));
+        }
+        let (term, voted_for) = log.state()?;
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let mut raft = Raft {
+            id,
+            peers,
+            log,
+            timing,
+            term,
+            voted_for,
+            role: Role::Follower,
+            leader: None,
+            commit: applied,
+            applied,
+            deadline: now,
+            rng: (seed ^ id.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1,
+            votes: HashSet::new(),
+            asked: HashSet::new(),
+            progress: HashMap::new(),
+            term_start: 0,
+            round: 0,
+            compact_to: 0,
+        };
+        raft.reset_deadline(now);
+        Ok(raft)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn peers(&self) -> &[NodeId] {
+        &self.peers
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// The leader of the current term, if this node has heard from it.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit
+    }
+
+    pub fn applied_index(&self) -> u64 {
+        self.applied
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.log.last_index()
+    }
+
+    /// Stands for election once a follower or candidate has waited out its timeout.
+    pub fn tick(&mut self, now: Instant) -> io::Result<()> {
+        if self.role == Role::Leader || now < self.deadline {
+            return Ok(());
+        }
+        self.term += 1;
+        self.voted_for = Some(self.id);
+        self.log.save_state(self.term, self.voted_for)?;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes = HashSet::from([self.id]);
+        self.asked.clear();
+        self.reset_deadline(now);
+        Ok(())
+    }
+
+    /// The request to send `peer` now, if any. A node sends one request at a time to each peer,
+    /// and asks again once it has the response or `on_unreachable` is called.
+    pub fn next_request(&mut self, peer: NodeId, now: Instant) -> io::Result<Option<Request>> {
+        match self.role {
+            Role::Follower => Ok(None),
+            Role::Candidate => Ok(self.asked.insert(peer).then(|| {
+                Request::Vote(VoteRequest {
+                    term: self.term,
+                    candidate: self.id,
+                    last_index: self.log.last_index(),
+                    last_term: self.log.last_term(),
+                })
+            })),
+            Role::Leader => {
+                let last_index = self.log.last_index();
+                let compact_to = self.compact_floor();
+                let Some(progress) = self.progress.get_mut(&peer) else {
+                    return Ok(None);
+                };
+                let due = progress.next <= last_index
+                    || progress.acked_round < self.round
+                    || progress
+                        .sent_at
+                        .is_none_or(|at| now >= at + self.timing.heartbeat);
+                // The entries it needs are gone: the follower lost its log.
+                if !due || progress.next <= self.log.first_index() {
+                    return Ok(None);
+                }
+                let prev_index = progress.next - 1;
+                let entries = match progress.next <= last_index {
+                    true => self
+                        .log
+                        .entries(progress.next, last_index, MAX_APPEND_BYTES)?,
+                    false => Vec::new(),
+                };
+                progress.sent_at = Some(now);
+                Ok(Some(Request::Append(AppendRequest {
+                    term: self.term,
+                    leader: self.id,
+                    prev_index,
+                    prev_term: self.log.term_at(prev_index).unwrap_or(0),
+                    entries,
+                    commit: self.commit,
+                    compact_to,
+                    round: self.round,
+                })))
+            }
+        }
+    }
+
+    /// Answers a peer's request.
+    pub fn handle(&mut self, request: &Request, now: Instant) -> io::Result<Response> {
+        match request {
+            Request::Vote(request) => self.handle_vote(request, now).map(Response::Vote),
+            Request::Append(request) => self.handle_append(request, now).map(Response::Append),
+        }
+    }
+
+    fn handle_vote(&mut self, request: &VoteRequest, now: Instant) -> io::Result<VoteResponse> {
+        if request.term > self.term {
+            self.step_down(request.term)?;
+        }
+        let up_to_date = (request.last_term, request.last_index)
+            >= (self.log.last_term(), self.log.last_index());
+        let granted = request.term == self.term
+            && self.voted_for.is_none_or(|id| id == request.candidate)
+            && up_to_date;
+        if granted {
+            if self.voted_for.is_none() {
+                self.voted_for = Some(request.candidate);
+                self.log.save_state(self.term, self.voted_for)?;
+            }
+            self.reset_deadline(now);
+        }
+        Ok(VoteResponse {
+            term: self.term,
+            granted,
+        })
+    }
+
+    fn handle_append(
+        &mut self,
+        request: &AppendRequest,
+        now: Instant,
+    ) -> io::Result<AppendResponse> {
+        let refuse = |term, last_index| AppendResponse {
+            term,
+            success: false,
+            last_index,
+            round: request.round,
+        };
+        if request.term < self.term {
+            return Ok(refuse(self.term, self.log.last_index()));
+        }
+        if request.term > self.term {
+            self.step_down(request.term)?;
+        }
+        self.role = Role::Follower;
+        self.leader = Some(request.leader);
+        self.reset_deadline(now);
+
+        // Discarded entries are committed, so they match the leader's.
+        let mut prev_index = request.prev_index;
+        let mut entries = request.entries.as_slice();
+        let first_index = self.log.first_index();
+        if prev_index < first_index {
+            let skip = ((first_index - prev_index) as usize).min(entries.len());
+            entries = &entries[skip..];
+            prev_index += skip as u64;
+        }
+        let prev_term = match prev_index == request.prev_index {
+            true => Some(request.prev_term),
+            false => entries_term(&request.entries, prev_index - request.prev_index),
+        };
+        if prev_index >= first_index && self.log.term_at(prev_index) != prev_term {
+            let last_index = self.log.last_index().min(prev_index.saturating_sub(1));
+            return Ok(refuse(self.term, last_index));
+        }
+        let new = entries
+            .iter()
+            .zip(prev_index + 1..)
+            .position(|(entry, index)| self.log.term_at(index) != Some(entry.term));
+        if let Some(new) = new {
+            self.log
+                .append(prev_index + 1 + new as u64, &entries[new..])?;
+        }
+        let matched = prev_index + entries.len() as u64;
+        self.commit = self.commit.max(request.commit.min(matched));
+        self.compact_to = self.compact_to.max(request.compact_to);
+        Ok(AppendResponse {
+            term: self.term,
+            success: true,
+            last_index: matched,
+            round: request.round,
+        })
+    }
+
+    /// Takes in `peer`'s response to the last request sent to it.
+    pub fn on_response(
+        &mut self,
+        peer: NodeId,
+        response: &Response,
+        now: Instant,
+    ) -> io::Result<()> {
+        let term = match response {
+            Response::Vote(response) => response.term,
+            Response::Append(response) => response.term,
+        };
+        if term > self.term {
+            return self.step_down(term);
+        }
+        if term < self.term {
+            return Ok(());
+        }
+        match response {
+            Response::Vote(response) => {
+                if self.role == Role::Candidate && response.granted {
+                    self.votes.insert(peer);
+                    if self.votes.len() >= self.quorum() {
+                        self.become_leader(now)?;
+                    }
+                }
+            }
+            Response::Append(response) => {
+                if self.role != Role::Leader {
+                    return Ok(());
+                }
+                let Some(progress) = self.progress.get_mut(&peer) else {
+                    return Ok(());
+                };
+                progress.acked_round = progress.acked_round.max(response.round);
+                if response.success {
+                    progress.matched = progress.matched.max(response.last_index);
+                    progress.next = progress.matched + 1;
+                    self.advance_commit();
+                } else {
+                    let next = progress.next.saturating_sub(1).min(response.last_index + 1);
+                    progress.next = next.max(progress.matched + 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Notes that the last request to `peer` got no response, so it is asked again.
+    pub fn on_unreachable(&mut self, peer: NodeId) {
+        if self.role == Role::Candidate {
+            self.asked.remove(&peer);
+        }
+    }
+
+    /// Appends a batch to the leader's log, returning its index and term. The batch is applied
+    /// once `committed` hands back an entry with that index and term; an entry of another term
+    /// there means the batch was lost with the leadership. `None` when this node is not the
+    /// leader.
+    pub fn propose(&mut self, ops: Vec<BatchOp>) -> io::Result<Option<(u64, u64)>> {
+        if self.role != Role::Leader {
+            return Ok(None);
+        }
+        let index = self.log.last_index() + 1;
+        self.log.append(
+            index,
+            &[Entry {
+                term: self.term,
+                ops,
+            }],
+        )?;
+        Ok(Some((index, self.term)))
+    }
+
+    /// Starts a linearizable read, or `None` when this node is not the leader.
+    pub fn read_index(&mut self) -> Option<ReadTicket> {
+        if self.role != Role::Leader {
+            return None;
+        }
+        self.round += 1;
+        Some(ReadTicket {
+            term: self.term,
+            // Until the entry opening its term commits, a new leader may not know the last
+            // commit index.
+            index: self.commit.max(self.term_start),
+            round: self.round,
+        })
+    }
+
+    /// Whether a majority has acknowledged this node as leader since `ticket` was issued.
+    pub fn confirmed(&self, ticket: &ReadTicket) -> bool {
+        let acked = self
+            .progress
+            .values()
+            .filter(|progress| progress.acked_round >= ticket.round)
+            .count();
+        self.role == Role::Leader && self.term == ticket.term && acked + 1 >= self.quorum()
+    }
+
+    /// Committed entries the store has yet to apply, with their indexes, in order. Stops once
+    /// they pass `max_bytes`.
+    pub fn committed(&self, max_bytes: usize) -> io::Result<Vec<(u64, Entry)>> {
+        if self.applied >= self.commit {
+            return Ok(Vec::new());
+        }
+        let entries = self.log.entries(self.applied + 1, self.commit, max_bytes)?;
+        Ok((self.applied + 1..).zip(entries).collect())
+    }
+
+    /// Records that the store has applied the entries up to `index`, and discards those every
+    /// node holds.
+    pub fn applied_to(&mut self, index: u64) -> io::Result<()> {
+        self.applied = self.applied.max(index);
+        let floor = match self.role {
+            Role::Leader => self.compact_floor(),
+            _ => self.compact_to,
+        };
+        let to = floor
+            .min(self.applied)
+            .min(self.log.first_index() + DISCARD_BATCH);
+        self.log.discard_to(to)
+    }
+
+    fn quorum(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    /// The last index every follower is known to hold.
+    fn compact_floor(&self) -> u64 {
+        match self.role {
+            Role::Leader => self.progress.values().map(|p| p.matched).min().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn step_down(&mut self, term: u64) -> io::Result<()> {
+        self.term = term;
+        self.voted_for = None;
+        self.log.save_state(term, None)?;
+        self.role = Role::Follower;
+        self.leader = None;
+        self.progress.clear();
+        Ok(())
+    }
+
+    fn become_leader(&mut self, now: Instant) -> io::Result<()> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        let next = self.log.last_index() + 1;
+        self.progress = self
+            .peers
+            .iter()
+            .map(|&peer| {
+                let progress = Progress {
+                    next,
+                    matched: 0,
+                    acked_round: 0,
+                    sent_at: None,
+                };
+                (peer, progress)
+            })
+            .collect();
+        self.log.append(
+            next,
+            &[Entry {
+                term: self.term,
+                ops: Vec::new(),
+            }],
+        )?;
+        self.term_start = next;
+        self.reset_deadline(now);
+        Ok(())
+    }
+
+    /// Commits the last entry of this term a majority holds, with every entry before it.
+    fn advance_commit(&mut self) {
+        let mut matched: Vec<u64> = self.progress.values().map(|p| p.matched).collect();
+        matched.push(self.log.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let index = matched[self.quorum() - 1];
+        if index > self.commit && self.log.term_at(index) == Some(self.term) {
+            self.commit = index;
+        }
+    }
+
+    /// Picks when to stand for election, between one and two election timeouts from `now`.
+    fn reset_deadline(&mut self, now: Instant) {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let timeout = self.timing.election_timeout;
+        let jitter = self.rng % (timeout.as_millis() as u64).max(1);
+        self.deadline = now + timeout + Duration::from_millis(jitter);
+    }
+}
+
+/// Applies a committed entry to the store it replicates, recording `index` as applied in the
+/// same batch, after the entry's ops. Entries are only ever applied whole and in order, so a
+/// crash part way through leaves the entry to be applied again. Returns, for each op, whether
+/// its key existed just before.
+pub fn apply(engine: &Engine, index: u64, entry: Entry) -> io::Result<Vec<bool>> {
+    let mut ops = entry.ops;
+    ops.push(BatchOp::Set {
+        key: RAFT_APPLIED_KEY.to_vec(),
+        value: index.to_be_bytes().to_vec(),
+    });
+    let mut existed = engine.write_batch(&ops)?;
+    existed.pop();
+    Ok(existed)
+}
+
+/// The last index `apply` recorded in `engine`, or 0.
+pub fn applied_index(engine: &Engine) -> io::Result<u64> {
+    match engine.get(RAFT_APPLIED_KEY)? {
+        Some(value) => {
+            let mut value = Decoder(&value);
+            let index = value.u64()?;
+            value.finish()?;
+            Ok(index)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Term of the `n`th (from 1) of `entries`.
+fn entries_term(entries: &[Entry], n: u64) -> Option<u64> {
+    let i = usize::try_from(n).ok()?.checked_sub(1)?;
+    entries.get(i).map(|entry| entry.term)
+}
+
+fn entry_key(index: u64) -> Vec<u8> {
+    let mut key = ENTRY_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+// Integers are big-endian; byte strings carry a u32 length.
+//
+// Entry: `[u64 term][u32 op count]` then per op `[u8 kind][key]` and, for a set, `[value]`.
+// Vote request: `[u64 term][u64 candidate][u64 last index][u64 last term]`.
+// Vote response: `[u64 term][u8 granted]`.
+// Append request: `[u64 term][u64 leader][u64 prev index][u64 prev term][u64 commit]
+// [u64 compact to][u64 round][u32 entry count]` then per entry `[entry]`.
+// Append response: `[u64 term][u8 success][u64 last index][u64 round]`.
+
+impl Entry {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.term.to_be_bytes());
+        buf.extend_from_slice(&(self.ops.len() as u32).to_be_bytes());
+        for op in &self.ops {
+            match op {
+                BatchOp::Set { key, value } => {
+                    buf.push(OP_SET);
+                    put_bytes(buf, key);
+                    put_bytes(buf, value);
+                }
+                BatchOp::Del { key } => {
+                    buf.push(OP_DEL);
+                    put_bytes(buf, key);
+                }
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder(bytes);
+        let entry = Self::decode_from(&mut decoder)?;
+        decoder.finish()?;
+        Ok(entry)
+    }
+
+    fn decode_from(decoder: &mut Decoder) -> io::Result<Self> {
+        let term = decoder.u64()?;
+        let count = decoder.u32()?;
+        let mut ops = Vec::new();
+        for _ in 0..count {
+            ops.push(match decoder.u8()? {
+                OP_SET => BatchOp::Set {
+                    key: decoder.bytes()?,
+                    value: decoder.bytes()?,
+                },
+                OP_DEL => BatchOp::Del {
+                    key: decoder.bytes()?,
+                },
+                _ => return Err(invalid("unknown op in a Raft entry")),
+            });
+        }
+        Ok(Entry { term, ops })
+    }
+}
+
+impl VoteRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        [self.term, self.candidate, self.last_index, self.last_term]
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder(bytes);
+        let request = VoteRequest {
+            term: decoder.u64()?,
+            candidate: decoder.u64()?,
+            last_index: decoder.u64()?,
+            last_term: decoder.u64()?,
+        };
+        decoder.finish()?;
+        Ok(request)
+    }
+}
+
+impl VoteResponse {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.term.to_be_bytes().to_vec();
+        buf.push(self.granted as u8);
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder(bytes);
+        let response = VoteResponse {
+            term: decoder.u64()?,
+            granted: decoder.u8()? != 0,
+        };
+        decoder.finish()?;
+        Ok(response)
+    }
+}
+
+impl AppendRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for n in [
+            self.term,
+            self.leader,
+            self.prev_index,
+            self.prev_term,
+            self.commit,
+            self.compact_to,
+            self.round,
+        ] {
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            entry.encode_into(&mut buf);
+        }
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder(bytes);
+        let mut request = AppendRequest {
+            term: decoder.u64()?,
+            leader: decoder.u64()?,
+            prev_index: decoder.u64()?,
+            prev_term: decoder.u64()?,
+            commit: decoder.u64()?,
+            compact_to: decoder.u64()?,
+            round: decoder.u64()?,
+            entries: Vec::new(),
+        };
+        for _ in 0..decoder.u32()? {
+            request.entries.push(Entry::decode_from(&mut decoder)?);
+        }
+        decoder.finish()?;
+        Ok(request)
+    }
+}
+
+impl AppendResponse {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.term.to_be_bytes().to_vec();
+        buf.push(self.success as u8);
+        buf.extend_from_slice(&self.last_index.to_be_bytes());
+        buf.extend_from_slice(&self.round.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder(bytes);
+        let response = AppendResponse {
+            term: decoder.u64()?,
+            success: decoder.u8()? != 0,
+            last_index: decoder.u64()?,
+            round: decoder.u64()?,
+        };
+        decoder.finish()?;
+        Ok(response)
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Reads the fields of an encoded message in turn.
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let (head, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or_else(|| invalid("Raft message is cut short"))?;
+        self.0 = rest;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return Err(invalid("Raft message is cut short"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes.to_vec())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err(invalid("Raft message has trailing bytes")),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
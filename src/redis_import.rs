@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::Engine;
+use crate::constants::IMPORT_BATCH;
+use crate::engine::now_millis;
+use crate::types::{BatchOp, ImportReport};
+
+const RDB_MAGIC: &[u8] = b"REDIS";
+
+const OPCODE_SLOT_INFO: u8 = 244;
+const OPCODE_FUNCTION2: u8 = 245;
+const OPCODE_MODULE_AUX: u8 = 247;
+const OPCODE_IDLE: u8 = 248;
+const OPCODE_FREQ: u8 = 249;
+const OPCODE_AUX: u8 = 250;
+const OPCODE_RESIZEDB: u8 = 251;
+const OPCODE_EXPIRETIME_MS: u8 = 252;
+const OPCODE_EXPIRETIME: u8 = 253;
+const OPCODE_SELECTDB: u8 = 254;
+const OPCODE_EOF: u8 = 255;
+
+const TYPE_STRING: u8 = 0;
+
+/// A string key read from a dump, with its expiry in milliseconds since the Unix epoch.
+struct Item {
+    value: Vec<u8>,
+    expires_at: Option<i64>,
+}
+
+/// The string keys of database 0, built up by reading a snapshot and replaying commands.
+#[derive(Default)]
+struct Dataset {
+    keys: HashMap<Vec<u8>, Item>,
+    /// Values of other types, keys in other databases and commands on either.
+    skipped: usize,
+    /// Database the commands being replayed apply to.
+    db: u64,
+}
+
+impl Engine {
+    /// Imports the string keys of database 0 from a Redis RDB snapshot, an AOF file (with or
+    /// without an RDB preamble) or a Redis 7 `appendonlydir` with its manifest. Keys keep their
+    /// remaining TTL; keys already expired are left out. Other value types and databases are
+    /// skipped and counted. The dump is read whole before anything is written, so the keys are
+    /// held in memory while importing, and keys already in the store are overwritten.
+    pub fn import_redis(&self, path: impl AsRef<Path>) -> io::Result<ImportReport> {
+        let path = path.as_ref();
+        let mut dataset = Dataset::default();
+        if path.is_dir() {
+            for file in manifest_files(path)? {
+                dataset.load(&path.join(file))?;
+            }
+        } else {
+            dataset.load(path)?;
+        }
+        dataset.write_to(self)
+    }
+}
+
+/// The base and incremental files listed in an `appendonlydir` manifest, in replay order.
+fn manifest_files(dir: &Path) -> io::Result<Vec<String>> {
+    let manifest = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "manifest"))
+        .ok_or_else(|| invalid("directory has no AOF manifest"))?;
+
+    let mut base = Vec::new();
+    let mut incr = Vec::new();
+    for line in fs::read_to_string(manifest)?.lines() {
+        // Lines are `file <name> seq <n> type <b|h|i>`.
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let field = |name: &str| {
+            words
+                .chunks(2)
+                .find(|pair| pair[0] == name)
+                .and_then(|pair| pair.get(1).copied())
+        };
+        let (Some(file), Some(kind)) = (field("file"), field("type")) else {
+            continue;
+        };
+        let seq: u64 = field("seq").and_then(|seq| seq.parse().ok()).unwrap_or(0);
+        match kind {
+            "b" => base.push((seq, file.to_string())),
+            "i" => incr.push((seq, file.to_string())),
+            _ => {}
+        }
+    }
+    base.sort();
+    incr.sort();
+    Ok(base.into_iter().chain(incr).map(|(_, file)| file).collect())
+}
+
+impl Dataset {
+    /// Reads one file: an RDB snapshot, commands in AOF format, or a snapshot followed by
+    /// commands.
+    fn load(&mut self, path: &Path) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if reader.fill_buf()?.starts_with(RDB_MAGIC) {
+            self.load_rdb(&mut reader)?;
+        }
+        self.db = 0;
+        self.replay_aof(&mut reader)
+    }
+
+    fn load_rdb(&mut self, reader: &mut impl BufRead) -> io::Result<()> {
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header)?;
+        let version: u32 = std::str::from_utf8(&header[RDB_MAGIC.len()..])
+            .ok()
+            .and_then(|version| version.parse().ok())
+            .ok_or_else(|| invalid("bad RDB version"))?;
+        let mut rdb = Rdb { reader };
+        let mut db = 0;
+        let mut expires_at = None;
+
+        loop {
+            let op = rdb.byte()?;
+            match op {
+                OPCODE_EOF => break,
+                OPCODE_SELECTDB => db = rdb.length()?,
+                OPCODE_RESIZEDB => {
+                    rdb.length()?;
+                    rdb.length()?;
+                }
+                OPCODE_AUX => {
+                    rdb.string()?;
+                    rdb.string()?;
+                }
+                OPCODE_EXPIRETIME_MS => expires_at = Some(rdb.u64_le()? as i64),
+                OPCODE_EXPIRETIME => expires_at = Some(rdb.u32_le()? as i64 * 1000),
+                OPCODE_IDLE => {
+                    rdb.length()?;
+                }
+                OPCODE_FREQ => {
+                    rdb.byte()?;
+                }
+                OPCODE_FUNCTION2 => {
+                    rdb.string()?;
+                }
+                OPCODE_SLOT_INFO => {
+                    for _ in 0..3 {
+                        rdb.length()?;
+                    }
+                }
+                OPCODE_MODULE_AUX => return Err(invalid("RDB module data is not supported")),
+                value_type => {
+                    let key = rdb.string()?;
+                    if value_type == TYPE_STRING && db == 0 {
+                        let value = rdb.string()?;
+                        self.keys.insert(key, Item { value, expires_at });
+                    } else {
+                        rdb.skip_value(value_type)?;
+                        self.skipped += 1;
+                    }
+                    expires_at = None;
+                }
+            }
+        }
+        // An 8-byte checksum follows from version 5 on.
+        if version >= 5 {
+            rdb.u64_le()?;
+        }
+        Ok(())
+    }
+
+    /// Replays AOF commands until the end of the file. A command cut off by a crash at the end
+    /// is ignored, as Redis does by default.
+    fn replay_aof(&mut self, reader: &mut impl BufRead) -> io::Result<()> {
+        loop {
+            let mut line = Vec::new();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            // Redis 7 writes `#TS:<unix time>` annotations between commands.
+            if line.starts_with(b"#") || line.trim_ascii().is_empty() {
+                continue;
+            }
+            let argc = resp_number(&line, b'*')?;
+            let mut args = Vec::with_capacity(argc);
+            for _ in 0..argc {
+                match read_bulk(reader)? {
+                    Some(arg) => args.push(arg),
+                    None => return Ok(()),
+                }
+            }
+            self.apply(&args)?;
+        }
+    }
+
+    /// Applies one command to the string keys. Commands this import cannot represent, such as
+    /// those on lists or hashes, are skipped.
+    fn apply(&mut self, args: &[Vec<u8>]) -> io::Result<()> {
+        let Some((name, args)) = args.split_first() else {
+            return Ok(());
+        };
+        let name = name.to_ascii_uppercase();
+        if name == b"SELECT" {
+            self.db = parse_arg(args.first())?;
+            return Ok(());
+        }
+        if self.db != 0 {
+            self.skipped += 1;
+            return Ok(());
+        }
+        let now = now_millis();
+
+        match (name.as_slice(), args) {
+            (b"SET", [key, value, options @ ..]) => self.set_command(key, value, options, now)?,
+            (b"SETNX", [key, value]) => {
+                if !self.exists(key, now) {
+                    self.put(key, value.clone(), None);
+                }
+            }
+            (b"SETEX", [key, secs, value]) => {
+                let at = now + parse_arg::<i64>(Some(secs))? * 1000;
+                self.put(key, value.clone(), Some(at));
+            }
+            (b"PSETEX", [key, ms, value]) => {
+                let at = now + parse_arg::<i64>(Some(ms))?;
+                self.put(key, value.clone(), Some(at));
+            }
+            (b"GETSET", [key, value]) => self.put(key, value.clone(), None),
+            (b"MSET", pairs) => {
+                for pair in pairs.chunks_exact(2) {
+                    self.put(&pair[0], pair[1].clone(), None);
+                }
+            }
+            (b"MSETNX", pairs) => {
+                if pairs
+                    .chunks_exact(2)
+                    .all(|pair| !self.exists(&pair[0], now))
+                {
+                    for pair in pairs.chunks_exact(2) {
+                        self.put(&pair[0], pair[1].clone(), None);
+                    }
+                }
+            }
+            (b"APPEND", [key, data]) => match self.keys.get_mut(key.as_slice()) {
+                Some(item) => item.value.extend_from_slice(data),
+                None => self.put(key, data.clone(), None),
+            },
+            (b"INCR", [key]) => self.incr(key, 1)?,
+            (b"DECR", [key]) => self.incr(key, -1)?,
+            (b"INCRBY", [key, by]) => self.incr(key, parse_arg(Some(by))?)?,
+            (b"DECRBY", [key, by]) => self.incr(key, -parse_arg::<i64>(Some(by))?)?,
+            (b"DEL" | b"UNLINK", keys) => {
+                for key in keys {
+                    self.keys.remove(key.as_slice());
+                }
+            }
+            (b"GETDEL", [key]) => {
+                self.keys.remove(key.as_slice());
+            }
+            (b"EXPIRE", [key, secs, ..]) => {
+                self.expire(key, now + parse_arg::<i64>(Some(secs))? * 1000)
+            }
+            (b"PEXPIRE", [key, ms, ..]) => self.expire(key, now + parse_arg::<i64>(Some(ms))?),
+            (b"EXPIREAT", [key, at, ..]) => self.expire(key, parse_arg::<i64>(Some(at))? * 1000),
+            (b"PEXPIREAT", [key, at, ..]) => self.expire(key, parse_arg(Some(at))?),
+            (b"PERSIST", [key]) => {
+                if let Some(item) = self.keys.get_mut(key.as_slice()) {
+                    item.expires_at = None;
+                }
+            }
+            (b"RENAME", [from, to]) => {
+                if let Some(item) = self.keys.remove(from.as_slice()) {
+                    self.keys.insert(to.clone(), item);
+                }
+            }
+            (b"RENAMENX", [from, to]) => {
+                if !self.exists(to, now)
+                    && let Some(item) = self.keys.remove(from.as_slice())
+                {
+                    self.keys.insert(to.clone(), item);
+                }
+            }
+            (b"FLUSHDB" | b"FLUSHALL", _) => self.keys.clear(),
+            (b"MULTI" | b"EXEC", _) => {}
+            _ => self.skipped += 1,
+        }
+        Ok(())
+    }
+
+    /// `SET key value [NX|XX] [GET] [EX s|PX ms|EXAT s|PXAT ms|KEEPTTL]`.
+    fn set_command(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        options: &[Vec<u8>],
+        now: i64,
+    ) -> io::Result<()> {
+        let mut expires_at = None;
+        let mut keep_ttl = false;
+        let (mut nx, mut xx) = (false, false);
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GET" => {}
+                b"KEEPTTL" => keep_ttl = true,
+                b"EX" => expires_at = Some(now + parse_arg::<i64>(options.next())? * 1000),
+                b"PX" => expires_at = Some(now + parse_arg::<i64>(options.next())?),
+                b"EXAT" => expires_at = Some(parse_arg::<i64>(options.next())? * 1000),
+                b"PXAT" => expires_at = Some(parse_arg(options.next())?),
+                _ => return Err(invalid("unknown SET option in AOF")),
+            }
+        }
+        let exists = self.exists(key, now);
+        if (nx && exists) || (xx && !exists) {
+            return Ok(());
+        }
+        if keep_ttl {
+            expires_at = self.keys.get(key).and_then(|item| item.expires_at);
+        }
+        self.put(key, value.to_vec(), expires_at);
+        Ok(())
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>, expires_at: Option<i64>) {
+        self.keys.insert(key.to_vec(), Item { value, expires_at });
+    }
+
+    fn exists(&self, key: &[u8], now: i64) -> bool {
+        self.keys
+            .get(key)
+            .is_some_and(|item| item.expires_at.is_none_or(|at| at > now))
+    }
+
+    fn expire(&mut self, key: &[u8], at: i64) {
+        if let Some(item) = self.keys.get_mut(key) {
+            item.expires_at = Some(at);
+        }
+    }
+
+    fn incr(&mut self, key: &[u8], by: i64) -> io::Result<()> {
+        let item = self.keys.entry(key.to_vec()).or_insert_with(|| Item {
+            value: b"0".to_vec(),
+            expires_at: None,
+        });
+        let current: i64 = parse_arg(Some(&item.value))?;
+        item.value = current.saturating_add(by).to_string().into_bytes();
+        Ok(())
+    }
+
+    /// Writes the live keys: those without a TTL in batches, the others one at a time with the
+    /// TTL they have left.
+    fn write_to(self, engine: &Engine) -> io::Result<ImportReport> {
+        let now = now_millis();
+        let mut report = ImportReport {
+            skipped: self.skipped,
+            ..ImportReport::default()
+        };
+        let mut batch = Vec::with_capacity(IMPORT_BATCH);
+        for (key, item) in self.keys {
+            match item.expires_at {
+                Some(at) if at <= now => {
+                    report.expired += 1;
+                    continue;
+                }
+                Some(at) => {
+                    let ttl = Duration::from_millis((at - now) as u64);
+                    engine.set_with_ttl(&key, &item.value, ttl)?;
+                }
+                None => {
+                    batch.push(BatchOp::Set {
+                        key,
+                        value: item.value,
+                    });
+                    if batch.len() == IMPORT_BATCH {
+                        engine.write_batch(&batch)?;
+                        batch.clear();
+                    }
+                }
+            }
+            report.keys += 1;
+        }
+        if !batch.is_empty() {
+            engine.write_batch(&batch)?;
+        }
+        Ok(report)
+    }
+}
+
+/// Reads the RDB encoding: length-prefixed strings, which may be integers or LZF-compressed,
+/// and the layouts of the value types.
+struct Rdb<'a, R> {
+    reader: &'a mut R,
+}
+
+/// A length, or the special encoding flagged in its place.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+impl<R: Read> Rdb<'_, R> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut buf = [0u8; 1];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn bytes(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut *self.reader).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "RDB file ends early",
+            ));
+        }
+        Ok(buf)
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        self.reader.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64_le(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        self.reader.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn length_or_encoding(&mut self) -> io::Result<Length> {
+        let first = self.byte()?;
+        Ok(match first >> 6 {
+            0 => Length::Len((first & 0x3f) as u64),
+            1 => Length::Len((((first & 0x3f) as u64) << 8) | self.byte()? as u64),
+            2 if first == 0x80 => {
+                let mut buf = [0u8; 4];
+                self.reader.read_exact(&mut buf)?;
+                Length::Len(u32::from_be_bytes(buf) as u64)
+            }
+            2 if first == 0x81 => {
+                let mut buf = [0u8; 8];
+                self.reader.read_exact(&mut buf)?;
+                Length::Len(u64::from_be_bytes(buf))
+            }
+            2 => return Err(invalid("bad RDB length")),
+            _ => Length::Encoded(first & 0x3f),
+        })
+    }
+
+    fn length(&mut self) -> io::Result<u64> {
+        match self.length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid("RDB length is an encoded string")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        let number = match self.length_or_encoding()? {
+            Length::Len(len) => return self.bytes(len),
+            Length::Encoded(0) => self.byte()? as i8 as i64,
+            Length::Encoded(1) => {
+                let mut buf = [0u8; 2];
+                self.reader.read_exact(&mut buf)?;
+                i16::from_le_bytes(buf) as i64
+            }
+            Length::Encoded(2) => self.u32_le()? as i32 as i64,
+            Length::Encoded(3) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                let compressed = self.bytes(compressed_len)?;
+                return lzf_decompress(&compressed, len as usize);
+            }
+            Length::Encoded(_) => return Err(invalid("unknown RDB string encoding")),
+        };
+        Ok(number.to_string().into_bytes())
+    }
+
+    fn skip_strings(&mut self, count: u64) -> io::Result<()> {
+        for _ in 0..count {
+            self.string()?;
+        }
+        Ok(())
+    }
+
+    /// Reads past a value of a type the import does not keep.
+    fn skip_value(&mut self, value_type: u8) -> io::Result<()> {
+        match value_type {
+            // list, set
+            1 | 2 => {
+                let len = self.length()?;
+                self.skip_strings(len)
+            }
+            // zset: members with scores as strings of a one-byte length
+            3 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    match self.byte()? {
+                        253..=255 => {}
+                        len => {
+                            self.bytes(len as u64)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            // hash
+            4 => {
+                let len = self.length()?;
+                self.skip_strings(len * 2)
+            }
+            // zset2: members with binary double scores
+            5 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.u64_le()?;
+                }
+                Ok(())
+            }
+            // zipmap, ziplist, intset and listpack encodings are one string each
+            9..=13 | 16 | 17 | 20 => self.skip_strings(1),
+            // quicklist of ziplists
+            14 => {
+                let len = self.length()?;
+                self.skip_strings(len)
+            }
+            // quicklist2: nodes with a container type
+            18 => {
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.string()?;
+                }
+                Ok(())
+            }
+            15 | 19 | 21 => self.skip_stream(value_type),
+            // hash with field expiry: minimum expiry, then fields with their TTLs
+            24 => {
+                self.u64_le()?;
+                for _ in 0..self.length()? {
+                    self.length()?;
+                    self.skip_strings(2)?;
+                }
+                Ok(())
+            }
+            // listpack hash with field expiry
+            25 => {
+                self.u64_le()?;
+                self.skip_strings(1)
+            }
+            6 | 7 => Err(invalid("RDB module values are not supported")),
+            other => Err(invalid(&format!("unknown RDB value type {}", other))),
+        }
+    }
+
+    /// Streams, in their three RDB layouts: 15, 19 (Redis 7) and 21 (Redis 7.4).
+    fn skip_stream(&mut self, value_type: u8) -> io::Result<()> {
+        let nodes = self.length()?;
+        self.skip_strings(nodes * 2)?;
+        // Length and last id, then first id, max deleted id and entries added.
+        let fields = if value_type == 15 { 3 } else { 8 };
+        for _ in 0..fields {
+            self.length()?;
+        }
+        for _ in 0..self.length()? {
+            self.string()?;
+            self.length()?;
+            self.length()?;
+            if value_type != 15 {
+                self.length()?;
+            }
+            // Pending entries: a 128-bit id, delivery time and count.
+            for _ in 0..self.length()? {
+                self.bytes(16 + 8)?;
+                self.length()?;
+            }
+            for _ in 0..self.length()? {
+                self.string()?;
+                self.u64_le()?;
+                if value_type == 21 {
+                    self.u64_le()?;
+                }
+                let pending = self.length()?;
+                self.bytes(pending * 16)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let corrupt = || invalid("corrupt LZF string in RDB");
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            let literal = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(literal);
+            i += ctrl + 1;
+            continue;
+        }
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += *input.get(i).ok_or_else(corrupt)? as usize;
+            i += 1;
+        }
+        let back = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or_else(corrupt)? as usize + 1;
+        i += 1;
+        let start = out.len().checked_sub(back).ok_or_else(corrupt)?;
+        for k in 0..run + 2 {
+            out.push(out[start + k]);
+        }
+    }
+    match out.len() == len {
+        true => Ok(out),
+        false => Err(corrupt()),
+    }
+}
+
+/// Reads `$<len>\r\n<bytes>\r\n`, or `None` if the file ends part way.
+fn read_bulk(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Ok(None);
+    }
+    let len = resp_number(&line, b'$')?;
+    let mut arg = Vec::with_capacity(len + 2);
+    (&mut *reader).take(len as u64 + 2).read_to_end(&mut arg)?;
+    if arg.len() < len + 2 {
+        return Ok(None);
+    }
+    arg.truncate(len);
+    Ok(Some(arg))
+}
+
+/// The number in a `*<n>` or `$<n>` line.
+fn resp_number(line: &[u8], marker: u8) -> io::Result<usize> {
+    line.strip_prefix(&[marker])
+        .and_then(|rest| std::str::from_utf8(rest).ok())
+        .and_then(|rest| rest.trim_end().parse().ok())
+        .ok_or_else(|| invalid("bad AOF command"))
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&Vec<u8>>) -> io::Result<T> {
+    arg.and_then(|arg| std::str::from_utf8(arg).ok())
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| invalid("bad number in AOF command"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
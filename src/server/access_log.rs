@@ -0,0 +1,61 @@
+use std::time::Instant;
+
+use actix_web::HttpMessage;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use tracing::info;
+
+use super::auth::Grant;
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Logs one `access` event per request: method, path, status, latency, response size, client
+/// address and the id of the credential used (never the credential itself). Runs outermost so
+/// requests refused by auth or rate limiting are logged too.
+pub async fn log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let client = req.peer_addr().map(|addr| addr.ip().to_string());
+
+    let result = next.call(req).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let (status, bytes, credential) = match &result {
+        Ok(res) => (
+            res.status(),
+            match res.response().body().size() {
+                BodySize::Sized(len) => Some(len),
+                BodySize::None => Some(0),
+                BodySize::Stream => None,
+            },
+            res.request()
+                .extensions()
+                .get::<Grant>()
+                .map(|grant| grant.id.clone()),
+        ),
+        Err(e) => (e.as_response_error().status_code(), None, None),
+    };
+
+    info!(
+        target: "access",
+        method = %method,
+        path = %path,
+        status = status.as_u16(),
+        latency_ms,
+        bytes,
+        client = client.as_deref(),
+        credential = credential.as_deref(),
+    );
+    result
+}
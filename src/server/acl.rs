@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::io;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use super::auth::{self, Grant};
+
+/// One `[[acl]]` table of the config file: the key prefixes a credential is confined to.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AclRule {
+    /// API key the rule applies to.
+    pub api_key: Option<String>,
+    /// `sub` claim of the JWTs it applies to.
+    pub subject: Option<String>,
+    /// Prefixes the credential may read.
+    #[serde(default)]
+    pub read: Vec<String>,
+    /// Prefixes the credential may read and write.
+    #[serde(default)]
+    pub write: Vec<String>,
+}
+
+/// Key prefixes a credential may touch.
+#[derive(Debug)]
+pub struct Prefixes {
+    read: Vec<Box<[u8]>>,
+    write: Vec<Box<[u8]>>,
+}
+
+impl Prefixes {
+    pub fn can_read(&self, key: &[u8]) -> bool {
+        self.can_write(key) || self.read.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    pub fn can_write(&self, key: &[u8]) -> bool {
+        self.write.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    /// Whether every key in the range starts with one readable prefix.
+    fn can_read_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+        self.read.iter().chain(&self.write).any(|prefix| {
+            let after_start = match start {
+                Bound::Included(start) | Bound::Excluded(start) => start >= &prefix[..],
+                Bound::Unbounded => false,
+            };
+            let before_end = match (end, successor(prefix)) {
+                (_, None) => true,
+                (Bound::Included(end), Some(limit)) => end < &limit[..],
+                (Bound::Excluded(end), Some(limit)) => end <= &limit[..],
+                (Bound::Unbounded, Some(_)) => false,
+            };
+            after_start && before_end
+        })
+    }
+}
+
+/// The smallest key above every key starting with `prefix`, or `None` if there is none.
+fn successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut limit = prefix.to_vec();
+    while let Some(last) = limit.pop() {
+        if last < u8::MAX {
+            limit.push(last + 1);
+            return Some(limit);
+        }
+    }
+    None
+}
+
+/// Confinements by credential. Credentials without a rule may touch every key.
+#[derive(Debug, Default)]
+pub struct Acl {
+    api_keys: HashMap<String, Arc<Prefixes>>,
+    subjects: HashMap<String, Arc<Prefixes>>,
+}
+
+impl Acl {
+    /// Fails if a rule names neither or both of an API key and a subject, or a credential twice.
+    pub fn new(rules: Vec<AclRule>) -> io::Result<Self> {
+        let mut acl = Acl::default();
+        for rule in rules {
+            let prefixes = Arc::new(Prefixes {
+                read: rule.read.iter().map(|p| p.as_bytes().into()).collect(),
+                write: rule.write.iter().map(|p| p.as_bytes().into()).collect(),
+            });
+            let (table, credential) = match (rule.api_key, rule.subject) {
+                (Some(api_key), None) => (&mut acl.api_keys, api_key),
+                (None, Some(subject)) => (&mut acl.subjects, subject),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "each acl rule needs exactly one of api_key and subject",
+                    ));
+                }
+            };
+            if table.insert(credential.clone(), prefixes).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("more than one acl rule for {}", credential),
+                ));
+            }
+        }
+        Ok(acl)
+    }
+
+    pub fn for_api_key(&self, api_key: &str) -> Option<Arc<Prefixes>> {
+        self.api_keys.get(api_key).cloned()
+    }
+
+    pub fn for_subject(&self, subject: &str) -> Option<Arc<Prefixes>> {
+        self.subjects.get(subject).cloned()
+    }
+}
+
+/// Extracts the key prefixes the request's credentials are confined to, as recorded by
+/// `auth::authenticate`. The checks answer `403` for keys outside them.
+pub struct Scope(Option<Arc<Prefixes>>);
+
+impl FromRequest for Scope {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let prefixes = req
+            .extensions()
+            .get::<Grant>()
+            .and_then(|grant| grant.prefixes.clone());
+        ready(Ok(Scope(prefixes)))
+    }
+}
+
+impl Scope {
+    pub fn read(&self, key: &[u8]) -> Result<(), HttpResponse> {
+        self.check(|prefixes| prefixes.can_read(key))
+    }
+
+    /// Also answers `403` for keys under the engine's reserved prefixes.
+    pub fn write(&self, key: &[u8]) -> Result<(), HttpResponse> {
+        self.write_all(&[key])
+    }
+
+    pub fn read_all<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<(), HttpResponse> {
+        self.check(|prefixes| keys.iter().all(|key| prefixes.can_read(key.as_ref())))
+    }
+
+    pub fn write_all<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<(), HttpResponse> {
+        if !auth::writable(keys) {
+            return Err(HttpResponse::Forbidden().body(auth::RESERVED_KEY));
+        }
+        self.check(|prefixes| keys.iter().all(|key| prefixes.can_write(key.as_ref())))
+    }
+
+    pub fn read_range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<(), HttpResponse> {
+        self.check(|prefixes| prefixes.can_read_range(start, end))
+    }
+
+    fn check(&self, allowed: impl FnOnce(&Prefixes) -> bool) -> Result<(), HttpResponse> {
+        match &self.0 {
+            Some(prefixes) if !allowed(prefixes) => {
+                Err(HttpResponse::Forbidden().body("key is outside the credentials' prefixes"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use breakout1_kv_store::types::{IndexedKey, LogIndex, SlowOp, SlowOpKind, VerifyReport};
+use serde::{Deserialize, Serialize};
+
+use super::auth::Access;
+use super::encoding::{Encoding, EncodingQuery};
+use super::state::{AppState, Db};
+use super::stats::CompactionSummary;
+
+/// Admin endpoints need the admin token or a JWT with the admin scope, and are disabled outright
+/// when neither is configured.
+pub fn authorize(req: &HttpRequest, state: &AppState) -> Result<(), HttpResponse> {
+    let auth = state.auth();
+    if !auth.admin_enabled() {
+        return Err(HttpResponse::Forbidden().body("admin endpoints are disabled"));
+    }
+    if auth.access(req) == Some(Access::Admin) {
+        Ok(())
+    } else {
+        Err(HttpResponse::Unauthorized().body("invalid admin credentials"))
+    }
+}
+
+/// Compacts the log now and returns the report. Writes wait until it finishes.
+pub async fn compact(req: HttpRequest, engine: Db, state: web::Data<AppState>) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    match web::block(move || engine.compact()).await {
+        Ok(Ok(report)) => HttpResponse::Ok().json(CompactionSummary::from(report)),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Serialize)]
+struct CorruptRecordSummary {
+    pos: u64,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct OrphanedEntrySummary {
+    key: String,
+    reason: String,
+}
+
+/// JSON shape of a `VerifyReport`, with keys in the requested encoding.
+#[derive(Serialize)]
+struct VerifySummary {
+    ok: bool,
+    records: u64,
+    bytes: u64,
+    unread_bytes: u64,
+    corrupt: Vec<CorruptRecordSummary>,
+    orphaned: Vec<OrphanedEntrySummary>,
+    missing: Vec<String>,
+}
+
+impl VerifySummary {
+    fn new(report: VerifyReport, encoding: Encoding) -> Self {
+        Self {
+            ok: report.is_ok(),
+            records: report.records,
+            bytes: report.bytes,
+            unread_bytes: report.unread_bytes,
+            corrupt: report
+                .corrupt
+                .into_iter()
+                .map(|record| CorruptRecordSummary {
+                    pos: record.pos,
+                    reason: record.reason,
+                })
+                .collect(),
+            orphaned: report
+                .orphaned
+                .into_iter()
+                .map(|entry| OrphanedEntrySummary {
+                    key: encoding.encode(&entry.key),
+                    reason: entry.reason,
+                })
+                .collect(),
+            missing: report
+                .missing
+                .iter()
+                .map(|key| encoding.encode(key))
+                .collect(),
+        }
+    }
+}
+
+/// Reads the whole log and checks it against the index. Answers `200` with the report whether
+/// or not it found anything, so check `ok`. Writes wait until it finishes.
+pub async fn verify(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    match web::block(move || engine.verify()).await {
+        Ok(Ok(report)) => HttpResponse::Ok().json(VerifySummary::new(report, query.encoding)),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+const DEFAULT_INDEX_LIMIT: usize = 100;
+const MAX_INDEX_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct IndexQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// JSON shape of a `LogIndex`: where a record's payload sits in the log.
+#[derive(Serialize)]
+struct RecordSummary {
+    pos: u64,
+    len: u64,
+}
+
+impl From<LogIndex> for RecordSummary {
+    fn from(log_index: LogIndex) -> Self {
+        Self {
+            pos: log_index.pos,
+            len: log_index.len,
+        }
+    }
+}
+
+/// JSON shape of an `IndexedKey`, with the key in the requested encoding.
+#[derive(Serialize)]
+struct IndexedKeySummary {
+    key: String,
+    pos: Option<u64>,
+    len: Option<u64>,
+    seq: Option<u64>,
+    pieces: Vec<RecordSummary>,
+    operands: Vec<RecordSummary>,
+    expires_at: Option<i64>,
+}
+
+impl IndexedKeySummary {
+    fn new(entry: IndexedKey, encoding: Encoding) -> Self {
+        Self {
+            key: encoding.encode(&entry.key),
+            pos: entry.record.as_ref().map(|record| record.pos),
+            len: entry.record.as_ref().map(|record| record.len),
+            seq: entry.seq,
+            pieces: entry.pieces.into_iter().map(RecordSummary::from).collect(),
+            operands: entry
+                .operands
+                .into_iter()
+                .map(RecordSummary::from)
+                .collect(),
+            expires_at: entry.expires_at,
+        }
+    }
+}
+
+/// Dumps the index entries of the keys under `prefix`, expired ones included: where each key's
+/// record is in the log and the sequence number it carries. For debugging a key that reads the
+/// wrong value.
+pub async fn index(
+    req: HttpRequest,
+    query: web::Query<IndexQuery>,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    let encoding = query.encoding;
+    let prefix = match encoding.decode(&query.prefix) {
+        Ok(prefix) => prefix,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_INDEX_LIMIT)
+        .clamp(1, MAX_INDEX_LIMIT);
+    match web::block(move || engine.index_entries(&prefix, None, limit)).await {
+        Ok(Ok(entries)) => HttpResponse::Ok().json(
+            entries
+                .into_iter()
+                .map(|entry| IndexedKeySummary::new(entry, encoding))
+                .collect::<Vec<_>>(),
+        ),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SlowLogQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct PhaseSummary {
+    name: &'static str,
+    duration_us: u64,
+}
+
+/// JSON shape of a `SlowOp`, with times in microseconds.
+#[derive(Serialize)]
+struct SlowOpSummary {
+    op: &'static str,
+    at: i64,
+    key_len: Option<usize>,
+    value_len: Option<u64>,
+    duration_us: u64,
+    phases: Vec<PhaseSummary>,
+}
+
+impl From<SlowOp> for SlowOpSummary {
+    fn from(op: SlowOp) -> Self {
+        Self {
+            op: match op.kind {
+                SlowOpKind::Get => "get",
+                SlowOpKind::Set => "set",
+                SlowOpKind::Del => "del",
+                SlowOpKind::Compact => "compact",
+                SlowOpKind::Rebuild => "rebuild",
+            },
+            at: op.at,
+            key_len: op.key_len,
+            value_len: op.value_len,
+            duration_us: op.duration.as_micros() as u64,
+            phases: op
+                .phases
+                .into_iter()
+                .map(|(name, took)| PhaseSummary {
+                    name,
+                    duration_us: took.as_micros() as u64,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Lists the engine operations that took at least `slow_op_ms`, newest first. Empty when the
+/// slow-operation log is off.
+pub async fn slowlog(
+    req: HttpRequest,
+    query: web::Query<SlowLogQuery>,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    let ops = engine.slow_ops();
+    let limit = query.limit.unwrap_or(ops.len());
+    HttpResponse::Ok().json(
+        ops.into_iter()
+            .rev()
+            .take(limit)
+            .map(SlowOpSummary::from)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Empties the slow-operation log.
+pub async fn clear_slowlog(
+    req: HttpRequest,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    engine.clear_slow_ops();
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Deserialize)]
+pub struct BackupRequest {
+    /// Directory on the server to write the snapshot into.
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct BackupResponse {
+    path: PathBuf,
+    bytes: u64,
+}
+
+/// Writes a consistent snapshot of the log to `backup-<unix millis>.db` in the requested
+/// directory. Writes carry on while it is copied.
+pub async fn backup(
+    req: HttpRequest,
+    body: web::Json<BackupRequest>,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = body.into_inner().dir.join(format!("backup-{}.db", millis));
+
+    let target = path.clone();
+    match web::block(move || engine.backup(&target)).await {
+        Ok(Ok(bytes)) => HttpResponse::Ok().json(BackupResponse { path, bytes }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    On,
+    Off,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    mode: Mode,
+}
+
+#[derive(Serialize)]
+struct MaintenanceResponse {
+    maintenance: bool,
+}
+
+/// Turns maintenance mode on or off. While it is on every route outside `/admin` answers `503`,
+/// so the data can be worked on without stopping the process.
+pub async fn maintenance(
+    req: HttpRequest,
+    body: web::Json<MaintenanceRequest>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &state) {
+        return response;
+    }
+    state.set_maintenance(matches!(body.mode, Mode::On));
+    HttpResponse::Ok().json(MaintenanceResponse {
+        maintenance: state.in_maintenance(),
+    })
+}
@@ -0,0 +1,36 @@
+use actix_web::{HttpResponse, web};
+use serde::Serialize;
+
+use super::acl::Scope;
+use super::encoding::EncodingQuery;
+use super::limits;
+use super::state::Db;
+
+#[derive(Serialize)]
+struct AppendResponse {
+    length: u64,
+}
+
+/// Appends the raw request body to the value of `key`, creating it if missing, and returns the
+/// new length.
+pub async fn append(
+    key: web::Path<String>,
+    body: web::Bytes,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+
+    match web::block(move || engine.append(&key, &body)).await {
+        Ok(Ok(length)) => HttpResponse::Ok().json(AppendResponse { length }),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,376 @@
+use std::cell::RefCell;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use breakout1_kv_store::types::BatchOp;
+use breakout1_kv_store::{Engine, EngineOptions};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use super::admin;
+use super::auth::{self, Access, Grant};
+use super::batch;
+use super::buckets;
+use super::state::AppState;
+
+/// Principal recorded for writes made while the server requires no credentials.
+const ANONYMOUS: &str = "anonymous";
+/// Routes whose keys and values are in a JSON body rather than the path.
+const JSON_ROUTES: &[&str] = &["/set", "/batch/set", "/batch/del"];
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// One write made through the HTTP API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    at: i64,
+    /// The `Grant` id of the credential: `admin`, `key:<hash>` or `jwt:<sub>`.
+    principal: String,
+    method: String,
+    /// Path and query string.
+    path: String,
+    bucket: Option<String>,
+    /// As sent, so base64 with `?encoding=base64`. `None` for writes without a single key, such as
+    /// `DELETE /keys?prefix=`.
+    key: Option<String>,
+    /// Hex SHA-256 of the value as sent, or of the request body for routes taking one that is
+    /// not a value, such as `/cas`.
+    value_sha256: Option<String>,
+    status: u16,
+}
+
+/// Append-only record of the writes made through the HTTP API, in a data file of its own that no
+/// route writes to. Entries are keyed by a zero-padded counter, so they list in the order they
+/// were recorded.
+pub struct AuditLog {
+    engine: Engine,
+    /// Id of the next entry; held while entries are written so ids commit in order.
+    next_id: Mutex<u64>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, options: EngineOptions) -> io::Result<Self> {
+        let engine = Engine::load_with_options(path, options)?;
+        let last = engine.range(Bound::Unbounded, Bound::Unbounded, 1, true)?;
+        let next_id = match last.first() {
+            Some((id, _)) => parse_id(id)? + 1,
+            None => 1,
+        };
+        Ok(AuditLog {
+            engine,
+            next_id: Mutex::new(next_id),
+        })
+    }
+
+    fn append(&self, entries: &[AuditEntry]) -> io::Result<()> {
+        let mut next_id = self.next_id.lock().unwrap();
+        let ops = entries
+            .iter()
+            .zip(*next_id..)
+            .map(|(entry, id)| {
+                Ok(BatchOp::Set {
+                    key: format_id(id).into_bytes(),
+                    value: serde_json::to_vec(entry)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        self.engine.write_batch(&ops)?;
+        *next_id += entries.len() as u64;
+        Ok(())
+    }
+
+    /// Up to `limit` entries recorded after the one with id `after` that `filter` accepts, and
+    /// the id to continue from if there may be more.
+    fn list(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+        filter: impl Fn(&AuditEntry) -> bool,
+    ) -> io::Result<(Vec<Recorded>, Option<String>)> {
+        let mut from = after.map(|id| id.as_bytes().to_vec());
+        let mut entries = Vec::new();
+        loop {
+            let start = from.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+            let page = self
+                .engine
+                .range(start, Bound::Unbounded, MAX_LIMIT, false)?;
+            let exhausted = page.len() < MAX_LIMIT;
+            for (id, value) in page {
+                let entry: AuditEntry = serde_json::from_slice(&value)?;
+                let id = String::from_utf8_lossy(&id).into_owned();
+                if filter(&entry) {
+                    entries.push(Recorded {
+                        id: id.clone(),
+                        entry,
+                    });
+                }
+                if entries.len() == limit {
+                    return Ok((entries, Some(id)));
+                }
+                from = Some(id.into_bytes());
+            }
+            if exhausted {
+                return Ok((entries, None));
+            }
+        }
+    }
+}
+
+fn format_id(id: u64) -> String {
+    format!("{:020}", id)
+}
+
+fn parse_id(id: &[u8]) -> io::Result<u64> {
+    std::str::from_utf8(id)
+        .ok()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed audit entry id"))
+}
+
+/// What passed through a request body on its way to the handler.
+#[derive(Default)]
+struct Tap {
+    hasher: Sha256,
+    len: usize,
+    /// A copy of the body, kept for `JSON_ROUTES` only; their handlers buffer it anyway.
+    body: Option<Vec<u8>>,
+}
+
+impl Tap {
+    fn push(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.len += chunk.len();
+        if let Some(body) = &mut self.body {
+            body.extend_from_slice(chunk);
+        }
+    }
+}
+
+/// Records every successful write request in the `AuditLog`, with the credential that made it,
+/// one entry per key for batches. The body is hashed as the handler reads it, so streamed
+/// `/kv` uploads are not buffered. Runs inside `auth::authenticate` to see its `Grant`.
+pub async fn record(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let audit = req.app_data::<web::Data<AuditLog>>().cloned();
+    let Some(audit) = audit.filter(|_| auth::required_access(&req) == Access::Write) else {
+        return next.call(req).await;
+    };
+    let principal = principal(req.request());
+    let keep = JSON_ROUTES.contains(&buckets::route_path(req.path()));
+    let tap = Rc::new(RefCell::new(Tap {
+        body: keep.then(Vec::new),
+        ..Tap::default()
+    }));
+    let seen = tap.clone();
+    let body = req.take_payload().inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            seen.borrow_mut().push(chunk);
+        }
+    });
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(body),
+    });
+
+    let res = next.call(req).await?;
+    if res.status().is_success() {
+        let entries = entries(res.request(), principal, res.status().as_u16(), tap.take());
+        append(audit, entries).await;
+    }
+    Ok(res)
+}
+
+/// Records the writes of GraphQL mutations, which `record` cannot tell from reads in the request
+/// body: one entry per key, as for batches, under the method and path of the `/graphql` request.
+#[derive(Clone)]
+pub struct Recorder {
+    audit: web::Data<AuditLog>,
+    base: AuditEntry,
+}
+
+impl Recorder {
+    pub fn new(audit: web::Data<AuditLog>, req: &HttpRequest) -> Self {
+        Recorder {
+            audit,
+            base: AuditEntry {
+                at: 0,
+                principal: principal(req),
+                method: req.method().to_string(),
+                path: req.path().to_string(),
+                bucket: None,
+                key: None,
+                value_sha256: None,
+                status: 200,
+            },
+        }
+    }
+
+    /// Records a write to each key, as sent, with the value written as sent for sets.
+    pub async fn writes(&self, writes: Vec<(String, Option<String>)>) {
+        let at = now();
+        let entries = writes
+            .into_iter()
+            .map(|(key, value)| AuditEntry {
+                at,
+                key: Some(key),
+                value_sha256: value.map(|value| hex(&Sha256::digest(value.as_bytes()))),
+                ..self.base.clone()
+            })
+            .collect();
+        append(self.audit.clone(), entries).await;
+    }
+}
+
+/// Appends `entries` on a blocking thread. A failure is logged rather than failing the write,
+/// which has already happened.
+async fn append(audit: web::Data<AuditLog>, entries: Vec<AuditEntry>) {
+    match web::block(move || audit.append(&entries)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("failed to record a write in the audit log: {}", e),
+        Err(e) => error!("failed to record a write in the audit log: {}", e),
+    }
+}
+
+/// The `Grant` id of the credential that made `req`, or `ANONYMOUS`.
+fn principal(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<Grant>()
+        .map_or_else(|| ANONYMOUS.to_string(), |grant| grant.id.clone())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[derive(Deserialize)]
+struct SetItem {
+    key: String,
+    value: String,
+}
+
+/// The entries for one write request: one per key in the body of `JSON_ROUTES`, otherwise one
+/// with the key from the path.
+fn entries(req: &HttpRequest, principal: String, status: u16, tap: Tap) -> Vec<AuditEntry> {
+    let base = AuditEntry {
+        at: now(),
+        principal,
+        method: req.method().to_string(),
+        path: req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.path().to_string(), |path| path.to_string()),
+        bucket: req.match_info().get("bucket").map(String::from),
+        key: req.match_info().get("key").map(String::from),
+        value_sha256: None,
+        status,
+    };
+    let with_key = |key: String, value: Option<&str>| AuditEntry {
+        key: Some(key),
+        value_sha256: value.map(|value| hex(&Sha256::digest(value.as_bytes()))),
+        ..base.clone()
+    };
+
+    let body = tap.body.as_deref().unwrap_or_default();
+    let parsed = match buckets::route_path(req.path()) {
+        "/set" => serde_json::from_slice::<SetItem>(body)
+            .ok()
+            .map(|item| vec![with_key(item.key, Some(&item.value))]),
+        "/batch/set" => batch::parse_items::<SetItem>(req, body).ok().map(|items| {
+            items
+                .into_iter()
+                .map(|item| with_key(item.key, Some(&item.value)))
+                .collect()
+        }),
+        "/batch/del" => serde_json::from_slice::<Vec<String>>(body)
+            .ok()
+            .map(|keys| keys.into_iter().map(|key| with_key(key, None)).collect()),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| {
+        vec![AuditEntry {
+            value_sha256: (tap.len > 0).then(|| hex(&tap.hasher.finalize())),
+            ..base
+        }]
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// An entry id; only entries recorded after it are listed.
+    after: Option<String>,
+    limit: Option<usize>,
+    principal: Option<String>,
+    key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Recorded {
+    id: String,
+    #[serde(flatten)]
+    entry: AuditEntry,
+}
+
+#[derive(Serialize)]
+struct AuditPage {
+    entries: Vec<Recorded>,
+    /// Pass back as `after` for the next page; `null` once there are no more entries.
+    next_cursor: Option<String>,
+}
+
+/// Lists audit entries oldest first, optionally only those of one principal or key. Needs admin
+/// credentials; `404` when the audit log is off.
+pub async fn list(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    state: web::Data<AppState>,
+    audit: Option<web::Data<AuditLog>>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    let Some(audit) = audit else {
+        return HttpResponse::NotFound().body("the audit log is off");
+    };
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let listed = web::block(move || {
+        audit.list(query.after.as_deref(), limit, |entry| {
+            query
+                .principal
+                .as_ref()
+                .is_none_or(|principal| *principal == entry.principal)
+                && query
+                    .key
+                    .as_ref()
+                    .is_none_or(|key| entry.key.as_ref() == Some(key))
+        })
+    })
+    .await;
+    match listed {
+        Ok(Ok((entries, next_cursor))) => HttpResponse::Ok().json(AuditPage {
+            entries,
+            next_cursor,
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
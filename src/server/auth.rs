@@ -0,0 +1,183 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, header};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest, error, web};
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use breakout1_kv_store::engine::is_reserved;
+
+use super::acl::{Acl, Prefixes};
+use super::buckets;
+use super::jwt::JwtValidator;
+use super::state::AppState;
+
+/// Paths served without credentials or rate limits, so liveness probes keep working and clients
+/// can fetch the API description.
+pub const PUBLIC_PATHS: &[&str] = &["/health", "/openapi.json", "/docs"];
+/// `POST` routes that only read, or like `/graphql` check writes themselves.
+const READ_ONLY_POSTS: &[&str] = &["/batch/get", "/graphql"];
+
+/// Why a write to a key under the engine's reserved prefixes is refused, whatever the credentials.
+pub const RESERVED_KEY: &str = "key is reserved for the server's own records";
+
+/// What a request's credentials allow; each level includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    /// `/admin` endpoints as well.
+    Admin,
+}
+
+/// What a request's credentials were granted, recorded in its extensions by `authenticate`.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    /// Names the credential in logs without revealing it: `admin`, `key:<hash>` or `jwt:<sub>`.
+    pub id: String,
+    pub access: Access,
+    /// The keys it is confined to; `None` for every key.
+    pub prefixes: Option<Arc<Prefixes>>,
+}
+
+impl Grant {
+    /// Whether the grant allows `access` to every key in `keys`, for listeners that check keys
+    /// themselves rather than through `acl::Scope`.
+    pub fn allows(&self, access: Access, keys: &[&[u8]]) -> bool {
+        self.access >= access
+            && self.prefixes.as_ref().is_none_or(|prefixes| {
+                keys.iter().all(|key| match access {
+                    Access::Read => prefixes.can_read(key),
+                    _ => prefixes.can_write(key),
+                })
+            })
+    }
+}
+
+/// Whether clients may write every key in `keys`: keys under the engine's reserved prefixes hold
+/// its own records, such as request ids, which only change through the calls that keep them.
+pub fn writable<K: AsRef<[u8]>>(keys: &[K]) -> bool {
+    !keys.iter().any(|key| is_reserved(key.as_ref()))
+}
+
+/// The credentials the server accepts.
+pub struct Auth {
+    /// Grants `Admin`. `None` leaves admin endpoints to JWTs, or disables them without JWTs.
+    pub admin_token: Option<String>,
+    /// Each grants `Write`.
+    pub api_keys: Vec<String>,
+    /// JWTs grant the access of their scopes.
+    pub jwt: Option<JwtValidator>,
+    /// Key prefixes API keys and JWT subjects are confined to.
+    pub acl: Acl,
+}
+
+impl Auth {
+    /// Whether requests need credentials at all.
+    pub fn required(&self) -> bool {
+        !self.api_keys.is_empty() || self.jwt.is_some()
+    }
+
+    /// Whether anything can grant `Admin`.
+    pub fn admin_enabled(&self) -> bool {
+        self.admin_token.is_some() || self.jwt.is_some()
+    }
+
+    /// What `credential` grants, if anything. The admin token is never confined to prefixes.
+    pub fn grant(&self, credential: &str) -> Option<Grant> {
+        if self.admin_token.as_deref() == Some(credential) {
+            return Some(Grant {
+                id: "admin".to_string(),
+                access: Access::Admin,
+                prefixes: None,
+            });
+        }
+        if self.api_keys.iter().any(|key| key == credential) {
+            let mut hasher = DefaultHasher::new();
+            credential.hash(&mut hasher);
+            return Some(Grant {
+                id: format!("key:{:08x}", hasher.finish() as u32),
+                access: Access::Write,
+                prefixes: self.acl.for_api_key(credential),
+            });
+        }
+        let token = self.jwt.as_ref()?.validate(credential)?;
+        Some(Grant {
+            id: format!("jwt:{}", token.subject.as_deref().unwrap_or_default()),
+            access: token.access,
+            prefixes: token
+                .subject
+                .and_then(|subject| self.acl.for_subject(&subject)),
+        })
+    }
+
+    /// The access of `req`'s credentials, as already checked by `authenticate` if it ran.
+    pub fn access(&self, req: &HttpRequest) -> Option<Access> {
+        if let Some(grant) = req.extensions().get::<Grant>() {
+            return Some(grant.access);
+        }
+        self.grant(credential(req.headers())?)
+            .map(|grant| grant.access)
+    }
+}
+
+/// Requires credentials on every route but `PUBLIC_PATHS`: an admin token, API key or JWT sent
+/// as `Authorization: Bearer <credential>` or `X-Api-Key: <credential>`. Answers `401` without
+/// valid ones and `403` when they do not allow the request, and records the `Grant` in the
+/// request extensions for handlers to check keys against.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if PUBLIC_PATHS.contains(&req.path()) {
+        return next.call(req).await;
+    }
+
+    let granted = match (
+        credential(req.headers()),
+        req.app_data::<web::Data<AppState>>(),
+    ) {
+        (Some(credential), Some(state)) => state.auth().grant(credential),
+        _ => None,
+    };
+    let Some(granted) = granted else {
+        return Err(error::ErrorUnauthorized("missing or invalid credentials"));
+    };
+    if granted.access < required_access(&req) {
+        return Err(error::ErrorForbidden(
+            "credentials do not allow this request",
+        ));
+    }
+
+    req.extensions_mut().insert(granted);
+    next.call(req).await
+}
+
+/// The credential sent as `Authorization: Bearer <credential>` or `X-Api-Key: <credential>`.
+pub fn credential(headers: &header::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+        })
+}
+
+/// The access a request needs, judged from its method and path.
+pub fn required_access(req: &ServiceRequest) -> Access {
+    let path = buckets::route_path(req.path());
+    let reads = matches!(*req.method(), Method::GET | Method::HEAD);
+    // What is left of a `/b/...` path after `route_path` is a bucket itself.
+    if path.starts_with("/admin/") || (path.starts_with("/b/") && !reads) {
+        Access::Admin
+    } else if reads || READ_ONLY_POSTS.contains(&path) {
+        Access::Read
+    } else {
+        Access::Write
+    }
+}
@@ -0,0 +1,179 @@
+use std::io;
+
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, web};
+use base64::DecodeError;
+use breakout1_kv_store::types::BatchOp;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::{Encoding, EncodingQuery};
+use super::limits;
+use super::state::Db;
+
+#[derive(Deserialize)]
+struct SetItem {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetQuery {
+    #[serde(default)]
+    encoding: Encoding,
+    /// Leave keys that already exist alone.
+    #[serde(default)]
+    if_absent: bool,
+}
+
+#[derive(Serialize)]
+struct SetResult {
+    key: String,
+    existed: bool,
+}
+
+/// Writes every pair with one engine batch. The body is a JSON array of `{key, value}`, or one
+/// object per line when sent as `application/x-ndjson`. With `?if_absent=true`, pairs whose key
+/// exists are left out, and `existed` tells which.
+pub async fn set(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<SetQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let items: Vec<SetItem> = match parse_items(&req, &body) {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let encoding = query.encoding;
+    let pairs: Result<Vec<(Vec<u8>, Vec<u8>)>, DecodeError> = items
+        .iter()
+        .map(|item| Ok((encoding.decode(&item.key)?, encoding.decode(&item.value)?)))
+        .collect();
+    let pairs = match pairs {
+        Ok(pairs) => pairs,
+        Err(e) => return bad_encoding(e),
+    };
+    let op_keys: Vec<&[u8]> = pairs.iter().map(|(key, _)| key.as_slice()).collect();
+    if let Err(response) = scope.write_all(&op_keys) {
+        return response;
+    }
+    let keys: Vec<String> = items.into_iter().map(|item| item.key).collect();
+
+    let if_absent = query.if_absent;
+    let write = move || -> io::Result<Vec<bool>> {
+        if if_absent {
+            let written = engine.set_many_if_absent(&pairs)?;
+            return Ok(written.into_iter().map(|written| !written).collect());
+        }
+        let ops: Vec<BatchOp> = pairs
+            .into_iter()
+            .map(|(key, value)| BatchOp::Set { key, value })
+            .collect();
+        engine.write_batch(&ops)
+    };
+    match web::block(write).await {
+        Ok(Ok(existed)) => HttpResponse::Ok().json(
+            keys.into_iter()
+                .zip(existed)
+                .map(|(key, existed)| SetResult { key, existed })
+                .collect::<Vec<_>>(),
+        ),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Serialize)]
+struct DelResult {
+    deleted: usize,
+}
+
+/// Deletes a JSON array of keys with one engine batch, so readers never see some of them gone
+/// and others not. Returns how many of the keys existed.
+pub async fn del(
+    body: web::Json<Vec<String>>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let keys = match decode_keys(&body, query.encoding) {
+        Ok(keys) => keys,
+        Err(e) => return bad_encoding(e),
+    };
+    if let Err(response) = scope.write_all(&keys) {
+        return response;
+    }
+    let ops: Vec<BatchOp> = keys.into_iter().map(|key| BatchOp::Del { key }).collect();
+
+    match web::block(move || engine.write_batch(&ops)).await {
+        Ok(Ok(existed)) => HttpResponse::Ok().json(DelResult {
+            deleted: existed.into_iter().filter(|&existed| existed).count(),
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Looks up a JSON array of keys and returns their values in the same order, `null` for misses.
+pub async fn get(
+    body: web::Json<Vec<String>>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let encoding = query.encoding;
+    let keys = match decode_keys(&body, encoding) {
+        Ok(keys) => keys,
+        Err(e) => return bad_encoding(e),
+    };
+    if let Err(response) = scope.read_all(&keys) {
+        return response;
+    }
+    let result = web::block(move || {
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+        engine.get_many(&keys)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(values)) => HttpResponse::Ok().json(
+            values
+                .into_iter()
+                .map(|value| value.map(|v| encoding.encode(&v)))
+                .collect::<Vec<_>>(),
+        ),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+fn decode_keys(keys: &[String], encoding: Encoding) -> Result<Vec<Vec<u8>>, DecodeError> {
+    keys.iter().map(|key| encoding.decode(key)).collect()
+}
+
+fn bad_encoding(e: DecodeError) -> HttpResponse {
+    HttpResponse::BadRequest().body(e.to_string())
+}
+
+pub fn parse_items<T: DeserializeOwned>(
+    req: &HttpRequest,
+    body: &[u8],
+) -> serde_json::Result<Vec<T>> {
+    let ndjson = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/x-ndjson"));
+
+    if ndjson {
+        body.split(|&b| b == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(serde_json::from_slice)
+            .collect()
+    } else {
+        serde_json::from_slice(body)
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+
+use actix_web::web;
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::wire::{self, FRAME_PREFIX_SIZE, Request, Response};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use super::auth::{self, Access, Grant};
+use super::state::AppState;
+
+/// Accepts binary protocol connections until the server stops. Frames over `max_frame_size`
+/// bytes close the connection.
+pub async fn serve(listener: TcpListener, state: web::Data<AppState>, max_frame_size: usize) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = connection(stream, state, max_frame_size).await {
+                        debug!(%peer, "binary connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept binary connection: {}", e),
+        }
+    }
+}
+
+/// Answers requests in order until the client hangs up.
+async fn connection(
+    stream: TcpStream,
+    state: web::Data<AppState>,
+    max_frame_size: usize,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut grant: Option<Grant> = None;
+
+    loop {
+        let mut prefix = [0u8; FRAME_PREFIX_SIZE];
+        match reader.read_exact(&mut prefix).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        // Past an oversized frame there is no telling where the next one starts.
+        let len = match wire::frame_len(prefix, max_frame_size) {
+            Ok(len) => len,
+            Err(e) => {
+                writer
+                    .write_all(&Response::Error(e.to_string()).encode())
+                    .await?;
+                writer.flush().await?;
+                return Err(e);
+            }
+        };
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).await?;
+
+        let response = match Request::decode(&body) {
+            Ok(request) => handle(request, &state, &mut grant).await,
+            Err(e) => Response::Error(e.to_string()),
+        };
+        writer.write_all(&response.encode()).await?;
+        // Pipelined requests that already arrived are answered before flushing, so their
+        // responses go out together.
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await
+}
+
+async fn handle(
+    request: Request,
+    state: &web::Data<AppState>,
+    grant: &mut Option<Grant>,
+) -> Response {
+    let (access, key) = match &request {
+        Request::Auth(credential) => {
+            return match state.auth().grant(&String::from_utf8_lossy(credential)) {
+                Some(granted) => {
+                    *grant = Some(granted);
+                    Response::Ok(Vec::new())
+                }
+                None => Response::Error("invalid credentials".to_string()),
+            };
+        }
+        Request::Get(key) => (Access::Read, key),
+        Request::Set { key, .. } | Request::Del(key) => (Access::Write, key),
+    };
+    if state.auth().required() {
+        match grant {
+            None => return Response::Error("authentication required".to_string()),
+            Some(grant) if !grant.allows(access, &[key]) => {
+                return Response::Error("credentials do not allow this request".to_string());
+            }
+            Some(_) => {}
+        }
+    }
+    if access == Access::Write && !auth::writable(&[key]) {
+        return Response::Error(auth::RESERVED_KEY.to_string());
+    }
+    if state.in_maintenance() {
+        return Response::Error("server is in maintenance mode".to_string());
+    }
+    let Some(engine) = state.engine().cloned() else {
+        return Response::Error("engine is not ready".to_string());
+    };
+    web::block(move || execute(&engine, request))
+        .await
+        .unwrap_or_else(|e| Response::Error(e.to_string()))
+}
+
+/// Runs one data request against the engine, on a blocking thread.
+fn execute(engine: &Engine, request: Request) -> Response {
+    let result = match request {
+        Request::Get(key) => engine
+            .get(&key)
+            .map(|value| value.map_or(Response::NotFound, Response::Ok)),
+        Request::Set { key, value } => engine.set(&key, &value).map(|_| Response::Ok(Vec::new())),
+        Request::Del(key) => engine.del(&key).map(|()| Response::Ok(Vec::new())),
+        Request::Auth(_) => unreachable!("handled before the engine is needed"),
+    };
+    result.unwrap_or_else(|e| Response::Error(e.to_string()))
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use breakout1_kv_store::Buckets;
+use serde::Serialize;
+
+use super::admin;
+use super::state::AppState;
+
+#[derive(Serialize)]
+struct BucketList {
+    buckets: Vec<String>,
+}
+
+/// The route a request targets within its bucket: `/b/{bucket}/kv/k` is `/kv/k`. Other paths,
+/// including `/b/{bucket}` itself, are returned whole.
+pub fn route_path(path: &str) -> &str {
+    path.strip_prefix("/b/")
+        .and_then(|rest| rest.find('/').map(|slash| &rest[slash..]))
+        .unwrap_or(path)
+}
+
+/// The buckets, or the response to send when there are none to work with.
+fn buckets(state: &AppState) -> Result<&Buckets, HttpResponse> {
+    if !state.is_ready() {
+        return Err(HttpResponse::ServiceUnavailable().body("engine is not ready"));
+    }
+    state
+        .buckets()
+        .ok_or_else(|| HttpResponse::NotFound().body("buckets are disabled"))
+}
+
+/// Names of all buckets in ascending order.
+pub async fn list(state: web::Data<AppState>) -> HttpResponse {
+    match buckets(&state) {
+        Ok(buckets) => HttpResponse::Ok().json(BucketList {
+            buckets: buckets.names(),
+        }),
+        Err(response) => response,
+    }
+}
+
+/// Creates an empty bucket (`201`), or `409` if it exists.
+pub async fn create(
+    req: HttpRequest,
+    name: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    if let Err(response) = buckets(&state) {
+        return response;
+    }
+    let result = web::block(move || {
+        let buckets = state.buckets().expect("checked above");
+        buckets.create(&name).map(|_| ())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => HttpResponse::Created().finish(),
+        Ok(Err(e)) => match e.kind() {
+            io::ErrorKind::AlreadyExists => HttpResponse::Conflict().body(e.to_string()),
+            io::ErrorKind::InvalidInput => HttpResponse::BadRequest().body(e.to_string()),
+            _ => HttpResponse::InternalServerError().body(e.to_string()),
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Deletes a bucket with all its keys and its data file (`204`), or `404` if there is none.
+pub async fn delete(
+    req: HttpRequest,
+    name: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    if let Err(response) = buckets(&state) {
+        return response;
+    }
+    let result = web::block(move || {
+        let buckets = state.buckets().expect("checked above");
+        buckets.delete(&name)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::NotFound().body("bucket not found"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
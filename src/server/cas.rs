@@ -0,0 +1,57 @@
+use actix_web::{HttpResponse, web};
+use breakout1_kv_store::types::Metadata;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::EncodingQuery;
+use super::limits;
+use super::state::Db;
+
+#[derive(Deserialize)]
+pub struct CasRequest {
+    /// Version the key must be at, as returned by a previous write; `null` if it must not exist.
+    expected_seq: Option<u64>,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Swapped {
+    seq: u64,
+}
+
+#[derive(Serialize)]
+struct Conflict {
+    /// `null` if the key does not exist.
+    current_seq: Option<u64>,
+}
+
+/// Sets `key` only if it is still at `expected_seq`, returning the new sequence number, or `409`
+/// with the key's current version. The same check as `If-Match` on `/kv/{key}`, for clients that
+/// would rather send versions in the body than in headers.
+pub async fn cas(
+    key: web::Path<String>,
+    req: web::Json<CasRequest>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let (key, value) = match (
+        query.encoding.decode(&key),
+        query.encoding.decode(&req.value),
+    ) {
+        (Ok(key), Ok(value)) => (key, value),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let expected = req.expected_seq;
+
+    match web::block(move || engine.compare_and_set(&key, expected, &value, &Metadata::new())).await
+    {
+        Ok(Ok(Ok(seq))) => HttpResponse::Ok().json(Swapped { seq }),
+        Ok(Ok(Err(current_seq))) => HttpResponse::Conflict().json(Conflict { current_seq }),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,77 @@
+use std::convert::Infallible;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use futures_util::stream;
+use serde::Deserialize;
+
+use super::acl::Scope;
+use super::encoding::Encoding;
+use super::state::Db;
+use super::watch::{self, ChangeEvent};
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    since_seq: Option<u64>,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// Server-Sent Events stream of the changes under `prefix` after `since_seq`: first those the
+/// log still holds, then new ones as they happen. Each event's id is its sequence number, so a
+/// reconnecting `EventSource` resumes through `Last-Event-ID`. The stream ends if the client
+/// falls too far behind.
+pub async fn changes(
+    req: HttpRequest,
+    query: web::Query<ChangesQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let encoding = query.encoding;
+    let prefix = match encoding.decode(&query.prefix) {
+        Ok(prefix) => prefix,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&prefix) {
+        return response;
+    }
+    let since = query
+        .since_seq
+        .or_else(|| {
+            req.headers()
+                .get("Last-Event-ID")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        })
+        .unwrap_or(0);
+
+    let (history, subscription) = match web::block(move || engine.watch_since(&prefix, since)).await
+    {
+        Ok(Ok(changes)) => changes,
+        Ok(Err(e)) => return HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let tail = stream::unfold(watch::forward(subscription), |mut changes| async move {
+        changes.recv().await.map(|change| (change, changes))
+    });
+    let events = stream::iter(history).chain(tail).map(move |change| {
+        let seq = change.seq;
+        let data = serde_json::to_string(&ChangeEvent::new(change, encoding))
+            .expect("change events serialize");
+        Ok::<_, Infallible>(Bytes::from(format!("id: {}\ndata: {}\n\n", seq, data)))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Compression would hold events back until a block fills up.
+        .insert_header((
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        ))
+        .streaming(events)
+}
@@ -0,0 +1,877 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use base64::DecodeError;
+use breakout1_kv_store::raft::{
+    self, AppendRequest, AppendResponse, NodeId, Raft, RaftLog, Request, Response, Role, Timing,
+    VoteRequest, VoteResponse,
+};
+use breakout1_kv_store::types::BatchOp;
+use breakout1_kv_store::{Engine, EngineOptions};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use super::acl::Scope;
+use super::admin;
+use super::batch;
+use super::encoding::{Encoding, EncodingQuery};
+use super::health;
+use super::idempotency;
+use super::limits;
+use super::router;
+use super::state::AppState;
+
+/// How often a node checks whether to stand for election, and how long the threads sending
+/// requests to its peers sleep while there is nothing to send.
+const TICK: Duration = Duration::from_millis(10);
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(1000);
+const HEARTBEAT: Duration = Duration::from_millis(100);
+/// Longest a peer gets to answer a request.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause before asking a peer that did not answer again, or retrying an entry the store failed
+/// to apply.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest a write waits to be committed and applied, and a read for the leader to be confirmed.
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Bytes of committed entries applied at a time.
+const APPLY_BATCH: usize = 4 * 1024 * 1024;
+/// Header naming the node that forwarded a request to the leader. A node receiving it answers
+/// itself rather than forwarding again.
+const FORWARDED_BY: &str = "kv-cluster-forwarded-by";
+const UNSUPPORTED: &str = "cluster mode does not support ttl_secs, if_absent or Idempotency-Key";
+
+/// Cluster mode settings. The server runs on its own while `nodes` is empty.
+#[derive(Debug, Clone)]
+pub struct ClusterSettings {
+    /// Every node of the cluster, this one included, as `id=url` with the base URL of its HTTP
+    /// API.
+    pub nodes: Vec<String>,
+    /// This node's id among `nodes`.
+    pub id: Option<NodeId>,
+    /// Admin credential for the other nodes' `/cluster` endpoints.
+    pub token: Option<String>,
+    /// Raft log file; `None` puts it beside the data file.
+    pub log_path: Option<PathBuf>,
+}
+
+impl ClusterSettings {
+    pub fn enabled(&self) -> bool {
+        !self.nodes.is_empty()
+    }
+
+    /// The node to serve as, or `None` when cluster mode is off.
+    pub fn node(&self, data_path: &Path) -> io::Result<Option<ClusterNode>> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        let mut urls = HashMap::new();
+        for node in &self.nodes {
+            let parsed = node.split_once('=').and_then(|(id, url)| {
+                let id = id.trim().parse().ok().filter(|&id| id != 0)?;
+                Some((id, url.trim().trim_end_matches('/').to_string()))
+            });
+            let Some((id, url)) = parsed else {
+                return Err(invalid_input(format!(
+                    "cluster node {:?} is not `id=url` with a nonzero id",
+                    node
+                )));
+            };
+            if urls.insert(id, url).is_some() {
+                return Err(invalid_input(format!(
+                    "cluster node id {} is used twice",
+                    id
+                )));
+            }
+        }
+        if urls.len() < 3 {
+            return Err(invalid_input(
+                "a cluster needs at least 3 nodes".to_string(),
+            ));
+        }
+        let Some(id) = self.id.filter(|id| urls.contains_key(id)) else {
+            return Err(invalid_input(
+                "cluster_id must be the id of one of cluster_nodes".to_string(),
+            ));
+        };
+        Ok(Some(ClusterNode {
+            id,
+            urls,
+            token: self.token.clone(),
+            log_path: self
+                .log_path
+                .clone()
+                .unwrap_or_else(|| data_path.with_extension("raft")),
+            http: reqwest::Client::new(),
+            cluster: OnceLock::new(),
+        }))
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// This node's place in a Raft cluster, shared by the handlers. The node takes part once its
+/// store has loaded and `start` has run.
+pub struct ClusterNode {
+    id: NodeId,
+    /// Base URL of every node's HTTP API, by id.
+    urls: HashMap<NodeId, String>,
+    token: Option<String>,
+    log_path: PathBuf,
+    http: reqwest::Client,
+    cluster: OnceLock<Arc<Cluster>>,
+}
+
+impl ClusterNode {
+    /// Opens the Raft log and starts the threads that hold elections, replicate entries to the
+    /// peers and apply committed entries to `engine`. `max_value_size` is checked before a write
+    /// is proposed, since an entry the store refuses would hold up every entry after it.
+    pub fn start(
+        &self,
+        engine: Arc<Engine>,
+        options: EngineOptions,
+        max_value_size: u64,
+    ) -> io::Result<()> {
+        let log = RaftLog::open(&self.log_path, options)?;
+        let applied = raft::applied_index(&engine)?;
+        let peers: Vec<NodeId> = self
+            .urls
+            .keys()
+            .copied()
+            .filter(|&id| id != self.id)
+            .collect();
+        let timing = Timing {
+            election_timeout: ELECTION_TIMEOUT,
+            heartbeat: HEARTBEAT,
+        };
+        let raft = Raft::open(self.id, peers.clone(), log, timing, applied, Instant::now())?;
+        let cluster = Arc::new(Cluster {
+            engine,
+            consensus: Mutex::new(Consensus {
+                raft,
+                waiters: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            agent: ureq::AgentBuilder::new().timeout(PEER_TIMEOUT).build(),
+            token: self.token.clone(),
+            max_value_size,
+        });
+        info!(
+            "node {} joined a cluster of {} with {} entries applied; Raft log at {}",
+            self.id,
+            self.urls.len(),
+            applied,
+            self.log_path.display()
+        );
+        for peer in peers {
+            let url = self.urls[&peer].clone();
+            let cluster = cluster.clone();
+            thread::spawn(move || cluster.replicate_to(peer, &url));
+        }
+        let ticker = cluster.clone();
+        thread::spawn(move || ticker.tick());
+        let applier = cluster.clone();
+        thread::spawn(move || applier.apply_committed());
+        if self.cluster.set(cluster).is_err() {
+            panic!("cluster node started twice");
+        }
+        Ok(())
+    }
+
+    fn cluster(&self) -> Result<&Arc<Cluster>, HttpResponse> {
+        self.cluster
+            .get()
+            .ok_or_else(|| HttpResponse::ServiceUnavailable().body("engine is not ready"))
+    }
+
+    /// Sends `req` on to `leader` and passes its answer back.
+    async fn forward(
+        &self,
+        leader: Option<NodeId>,
+        req: &HttpRequest,
+        body: web::Bytes,
+    ) -> HttpResponse {
+        let url = leader
+            .filter(|&id| id != self.id && !req.headers().contains_key(FORWARDED_BY))
+            .and_then(|id| self.urls.get(&id));
+        let Some(url) = url else {
+            return HttpResponse::ServiceUnavailable().body("the cluster has no leader");
+        };
+        let request = router::outgoing(&self.http, url, req, body.to_vec(), None)
+            .header(FORWARDED_BY, self.id.to_string());
+        match request.send().await {
+            Ok(response) => router::relay(response),
+            Err(e) => HttpResponse::BadGateway().body(format!("{}: {}", url, e)),
+        }
+    }
+
+    /// Runs `op` against the leader's store: here if this node leads, otherwise by forwarding
+    /// `req` to the leader, whose answer is returned as the error.
+    async fn on_leader<T: Send + 'static>(
+        &self,
+        state: &AppState,
+        req: &HttpRequest,
+        body: web::Bytes,
+        op: impl FnOnce(&Cluster) -> Result<T, Refused> + Send + 'static,
+    ) -> Result<T, HttpResponse> {
+        if state.in_maintenance() {
+            return Err(HttpResponse::ServiceUnavailable().body("server is in maintenance mode"));
+        }
+        let cluster = self.cluster()?.clone();
+        match web::block(move || op(&cluster)).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(Refused::NotLeader(leader))) => Err(self.forward(leader, req, body).await),
+            Ok(Err(Refused::Failed(e))) if e.kind() == io::ErrorKind::TimedOut => {
+                Err(HttpResponse::ServiceUnavailable().body(e.to_string()))
+            }
+            Ok(Err(Refused::Failed(e))) => Err(limits::write_error(e)),
+            Err(e) => Err(HttpResponse::InternalServerError().body(e.to_string())),
+        }
+    }
+}
+
+/// Why the leader's store could not be used for a request.
+enum Refused {
+    /// This node does not lead; the leader it knows of, if any.
+    NotLeader(Option<NodeId>),
+    Failed(io::Error),
+}
+
+impl From<io::Error> for Refused {
+    fn from(e: io::Error) -> Self {
+        Refused::Failed(e)
+    }
+}
+
+/// A running node: its Raft state and the store committed entries are applied to.
+struct Cluster {
+    engine: Arc<Engine>,
+    consensus: Mutex<Consensus>,
+    /// Notified whenever `consensus` changes, waking the threads and requests waiting on it.
+    changed: Condvar,
+    agent: ureq::Agent,
+    token: Option<String>,
+    max_value_size: u64,
+}
+
+struct Consensus {
+    raft: Raft,
+    /// Writes waiting for the entry at an index to be applied, with the term they proposed it in.
+    waiters: HashMap<u64, (u64, Waiter)>,
+}
+
+/// Answers a write with whether each of its keys existed just before.
+type Waiter = SyncSender<io::Result<Vec<bool>>>;
+
+impl Cluster {
+    fn lock(&self) -> MutexGuard<'_, Consensus> {
+        self.consensus.lock().unwrap()
+    }
+
+    fn wait<'a>(
+        &self,
+        consensus: MutexGuard<'a, Consensus>,
+        timeout: Duration,
+    ) -> MutexGuard<'a, Consensus> {
+        self.changed.wait_timeout(consensus, timeout).unwrap().0
+    }
+
+    /// Answers another node's request.
+    fn handle(&self, request: &Request) -> io::Result<Response> {
+        let response = self.lock().raft.handle(request, Instant::now());
+        self.changed.notify_all();
+        response
+    }
+
+    /// Commits `ops` as one entry and waits until the store has applied it. Returns, for each
+    /// op, whether its key existed just before.
+    fn write(&self, ops: Vec<BatchOp>) -> Result<Vec<bool>, Refused> {
+        for op in &ops {
+            if let BatchOp::Set { value, .. } = op
+                && value.len() as u64 > self.max_value_size
+            {
+                return Err(Refused::Failed(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    format!(
+                        "value is larger than the {}-byte limit",
+                        self.max_value_size
+                    ),
+                )));
+            }
+        }
+        let (done, applied) = mpsc::sync_channel(1);
+        {
+            let mut consensus = self.lock();
+            let leader = consensus.raft.leader();
+            let Some((index, term)) = consensus.raft.propose(ops)? else {
+                return Err(Refused::NotLeader(leader));
+            };
+            consensus.waiters.insert(index, (term, done));
+        }
+        self.changed.notify_all();
+        match applied.recv_timeout(COMMIT_TIMEOUT) {
+            Ok(existed) => Ok(existed?),
+            Err(_) => Err(Refused::Failed(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the write was not committed in time",
+            ))),
+        }
+    }
+
+    /// Returns once a read of the store is linearizable: a majority of the nodes has confirmed
+    /// that this node still leads, and the store has applied every write committed when the
+    /// read began.
+    fn read_barrier(&self) -> Result<(), Refused> {
+        let deadline = Instant::now() + COMMIT_TIMEOUT;
+        let mut consensus = self.lock();
+        let Some(ticket) = consensus.raft.read_index() else {
+            return Err(Refused::NotLeader(consensus.raft.leader()));
+        };
+        self.changed.notify_all();
+        let mut confirmed = false;
+        loop {
+            let raft = &consensus.raft;
+            confirmed = confirmed || raft.confirmed(&ticket);
+            if confirmed && raft.applied_index() >= ticket.index {
+                return Ok(());
+            }
+            if !confirmed && (raft.role() != Role::Leader || raft.term() != ticket.term) {
+                return Err(Refused::NotLeader(raft.leader()));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Refused::Failed(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the leader could not reach a majority of the cluster in time",
+                )));
+            }
+            consensus = self.wait(consensus, deadline - now);
+        }
+    }
+
+    /// Stands for election whenever the leader goes quiet, logging every change of role.
+    fn tick(&self) {
+        let mut seen = None;
+        loop {
+            thread::sleep(TICK);
+            let mut consensus = self.lock();
+            if let Err(e) = consensus.raft.tick(Instant::now()) {
+                error!("failed to stand for election: {}", e);
+            }
+            let raft = &consensus.raft;
+            let now = (raft.role(), raft.term());
+            if seen != Some(now) {
+                seen = Some(now);
+                info!(
+                    "node {} is {} in term {}",
+                    raft.id(),
+                    role_name(now.0),
+                    now.1
+                );
+                drop(consensus);
+                self.changed.notify_all();
+            }
+        }
+    }
+
+    /// Sends `peer` at `url` whatever Raft has for it, one request at a time.
+    fn replicate_to(&self, peer: NodeId, url: &str) {
+        loop {
+            let request = {
+                let mut consensus = self.lock();
+                loop {
+                    match consensus.raft.next_request(peer, Instant::now()) {
+                        Ok(Some(request)) => break request,
+                        Ok(None) => {}
+                        Err(e) => error!("failed to read the Raft log for node {}: {}", peer, e),
+                    }
+                    consensus = self.wait(consensus, TICK);
+                }
+            };
+            match self.send(url, &request) {
+                Ok(response) => {
+                    let mut consensus = self.lock();
+                    if let Err(e) = consensus.raft.on_response(peer, &response, Instant::now()) {
+                        error!("failed to take in node {}'s response: {}", peer, e);
+                    }
+                    drop(consensus);
+                    self.changed.notify_all();
+                }
+                Err(e) => {
+                    debug!("node {} at {} did not answer: {}", peer, url, e);
+                    self.lock().raft.on_unreachable(peer);
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    fn send(&self, url: &str, request: &Request) -> io::Result<Response> {
+        let (path, body) = match request {
+            Request::Vote(request) => ("/cluster/vote", request.encode()),
+            Request::Append(request) => ("/cluster/append", request.encode()),
+        };
+        let mut call = self
+            .agent
+            .post(&format!("{}{}", url, path))
+            .set("Content-Type", "application/octet-stream");
+        if let Some(token) = &self.token {
+            call = call.set("Authorization", &format!("Bearer {}", token));
+        }
+        let response = call.send_bytes(&body).map_err(io::Error::other)?;
+        let mut bytes = Vec::new();
+        response.into_reader().read_to_end(&mut bytes)?;
+        match request {
+            Request::Vote(_) => VoteResponse::decode(&bytes).map(Response::Vote),
+            Request::Append(_) => AppendResponse::decode(&bytes).map(Response::Append),
+        }
+    }
+
+    /// Applies committed entries to the store in order and answers the writes waiting on them.
+    /// An entry the store fails to apply is retried until it goes in, since skipping it would
+    /// set this node's store apart from the others'.
+    fn apply_committed(&self) {
+        loop {
+            let entries = {
+                let mut consensus = self.lock();
+                loop {
+                    match consensus.raft.committed(APPLY_BATCH) {
+                        Ok(entries) if !entries.is_empty() => break entries,
+                        Ok(_) => {}
+                        Err(e) => error!("failed to read committed Raft entries: {}", e),
+                    }
+                    consensus = self.wait(consensus, TICK);
+                }
+            };
+            for (index, entry) in entries {
+                let term = entry.term;
+                let existed = loop {
+                    match raft::apply(&self.engine, index, entry.clone()) {
+                        Ok(existed) => break existed,
+                        Err(e) => {
+                            error!("failed to apply Raft entry {}, retrying: {}", index, e);
+                            thread::sleep(RETRY_DELAY);
+                        }
+                    }
+                };
+                let mut consensus = self.lock();
+                if let Err(e) = consensus.raft.applied_to(index) {
+                    error!("failed to discard applied Raft entries: {}", e);
+                }
+                if let Some((proposed, done)) = consensus.waiters.remove(&index) {
+                    let existed = match proposed == term {
+                        true => Ok(existed),
+                        false => Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "the write was lost to a change of leader",
+                        )),
+                    };
+                    let _ = done.try_send(existed);
+                }
+                drop(consensus);
+                self.changed.notify_all();
+            }
+        }
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Follower => "follower",
+        Role::Candidate => "candidate",
+        Role::Leader => "leader",
+    }
+}
+
+/// Registers the routes a cluster node serves in place of the usual ones: the Raft endpoints
+/// the nodes call each other on, and the single-key and batch routes, which go through the
+/// leader. Everything else answers `404`.
+pub fn configure(cfg: &mut web::ServiceConfig, node: web::Data<ClusterNode>, max_body_size: usize) {
+    // Entries are added to an append request until they pass `MAX_APPEND_BYTES`, and the last
+    // may hold a whole request body.
+    let append_limit = raft::MAX_APPEND_BYTES.saturating_add(max_body_size.saturating_mul(2));
+    cfg.app_data(node)
+        .route("/health", web::get().to(health::health))
+        .route("/ready", web::get().to(ready))
+        .route("/cluster/status", web::get().to(status))
+        .route("/cluster/vote", web::post().to(vote))
+        .service(
+            web::resource("/cluster/append")
+                .app_data(web::PayloadConfig::new(append_limit))
+                .route(web::post().to(append)),
+        )
+        .route("/set", web::post().to(set))
+        .route("/get/{key}", web::get().to(get))
+        .route("/del/{key}", web::delete().to(del))
+        .route("/batch/set", web::post().to(batch_set))
+        .route("/batch/get", web::post().to(batch_get))
+        .route("/batch/del", web::post().to(batch_del))
+        .route("/{path:.*}", web::route().to(not_served));
+}
+
+async fn not_served() -> HttpResponse {
+    HttpResponse::NotFound().body("a cluster node only serves single-key requests and batches")
+}
+
+/// Ready once the node has joined the cluster and knows its leader.
+async fn ready(state: web::Data<AppState>, node: web::Data<ClusterNode>) -> HttpResponse {
+    let leader = node
+        .cluster()
+        .ok()
+        .map(|cluster| cluster.lock().raft.leader());
+    match leader {
+        None => HttpResponse::ServiceUnavailable().body("loading"),
+        Some(_) if state.in_maintenance() => HttpResponse::ServiceUnavailable().body("maintenance"),
+        Some(None) => HttpResponse::ServiceUnavailable().body("no leader"),
+        Some(Some(_)) => HttpResponse::Ok().body("ready"),
+    }
+}
+
+#[derive(Serialize)]
+struct ClusterStatus {
+    id: NodeId,
+    role: &'static str,
+    term: u64,
+    leader: Option<NodeId>,
+    commit_index: u64,
+    applied_index: u64,
+    last_index: u64,
+}
+
+/// `GET /cluster/status`: this node's view of the cluster. Admin only.
+async fn status(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    let cluster = match node.cluster() {
+        Ok(cluster) => cluster,
+        Err(response) => return response,
+    };
+    let consensus = cluster.lock();
+    let raft = &consensus.raft;
+    HttpResponse::Ok().json(ClusterStatus {
+        id: raft.id(),
+        role: role_name(raft.role()),
+        term: raft.term(),
+        leader: raft.leader(),
+        commit_index: raft.commit_index(),
+        applied_index: raft.applied_index(),
+        last_index: raft.last_index(),
+    })
+}
+
+/// `POST /cluster/vote`: another node asks for this one's vote. Admin only, like every request
+/// between the nodes.
+async fn vote(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    match VoteRequest::decode(&body) {
+        Ok(request) => answer(&node, Request::Vote(request)).await,
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+/// `POST /cluster/append`: the leader's entries, or its heartbeat.
+async fn append(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    match AppendRequest::decode(&body) {
+        Ok(request) => answer(&node, Request::Append(request)).await,
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+async fn answer(node: &ClusterNode, request: Request) -> HttpResponse {
+    let cluster = match node.cluster() {
+        Ok(cluster) => cluster.clone(),
+        Err(response) => return response,
+    };
+    let body = match web::block(move || cluster.handle(&request)).await {
+        Ok(Ok(Response::Vote(response))) => response.encode(),
+        Ok(Ok(Response::Append(response))) => response.encode(),
+        Ok(Err(e)) => return HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(body)
+}
+
+#[derive(Deserialize)]
+struct SetRequest {
+    key: String,
+    value: String,
+    ttl_secs: Option<u64>,
+}
+
+/// `POST /set`, committed through the leader.
+async fn set(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<EncodingQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    let set: SetRequest = match serde_json::from_slice(&body) {
+        Ok(set) => set,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if set.ttl_secs.is_some() || !matches!(idempotency::request_id(&req), Ok(None)) {
+        return HttpResponse::BadRequest().body(UNSUPPORTED);
+    }
+    let (key, value) = match (
+        query.encoding.decode(&set.key),
+        query.encoding.decode(&set.value),
+    ) {
+        (Ok(key), Ok(value)) => (key, value),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let ops = vec![BatchOp::Set { key, value }];
+    match node
+        .on_leader(&state, &req, body, move |cluster| cluster.write(ops))
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().body("OK"),
+        Err(response) => response,
+    }
+}
+
+/// `GET /get/{key}`, a linearizable read on the leader.
+async fn get(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&path) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&key) {
+        return response;
+    }
+    let read = move |cluster: &Cluster| {
+        cluster.read_barrier()?;
+        Ok(cluster.engine.get(&key)?)
+    };
+    match node.on_leader(&state, &req, web::Bytes::new(), read).await {
+        Ok(Some(value)) => HttpResponse::Ok().body(query.encoding.encode(&value)),
+        Ok(None) => HttpResponse::NotFound().body("Key is not found"),
+        Err(response) => response,
+    }
+}
+
+/// `DELETE /del/{key}`, committed through the leader.
+async fn del(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&path) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let ops = vec![BatchOp::Del { key }];
+    match node
+        .on_leader(&state, &req, web::Bytes::new(), move |cluster| {
+            cluster.write(ops)
+        })
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().body("OK"),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct SetItem {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct BatchSetQuery {
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    if_absent: bool,
+}
+
+#[derive(Serialize)]
+struct SetResult {
+    key: String,
+    existed: bool,
+}
+
+#[derive(Serialize)]
+struct DelResult {
+    deleted: usize,
+}
+
+/// `POST /batch/set`, committed as one entry through the leader.
+async fn batch_set(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<BatchSetQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    if query.if_absent {
+        return HttpResponse::BadRequest().body(UNSUPPORTED);
+    }
+    let items: Vec<SetItem> = match batch::parse_items(&req, &body) {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let encoding = query.encoding;
+    let ops: Result<Vec<BatchOp>, DecodeError> = items
+        .iter()
+        .map(|item| {
+            Ok(BatchOp::Set {
+                key: encoding.decode(&item.key)?,
+                value: encoding.decode(&item.value)?,
+            })
+        })
+        .collect();
+    let ops = match ops {
+        Ok(ops) => ops,
+        Err(e) => return bad_encoding(e),
+    };
+    let keys: Vec<&[u8]> = ops
+        .iter()
+        .filter_map(|op| match op {
+            BatchOp::Set { key, .. } => Some(key.as_slice()),
+            BatchOp::Del { .. } => None,
+        })
+        .collect();
+    if let Err(response) = scope.write_all(&keys) {
+        return response;
+    }
+    match node
+        .on_leader(&state, &req, body, move |cluster| cluster.write(ops))
+        .await
+    {
+        Ok(existed) => HttpResponse::Ok().json(
+            items
+                .into_iter()
+                .zip(existed)
+                .map(|(item, existed)| SetResult {
+                    key: item.key,
+                    existed,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(response) => response,
+    }
+}
+
+/// `POST /batch/get`, a linearizable read of every key on the leader.
+async fn batch_get(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<EncodingQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    let encoding = query.encoding;
+    let keys = match decode_keys(&body, encoding) {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    if let Err(response) = scope.read_all(&keys) {
+        return response;
+    }
+    let read = move |cluster: &Cluster| {
+        cluster.read_barrier()?;
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+        Ok(cluster.engine.get_many(&keys)?)
+    };
+    match node.on_leader(&state, &req, body, read).await {
+        Ok(values) => HttpResponse::Ok().json(
+            values
+                .into_iter()
+                .map(|value| value.map(|v| encoding.encode(&v)))
+                .collect::<Vec<_>>(),
+        ),
+        Err(response) => response,
+    }
+}
+
+/// `POST /batch/del`, committed as one entry through the leader.
+async fn batch_del(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<EncodingQuery>,
+    state: web::Data<AppState>,
+    node: web::Data<ClusterNode>,
+    scope: Scope,
+) -> HttpResponse {
+    let keys = match decode_keys(&body, query.encoding) {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    if let Err(response) = scope.write_all(&keys) {
+        return response;
+    }
+    let ops: Vec<BatchOp> = keys.into_iter().map(|key| BatchOp::Del { key }).collect();
+    match node
+        .on_leader(&state, &req, body, move |cluster| cluster.write(ops))
+        .await
+    {
+        Ok(existed) => HttpResponse::Ok().json(DelResult {
+            deleted: existed.into_iter().filter(|&existed| existed).count(),
+        }),
+        Err(response) => response,
+    }
+}
+
+/// The keys of a JSON array body.
+fn decode_keys(body: &[u8], encoding: Encoding) -> Result<Vec<Vec<u8>>, HttpResponse> {
+    let keys: Vec<String> =
+        serde_json::from_slice(body).map_err(|e| HttpResponse::BadRequest().body(e.to_string()))?;
+    keys.iter()
+        .map(|key| encoding.decode(key))
+        .collect::<Result<_, _>>()
+        .map_err(bad_encoding)
+}
+
+fn bad_encoding(e: DecodeError) -> HttpResponse {
+    HttpResponse::BadRequest().body(e.to_string())
+}
@@ -0,0 +1,67 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+
+/// When a response is worth compressing, shared with `rules` through app data.
+pub struct CompressionRules {
+    /// Bodies smaller than this many bytes go out as they are.
+    pub min_size: u64,
+}
+
+/// Runs inside actix's `Compress` middleware and marks responses it should leave alone as
+/// `Content-Encoding: identity`: anything but `GET`, bodies under the minimum size, and content
+/// types that are already compressed or binary.
+pub async fn rules(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let min_size = req
+        .app_data::<web::Data<CompressionRules>>()
+        .map_or(0, |rules| rules.min_size);
+    let is_get = req.method() == Method::GET;
+
+    let mut res = next.call(req).await?;
+    let big_enough = match res.response().body().size() {
+        BodySize::Sized(len) => len >= min_size,
+        BodySize::Stream => true,
+        BodySize::None => false,
+    };
+    let compressible = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_compressible);
+
+    if !(is_get && big_enough && compressible)
+        && !res.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        res.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
+    }
+    Ok(res)
+}
+
+/// Text-like content types, which compress well.
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/x-ndjson"
+                | "application/javascript"
+                | "application/xml"
+        )
+}
@@ -0,0 +1,1093 @@
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use breakout1_kv_store::constants::{DEFAULT_COMPACT_THRESHOLD, DEFAULT_IDEMPOTENCY_WINDOW};
+use breakout1_kv_store::options::SyncPolicy;
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use super::access_log::LogFormat;
+use super::acl::AclRule;
+use super::cluster::ClusterSettings;
+use super::cors::CorsSettings;
+use super::jwt::JwtSettings;
+use super::kafka::KafkaSettings;
+use super::mqtt::MqttSettings;
+use super::replica::ReplicaSettings;
+use super::router::RouterSettings;
+use super::s3_backup::S3Settings;
+use super::timeout::Timeouts;
+use super::tls::TlsSettings;
+
+const DEFAULT_BIND: &str = "127.0.0.1:8080";
+const DEFAULT_DATA_PATH: &str = "data.db";
+const DEFAULT_MAX_VALUE_SIZE: u64 = 16 * 1024 * 1024;
+/// Room for JSON syntax around a value, on top of its base64-encoded size, in the default body
+/// limit.
+const BODY_OVERHEAD: u64 = 64 * 1024;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+const DEFAULT_REQUEST_TIMEOUT: u64 = 30;
+const DEFAULT_BULK_TIMEOUT: u64 = 120;
+const DEFAULT_SLOW_REQUEST_MS: u64 = 1000;
+const DEFAULT_COMPRESSION_MIN_SIZE: u64 = 1024;
+const DEFAULT_CORS_METHODS: &[&str] = &["GET", "HEAD", "PUT", "PATCH", "POST", "DELETE"];
+const DEFAULT_CORS_HEADERS: &[&str] =
+    &["content-type", "authorization", "if-match", "if-none-match"];
+const DEFAULT_CORS_MAX_AGE: usize = 3600;
+const DEFAULT_JWT_JWKS_REFRESH: u64 = 3600;
+const DEFAULT_JWT_SCOPE_CLAIM: &str = "scope";
+const DEFAULT_KAFKA_TOPIC: &str = "kv-changes";
+const DEFAULT_KAFKA_CHECKPOINT_KEY: &str = "__kafka_checkpoint";
+const DEFAULT_S3_ENDPOINT: &str = "https://s3.amazonaws.com";
+const DEFAULT_S3_REGION: &str = "us-east-1";
+const DEFAULT_S3_PREFIX: &str = "kv-backups";
+const DEFAULT_S3_RETAIN: usize = 7;
+const DEFAULT_MQTT_TOPIC: &str = "kv/changes";
+const DEFAULT_MQTT_CLIENT_ID: &str = "breakout1-kv-store";
+const DEFAULT_ROUTER_VNODES: u32 = 160;
+
+/// The server binary's command line. Without a subcommand it serves, as `serve` does.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "HTTP server for the breakout1 key-value store, and maintenance of its data file",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+/// Maintenance commands take the same flags, environment variables and config file as `serve`,
+/// which tell them where the data file is and how to load it. Run them while the server is
+/// stopped.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the HTTP server (the default)
+    Serve(Args),
+    /// Compact the data file, then exit
+    Compact(Args),
+    /// Copy a consistent snapshot of the data file to DEST, then exit
+    Backup {
+        /// File to write the copy to
+        dest: PathBuf,
+        #[command(flatten)]
+        args: Args,
+    },
+    /// Check that every record in the data file decodes, then exit; with status 1 if any does not
+    Verify(Args),
+}
+
+/// What the server binary was asked to do.
+#[derive(Debug)]
+pub enum Task {
+    Serve,
+    Maintain(Maintenance),
+}
+
+#[derive(Debug)]
+pub enum Maintenance {
+    Compact,
+    Backup(PathBuf),
+    Verify,
+}
+
+/// Command-line flags for the HTTP server. Every flag can also be set through its environment
+/// variable, which takes precedence over it, and both override the config file.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// TOML configuration file
+    #[arg(long, env = "KV_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address and port to listen on, or `none` to only listen on --unix-socket
+    /// [default: 127.0.0.1:8080]
+    #[arg(long, env = "KV_BIND")]
+    pub bind: Option<String>,
+
+    /// Unix domain socket path to listen on as well as (or instead of) TCP
+    #[arg(long, env = "KV_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Address and port of a Redis protocol (RESP2) listener; off when unset
+    #[arg(long, env = "KV_RESP_BIND")]
+    pub resp_bind: Option<String>,
+
+    /// Address and port of a memcached text protocol listener; off when unset
+    #[arg(long, env = "KV_MEMCACHED_BIND")]
+    pub memcached_bind: Option<String>,
+
+    /// Address and port of a gRPC listener serving proto/kv.proto; off when unset
+    #[arg(long, env = "KV_GRPC_BIND")]
+    pub grpc_bind: Option<String>,
+
+    /// Address and port of a listener for the length-prefixed binary protocol; off when unset
+    #[arg(long, env = "KV_BINARY_BIND")]
+    pub binary_bind: Option<String>,
+
+    /// `host:port` of an MQTT broker to publish change events to; the bridge is off when unset
+    #[arg(long, env = "KV_MQTT_BROKER")]
+    pub mqtt_broker: Option<String>,
+
+    /// MQTT topic change events are published to [default: kv/changes]
+    #[arg(long, env = "KV_MQTT_TOPIC")]
+    pub mqtt_topic: Option<String>,
+
+    /// Only changes to keys under this prefix are published [default: every key]
+    #[arg(long, env = "KV_MQTT_PREFIX")]
+    pub mqtt_prefix: Option<String>,
+
+    /// Publish values rather than their SHA-256 digest [default: false]
+    #[arg(long, env = "KV_MQTT_VALUES")]
+    pub mqtt_values: Option<bool>,
+
+    /// MQTT client identifier [default: breakout1-kv-store]
+    #[arg(long, env = "KV_MQTT_CLIENT_ID")]
+    pub mqtt_client_id: Option<String>,
+
+    /// MQTT user name
+    #[arg(long, env = "KV_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(long, env = "KV_MQTT_PASSWORD", hide_env_values = true)]
+    pub mqtt_password: Option<String>,
+
+    /// Comma-separated Kafka bootstrap servers to produce change records to; the sink is off
+    /// when unset
+    #[arg(long, env = "KV_KAFKA_BROKERS")]
+    pub kafka_brokers: Option<String>,
+
+    /// Kafka topic change records are produced to [default: kv-changes]
+    #[arg(long, env = "KV_KAFKA_TOPIC")]
+    pub kafka_topic: Option<String>,
+
+    /// Only changes to keys under this prefix are produced [default: every key]
+    #[arg(long, env = "KV_KAFKA_PREFIX")]
+    pub kafka_prefix: Option<String>,
+
+    /// Key the Kafka sink keeps its checkpoint under [default: __kafka_checkpoint]
+    #[arg(long, env = "KV_KAFKA_CHECKPOINT_KEY")]
+    pub kafka_checkpoint_key: Option<String>,
+
+    /// Base URL of a primary to replicate; the server then starts read-only, bootstraps its data
+    /// file from the primary if it is empty and applies the primary's writes as they happen
+    #[arg(long, env = "KV_REPLICA_OF")]
+    pub replica_of: Option<String>,
+
+    /// Admin token or JWT the replica sends to the primary
+    #[arg(long, env = "KV_REPLICA_TOKEN", hide_env_values = true)]
+    pub replica_token: Option<String>,
+
+    /// Writes a replica may fall behind its primary before /ready fails [default: no bound]
+    #[arg(long, env = "KV_REPLICA_MAX_LAG")]
+    pub replica_max_lag: Option<u64>,
+
+    /// Comma-separated base URLs of backend nodes; the server then holds no data and forwards
+    /// each key to one of them by consistent hashing
+    #[arg(long, env = "KV_ROUTE_TO", value_delimiter = ',')]
+    pub route_to: Option<Vec<String>>,
+
+    /// Points each backend gets on the router's hash ring [default: 160]
+    #[arg(long, env = "KV_ROUTER_VNODES")]
+    pub router_vnodes: Option<u32>,
+
+    /// Comma-separated `id=url` of every node of a Raft cluster, this one included; writes are
+    /// then committed by a majority of the nodes and reads go through the leader
+    #[arg(long, env = "KV_CLUSTER_NODES", value_delimiter = ',')]
+    pub cluster_nodes: Option<Vec<String>>,
+
+    /// This node's id among cluster_nodes
+    #[arg(long, env = "KV_CLUSTER_ID")]
+    pub cluster_id: Option<u64>,
+
+    /// Admin token or JWT sent to the other nodes of the cluster [default: admin_token]
+    #[arg(long, env = "KV_CLUSTER_TOKEN", hide_env_values = true)]
+    pub cluster_token: Option<String>,
+
+    /// Path of the cluster's Raft log [default: the data path with a .raft extension]
+    #[arg(long, env = "KV_CLUSTER_LOG")]
+    pub cluster_log: Option<PathBuf>,
+
+    /// Path of the data file [default: data.db]
+    #[arg(long, visible_alias = "data", env = "KV_DATA_PATH")]
+    pub data_path: Option<PathBuf>,
+
+    /// Directory of bucket data files, served under /b/{bucket}; buckets are off when unset
+    #[arg(long, env = "KV_BUCKET_DIR")]
+    pub bucket_dir: Option<PathBuf>,
+
+    /// Data file recording who made each write through the HTTP API, listed by /admin/audit;
+    /// the audit log is off when unset. Cannot be combined with the other protocol listeners
+    #[arg(long, env = "KV_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Log size in bytes that triggers automatic compaction [default: 1048576]
+    #[arg(long, visible_alias = "threshold", env = "KV_COMPACT_THRESHOLD")]
+    pub compact_threshold: Option<u64>,
+
+    /// When writes are synced to disk: `never` or `always` [default: never]
+    #[arg(long, env = "KV_SYNC", value_parser = parse_sync)]
+    pub sync: Option<SyncPolicy>,
+
+    /// Directory to copy every record written to the main data file into, in hourly files that
+    /// compaction leaves alone; archiving is off when unset
+    #[arg(long, env = "KV_ARCHIVE_DIR")]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Hours archive files are kept after they are last written; 0 keeps them forever [default: 0]
+    #[arg(long, env = "KV_ARCHIVE_RETENTION_HOURS")]
+    pub archive_retention_hours: Option<u64>,
+
+    /// Largest request body accepted, in bytes; streamed /kv uploads are exempt [default: enough
+    /// for one value of the maximum size, base64-encoded in JSON]
+    #[arg(long, env = "KV_MAX_BODY_SIZE")]
+    pub max_body_size: Option<usize>,
+
+    /// Largest value stored, in bytes [default: 16777216]
+    #[arg(long, env = "KV_MAX_VALUE_SIZE")]
+    pub max_value_size: Option<u64>,
+
+    /// Refuse writes with 507 while the data file's file system has fewer bytes free than this;
+    /// reads carry on [default: off]
+    #[arg(long, env = "KV_MIN_FREE_SPACE")]
+    pub min_free_space: Option<u64>,
+
+    /// Refuse writes of new keys with 507 once the in-memory index would use more than this many
+    /// bytes; existing keys can still be updated and deleted [default: off]
+    #[arg(long, env = "KV_MAX_INDEX_MEMORY")]
+    pub max_index_memory: Option<u64>,
+
+    /// Engine gets, sets, deletes, compactions and index rebuilds taking at least this many
+    /// milliseconds are logged and listed by /admin/slowlog [default: off]
+    #[arg(long, env = "KV_SLOW_OP_MS")]
+    pub slow_op_ms: Option<u64>,
+
+    /// Seconds in-flight requests get to finish after SIGINT/SIGTERM [default: 30]
+    #[arg(long, env = "KV_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Seconds the request ids of writes with an `Idempotency-Key` are remembered, so retries
+    /// within them are not applied again [default: 86400]
+    #[arg(long, env = "KV_IDEMPOTENCY_WINDOW")]
+    pub idempotency_window: Option<u64>,
+
+    /// Seconds deleted values are kept in the trash, so POST /undelete/{key} can restore them
+    /// [default: off]
+    #[arg(long, env = "KV_TRASH_RETENTION")]
+    pub trash_retention: Option<u64>,
+
+    /// Seconds a request may take before it is answered with 503; 0 disables the limit
+    /// [default: 30]
+    #[arg(long, env = "KV_REQUEST_TIMEOUT")]
+    pub request_timeout: Option<u64>,
+
+    /// Seconds /scan, /range, /keys and /batch requests may take; 0 disables the limit
+    /// [default: 120]
+    #[arg(long, env = "KV_BULK_TIMEOUT")]
+    pub bulk_timeout: Option<u64>,
+
+    /// Requests taking at least this many milliseconds are logged; 0 disables the log
+    /// [default: 1000]
+    #[arg(long, env = "KV_SLOW_REQUEST_MS")]
+    pub slow_request_ms: Option<u64>,
+
+    /// Bearer token for /admin endpoints; they are disabled when unset
+    #[arg(long, env = "KV_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Comma-separated API keys; every route but /health requires one when set
+    #[arg(
+        long,
+        env = "KV_API_KEYS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub api_keys: Option<Vec<String>>,
+
+    /// Access rules as a TOML array of tables, such as `[{ api_key = "k1", read = ["pub:"] }]`;
+    /// replaces the config file's `[[acl]]` tables
+    #[arg(long, env = "KV_ACL", hide_env_values = true)]
+    pub acl: Option<String>,
+
+    /// Compress GET responses with gzip, brotli or zstd, as the client accepts [default: false]
+    #[arg(long, env = "KV_COMPRESSION")]
+    pub compression: Option<bool>,
+
+    /// Smallest response body compressed, in bytes [default: 1024]
+    #[arg(long, env = "KV_COMPRESSION_MIN_SIZE")]
+    pub compression_min_size: Option<u64>,
+
+    /// Comma-separated origins allowed to make cross-origin requests, or `*`; CORS is off when
+    /// unset
+    #[arg(long, env = "KV_CORS_ORIGINS", value_delimiter = ',')]
+    pub cors_origins: Option<Vec<String>>,
+
+    /// Comma-separated methods allowed cross-origin [default: GET,HEAD,PUT,PATCH,POST,DELETE]
+    #[arg(long, env = "KV_CORS_METHODS", value_delimiter = ',')]
+    pub cors_methods: Option<Vec<String>>,
+
+    /// Comma-separated request headers allowed cross-origin, or `*`
+    /// [default: content-type,authorization,if-match,if-none-match]
+    #[arg(long, env = "KV_CORS_HEADERS", value_delimiter = ',')]
+    pub cors_headers: Option<Vec<String>>,
+
+    /// Seconds browsers may cache a preflight response [default: 3600]
+    #[arg(long, env = "KV_CORS_MAX_AGE")]
+    pub cors_max_age: Option<usize>,
+
+    /// Shared secret for HS256/HS384/HS512 JWTs; set this or --jwt-jwks-url to accept JWTs
+    #[arg(long, env = "KV_JWT_SECRET", hide_env_values = true)]
+    pub jwt_secret: Option<String>,
+
+    /// URL of the JWKS document with the keys of RS*/ES*/PS*/EdDSA JWTs
+    #[arg(long, env = "KV_JWT_JWKS_URL")]
+    pub jwt_jwks_url: Option<String>,
+
+    /// Seconds between JWKS refreshes [default: 3600]
+    #[arg(long, env = "KV_JWT_JWKS_REFRESH")]
+    pub jwt_jwks_refresh: Option<u64>,
+
+    /// Required `aud` claim of JWTs
+    #[arg(long, env = "KV_JWT_AUDIENCE")]
+    pub jwt_audience: Option<String>,
+
+    /// Required `iss` claim of JWTs
+    #[arg(long, env = "KV_JWT_ISSUER")]
+    pub jwt_issuer: Option<String>,
+
+    /// JWT claim holding the kv:read, kv:write and kv:admin scopes [default: scope]
+    #[arg(long, env = "KV_JWT_SCOPE_CLAIM")]
+    pub jwt_scope_claim: Option<String>,
+
+    /// PEM certificate chain; with --tls-key the server speaks HTTPS
+    #[arg(long, env = "KV_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "KV_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA certificates client certificates must chain to; enables mutual TLS
+    #[arg(long, env = "KV_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// S3 bucket for /admin/backup/s3; remote backups are off when unset
+    #[arg(long, env = "KV_S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+
+    /// URL of the S3-compatible service [default: https://s3.amazonaws.com]
+    #[arg(long, env = "KV_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    /// Region to sign S3 requests for [default: us-east-1]
+    #[arg(long, env = "KV_S3_REGION")]
+    pub s3_region: Option<String>,
+
+    /// S3 access key ID
+    #[arg(long, env = "KV_S3_ACCESS_KEY")]
+    pub s3_access_key: Option<String>,
+
+    /// S3 secret access key
+    #[arg(long, env = "KV_S3_SECRET_KEY", hide_env_values = true)]
+    pub s3_secret_key: Option<String>,
+
+    /// Object key prefix backup sets are stored under [default: kv-backups]
+    #[arg(long, env = "KV_S3_PREFIX")]
+    pub s3_prefix: Option<String>,
+
+    /// Backup sets kept in the bucket; older ones are deleted after a full backup [default: 7]
+    #[arg(long, env = "KV_S3_RETAIN")]
+    pub s3_retain: Option<usize>,
+
+    /// Requests per second allowed per client (API key or IP address); 0 disables the limit
+    /// [default: 0]
+    #[arg(long, env = "KV_RATE_LIMIT")]
+    pub rate_limit: Option<u32>,
+
+    /// Requests a client may send at once before the rate applies [default: the rate limit]
+    #[arg(long, env = "KV_RATE_LIMIT_BURST")]
+    pub rate_limit_burst: Option<u32>,
+
+    /// Log every request (method, path, status, latency, bytes, client) [default: true]
+    #[arg(long, env = "KV_ACCESS_LOG")]
+    pub access_log: Option<bool>,
+
+    /// Log output: `text` or `json` [default: text]
+    #[arg(long, env = "KV_LOG_FORMAT", value_parser = parse_log_format)]
+    pub log_format: Option<LogFormat>,
+
+    /// Serve Swagger UI for the API at /docs [default: false]
+    #[arg(long, env = "KV_DOCS")]
+    pub docs: Option<bool>,
+
+    /// Serve a GraphQL API at /graphql [default: false]
+    #[arg(long, env = "KV_GRAPHQL")]
+    pub graphql: Option<bool>,
+}
+
+impl Args {
+    /// These flags with any set in `env` replacing them.
+    fn overridden_by(self, env: Args) -> Args {
+        Args {
+            config: env.config.or(self.config),
+            bind: env.bind.or(self.bind),
+            unix_socket: env.unix_socket.or(self.unix_socket),
+            resp_bind: env.resp_bind.or(self.resp_bind),
+            memcached_bind: env.memcached_bind.or(self.memcached_bind),
+            grpc_bind: env.grpc_bind.or(self.grpc_bind),
+            binary_bind: env.binary_bind.or(self.binary_bind),
+            mqtt_broker: env.mqtt_broker.or(self.mqtt_broker),
+            mqtt_topic: env.mqtt_topic.or(self.mqtt_topic),
+            mqtt_prefix: env.mqtt_prefix.or(self.mqtt_prefix),
+            mqtt_values: env.mqtt_values.or(self.mqtt_values),
+            mqtt_client_id: env.mqtt_client_id.or(self.mqtt_client_id),
+            mqtt_username: env.mqtt_username.or(self.mqtt_username),
+            mqtt_password: env.mqtt_password.or(self.mqtt_password),
+            kafka_brokers: env.kafka_brokers.or(self.kafka_brokers),
+            kafka_topic: env.kafka_topic.or(self.kafka_topic),
+            kafka_prefix: env.kafka_prefix.or(self.kafka_prefix),
+            kafka_checkpoint_key: env.kafka_checkpoint_key.or(self.kafka_checkpoint_key),
+            replica_of: env.replica_of.or(self.replica_of),
+            replica_token: env.replica_token.or(self.replica_token),
+            replica_max_lag: env.replica_max_lag.or(self.replica_max_lag),
+            route_to: env.route_to.or(self.route_to),
+            router_vnodes: env.router_vnodes.or(self.router_vnodes),
+            cluster_nodes: env.cluster_nodes.or(self.cluster_nodes),
+            cluster_id: env.cluster_id.or(self.cluster_id),
+            cluster_token: env.cluster_token.or(self.cluster_token),
+            cluster_log: env.cluster_log.or(self.cluster_log),
+            data_path: env.data_path.or(self.data_path),
+            bucket_dir: env.bucket_dir.or(self.bucket_dir),
+            audit_log: env.audit_log.or(self.audit_log),
+            compact_threshold: env.compact_threshold.or(self.compact_threshold),
+            sync: env.sync.or(self.sync),
+            archive_dir: env.archive_dir.or(self.archive_dir),
+            archive_retention_hours: env.archive_retention_hours.or(self.archive_retention_hours),
+            max_body_size: env.max_body_size.or(self.max_body_size),
+            max_value_size: env.max_value_size.or(self.max_value_size),
+            min_free_space: env.min_free_space.or(self.min_free_space),
+            max_index_memory: env.max_index_memory.or(self.max_index_memory),
+            slow_op_ms: env.slow_op_ms.or(self.slow_op_ms),
+            shutdown_timeout: env.shutdown_timeout.or(self.shutdown_timeout),
+            idempotency_window: env.idempotency_window.or(self.idempotency_window),
+            trash_retention: env.trash_retention.or(self.trash_retention),
+            request_timeout: env.request_timeout.or(self.request_timeout),
+            bulk_timeout: env.bulk_timeout.or(self.bulk_timeout),
+            slow_request_ms: env.slow_request_ms.or(self.slow_request_ms),
+            admin_token: env.admin_token.or(self.admin_token),
+            api_keys: env.api_keys.or(self.api_keys),
+            acl: env.acl.or(self.acl),
+            compression: env.compression.or(self.compression),
+            compression_min_size: env.compression_min_size.or(self.compression_min_size),
+            cors_origins: env.cors_origins.or(self.cors_origins),
+            cors_methods: env.cors_methods.or(self.cors_methods),
+            cors_headers: env.cors_headers.or(self.cors_headers),
+            cors_max_age: env.cors_max_age.or(self.cors_max_age),
+            jwt_secret: env.jwt_secret.or(self.jwt_secret),
+            jwt_jwks_url: env.jwt_jwks_url.or(self.jwt_jwks_url),
+            jwt_jwks_refresh: env.jwt_jwks_refresh.or(self.jwt_jwks_refresh),
+            jwt_audience: env.jwt_audience.or(self.jwt_audience),
+            jwt_issuer: env.jwt_issuer.or(self.jwt_issuer),
+            jwt_scope_claim: env.jwt_scope_claim.or(self.jwt_scope_claim),
+            tls_cert: env.tls_cert.or(self.tls_cert),
+            tls_key: env.tls_key.or(self.tls_key),
+            tls_client_ca: env.tls_client_ca.or(self.tls_client_ca),
+            s3_bucket: env.s3_bucket.or(self.s3_bucket),
+            s3_endpoint: env.s3_endpoint.or(self.s3_endpoint),
+            s3_region: env.s3_region.or(self.s3_region),
+            s3_access_key: env.s3_access_key.or(self.s3_access_key),
+            s3_secret_key: env.s3_secret_key.or(self.s3_secret_key),
+            s3_prefix: env.s3_prefix.or(self.s3_prefix),
+            s3_retain: env.s3_retain.or(self.s3_retain),
+            rate_limit: env.rate_limit.or(self.rate_limit),
+            rate_limit_burst: env.rate_limit_burst.or(self.rate_limit_burst),
+            access_log: env.access_log.or(self.access_log),
+            log_format: env.log_format.or(self.log_format),
+            docs: env.docs.or(self.docs),
+            graphql: env.graphql.or(self.graphql),
+        }
+    }
+}
+
+/// Parsed from no command-line arguments at all, so it holds just the environment variables.
+#[derive(Parser, Debug)]
+struct EnvOnly {
+    #[command(flatten)]
+    args: Args,
+}
+
+/// Settings read from the `--config` file. Anything left out falls back to the defaults.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<String>,
+    unix_socket: Option<PathBuf>,
+    resp_bind: Option<String>,
+    memcached_bind: Option<String>,
+    grpc_bind: Option<String>,
+    binary_bind: Option<String>,
+    mqtt_broker: Option<String>,
+    mqtt_topic: Option<String>,
+    mqtt_prefix: Option<String>,
+    mqtt_values: Option<bool>,
+    mqtt_client_id: Option<String>,
+    mqtt_username: Option<String>,
+    mqtt_password: Option<String>,
+    kafka_brokers: Option<String>,
+    kafka_topic: Option<String>,
+    kafka_prefix: Option<String>,
+    kafka_checkpoint_key: Option<String>,
+    replica_of: Option<String>,
+    replica_token: Option<String>,
+    replica_max_lag: Option<u64>,
+    route_to: Option<Vec<String>>,
+    router_vnodes: Option<u32>,
+    cluster_nodes: Option<Vec<String>>,
+    cluster_id: Option<u64>,
+    cluster_token: Option<String>,
+    cluster_log: Option<PathBuf>,
+    data_path: Option<PathBuf>,
+    bucket_dir: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    compact_threshold: Option<u64>,
+    sync: Option<String>,
+    archive_dir: Option<PathBuf>,
+    archive_retention_hours: Option<u64>,
+    max_body_size: Option<usize>,
+    max_value_size: Option<u64>,
+    min_free_space: Option<u64>,
+    max_index_memory: Option<u64>,
+    slow_op_ms: Option<u64>,
+    shutdown_timeout: Option<u64>,
+    idempotency_window: Option<u64>,
+    trash_retention: Option<u64>,
+    request_timeout: Option<u64>,
+    bulk_timeout: Option<u64>,
+    slow_request_ms: Option<u64>,
+    admin_token: Option<String>,
+    api_keys: Option<Vec<String>>,
+    compression: Option<bool>,
+    compression_min_size: Option<u64>,
+    cors_origins: Option<Vec<String>>,
+    cors_methods: Option<Vec<String>>,
+    cors_headers: Option<Vec<String>>,
+    cors_max_age: Option<usize>,
+    jwt_secret: Option<String>,
+    jwt_jwks_url: Option<String>,
+    jwt_jwks_refresh: Option<u64>,
+    jwt_audience: Option<String>,
+    jwt_issuer: Option<String>,
+    jwt_scope_claim: Option<String>,
+    acl: Vec<AclRule>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_client_ca: Option<PathBuf>,
+    s3_bucket: Option<String>,
+    s3_endpoint: Option<String>,
+    s3_region: Option<String>,
+    s3_access_key: Option<String>,
+    s3_secret_key: Option<String>,
+    s3_prefix: Option<String>,
+    s3_retain: Option<usize>,
+    rate_limit: Option<u32>,
+    rate_limit_burst: Option<u32>,
+    access_log: Option<bool>,
+    log_format: Option<String>,
+    docs: Option<bool>,
+    graphql: Option<bool>,
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// Fully resolved server settings: environment variables, then flags, then the config file,
+/// then the built-in defaults.
+#[derive(Debug)]
+pub struct Config {
+    /// `None` when TCP is turned off with `bind = "none"`.
+    pub bind: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    pub resp_bind: Option<String>,
+    pub memcached_bind: Option<String>,
+    pub grpc_bind: Option<String>,
+    pub binary_bind: Option<String>,
+    pub mqtt: MqttSettings,
+    pub kafka: KafkaSettings,
+    pub replica: ReplicaSettings,
+    pub router: RouterSettings,
+    pub cluster: ClusterSettings,
+    pub data_path: PathBuf,
+    pub bucket_dir: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub compact_threshold: u64,
+    pub sync: SyncPolicy,
+    pub archive_dir: Option<PathBuf>,
+    pub archive_retention: Option<Duration>,
+    pub max_body_size: usize,
+    pub max_value_size: u64,
+    pub min_free_space: Option<u64>,
+    pub max_index_memory: Option<u64>,
+    pub slow_op_threshold: Option<Duration>,
+    pub shutdown_timeout: u64,
+    pub idempotency_window: Duration,
+    pub trash_retention: Option<Duration>,
+    pub timeouts: Timeouts,
+    pub admin_token: Option<String>,
+    pub api_keys: Vec<String>,
+    pub compression: bool,
+    pub compression_min_size: u64,
+    pub cors: CorsSettings,
+    pub jwt: JwtSettings,
+    /// `[[acl]]` tables in the config file, or `--acl`.
+    pub acl: Vec<AclRule>,
+    pub tls: TlsSettings,
+    pub s3: S3Settings,
+    pub rate_limit: u32,
+    pub rate_limit_burst: u32,
+    pub access_log: bool,
+    pub log_format: LogFormat,
+    pub docs: bool,
+    pub graphql: bool,
+}
+
+impl Config {
+    /// Parses the command line into what to do and the configuration to do it with.
+    pub fn load() -> io::Result<(Task, Self)> {
+        let cli = Cli::parse();
+        let (task, args) = match cli.command {
+            None => (Task::Serve, cli.args),
+            Some(Command::Serve(args)) => (Task::Serve, args),
+            Some(Command::Compact(args)) => (Task::Maintain(Maintenance::Compact), args),
+            Some(Command::Backup { dest, args }) => {
+                (Task::Maintain(Maintenance::Backup(dest)), args)
+            }
+            Some(Command::Verify(args)) => (Task::Maintain(Maintenance::Verify), args),
+        };
+        // clap lets a flag win over its environment variable; here the variable wins, so a
+        // container's environment overrides flags baked into its command.
+        let env = EnvOnly::parse_from(env::args_os().take(1)).args;
+        Ok((task, Self::from_args(args.overridden_by(env))?))
+    }
+
+    fn from_args(args: Args) -> io::Result<Self> {
+        let file = match &args.config {
+            Some(path) => FileConfig::read(path)?,
+            None => FileConfig::default(),
+        };
+
+        let sync = match (args.sync, file.sync) {
+            (Some(sync), _) => sync,
+            (None, Some(sync)) => parse_sync(&sync).map_err(invalid)?,
+            (None, None) => SyncPolicy::default(),
+        };
+        let log_format = match (args.log_format, file.log_format) {
+            (Some(format), _) => format,
+            (None, Some(format)) => parse_log_format(&format).map_err(invalid)?,
+            (None, None) => LogFormat::default(),
+        };
+
+        let bind = args
+            .bind
+            .or(file.bind)
+            .unwrap_or_else(|| DEFAULT_BIND.to_string());
+        let bind = (bind != "none").then_some(bind);
+        let unix_socket = args.unix_socket.or(file.unix_socket);
+        if bind.is_none() && unix_socket.is_none() {
+            return Err(invalid(
+                "bind is `none` and no unix_socket is set, so there is nothing to listen on"
+                    .to_string(),
+            ));
+        }
+
+        let slow_request_ms = args
+            .slow_request_ms
+            .or(file.slow_request_ms)
+            .unwrap_or(DEFAULT_SLOW_REQUEST_MS);
+        let rate_limit = args.rate_limit.or(file.rate_limit).unwrap_or(0);
+        let admin_token = args.admin_token.or(file.admin_token);
+        let max_value_size = args
+            .max_value_size
+            .or(file.max_value_size)
+            .unwrap_or(DEFAULT_MAX_VALUE_SIZE);
+
+        Ok(Config {
+            bind,
+            unix_socket,
+            resp_bind: args.resp_bind.or(file.resp_bind),
+            memcached_bind: args.memcached_bind.or(file.memcached_bind),
+            grpc_bind: args.grpc_bind.or(file.grpc_bind),
+            binary_bind: args.binary_bind.or(file.binary_bind),
+            mqtt: MqttSettings {
+                broker: args.mqtt_broker.or(file.mqtt_broker),
+                topic: args
+                    .mqtt_topic
+                    .or(file.mqtt_topic)
+                    .unwrap_or_else(|| DEFAULT_MQTT_TOPIC.to_string()),
+                prefix: args.mqtt_prefix.or(file.mqtt_prefix).unwrap_or_default(),
+                values: args.mqtt_values.or(file.mqtt_values).unwrap_or(false),
+                client_id: args
+                    .mqtt_client_id
+                    .or(file.mqtt_client_id)
+                    .unwrap_or_else(|| DEFAULT_MQTT_CLIENT_ID.to_string()),
+                username: args.mqtt_username.or(file.mqtt_username),
+                password: args.mqtt_password.or(file.mqtt_password),
+            },
+            kafka: KafkaSettings {
+                brokers: args.kafka_brokers.or(file.kafka_brokers),
+                topic: args
+                    .kafka_topic
+                    .or(file.kafka_topic)
+                    .unwrap_or_else(|| DEFAULT_KAFKA_TOPIC.to_string()),
+                prefix: args.kafka_prefix.or(file.kafka_prefix).unwrap_or_default(),
+                checkpoint_key: args
+                    .kafka_checkpoint_key
+                    .or(file.kafka_checkpoint_key)
+                    .unwrap_or_else(|| DEFAULT_KAFKA_CHECKPOINT_KEY.to_string()),
+            },
+            replica: ReplicaSettings {
+                primary: args.replica_of.or(file.replica_of),
+                token: args.replica_token.or(file.replica_token),
+                max_lag: args.replica_max_lag.or(file.replica_max_lag),
+            },
+            router: RouterSettings {
+                backends: args.route_to.or(file.route_to).unwrap_or_default(),
+                vnodes: args
+                    .router_vnodes
+                    .or(file.router_vnodes)
+                    .unwrap_or(DEFAULT_ROUTER_VNODES),
+            },
+            cluster: ClusterSettings {
+                nodes: args
+                    .cluster_nodes
+                    .or(file.cluster_nodes)
+                    .unwrap_or_default(),
+                id: args.cluster_id.or(file.cluster_id),
+                token: args
+                    .cluster_token
+                    .or(file.cluster_token)
+                    .or_else(|| admin_token.clone()),
+                log_path: args.cluster_log.or(file.cluster_log),
+            },
+            data_path: args
+                .data_path
+                .or(file.data_path)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_PATH)),
+            bucket_dir: args.bucket_dir.or(file.bucket_dir),
+            audit_log: args.audit_log.or(file.audit_log),
+            compact_threshold: args
+                .compact_threshold
+                .or(file.compact_threshold)
+                .unwrap_or(DEFAULT_COMPACT_THRESHOLD),
+            sync,
+            archive_dir: args.archive_dir.or(file.archive_dir),
+            archive_retention: args
+                .archive_retention_hours
+                .or(file.archive_retention_hours)
+                .and_then(|hours| nonzero_secs(hours.saturating_mul(60 * 60))),
+            max_body_size: args
+                .max_body_size
+                .or(file.max_body_size)
+                .unwrap_or_else(|| (max_value_size.div_ceil(3) * 4 + BODY_OVERHEAD) as usize),
+            max_value_size,
+            min_free_space: args.min_free_space.or(file.min_free_space),
+            max_index_memory: args.max_index_memory.or(file.max_index_memory),
+            slow_op_threshold: args
+                .slow_op_ms
+                .or(file.slow_op_ms)
+                .map(Duration::from_millis),
+            shutdown_timeout: args
+                .shutdown_timeout
+                .or(file.shutdown_timeout)
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            idempotency_window: args
+                .idempotency_window
+                .or(file.idempotency_window)
+                .map_or(DEFAULT_IDEMPOTENCY_WINDOW, Duration::from_secs),
+            trash_retention: args
+                .trash_retention
+                .or(file.trash_retention)
+                .map(Duration::from_secs),
+            timeouts: Timeouts {
+                request: nonzero_secs(
+                    args.request_timeout
+                        .or(file.request_timeout)
+                        .unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+                ),
+                bulk: nonzero_secs(
+                    args.bulk_timeout
+                        .or(file.bulk_timeout)
+                        .unwrap_or(DEFAULT_BULK_TIMEOUT),
+                ),
+                slow: (slow_request_ms > 0).then_some(Duration::from_millis(slow_request_ms)),
+            },
+            admin_token,
+            api_keys: args.api_keys.or(file.api_keys).unwrap_or_default(),
+            compression: args.compression.or(file.compression).unwrap_or(false),
+            compression_min_size: args
+                .compression_min_size
+                .or(file.compression_min_size)
+                .unwrap_or(DEFAULT_COMPRESSION_MIN_SIZE),
+            cors: CorsSettings {
+                origins: args.cors_origins.or(file.cors_origins).unwrap_or_default(),
+                methods: args
+                    .cors_methods
+                    .or(file.cors_methods)
+                    .unwrap_or_else(|| strings(DEFAULT_CORS_METHODS)),
+                headers: args
+                    .cors_headers
+                    .or(file.cors_headers)
+                    .unwrap_or_else(|| strings(DEFAULT_CORS_HEADERS)),
+                max_age: args
+                    .cors_max_age
+                    .or(file.cors_max_age)
+                    .unwrap_or(DEFAULT_CORS_MAX_AGE),
+            },
+            jwt: JwtSettings {
+                secret: args.jwt_secret.or(file.jwt_secret),
+                jwks_url: args.jwt_jwks_url.or(file.jwt_jwks_url),
+                jwks_refresh: Duration::from_secs(
+                    args.jwt_jwks_refresh
+                        .or(file.jwt_jwks_refresh)
+                        .unwrap_or(DEFAULT_JWT_JWKS_REFRESH),
+                ),
+                audience: args.jwt_audience.or(file.jwt_audience),
+                issuer: args.jwt_issuer.or(file.jwt_issuer),
+                scope_claim: args
+                    .jwt_scope_claim
+                    .or(file.jwt_scope_claim)
+                    .unwrap_or_else(|| DEFAULT_JWT_SCOPE_CLAIM.to_string()),
+            },
+            acl: match &args.acl {
+                Some(rules) => parse_acl(rules)?,
+                None => file.acl,
+            },
+            tls: TlsSettings {
+                cert: args.tls_cert.or(file.tls_cert),
+                key: args.tls_key.or(file.tls_key),
+                client_ca: args.tls_client_ca.or(file.tls_client_ca),
+            },
+            s3: S3Settings {
+                endpoint: args
+                    .s3_endpoint
+                    .or(file.s3_endpoint)
+                    .unwrap_or_else(|| DEFAULT_S3_ENDPOINT.to_string()),
+                region: args
+                    .s3_region
+                    .or(file.s3_region)
+                    .unwrap_or_else(|| DEFAULT_S3_REGION.to_string()),
+                bucket: args.s3_bucket.or(file.s3_bucket),
+                access_key: args.s3_access_key.or(file.s3_access_key),
+                secret_key: args.s3_secret_key.or(file.s3_secret_key),
+                prefix: args
+                    .s3_prefix
+                    .or(file.s3_prefix)
+                    .unwrap_or_else(|| DEFAULT_S3_PREFIX.to_string()),
+                retain: args
+                    .s3_retain
+                    .or(file.s3_retain)
+                    .unwrap_or(DEFAULT_S3_RETAIN),
+            },
+            rate_limit,
+            rate_limit_burst: args
+                .rate_limit_burst
+                .or(file.rate_limit_burst)
+                .unwrap_or(rate_limit),
+            access_log: args.access_log.or(file.access_log).unwrap_or(true),
+            log_format,
+            docs: args.docs.or(file.docs).unwrap_or(false),
+            graphql: args.graphql.or(file.graphql).unwrap_or(false),
+        })
+    }
+
+    /// One `key = value` line per setting, named as in the config file, for logging at startup.
+    /// Unset settings are commented out, and secrets are only reported as set.
+    pub fn effective(&self) -> Vec<String> {
+        fn secs(duration: Option<Duration>) -> u64 {
+            duration.map_or(0, |duration| duration.as_secs())
+        }
+        fn millis(duration: Option<Duration>) -> u64 {
+            duration.map_or(0, |duration| duration.as_millis() as u64)
+        }
+
+        let mut lines = Settings(Vec::new());
+        lines.set("bind", self.bind.as_deref().unwrap_or("none"));
+        lines.opt("unix_socket", &self.unix_socket);
+        lines.opt("resp_bind", &self.resp_bind);
+        lines.opt("memcached_bind", &self.memcached_bind);
+        lines.opt("grpc_bind", &self.grpc_bind);
+        lines.opt("binary_bind", &self.binary_bind);
+        lines.opt("mqtt_broker", &self.mqtt.broker);
+        lines.set("mqtt_topic", &self.mqtt.topic);
+        lines.set("mqtt_prefix", &self.mqtt.prefix);
+        lines.set("mqtt_values", self.mqtt.values);
+        lines.set("mqtt_client_id", &self.mqtt.client_id);
+        lines.opt("mqtt_username", &self.mqtt.username);
+        lines.secret("mqtt_password", self.mqtt.password.is_some());
+        lines.opt("kafka_brokers", &self.kafka.brokers);
+        lines.set("kafka_topic", &self.kafka.topic);
+        lines.set("kafka_prefix", &self.kafka.prefix);
+        lines.set("kafka_checkpoint_key", &self.kafka.checkpoint_key);
+        lines.opt("replica_of", &self.replica.primary);
+        lines.secret("replica_token", self.replica.token.is_some());
+        lines.opt("replica_max_lag", &self.replica.max_lag);
+        lines.set("route_to", &self.router.backends);
+        lines.set("router_vnodes", self.router.vnodes);
+        lines.set("cluster_nodes", &self.cluster.nodes);
+        lines.opt("cluster_id", &self.cluster.id);
+        lines.secret("cluster_token", self.cluster.token.is_some());
+        lines.opt("cluster_log", &self.cluster.log_path);
+        lines.set("data_path", &self.data_path);
+        lines.opt("bucket_dir", &self.bucket_dir);
+        lines.opt("audit_log", &self.audit_log);
+        lines.set("compact_threshold", self.compact_threshold);
+        lines.set(
+            "sync",
+            match self.sync {
+                SyncPolicy::Never => "never",
+                SyncPolicy::Always => "always",
+            },
+        );
+        lines.opt("archive_dir", &self.archive_dir);
+        lines.set(
+            "archive_retention_hours",
+            secs(self.archive_retention) / 3600,
+        );
+        lines.set("max_body_size", self.max_body_size);
+        lines.set("max_value_size", self.max_value_size);
+        lines.opt("min_free_space", &self.min_free_space);
+        lines.opt("max_index_memory", &self.max_index_memory);
+        lines.opt(
+            "slow_op_ms",
+            &self
+                .slow_op_threshold
+                .map(|threshold| threshold.as_millis() as u64),
+        );
+        lines.set("shutdown_timeout", self.shutdown_timeout);
+        lines.set("idempotency_window", self.idempotency_window.as_secs());
+        lines.opt(
+            "trash_retention",
+            &self.trash_retention.map(|retention| retention.as_secs()),
+        );
+        lines.set("request_timeout", secs(self.timeouts.request));
+        lines.set("bulk_timeout", secs(self.timeouts.bulk));
+        lines.set("slow_request_ms", millis(self.timeouts.slow));
+        lines.secret("admin_token", self.admin_token.is_some());
+        lines.secret("api_keys", !self.api_keys.is_empty());
+        lines.secret("acl", !self.acl.is_empty());
+        lines.set("compression", self.compression);
+        lines.set("compression_min_size", self.compression_min_size);
+        lines.set("cors_origins", &self.cors.origins);
+        lines.set("cors_methods", &self.cors.methods);
+        lines.set("cors_headers", &self.cors.headers);
+        lines.set("cors_max_age", self.cors.max_age);
+        lines.secret("jwt_secret", self.jwt.secret.is_some());
+        lines.opt("jwt_jwks_url", &self.jwt.jwks_url);
+        lines.set("jwt_jwks_refresh", self.jwt.jwks_refresh.as_secs());
+        lines.opt("jwt_audience", &self.jwt.audience);
+        lines.opt("jwt_issuer", &self.jwt.issuer);
+        lines.set("jwt_scope_claim", &self.jwt.scope_claim);
+        lines.opt("tls_cert", &self.tls.cert);
+        lines.opt("tls_key", &self.tls.key);
+        lines.opt("tls_client_ca", &self.tls.client_ca);
+        lines.opt("s3_bucket", &self.s3.bucket);
+        lines.set("s3_endpoint", &self.s3.endpoint);
+        lines.set("s3_region", &self.s3.region);
+        lines.opt("s3_access_key", &self.s3.access_key);
+        lines.secret("s3_secret_key", self.s3.secret_key.is_some());
+        lines.set("s3_prefix", &self.s3.prefix);
+        lines.set("s3_retain", self.s3.retain);
+        lines.set("rate_limit", self.rate_limit);
+        lines.set("rate_limit_burst", self.rate_limit_burst);
+        lines.set("access_log", self.access_log);
+        lines.set(
+            "log_format",
+            match self.log_format {
+                LogFormat::Text => "text",
+                LogFormat::Json => "json",
+            },
+        );
+        lines.set("docs", self.docs);
+        lines.set("graphql", self.graphql);
+        lines.0
+    }
+}
+
+/// Lines of [`Config::effective`]. Values are written with `Debug`, which quotes strings and
+/// paths and brackets lists as TOML does.
+struct Settings(Vec<String>);
+
+impl Settings {
+    fn set(&mut self, key: &str, value: impl Debug) {
+        self.0.push(format!("{key} = {value:?}"));
+    }
+
+    fn opt(&mut self, key: &str, value: &Option<impl Debug>) {
+        match value {
+            Some(value) => self.set(key, value),
+            None => self.0.push(format!("# {key} is unset")),
+        }
+    }
+
+    fn secret(&mut self, key: &str, set: bool) {
+        let state = if set { "set" } else { "unset" };
+        self.0.push(format!("# {key} is {state}"));
+    }
+}
+
+/// `None` for 0, which turns a timeout off.
+fn nonzero_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then_some(Duration::from_secs(secs))
+}
+
+fn parse_sync(s: &str) -> Result<SyncPolicy, String> {
+    match s {
+        "never" => Ok(SyncPolicy::Never),
+        "always" => Ok(SyncPolicy::Always),
+        other => Err(format!(
+            "unknown sync policy `{}`, expected `never` or `always`",
+            other
+        )),
+    }
+}
+
+fn parse_log_format(s: &str) -> Result<LogFormat, String> {
+    match s {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        other => Err(format!(
+            "unknown log format `{}`, expected `text` or `json`",
+            other
+        )),
+    }
+}
+
+/// Parses `--acl`: the config file's `[[acl]]` tables written as one TOML array.
+fn parse_acl(rules: &str) -> io::Result<Vec<AclRule>> {
+    #[derive(Deserialize)]
+    struct Acl {
+        acl: Vec<AclRule>,
+    }
+
+    toml::from_str::<Acl>(&format!("acl = {rules}"))
+        .map(|acl| acl.acl)
+        .map_err(|e| invalid(format!("acl: {}", e)))
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,43 @@
+use actix_cors::Cors;
+use actix_web::http::header;
+
+/// Cross-origin settings. CORS is off while `origins` is empty.
+#[derive(Debug, Clone)]
+pub struct CorsSettings {
+    /// Origins allowed to call the API, or `*` for any.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    /// Request headers browsers may send, or `*` for any.
+    pub headers: Vec<String>,
+    /// Seconds browsers may cache a preflight response.
+    pub max_age: usize,
+}
+
+impl CorsSettings {
+    pub fn enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    /// The middleware for these settings. `ETag` is exposed so browser code can send it back in
+    /// `If-Match`.
+    pub fn middleware(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.iter().map(String::as_str))
+            .expose_headers([header::ETAG])
+            .max_age(self.max_age);
+
+        if self.origins.iter().any(|origin| origin == "*") {
+            cors = cors.allow_any_origin();
+        } else {
+            for origin in &self.origins {
+                cors = cors.allowed_origin(origin);
+            }
+        }
+
+        if self.headers.iter().any(|name| name == "*") {
+            cors.allow_any_header()
+        } else {
+            cors.allowed_headers(self.headers.iter().map(String::as_str))
+        }
+    }
+}
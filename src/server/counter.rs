@@ -0,0 +1,70 @@
+use std::io;
+
+use actix_web::{HttpResponse, web};
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::Encoding;
+use super::limits;
+use super::state::Db;
+
+#[derive(Deserialize)]
+pub struct CounterQuery {
+    by: Option<i64>,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Serialize)]
+struct CounterResponse {
+    value: i64,
+}
+
+/// Adds `by` (default 1) to the counter at `key`, creating it at 0, and returns the new value.
+pub async fn incr(
+    key: web::Path<String>,
+    query: web::Query<CounterQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    add(key, query.by.unwrap_or(1), query.encoding, engine, scope).await
+}
+
+/// Subtracts `by` (default 1) from the counter at `key`, creating it at 0.
+pub async fn decr(
+    key: web::Path<String>,
+    query: web::Query<CounterQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    match query.by.unwrap_or(1).checked_neg() {
+        Some(by) => add(key, by, query.encoding, engine, scope).await,
+        None => HttpResponse::BadRequest().body("by is out of range"),
+    }
+}
+
+async fn add(
+    key: web::Path<String>,
+    by: i64,
+    encoding: Encoding,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+
+    match web::block(move || engine.incr(&key, by)).await {
+        Ok(Ok(value)) => HttpResponse::Ok().json(CounterResponse { value }),
+        // Not an integer, or the counter would overflow.
+        Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+            HttpResponse::Conflict().body(e.to_string())
+        }
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
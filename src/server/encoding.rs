@@ -0,0 +1,47 @@
+use base64::Engine as _;
+use base64::engine::DecodePaddingMode;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::{DecodeError, alphabet};
+use serde::Deserialize;
+
+const ANY_PADDING: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const STANDARD_ANY_PADDING: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, ANY_PADDING);
+const URL_SAFE_ANY_PADDING: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, ANY_PADDING);
+
+/// How keys and values are represented as JSON strings (or path segments).
+#[derive(Deserialize, async_graphql::Enum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Plain text; values that are not valid UTF-8 are returned lossily.
+    #[default]
+    Utf8,
+    /// Base64, so arbitrary bytes survive. Decoding accepts the standard and URL-safe alphabets
+    /// with or without padding; encoding uses the standard alphabet with padding.
+    Base64,
+}
+
+/// The `?encoding=` query parameter.
+#[derive(Deserialize)]
+pub struct EncodingQuery {
+    #[serde(default)]
+    pub encoding: Encoding,
+}
+
+impl Encoding {
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, DecodeError> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Base64 => STANDARD_ANY_PADDING
+                .decode(text)
+                .or_else(|_| URL_SAFE_ANY_PADDING.decode(text)),
+        }
+    }
+
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use actix_web::{HttpMessage, HttpRequest, web};
+use async_graphql::{
+    Context, EmptySubscription, Error, InputObject, Object, Result, Schema, SimpleObject,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::BatchOp;
+
+use super::audit::{AuditLog, Recorder};
+use super::auth::{self, Access, Grant};
+use super::encoding::Encoding;
+use super::state::Db;
+
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const MAX_SCAN_LIMIT: usize = 1000;
+
+pub type KvSchema = Schema<Query, Mutation, EmptySubscription>;
+
+pub fn schema() -> KvSchema {
+    Schema::build(Query, Mutation, EmptySubscription).finish()
+}
+
+/// Runs a GraphQL request against the engine `Db` picks. Read credentials pass `authenticate`
+/// here, so mutations and keys are checked against the `Grant` field by field.
+pub async fn graphql(
+    schema: web::Data<KvSchema>,
+    req: HttpRequest,
+    engine: Db,
+    audit: Option<web::Data<AuditLog>>,
+    body: GraphQLRequest,
+) -> GraphQLResponse {
+    let grant = req.extensions().get::<Grant>().cloned();
+    let mut request = body.into_inner().data(engine).data(grant);
+    if let Some(audit) = audit {
+        request = request.data(Recorder::new(audit, &req));
+    }
+    schema.execute(request).await.into()
+}
+
+#[derive(SimpleObject)]
+struct Pair {
+    key: String,
+    value: String,
+}
+
+#[derive(InputObject)]
+struct PairInput {
+    key: String,
+    value: String,
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The value of `key`, or null if it does not exist.
+    async fn get(
+        &self,
+        ctx: &Context<'_>,
+        key: String,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<Option<String>> {
+        let key = decode(encoding, &key)?;
+        check(ctx, Access::Read, &[key.as_slice()])?;
+        let value = blocking(ctx, move |engine| engine.get(&key)).await?;
+        Ok(value.map(|value| encoding.encode(&value)))
+    }
+
+    /// The values of `keys` in order, null where a key does not exist.
+    async fn batch_get(
+        &self,
+        ctx: &Context<'_>,
+        keys: Vec<String>,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<Vec<Option<String>>> {
+        let keys = keys
+            .iter()
+            .map(|key| decode(encoding, key))
+            .collect::<Result<Vec<_>>>()?;
+        check(
+            ctx,
+            Access::Read,
+            &keys.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+        )?;
+        let values = blocking(ctx, move |engine| {
+            engine.get_many(&keys.iter().map(Vec::as_slice).collect::<Vec<_>>())
+        })
+        .await?;
+        Ok(values
+            .into_iter()
+            .map(|value| value.map(|value| encoding.encode(&value)))
+            .collect())
+    }
+
+    /// Keys starting with `prefix` and their values, in key order; `limit` is capped at 1000.
+    async fn scan(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] prefix: String,
+        limit: Option<usize>,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<Vec<Pair>> {
+        let prefix = decode(encoding, &prefix)?;
+        check(ctx, Access::Read, &[prefix.as_slice()])?;
+        let limit = limit.unwrap_or(DEFAULT_SCAN_LIMIT).clamp(1, MAX_SCAN_LIMIT);
+        let pairs = blocking(ctx, move |engine| engine.scan(&prefix, limit)).await?;
+        Ok(pairs
+            .into_iter()
+            .map(|(key, value)| Pair {
+                key: encoding.encode(&key),
+                value: encoding.encode(&value),
+            })
+            .collect())
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Stores `value` under `key`, expiring after `ttlSecs` if given.
+    async fn set(
+        &self,
+        ctx: &Context<'_>,
+        key: String,
+        value: String,
+        ttl_secs: Option<u64>,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<bool> {
+        let writes = vec![(key.clone(), Some(value.clone()))];
+        let (key, value) = (decode(encoding, &key)?, decode(encoding, &value)?);
+        check(ctx, Access::Write, &[key.as_slice()])?;
+        blocking(ctx, move |engine| match ttl_secs {
+            Some(secs) => engine.set_with_ttl(&key, &value, Duration::from_secs(secs)),
+            None => engine.set(&key, &value),
+        })
+        .await?;
+        record(ctx, writes).await;
+        Ok(true)
+    }
+
+    /// Deletes `key`, returning whether it existed.
+    async fn del(
+        &self,
+        ctx: &Context<'_>,
+        key: String,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<bool> {
+        let writes = vec![(key.clone(), None)];
+        let key = decode(encoding, &key)?;
+        check(ctx, Access::Write, &[key.as_slice()])?;
+        let ops = [BatchOp::Del { key }];
+        let existed = blocking(ctx, move |engine| engine.write_batch(&ops)).await?;
+        record(ctx, writes).await;
+        Ok(existed[0])
+    }
+
+    /// Writes every pair with one engine batch, returning whether each key existed.
+    async fn batch_set(
+        &self,
+        ctx: &Context<'_>,
+        pairs: Vec<PairInput>,
+        #[graphql(default)] encoding: Encoding,
+    ) -> Result<Vec<bool>> {
+        let ops = pairs
+            .iter()
+            .map(|pair| {
+                Ok(BatchOp::Set {
+                    key: decode(encoding, &pair.key)?,
+                    value: decode(encoding, &pair.value)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let keys: Vec<&[u8]> = ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Set { key, .. } | BatchOp::Del { key } => key.as_slice(),
+            })
+            .collect();
+        check(ctx, Access::Write, &keys)?;
+        let existed = blocking(ctx, move |engine| engine.write_batch(&ops)).await?;
+        let writes = pairs
+            .into_iter()
+            .map(|pair| (pair.key, Some(pair.value)))
+            .collect();
+        record(ctx, writes).await;
+        Ok(existed)
+    }
+}
+
+/// Refuses keys outside the request's credentials, and writes with read-only ones. Without
+/// credentials required there is no grant and everything is allowed but writing reserved keys.
+fn check(ctx: &Context<'_>, access: Access, keys: &[&[u8]]) -> Result<()> {
+    if access == Access::Write && !auth::writable(keys) {
+        return Err(Error::new(auth::RESERVED_KEY));
+    }
+    match ctx.data_unchecked::<Option<Grant>>() {
+        Some(grant) if !grant.allows(access, keys) => {
+            Err(Error::new("credentials do not allow this request"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Records successful writes when the audit log is on. `audit::record` lets `/graphql` through as a
+/// read, so mutations are recorded here, one entry per key.
+async fn record(ctx: &Context<'_>, writes: Vec<(String, Option<String>)>) {
+    if let Some(recorder) = ctx.data_opt::<Recorder>() {
+        recorder.writes(writes).await;
+    }
+}
+
+/// Runs engine work on a blocking thread.
+async fn blocking<T: Send + 'static>(
+    ctx: &Context<'_>,
+    work: impl FnOnce(&Engine) -> std::io::Result<T> + Send + 'static,
+) -> Result<T> {
+    let engine = ctx.data_unchecked::<Db>().clone();
+    match web::block(move || work(&engine)).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(Error::new(e.to_string())),
+        Err(e) => Err(Error::new(e.to_string())),
+    }
+}
+
+fn decode(encoding: Encoding, text: &str) -> Result<Vec<u8>> {
+    encoding.decode(text).map_err(|e| Error::new(e.to_string()))
+}
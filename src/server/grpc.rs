@@ -0,0 +1,242 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web;
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::{Change, ChangeKind};
+use futures_util::{Stream, StreamExt, stream};
+use tonic::metadata::MetadataMap;
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use tracing::error;
+
+use super::auth::{self, Access};
+use super::state::AppState;
+use super::watch;
+
+/// Types generated from `proto/kv.proto`.
+pub mod proto {
+    tonic::include_proto!("kv.v1");
+}
+
+use proto::kv_server::{Kv, KvServer};
+
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const MAX_SCAN_LIMIT: usize = 1000;
+
+/// Binds the gRPC listener, so a taken port fails startup like the HTTP one does.
+pub fn bind(addr: &str) -> io::Result<TcpIncoming> {
+    let addr: SocketAddr = addr.parse().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("grpc_bind {}: {}", addr, e),
+        )
+    })?;
+    TcpIncoming::new(addr, true, None).map_err(io::Error::other)
+}
+
+/// Serves the `kv.v1.Kv` service until the server stops. Messages are limited to
+/// `max_message_size` bytes, like buffered HTTP bodies.
+pub async fn serve(incoming: TcpIncoming, state: web::Data<AppState>, max_message_size: usize) {
+    let service = KvServer::new(KvService { state })
+        .max_decoding_message_size(max_message_size)
+        .max_encoding_message_size(max_message_size);
+    if let Err(e) = Server::builder()
+        .add_service(service)
+        .serve_with_incoming(incoming)
+        .await
+    {
+        error!("gRPC server stopped: {}", e);
+    }
+}
+
+struct KvService {
+    state: web::Data<AppState>,
+}
+
+impl KvService {
+    /// The main engine, once the request's credentials allow `access` to `keys` and no key to be
+    /// written is reserved. Credentials are sent as `authorization: Bearer <credential>` or
+    /// `x-api-key` metadata.
+    fn engine<T>(
+        &self,
+        req: &Request<T>,
+        access: Access,
+        keys: &[&[u8]],
+    ) -> Result<Arc<Engine>, Status> {
+        let auth = self.state.auth();
+        if auth.required() {
+            let grant = credential(req.metadata())
+                .and_then(|credential| auth.grant(credential))
+                .ok_or_else(|| Status::unauthenticated("missing or invalid credentials"))?;
+            if !grant.allows(access, keys) {
+                return Err(Status::permission_denied(
+                    "credentials do not allow this request",
+                ));
+            }
+        }
+        if access == Access::Write && !auth::writable(keys) {
+            return Err(Status::permission_denied(auth::RESERVED_KEY));
+        }
+        if self.state.in_maintenance() {
+            return Err(Status::unavailable("server is in maintenance mode"));
+        }
+        self.state
+            .engine()
+            .cloned()
+            .ok_or_else(|| Status::unavailable("engine is not ready"))
+    }
+}
+
+/// Like `auth::credential`, from gRPC metadata.
+fn credential(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            metadata
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+        })
+}
+
+/// Runs engine work on the blocking pool.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Result<T, Status> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::FileTooLarge => {
+            Err(Status::resource_exhausted(e.to_string()))
+        }
+        Ok(Err(e)) => Err(Status::internal(e.to_string())),
+        Err(e) => Err(Status::internal(e.to_string())),
+    }
+}
+
+impl From<Change> for proto::Change {
+    fn from(change: Change) -> Self {
+        let op = match change.kind {
+            ChangeKind::Set => proto::Op::Set,
+            ChangeKind::Del => proto::Op::Del,
+            ChangeKind::Merge => proto::Op::Merge,
+        };
+        proto::Change {
+            seq: change.seq,
+            op: op.into(),
+            key: change.key,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Kv for KvService {
+    async fn get(
+        &self,
+        req: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let engine = self.engine(&req, Access::Read, &[req.get_ref().key.as_slice()])?;
+        let key = req.into_inner().key;
+        match blocking(move || engine.get_versioned(&key)).await? {
+            Some(versioned) => Ok(Response::new(proto::GetResponse {
+                value: versioned.value,
+                seq: versioned.seq,
+            })),
+            None => Err(Status::not_found("key is not found")),
+        }
+    }
+
+    async fn set(
+        &self,
+        req: Request<proto::SetRequest>,
+    ) -> Result<Response<proto::SetResponse>, Status> {
+        let engine = self.engine(&req, Access::Write, &[req.get_ref().key.as_slice()])?;
+        let proto::SetRequest { key, value, ttl_ms } = req.into_inner();
+        blocking(move || match ttl_ms {
+            0 => engine.set(&key, &value),
+            ms => engine.set_with_ttl(&key, &value, Duration::from_millis(ms)),
+        })
+        .await?;
+        Ok(Response::new(proto::SetResponse {}))
+    }
+
+    async fn del(
+        &self,
+        req: Request<proto::DelRequest>,
+    ) -> Result<Response<proto::DelResponse>, Status> {
+        let engine = self.engine(&req, Access::Write, &[req.get_ref().key.as_slice()])?;
+        let key = req.into_inner().key;
+        blocking(move || engine.del(&key)).await?;
+        Ok(Response::new(proto::DelResponse {}))
+    }
+
+    async fn batch_get(
+        &self,
+        req: Request<proto::BatchGetRequest>,
+    ) -> Result<Response<proto::BatchGetResponse>, Status> {
+        let keys: Vec<&[u8]> = req.get_ref().keys.iter().map(Vec::as_slice).collect();
+        let engine = self.engine(&req, Access::Read, &keys)?;
+        let keys = req.into_inner().keys;
+        let values =
+            blocking(move || engine.get_many(&keys.iter().map(Vec::as_slice).collect::<Vec<_>>()))
+                .await?;
+        Ok(Response::new(proto::BatchGetResponse {
+            values: values
+                .into_iter()
+                .map(|value| proto::Value {
+                    found: value.is_some(),
+                    value: value.unwrap_or_default(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn scan(
+        &self,
+        req: Request<proto::ScanRequest>,
+    ) -> Result<Response<proto::ScanResponse>, Status> {
+        let engine = self.engine(&req, Access::Read, &[req.get_ref().prefix.as_slice()])?;
+        let proto::ScanRequest { prefix, limit } = req.into_inner();
+        let limit = match limit {
+            0 => DEFAULT_SCAN_LIMIT,
+            limit => (limit as usize).min(MAX_SCAN_LIMIT),
+        };
+        let pairs = blocking(move || engine.scan(&prefix, limit)).await?;
+        Ok(Response::new(proto::ScanResponse {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| proto::Pair { key, value })
+                .collect(),
+        }))
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<proto::Change, Status>> + Send>>;
+
+    /// Like `/changes`: replayed history first, then live changes. The stream ends if the
+    /// client falls too far behind, as `/watch` connections are closed.
+    async fn watch(
+        &self,
+        req: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let engine = self.engine(&req, Access::Read, &[req.get_ref().prefix.as_slice()])?;
+        let proto::WatchRequest { prefix, since_seq } = req.into_inner();
+        let (history, subscription) = blocking(move || match since_seq {
+            0 => Ok((Vec::new(), engine.watch(&prefix))),
+            after => engine.watch_since(&prefix, after),
+        })
+        .await?;
+
+        let live = stream::unfold(watch::forward(subscription), |mut changes| async move {
+            changes.recv().await.map(|change| (change, changes))
+        });
+        let changes = stream::iter(history)
+            .chain(live)
+            .map(|change| Ok(change.into()));
+        Ok(Response::new(Box::pin(changes)))
+    }
+}
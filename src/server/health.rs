@@ -0,0 +1,30 @@
+use actix_web::{HttpResponse, Responder, web};
+
+use super::replica::ReplicaStatus;
+use super::state::AppState;
+
+/// Liveness: the process is up and serving HTTP.
+pub async fn health() -> impl Responder {
+    HttpResponse::Ok().body("OK")
+}
+
+/// Readiness: the index has been rebuilt, the engine accepts requests, the server is not in
+/// maintenance mode and, on a replica, it is no further behind its primary than `max_lag` allows.
+pub async fn ready(
+    state: web::Data<AppState>,
+    replica: Option<web::Data<ReplicaStatus>>,
+) -> impl Responder {
+    let lagging = state
+        .engine()
+        .zip(replica)
+        .is_some_and(|(engine, replica)| replica.lagging(&engine.replication_stats()));
+    if !state.is_ready() {
+        HttpResponse::ServiceUnavailable().body("loading")
+    } else if state.in_maintenance() {
+        HttpResponse::ServiceUnavailable().body("maintenance")
+    } else if lagging {
+        HttpResponse::ServiceUnavailable().body("lagging")
+    } else {
+        HttpResponse::Ok().body("ready")
+    }
+}
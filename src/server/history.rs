@@ -0,0 +1,66 @@
+use actix_web::{HttpResponse, web};
+use breakout1_kv_store::types::HistoryEntry;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::Encoding;
+use super::kv::KeyPath;
+use super::state::Db;
+use super::watch;
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<usize>,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// JSON shape of a `HistoryEntry`.
+#[derive(Serialize)]
+struct Version {
+    seq: u64,
+    tstamp: i64,
+    op: &'static str,
+    value: Option<String>,
+}
+
+impl Version {
+    fn new(entry: HistoryEntry, encoding: Encoding) -> Self {
+        Self {
+            seq: entry.seq,
+            tstamp: entry.tstamp,
+            op: watch::op(entry.kind),
+            value: entry.value.map(|value| encoding.encode(&value)),
+        }
+    }
+}
+
+/// Lists the writes to `key`, newest first: sets with their value, merges with their operand and
+/// deletes with a `null` value. Compaction keeps only the live version, so older writes are gone
+/// after it runs.
+pub async fn history(
+    key: web::Path<KeyPath>,
+    query: web::Query<HistoryQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = key.into_inner().key;
+    if let Err(response) = scope.read(key.as_bytes()) {
+        return response;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let encoding = query.encoding;
+    match web::block(move || engine.history(key.as_bytes(), limit)).await {
+        Ok(Ok(entries)) => HttpResponse::Ok().json(
+            entries
+                .into_iter()
+                .map(|entry| Version::new(entry, encoding))
+                .collect::<Vec<_>>(),
+        ),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,34 @@
+use std::io;
+
+use actix_web::{HttpRequest, HttpResponse};
+
+use super::limits;
+
+/// Longest `Idempotency-Key` accepted, in bytes.
+const MAX_KEY_LEN: usize = 255;
+
+/// Header on the answer to a retry that was not applied again.
+pub const REPLAYED: (&str, &str) = ("idempotent-replayed", "true");
+
+/// The `Idempotency-Key` header of a write, if it sent one: the request id that makes retries of
+/// the write no-ops. Answers `400` for an empty, overlong or non-ASCII key.
+pub fn request_id(req: &HttpRequest) -> Result<Option<String>, HttpResponse> {
+    let Some(value) = req.headers().get("idempotency-key") else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(id) if !id.is_empty() && id.len() <= MAX_KEY_LEN => Ok(Some(id.to_string())),
+        _ => Err(HttpResponse::BadRequest().body(format!(
+            "Idempotency-Key must be 1 to {} ASCII characters",
+            MAX_KEY_LEN
+        ))),
+    }
+}
+
+/// `422` for a request id already sent for another key, otherwise as for any write.
+pub fn write_error(e: io::Error) -> HttpResponse {
+    match e.kind() {
+        io::ErrorKind::AlreadyExists => HttpResponse::UnprocessableEntity().body(e.to_string()),
+        _ => limits::write_error(e),
+    }
+}
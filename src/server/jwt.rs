@@ -0,0 +1,155 @@
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde_json::Value;
+use tracing::warn;
+
+use super::auth::Access;
+
+/// Scope values granting each access level, in the configured claim.
+const READ_SCOPE: &str = "kv:read";
+const WRITE_SCOPE: &str = "kv:write";
+const ADMIN_SCOPE: &str = "kv:admin";
+
+/// What a valid token grants.
+pub struct Token {
+    pub access: Access,
+    /// The `sub` claim, which `[[acl]]` rules match on.
+    pub subject: Option<String>,
+}
+
+/// Settings for accepting JWTs as credentials.
+#[derive(Debug, Clone)]
+pub struct JwtSettings {
+    /// Shared secret for HS256/HS384/HS512 tokens.
+    pub secret: Option<String>,
+    /// URL of a JWKS document with the public keys of asymmetric tokens.
+    pub jwks_url: Option<String>,
+    /// How often the JWKS document is fetched again, to pick up rotated keys.
+    pub jwks_refresh: Duration,
+    pub audience: Option<String>,
+    pub issuer: Option<String>,
+    /// Claim holding the token's scopes, space-separated or as an array.
+    pub scope_claim: String,
+}
+
+enum Keys {
+    Secret(DecodingKey),
+    Jwks(Arc<RwLock<JwkSet>>),
+}
+
+/// Checks JWTs and maps their scopes to an `Access` level.
+pub struct JwtValidator {
+    keys: Keys,
+    settings: JwtSettings,
+}
+
+impl JwtValidator {
+    /// Builds a validator if a secret or JWKS URL is configured. The JWKS document is fetched
+    /// before returning, then refreshed in the background.
+    pub fn new(settings: JwtSettings) -> io::Result<Option<Self>> {
+        let keys = match (&settings.secret, &settings.jwks_url) {
+            (Some(_), Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "set either jwt_secret or jwt_jwks_url, not both",
+                ));
+            }
+            (Some(secret), None) => Keys::Secret(DecodingKey::from_secret(secret.as_bytes())),
+            (None, Some(url)) => {
+                let jwks = Arc::new(RwLock::new(fetch_jwks(url)?));
+                spawn_refresh(url.clone(), settings.jwks_refresh, jwks.clone());
+                Keys::Jwks(jwks)
+            }
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(JwtValidator { keys, settings }))
+    }
+
+    /// What `token` grants, or `None` if it is invalid, expired or carries no known scope.
+    pub fn validate(&self, token: &str) -> Option<Token> {
+        let header = decode_header(token).ok()?;
+        // Shared secrets only verify HMAC tokens and JWKS keys only asymmetric ones, so a token
+        // cannot pick the other family.
+        let hmac = matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        );
+        if hmac != matches!(self.keys, Keys::Secret(_)) {
+            return None;
+        }
+
+        let (key, mut validation) = match &self.keys {
+            Keys::Secret(key) => {
+                let mut validation = Validation::new(Algorithm::HS256);
+                validation.algorithms = vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+                (key.clone(), validation)
+            }
+            Keys::Jwks(jwks) => {
+                let jwks = jwks.read().unwrap();
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid)?,
+                    None => jwks.keys.first()?,
+                };
+                (
+                    DecodingKey::from_jwk(jwk).ok()?,
+                    Validation::new(header.alg),
+                )
+            }
+        };
+        match &self.settings.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.settings.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let claims = decode::<Value>(token, &key, &validation).ok()?.claims;
+        let access = scopes(claims.get(&self.settings.scope_claim)?)
+            .filter_map(|scope| match scope {
+                READ_SCOPE => Some(Access::Read),
+                WRITE_SCOPE => Some(Access::Write),
+                ADMIN_SCOPE => Some(Access::Admin),
+                _ => None,
+            })
+            .max()?;
+        Some(Token {
+            access,
+            subject: claims.get("sub").and_then(Value::as_str).map(String::from),
+        })
+    }
+}
+
+/// Scopes from a space-separated string or an array of strings.
+fn scopes(claim: &Value) -> Box<dyn Iterator<Item = &str> + '_> {
+    match claim {
+        Value::String(scopes) => Box::new(scopes.split_whitespace()),
+        Value::Array(scopes) => Box::new(scopes.iter().filter_map(Value::as_str)),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn fetch_jwks(url: &str) -> io::Result<JwkSet> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| io::Error::other(format!("fetching {}: {}", url, e)))?
+        .into_json()
+}
+
+fn spawn_refresh(url: String, every: Duration, jwks: Arc<RwLock<JwkSet>>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(every);
+            match fetch_jwks(&url) {
+                Ok(fresh) => *jwks.write().unwrap() = fresh,
+                // Keep the keys we have; the next refresh may work.
+                Err(e) => warn!("failed to refresh JWKS: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,203 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web;
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::{Change, ChangeKind};
+use futures_util::StreamExt;
+use futures_util::stream::FuturesOrdered;
+use rdkafka::ClientConfig;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use tracing::{info, warn};
+
+use super::state::AppState;
+use super::watch;
+
+/// Records sent but not yet acknowledged before the sink waits for the broker.
+const MAX_IN_FLIGHT: usize = 1000;
+/// How often the checkpoint is written while records are being acknowledged.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+/// Pause before the sink resumes from its checkpoint after an error.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How often the sink checks whether the engine has finished loading.
+const LOAD_POLL: Duration = Duration::from_millis(100);
+
+/// Changefeed sink settings. The sink is off while `brokers` is unset.
+#[derive(Debug, Clone)]
+pub struct KafkaSettings {
+    /// Comma-separated `host:port` bootstrap servers.
+    pub brokers: Option<String>,
+    pub topic: String,
+    /// Only writes to keys under it are produced.
+    pub prefix: String,
+    /// Key holding the sequence number of the last write the broker acknowledged.
+    pub checkpoint_key: String,
+}
+
+impl KafkaSettings {
+    pub fn enabled(&self) -> bool {
+        self.brokers.is_some()
+    }
+
+    /// An idempotent producer, so retries neither duplicate nor reorder records.
+    fn producer(&self, max_message_size: usize) -> io::Result<FutureProducer> {
+        ClientConfig::new()
+            .set(
+                "bootstrap.servers",
+                self.brokers.as_deref().unwrap_or_default(),
+            )
+            .set("enable.idempotence", "true")
+            .set("message.max.bytes", max_message_size.to_string())
+            .create()
+            .map_err(io::Error::other)
+    }
+}
+
+/// Produces every write under the prefix to the topic, in sequence order, once the engine has
+/// loaded: the key as the record key, and the value read at the time or a tombstone (no
+/// payload) for deletes. The sequence number is in the `seq` header. Acknowledged writes are
+/// checkpointed in the engine itself, and the sink resumes after the checkpoint from the log,
+/// so every write reaches the topic at least once unless compaction drops it first.
+pub fn spawn(
+    settings: KafkaSettings,
+    state: web::Data<AppState>,
+    max_message_size: usize,
+) -> io::Result<()> {
+    let producer = settings.producer(max_message_size)?;
+    tokio::spawn(async move {
+        let engine = loop {
+            match state.engine() {
+                Some(engine) => break engine.clone(),
+                None => tokio::time::sleep(LOAD_POLL).await,
+            }
+        };
+        info!(
+            "producing changes to Kafka topic {} on {}",
+            settings.topic,
+            settings.brokers.as_deref().unwrap_or_default()
+        );
+        loop {
+            match sink(&settings, &producer, &engine).await {
+                Ok(()) => warn!("Kafka sink fell behind, resuming from its checkpoint"),
+                Err(e) => {
+                    warn!("Kafka sink failed, resuming from its checkpoint: {}", e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Replays the changes after the checkpoint, then follows new ones until the subscription is
+/// dropped for falling behind.
+async fn sink(
+    settings: &KafkaSettings,
+    producer: &FutureProducer,
+    engine: &Arc<Engine>,
+) -> io::Result<()> {
+    let checkpoint_key = settings.checkpoint_key.as_bytes().to_vec();
+    let prefix = settings.prefix.as_bytes().to_vec();
+    let checkpoint = {
+        let (engine, key) = (engine.clone(), checkpoint_key.clone());
+        blocking(move || engine.get(&key)).await?
+    };
+    let after = match checkpoint {
+        Some(value) => String::from_utf8_lossy(&value)
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad Kafka checkpoint"))?,
+        None => 0,
+    };
+    let (history, subscription) = {
+        let engine = engine.clone();
+        blocking(move || engine.watch_since(&prefix, after)).await?
+    };
+    let mut history = history.into_iter();
+    let mut changes = watch::forward(subscription);
+
+    let mut in_flight = FuturesOrdered::new();
+    let (mut acknowledged, mut saved) = (after, after);
+    let mut ticker = tokio::time::interval(CHECKPOINT_INTERVAL);
+    loop {
+        tokio::select! {
+            change = async {
+                match history.next() {
+                    Some(change) => Some(change),
+                    None => changes.recv().await,
+                }
+            }, if in_flight.len() < MAX_IN_FLIGHT => {
+                let Some(change) = change else { break };
+                // Writing the checkpoint is a change of its own.
+                if change.key == checkpoint_key {
+                    continue;
+                }
+                let seq = change.seq;
+                let delivery = produce(settings, producer, engine, change).await?;
+                in_flight.push_back(async move { delivery.await.map(|()| seq) });
+            }
+            Some(delivered) = in_flight.next() => acknowledged = delivered?,
+            _ = ticker.tick() => {
+                if acknowledged > saved {
+                    save_checkpoint(engine, &checkpoint_key, acknowledged).await?;
+                    saved = acknowledged;
+                }
+            }
+        }
+    }
+    while let Some(delivered) = in_flight.next().await {
+        acknowledged = delivered?;
+    }
+    save_checkpoint(engine, &checkpoint_key, acknowledged).await
+}
+
+/// Queues the record for `change` and returns the wait for its acknowledgement.
+async fn produce(
+    settings: &KafkaSettings,
+    producer: &FutureProducer,
+    engine: &Arc<Engine>,
+    change: Change,
+) -> io::Result<impl Future<Output = io::Result<()>> + use<>> {
+    let value = match change.kind {
+        ChangeKind::Del => None,
+        // Read now, so a merge produces the folded value.
+        ChangeKind::Set | ChangeKind::Merge => {
+            let (engine, key) = (engine.clone(), change.key.clone());
+            blocking(move || engine.get(&key)).await?
+        }
+    };
+    let seq = change.seq.to_string();
+    let mut record =
+        FutureRecord::to(&settings.topic)
+            .key(&change.key)
+            .headers(OwnedHeaders::new().insert(Header {
+                key: "seq",
+                value: Some(seq.as_str()),
+            }));
+    if let Some(value) = &value {
+        record = record.payload(value);
+    }
+    let delivery = producer
+        .send_result(record)
+        .map_err(|(e, _)| io::Error::other(e))?;
+    Ok(async move {
+        match delivery.await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err((e, _))) => Err(io::Error::other(e)),
+            Err(_) => Err(io::Error::other("Kafka producer dropped a record")),
+        }
+    })
+}
+
+async fn save_checkpoint(engine: &Arc<Engine>, key: &[u8], seq: u64) -> io::Result<()> {
+    let (engine, key) = (engine.clone(), key.to_vec());
+    blocking(move || engine.set(&key, seq.to_string().as_bytes()).map(drop)).await
+}
+
+/// Runs engine work on a blocking thread.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    web::block(work).await.map_err(io::Error::other)?
+}
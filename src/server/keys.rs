@@ -0,0 +1,158 @@
+use actix_web::{HttpResponse, web};
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::EncodingQuery;
+use super::state::Db;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct KeysQuery {
+    #[serde(default)]
+    prefix: String,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct CountQuery {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Serialize)]
+struct Count {
+    count: usize,
+}
+
+#[derive(Deserialize)]
+pub struct DeletePrefixQuery {
+    prefix: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct DeletedPrefix {
+    deleted: usize,
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct Exists {
+    exists: bool,
+}
+
+#[derive(Serialize)]
+struct KeysPage {
+    keys: Vec<String>,
+    next_cursor: Option<String>,
+}
+
+/// Lists keys in ascending order. `next_cursor` is an opaque token (the hex-encoded last key) to
+/// pass back as `cursor` for the following page; it is `null` on the last page.
+pub async fn keys(query: web::Query<KeysQuery>, engine: Db, scope: Scope) -> HttpResponse {
+    if let Err(response) = scope.read(query.prefix.as_bytes()) {
+        return response;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let after = match query.cursor.as_deref().map(decode_cursor) {
+        Some(Some(after)) => Some(after),
+        Some(None) => return HttpResponse::BadRequest().body("invalid cursor"),
+        None => None,
+    };
+
+    let mut keys = engine.keys(query.prefix.as_bytes(), after.as_deref(), limit + 1);
+    let next_cursor = if keys.len() > limit {
+        keys.truncate(limit);
+        keys.last().map(|key| encode_cursor(key))
+    } else {
+        None
+    };
+
+    HttpResponse::Ok().json(KeysPage {
+        keys: keys
+            .iter()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect(),
+        next_cursor,
+    })
+}
+
+/// Number of live keys under `prefix`, counted from the index.
+pub async fn count(query: web::Query<CountQuery>, engine: Db, scope: Scope) -> HttpResponse {
+    let prefix = query.into_inner().prefix;
+    if let Err(response) = scope.read(prefix.as_bytes()) {
+        return response;
+    }
+    match web::block(move || engine.usage(prefix.as_bytes()).keys).await {
+        Ok(count) => HttpResponse::Ok().json(Count { count }),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Deletes every key under `prefix` in one atomic batch and returns how many there were. With
+/// `dry_run=true` it only counts them. An empty or missing prefix is refused, since it would
+/// delete everything.
+pub async fn delete_prefix(
+    query: web::Query<DeletePrefixQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let DeletePrefixQuery { prefix, dry_run } = query.into_inner();
+    let Some(prefix) = prefix.filter(|prefix| !prefix.is_empty()) else {
+        return HttpResponse::BadRequest().body("prefix must not be empty");
+    };
+    if let Err(response) = scope.write(prefix.as_bytes()) {
+        return response;
+    }
+    let result = web::block(move || {
+        if dry_run {
+            Ok(engine.usage(prefix.as_bytes()).keys)
+        } else {
+            engine.delete_prefix(prefix.as_bytes())
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Ok(deleted)) => HttpResponse::Ok().json(DeletedPrefix { deleted, dry_run }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Whether `key` exists, without reading its value.
+pub async fn exists(
+    key: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&key) {
+        return response;
+    }
+    HttpResponse::Ok().json(Exists {
+        exists: engine.contains_key(&key),
+    })
+}
+
+fn encode_cursor(key: &[u8]) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<Vec<u8>> {
+    if cursor.len() % 2 != 0 {
+        return None;
+    }
+    (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,455 @@
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, web};
+use breakout1_kv_store::types::{AsOf, Metadata, Versioned};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::acl::Scope;
+use super::idempotency;
+use super::limits;
+use super::state::Db;
+use super::stream::{self, ReaderBody};
+
+/// Metadata entry holding the `Content-Type` a value was stored with.
+const CONTENT_TYPE: &str = "content-type";
+/// Prefix of the headers carrying a value's other metadata entries, `X-Kv-Meta-{name}`.
+const META_HEADER_PREFIX: &str = "x-kv-meta-";
+/// Times a `PATCH` without `If-Match` is retried when other writes keep getting in first.
+const PATCH_ATTEMPTS: usize = 8;
+
+/// The `{key}` segment of `/kv/{key}`, which may sit under `/b/{bucket}`.
+#[derive(Deserialize)]
+pub struct KeyPath {
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetQuery {
+    /// A past point to read the value at: `seq:<n>` for after that write, or milliseconds since
+    /// the Unix epoch.
+    as_of: Option<String>,
+}
+
+/// A write precondition from `If-Match` (`*`: the key must exist, or one ETag returned by this
+/// server) or `If-None-Match: *` (the key must not exist).
+#[derive(Clone, Copy)]
+enum Precondition {
+    Exists,
+    Version(u64),
+    Absent,
+}
+
+/// How a `PUT` that met its precondition went.
+enum Put {
+    /// Written, with the new version.
+    Written(u64),
+    /// A retry of an `Idempotency-Key` already applied, so nothing was written; with the version
+    /// the first attempt wrote.
+    Replayed(u64),
+}
+
+/// Stores the raw request body as the value of `key`, remembering its `Content-Type` and any
+/// `X-Kv-Meta-*` headers as metadata. The body is streamed into the log as it arrives, with or
+/// without a `Content-Length`, and the new version comes back as the `ETag`. With `If-Match` the
+/// write only happens if the key is still at that version, and with `If-None-Match: *` only if the
+/// key does not exist yet; otherwise `412`. With `Idempotency-Key` it happens once per key sent.
+pub async fn put(
+    req: HttpRequest,
+    key: web::Path<KeyPath>,
+    payload: web::Payload,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = key.into_inner().key;
+    if let Err(response) = scope.write(key.as_bytes()) {
+        return response;
+    }
+    let precondition = match (if_match(&req), if_none_match(&req)) {
+        (Err(response), _) | (_, Err(response)) => return response,
+        (Ok(Some(_)), Ok(Some(_))) => {
+            return HttpResponse::BadRequest().body("send either If-Match or If-None-Match");
+        }
+        (Ok(precondition), Ok(None)) | (Ok(None), Ok(precondition)) => precondition,
+    };
+    let request_id = match idempotency::request_id(&req) {
+        Ok(Some(_)) if precondition.is_some() => {
+            return HttpResponse::BadRequest()
+                .body("Idempotency-Key cannot be combined with If-Match or If-None-Match");
+        }
+        Ok(request_id) => request_id,
+        Err(response) => return response,
+    };
+    let meta = match request_metadata(&req) {
+        Ok(meta) => meta,
+        Err(response) => return response,
+    };
+    let len = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+
+    let (body, forward) = stream::payload_reader(payload);
+    let write = web::block(move || -> io::Result<Result<Put, Option<u64>>> {
+        let key = key.as_bytes();
+        let expected = match precondition {
+            None => {
+                return Ok(Ok(match &request_id {
+                    Some(id) => {
+                        match engine.set_idempotent_from_reader(key, body, len, &meta, id)? {
+                            Ok(seq) => Put::Written(seq),
+                            Err(seq) => Put::Replayed(seq),
+                        }
+                    }
+                    None => {
+                        Put::Written(engine.set_from_reader_with_metadata(key, body, len, &meta)?)
+                    }
+                }));
+            }
+            Some(Precondition::Exists) => match engine.version(key)? {
+                Some(version) => Some(version),
+                None => return Ok(Err(None)),
+            },
+            Some(Precondition::Version(version)) => Some(version),
+            Some(Precondition::Absent) => None,
+        };
+        Ok(engine
+            .compare_and_set_from_reader(key, expected, body, len, &meta)?
+            .map(Put::Written))
+    });
+    forward.await;
+
+    match write.await {
+        Ok(Ok(Ok(Put::Written(seq)))) => HttpResponse::NoContent()
+            .insert_header((header::ETAG, etag(seq)))
+            .finish(),
+        Ok(Ok(Ok(Put::Replayed(seq)))) => HttpResponse::NoContent()
+            .insert_header((header::ETAG, etag(seq)))
+            .insert_header(idempotency::REPLAYED)
+            .finish(),
+        Ok(Ok(Err(current))) => precondition_failed(current),
+        Ok(Err(e)) => idempotency::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Returns the value of `key` as raw bytes, with the `Content-Type` and `X-Kv-Meta-*` headers it
+/// was stored with and an `ETag` of its version. The value is streamed out of the log rather
+/// than loaded whole. With `?as_of=` the value the key had then is rebuilt from the log instead.
+pub async fn get(
+    key: web::Path<KeyPath>,
+    query: web::Query<GetQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = key.into_inner().key;
+    if let Err(response) = scope.read(key.as_bytes()) {
+        return response;
+    }
+    if let Some(as_of) = &query.as_of {
+        let Some(as_of) = parse_as_of(as_of) else {
+            return HttpResponse::BadRequest()
+                .body("as_of must be seq:<n> or milliseconds since the Unix epoch");
+        };
+        return match web::block(move || engine.get_as_of(key.as_bytes(), as_of)).await {
+            Ok(Ok(Some(versioned))) => metadata_headers(HttpResponse::Ok(), &versioned.meta)
+                .insert_header((header::ETAG, etag(versioned.seq)))
+                .body(versioned.value),
+            Ok(Ok(None)) => HttpResponse::NotFound().body("Key is not found"),
+            Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        };
+    }
+    match web::block(move || engine.open_value(key.as_bytes())).await {
+        Ok(Ok(Some((info, reader)))) => metadata_headers(HttpResponse::Ok(), &info.meta)
+            .insert_header((header::ETAG, etag(info.seq)))
+            .body(ReaderBody::new(info.len, reader)),
+        Ok(Ok(None)) => HttpResponse::NotFound().body("Key is not found"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The headers `get` would send for `key`, including its `Content-Length`, without reading the
+/// value.
+pub async fn head(key: web::Path<KeyPath>, engine: Db, scope: Scope) -> HttpResponse {
+    let key = key.into_inner().key;
+    if scope.read(key.as_bytes()).is_err() {
+        return HttpResponse::Forbidden().finish();
+    }
+    match web::block(move || engine.value_info(key.as_bytes())).await {
+        Ok(Ok(Some(info))) => metadata_headers(HttpResponse::Ok(), &info.meta)
+            .insert_header((header::ETAG, etag(info.seq)))
+            .body(HeadBody(info.len)),
+        Ok(Ok(None)) => HttpResponse::NotFound().finish(),
+        Ok(Err(_)) | Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// An empty body that reports `len` bytes, so a `HEAD` response carries the value's
+/// `Content-Length`. actix never polls the body of a `HEAD` response.
+struct HeadBody(u64);
+
+impl MessageBody for HeadBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.0)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Poll::Ready(None)
+    }
+}
+
+enum PatchOutcome {
+    Written { seq: u64, document: Vec<u8> },
+    Mismatch(Option<u64>),
+    NotJson,
+    Contended,
+}
+
+/// Applies an RFC 7386 JSON Merge Patch to the JSON value of `key`, a missing key counting as
+/// `null`, and returns the patched document. The write is conditional on the version read and is
+/// retried if another write gets in first; with `If-Match` it happens at that version or not at
+/// all (`412`). Values that are not JSON answer `409`.
+pub async fn patch(
+    req: HttpRequest,
+    key: web::Path<KeyPath>,
+    body: web::Bytes,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = key.into_inner().key;
+    if let Err(response) = scope.write(key.as_bytes()) {
+        return response;
+    }
+    let precondition = match if_match(&req) {
+        Ok(precondition) => precondition,
+        Err(response) => return response,
+    };
+    let patch: Value = match serde_json::from_slice(&body) {
+        Ok(patch) => patch,
+        Err(e) => return HttpResponse::BadRequest().body(format!("invalid merge patch: {}", e)),
+    };
+
+    let result = web::block(move || -> io::Result<PatchOutcome> {
+        let key = key.as_bytes();
+        for _ in 0..PATCH_ATTEMPTS {
+            let current = engine.get_versioned(key)?;
+            let version = current.as_ref().map(|versioned| versioned.seq);
+            match precondition {
+                Some(Precondition::Version(expected)) if version != Some(expected) => {
+                    return Ok(PatchOutcome::Mismatch(version));
+                }
+                Some(Precondition::Exists) if version.is_none() => {
+                    return Ok(PatchOutcome::Mismatch(None));
+                }
+                _ => {}
+            }
+
+            let (mut document, meta) = match current {
+                Some(Versioned { value, meta, .. }) => match serde_json::from_slice(&value) {
+                    Ok(document) => (document, meta),
+                    Err(_) => return Ok(PatchOutcome::NotJson),
+                },
+                None => {
+                    let mut meta = Metadata::new();
+                    meta.insert(CONTENT_TYPE.to_string(), "application/json".to_string());
+                    (Value::Null, meta)
+                }
+            };
+            merge_patch(&mut document, &patch);
+            let document = serde_json::to_vec(&document)?;
+
+            match engine.compare_and_set(key, version, &document, &meta)? {
+                Ok(seq) => return Ok(PatchOutcome::Written { seq, document }),
+                Err(current) if matches!(precondition, Some(Precondition::Version(_))) => {
+                    return Ok(PatchOutcome::Mismatch(current));
+                }
+                Err(_) => continue,
+            }
+        }
+        Ok(PatchOutcome::Contended)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(PatchOutcome::Written { seq, document })) => HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::ETAG, etag(seq)))
+            .body(document),
+        Ok(Ok(PatchOutcome::Mismatch(current))) => precondition_failed(current),
+        Ok(Ok(PatchOutcome::NotJson)) => HttpResponse::Conflict().body("value is not JSON"),
+        Ok(Ok(PatchOutcome::Contended)) => {
+            HttpResponse::Conflict().body("the key kept changing; try again")
+        }
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// RFC 7386: objects merge member by member, `null` members are removed, anything else
+/// replaces the target.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let Value::Object(target) = target else {
+        unreachable!("replaced with an object above");
+    };
+    for (name, value) in patch {
+        if value.is_null() {
+            target.remove(name);
+        } else {
+            merge_patch(target.entry(name.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Deletes `key`. With `If-Match` the delete only happens if the key is still at that version,
+/// otherwise `412`.
+pub async fn delete(
+    req: HttpRequest,
+    key: web::Path<KeyPath>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = key.into_inner().key;
+    if let Err(response) = scope.write(key.as_bytes()) {
+        return response;
+    }
+    let precondition = match if_match(&req) {
+        Ok(precondition) => precondition,
+        Err(response) => return response,
+    };
+
+    let result = web::block(move || -> io::Result<Result<(), Option<u64>>> {
+        let key = key.as_bytes();
+        let expected = match precondition {
+            None => return engine.del(key).map(Ok),
+            Some(Precondition::Exists) => match engine.version(key)? {
+                Some(version) => version,
+                None => return Ok(Err(None)),
+            },
+            Some(Precondition::Version(version)) => version,
+            Some(Precondition::Absent) => unreachable!("DELETE only reads If-Match"),
+        };
+        engine.compare_and_del(key, expected)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Ok(()))) => HttpResponse::NoContent().finish(),
+        Ok(Ok(Err(current))) => precondition_failed(current),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The metadata a `PUT` stores: its `Content-Type`, and each `X-Kv-Meta-{name}` header as an
+/// entry named by the lowercased `{name}`.
+fn request_metadata(req: &HttpRequest) -> Result<Metadata, HttpResponse> {
+    let mut meta = Metadata::new();
+    if let Some(content_type) = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        meta.insert(CONTENT_TYPE.to_string(), content_type.to_string());
+    }
+    for (name, value) in req.headers() {
+        let Some(entry) = name.as_str().strip_prefix(META_HEADER_PREFIX) else {
+            continue;
+        };
+        if entry.is_empty() || entry == CONTENT_TYPE {
+            return Err(HttpResponse::BadRequest().body(format!("{} is not a metadata name", name)));
+        }
+        let Ok(value) = value.to_str() else {
+            return Err(HttpResponse::BadRequest().body(format!("{} is not ASCII", name)));
+        };
+        meta.insert(entry.to_string(), value.to_string());
+    }
+    Ok(meta)
+}
+
+/// Sets the `Content-Type` stored in `meta` on `response`, and sends each other entry as an
+/// `X-Kv-Meta-{name}` header. Entries written through the engine that do not make a valid header
+/// are left out.
+fn metadata_headers(mut response: HttpResponseBuilder, meta: &Metadata) -> HttpResponseBuilder {
+    response.content_type(
+        meta.get(CONTENT_TYPE)
+            .map_or("application/octet-stream", String::as_str),
+    );
+    for (name, value) in meta {
+        if name == CONTENT_TYPE {
+            continue;
+        }
+        let name = HeaderName::try_from(format!("{}{}", META_HEADER_PREFIX, name));
+        if let (Ok(name), Ok(value)) = (name, HeaderValue::try_from(value.as_str())) {
+            response.insert_header((name, value));
+        }
+    }
+    response
+}
+
+fn if_match(req: &HttpRequest) -> Result<Option<Precondition>, HttpResponse> {
+    let Some(value) = req.headers().get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(Some(Precondition::Exists));
+    }
+    parse_etag(value)
+        .map(|version| Some(Precondition::Version(version)))
+        .ok_or_else(|| HttpResponse::BadRequest().body("If-Match must be * or a single ETag"))
+}
+
+fn if_none_match(req: &HttpRequest) -> Result<Option<Precondition>, HttpResponse> {
+    let Some(value) = req.headers().get(header::IF_NONE_MATCH) else {
+        return Ok(None);
+    };
+    match value.to_str().unwrap_or_default().trim() {
+        "*" => Ok(Some(Precondition::Absent)),
+        _ => Err(HttpResponse::BadRequest().body("If-None-Match on PUT must be *")),
+    }
+}
+
+/// The `ETag` of the version written at `seq`.
+pub fn etag(seq: u64) -> String {
+    format!("\"{}\"", seq)
+}
+
+fn parse_etag(value: &str) -> Option<u64> {
+    value.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+/// `412`, carrying the key's current ETag if it exists.
+fn precondition_failed(current: Option<u64>) -> HttpResponse {
+    let mut response = HttpResponse::PreconditionFailed();
+    if let Some(seq) = current {
+        response.insert_header((header::ETAG, etag(seq)));
+    }
+    response.body("version mismatch")
+}
+
+fn parse_as_of(text: &str) -> Option<AsOf> {
+    match text.strip_prefix("seq:") {
+        Some(seq) => seq.parse().ok().map(AsOf::Seq),
+        None => text.parse().ok().map(AsOf::Time),
+    }
+}
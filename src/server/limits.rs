@@ -0,0 +1,56 @@
+use std::io;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{self, InternalError, JsonPayloadError};
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, web};
+
+use super::auth::{self, Access};
+use super::state::AppState;
+
+/// `JsonConfig` answering bodies over `limit` bytes with `413` and a message naming the limit.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, _| match err {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                let response = HttpResponse::PayloadTooLarge().body(format!(
+                    "request body is larger than the {}-byte limit",
+                    limit
+                ));
+                InternalError::from_response(err, response).into()
+            }
+            err => err.into(),
+        })
+}
+
+/// Maps a failed write to `413` if the value is over the engine's maximum size, `507` if the
+/// disk is too full or the index has reached `max_index_memory`, else `500`.
+pub fn write_error(e: io::Error) -> HttpResponse {
+    match e.kind() {
+        io::ErrorKind::FileTooLarge => HttpResponse::PayloadTooLarge().body(e.to_string()),
+        io::ErrorKind::StorageFull | io::ErrorKind::OutOfMemory => {
+            HttpResponse::InsufficientStorage().body(e.to_string())
+        }
+        _ => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Answers requests that need write access with `507` while the data file's file system has less
+/// than `min_free_space` free, before they send a body the engine would refuse. Reads and admin
+/// requests carry on.
+pub async fn reject_writes_when_full(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if auth::required_access(&req) == Access::Write {
+        let engine = req
+            .app_data::<web::Data<AppState>>()
+            .and_then(|state| state.engine());
+        if let Some(Err(e)) = engine.map(|engine| engine.check_free_space()) {
+            return Err(error::ErrorInsufficientStorage(e));
+        }
+    }
+    next.call(req).await
+}
@@ -0,0 +1,133 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpResponse, web};
+use breakout1_kv_store::types::Lease;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::limits;
+use super::state::Db;
+
+#[derive(Deserialize)]
+pub struct AcquireRequest {
+    owner: String,
+    ttl_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RenewRequest {
+    token: u64,
+    ttl_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseQuery {
+    token: u64,
+}
+
+#[derive(Serialize)]
+struct Acquired {
+    token: u64,
+}
+
+#[derive(Serialize)]
+struct Holder {
+    owner: String,
+    /// Milliseconds left on the lease.
+    ttl_ms: u64,
+}
+
+impl From<Lease> for Holder {
+    fn from(lease: Lease) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let left = lease.expires_at.saturating_sub(now);
+        Holder {
+            owner: lease.owner,
+            ttl_ms: left.max(0) as u64,
+        }
+    }
+}
+
+/// Takes the lock `name` for `owner` for `ttl_secs` and returns its fencing token, or `409` with
+/// the current holder. Asking again as the holder extends the lease and keeps the token.
+pub async fn acquire(
+    name: web::Path<String>,
+    req: web::Json<AcquireRequest>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let name = name.into_inner();
+    if let Err(response) = scope.write(name.as_bytes()) {
+        return response;
+    }
+    let AcquireRequest { owner, ttl_secs } = req.into_inner();
+    let ttl = Duration::from_secs(ttl_secs);
+
+    match web::block(move || engine.acquire(name.as_bytes(), &owner, ttl)).await {
+        Ok(Ok(Ok(token))) => HttpResponse::Ok().json(Acquired { token }),
+        Ok(Ok(Err(lease))) => HttpResponse::Conflict().json(Holder::from(lease)),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Extends the lease taken with `token` to `ttl_secs` from now (`204`), or `409` if the lock has
+/// expired or been taken since.
+pub async fn renew(
+    name: web::Path<String>,
+    req: web::Json<RenewRequest>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let name = name.into_inner();
+    if let Err(response) = scope.write(name.as_bytes()) {
+        return response;
+    }
+    let (token, ttl) = (req.token, Duration::from_secs(req.ttl_secs));
+
+    match web::block(move || engine.renew(name.as_bytes(), token, ttl)).await {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::Conflict().body("the lease has been lost"),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Gives up the lock taken with `?token=` (`204`), or `409` if it has expired or been taken since.
+pub async fn release(
+    name: web::Path<String>,
+    query: web::Query<ReleaseQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let name = name.into_inner();
+    if let Err(response) = scope.write(name.as_bytes()) {
+        return response;
+    }
+    let token = query.token;
+
+    match web::block(move || engine.release(name.as_bytes(), token)).await {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::Conflict().body("the lease has been lost"),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The holder of the lock `name` and the time left on their lease, or `404` if it is free. The
+/// token is not shown, so only the holder can renew or release the lock.
+pub async fn holder(name: web::Path<String>, engine: Db, scope: Scope) -> HttpResponse {
+    let name = name.into_inner();
+    if let Err(response) = scope.read(name.as_bytes()) {
+        return response;
+    }
+
+    match engine.lease(name.as_bytes()) {
+        Ok(Some(lease)) => HttpResponse::Ok().json(Holder::from(lease)),
+        Ok(None) => HttpResponse::NotFound().body("Lock is not held"),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
@@ -0,0 +1,65 @@
+use std::io;
+
+use breakout1_kv_store::{Engine, EngineOptions};
+
+use super::config::{Config, Maintenance};
+
+/// Runs a maintenance command on the data file, loaded with the options the server would use,
+/// and prints what it did. Nothing stops a running server from writing to the file meanwhile.
+pub fn run(task: Maintenance, config: &Config, options: EngineOptions) -> io::Result<()> {
+    let path = &config.data_path;
+    if !path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no data file at {}", path.display()),
+        ));
+    }
+    let engine = Engine::load_with_options(path, options)?;
+    match task {
+        Maintenance::Compact => {
+            let report = engine.compact()?;
+            println!(
+                "compacted {} from {} to {} bytes, keeping {} keys, in {:?}",
+                path.display(),
+                report.bytes_before,
+                report.bytes_after,
+                report.keys,
+                report.duration
+            );
+        }
+        Maintenance::Backup(dest) => {
+            let bytes = engine.backup(&dest)?;
+            println!("copied {} bytes to {}", bytes, dest.display());
+        }
+        Maintenance::Verify => {
+            let report = engine.verify()?;
+            println!(
+                "{} records in {} bytes, {} bytes unread at the end",
+                report.records, report.bytes, report.unread_bytes
+            );
+            for record in &report.corrupt {
+                println!("corrupt record at {}: {}", record.pos, record.reason);
+            }
+            for entry in &report.orphaned {
+                let key = String::from_utf8_lossy(&entry.key);
+                println!(
+                    "index entry for {:?} disagrees with the log: {}",
+                    key, entry.reason
+                );
+            }
+            for key in &report.missing {
+                println!(
+                    "{:?} is missing from the index",
+                    String::from_utf8_lossy(key)
+                );
+            }
+            if !report.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the data file failed verification",
+                ));
+            }
+        }
+    }
+    engine.sync()
+}
@@ -0,0 +1,300 @@
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::web;
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::Metadata;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use super::auth;
+use super::resp::read_line;
+use super::state::AppState;
+
+/// Metadata entry holding the opaque flags a client stored with an item.
+const FLAGS: &str = "memcached-flags";
+/// Longest key memcached accepts.
+const MAX_KEY_LEN: usize = 250;
+/// Expiry times above this many seconds are Unix timestamps rather than offsets.
+const MAX_RELATIVE_EXPIRY: u64 = 60 * 60 * 24 * 30;
+
+/// When a stored item expires.
+enum Expiry {
+    Never,
+    In(Duration),
+    /// Already in the past, so the item is gone as soon as it is stored.
+    Passed,
+}
+
+/// A parsed command with the data block of a `set` already read.
+enum Command {
+    Get {
+        keys: Vec<Vec<u8>>,
+        /// `gets`, which also returns each item's CAS value: its sequence number.
+        cas: bool,
+    },
+    Set {
+        key: Vec<u8>,
+        flags: u32,
+        expiry: Expiry,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    Incr {
+        key: Vec<u8>,
+        delta: u64,
+        decr: bool,
+    },
+    FlushAll,
+}
+
+/// Accepts memcached text protocol connections until the server stops. Data blocks over
+/// `max_data_len` bytes close the connection; the engine's `max_value_size` still applies.
+pub async fn serve(listener: TcpListener, state: web::Data<AppState>, max_data_len: usize) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = connection(stream, state, max_data_len).await {
+                        debug!(%peer, "memcached connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept memcached connection: {}", e),
+        }
+    }
+}
+
+/// Reads commands and answers them in order until the client hangs up or sends `quit`.
+async fn connection(
+    stream: TcpStream,
+    state: web::Data<AppState>,
+    max_data_len: usize,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    while let Some(line) = read_line(&mut reader).await? {
+        let words: Vec<&[u8]> = line
+            .split(u8::is_ascii_whitespace)
+            .filter(|word| !word.is_empty())
+            .collect();
+        let noreply = words.last() == Some(&&b"noreply"[..]);
+        let args = &words[..words.len() - noreply as usize];
+
+        let command = match args {
+            [] => Err(b"ERROR\r\n".to_vec()),
+            [b"quit"] => return Ok(()),
+            [b"version"] => Err(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes()),
+            [b"set", key, flags, exptime, bytes] => {
+                let Some(len) = parse::<usize>(bytes) else {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n").await?;
+                    return Ok(());
+                };
+                if len > max_data_len {
+                    writer
+                        .write_all(b"SERVER_ERROR object too large for cache\r\n")
+                        .await?;
+                    return Ok(());
+                }
+                // Read as it arrives rather than allocated up front from the client's word.
+                let mut value = Vec::new();
+                (&mut reader)
+                    .take(len as u64 + 2)
+                    .read_to_end(&mut value)
+                    .await?;
+                if value.len() < len + 2 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if !value.ends_with(b"\r\n") {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n").await?;
+                    continue;
+                }
+                value.truncate(len);
+                match (parse(flags), parse(exptime)) {
+                    (Some(flags), Some(exptime)) if valid_key(key) => Ok(Command::Set {
+                        key: key.to_vec(),
+                        flags,
+                        expiry: expiry(exptime),
+                        value,
+                    }),
+                    _ => Err(bad_format()),
+                }
+            }
+            [b"get" | b"gets", keys @ ..] if !keys.is_empty() => {
+                match keys.iter().all(|key| valid_key(key)) {
+                    true => Ok(Command::Get {
+                        keys: keys.iter().map(|key| key.to_vec()).collect(),
+                        cas: args[0] == b"gets",
+                    }),
+                    false => Err(bad_format()),
+                }
+            }
+            [b"delete", key] if valid_key(key) => Ok(Command::Delete { key: key.to_vec() }),
+            [command @ (b"incr" | b"decr"), key, delta] if valid_key(key) => match parse(delta) {
+                Some(delta) => Ok(Command::Incr {
+                    key: key.to_vec(),
+                    delta,
+                    decr: *command == b"decr",
+                }),
+                None => Err(b"CLIENT_ERROR invalid numeric delta argument\r\n".to_vec()),
+            },
+            [b"flush_all"] | [b"flush_all", b"0"] => Ok(Command::FlushAll),
+            [b"flush_all", _] => {
+                Err(b"CLIENT_ERROR delayed flush_all is not supported\r\n".to_vec())
+            }
+            [
+                b"set" | b"get" | b"gets" | b"delete" | b"incr" | b"decr",
+                ..,
+            ] => Err(bad_format()),
+            _ => Err(b"ERROR\r\n".to_vec()),
+        };
+
+        let reply = match command {
+            Err(reply) => reply,
+            Ok(
+                Command::Set { ref key, .. }
+                | Command::Delete { ref key }
+                | Command::Incr { ref key, .. },
+            ) if !auth::writable(&[key]) => {
+                format!("CLIENT_ERROR {}\r\n", auth::RESERVED_KEY).into_bytes()
+            }
+            Ok(_) if state.in_maintenance() => {
+                b"SERVER_ERROR server is in maintenance mode\r\n".to_vec()
+            }
+            Ok(command) => match state.engine() {
+                None => b"SERVER_ERROR engine is not ready\r\n".to_vec(),
+                Some(engine) => {
+                    let engine = engine.clone();
+                    web::block(move || execute(&engine, command))
+                        .await
+                        .unwrap_or_else(|e| format!("SERVER_ERROR {}\r\n", e).into_bytes())
+                }
+            },
+        };
+        if !noreply {
+            writer.write_all(&reply).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs one command against the engine, on a blocking thread.
+fn execute(engine: &Engine, command: Command) -> Vec<u8> {
+    let result = match command {
+        Command::Get { keys, cas } => get(engine, &keys, cas),
+        Command::Set {
+            key,
+            flags,
+            expiry,
+            value,
+        } => {
+            let mut meta = Metadata::new();
+            if flags != 0 {
+                meta.insert(FLAGS.to_string(), flags.to_string());
+            }
+            match expiry {
+                Expiry::Never => engine.set_with_metadata(&key, &value, &meta).map(drop),
+                Expiry::In(ttl) => engine
+                    .set_with_metadata_and_ttl(&key, &value, &meta, ttl)
+                    .map(drop),
+                Expiry::Passed => engine.del(&key),
+            }
+            .map(|()| b"STORED\r\n".to_vec())
+        }
+        Command::Delete { key } => match engine.contains_key(&key) {
+            true => engine.del(&key).map(|()| b"DELETED\r\n".to_vec()),
+            false => Ok(b"NOT_FOUND\r\n".to_vec()),
+        },
+        Command::Incr { key, delta, decr } => incr(engine, &key, delta, decr),
+        // Only the clients' keys: `delete_prefix` leaves the engine's own records, such as locks
+        // and tags, alone.
+        Command::FlushAll => engine.delete_prefix(b"").map(|_| b"OK\r\n".to_vec()),
+    };
+    result.unwrap_or_else(|e| match e.kind() {
+        io::ErrorKind::FileTooLarge => b"SERVER_ERROR object too large for cache\r\n".to_vec(),
+        _ => format!("SERVER_ERROR {}\r\n", e).into_bytes(),
+    })
+}
+
+fn get(engine: &Engine, keys: &[Vec<u8>], cas: bool) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for key in keys {
+        let Some(item) = engine.get_versioned(key)? else {
+            continue;
+        };
+        let flags = item.meta.get(FLAGS).map_or("0", String::as_str);
+        out.extend_from_slice(b"VALUE ");
+        out.extend_from_slice(key);
+        out.extend_from_slice(format!(" {} {}", flags, item.value.len()).as_bytes());
+        if cas {
+            out.extend_from_slice(format!(" {}", item.seq).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&item.value);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"END\r\n");
+    Ok(out)
+}
+
+/// `incr` and `decr`, which only apply to existing items; `decr` stops at 0 as in memcached.
+fn incr(engine: &Engine, key: &[u8], delta: u64, decr: bool) -> io::Result<Vec<u8>> {
+    if !engine.contains_key(key) {
+        return Ok(b"NOT_FOUND\r\n".to_vec());
+    }
+    let Ok(delta) = i64::try_from(delta) else {
+        return Ok(b"CLIENT_ERROR invalid numeric delta argument\r\n".to_vec());
+    };
+    let result = match decr {
+        true => engine.incr_with_floor(key, -delta, 0),
+        false => engine.incr(key, delta),
+    };
+    let value = match result {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            return Ok(
+                b"CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".to_vec(),
+            );
+        }
+        result => result?,
+    };
+    Ok(format!("{}\r\n", value).into_bytes())
+}
+
+/// Memcached expiry times: 0 for never, up to 30 days as seconds from now, beyond that a Unix
+/// time, and negative for already expired.
+fn expiry(exptime: i64) -> Expiry {
+    let Ok(secs) = u64::try_from(exptime) else {
+        return Expiry::Passed;
+    };
+    if secs == 0 {
+        return Expiry::Never;
+    }
+    if secs <= MAX_RELATIVE_EXPIRY {
+        return Expiry::In(Duration::from_secs(secs));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    match Duration::from_secs(secs).checked_sub(now) {
+        Some(ttl) if !ttl.is_zero() => Expiry::In(ttl),
+        _ => Expiry::Passed,
+    }
+}
+
+/// Keys are at most 250 bytes without whitespace or control characters.
+fn valid_key(key: &[u8]) -> bool {
+    key.len() <= MAX_KEY_LEN && key.iter().all(|&c| c > b' ' && c != 0x7f)
+}
+
+fn parse<T: std::str::FromStr>(text: &[u8]) -> Option<T> {
+    std::str::from_utf8(text).ok()?.parse().ok()
+}
+
+fn bad_format() -> Vec<u8> {
+    b"CLIENT_ERROR bad command line format\r\n".to_vec()
+}
@@ -0,0 +1,164 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, Responder, web};
+
+use super::replica::ReplicaStatus;
+use super::state::{AppState, Db};
+
+/// Prometheus text exposition of a gauge or counter.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// The `/stats` figures in the Prometheus text format, plus on a replica how far it has got
+/// through its primary's log. The replication metrics are left out on a primary.
+pub async fn metrics(
+    engine: Db,
+    state: web::Data<AppState>,
+    replica: Option<web::Data<ReplicaStatus>>,
+) -> impl Responder {
+    let stats = engine.stats();
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "kv_keys",
+        "gauge",
+        "Live keys.",
+        stats.keys as u64,
+    );
+    metric(
+        &mut out,
+        "kv_file_size_bytes",
+        "gauge",
+        "Size of the log file.",
+        stats.file_size,
+    );
+    metric(
+        &mut out,
+        "kv_live_bytes",
+        "gauge",
+        "Log bytes holding live values.",
+        stats.live_bytes,
+    );
+    metric(
+        &mut out,
+        "kv_dead_bytes",
+        "gauge",
+        "Log bytes compaction would reclaim.",
+        stats.dead_bytes,
+    );
+    metric(
+        &mut out,
+        "kv_index_bytes",
+        "gauge",
+        "Estimated memory used by the in-memory index.",
+        stats.index.bytes,
+    );
+    if let Some(max) = stats.index.max_bytes {
+        metric(
+            &mut out,
+            "kv_index_max_bytes",
+            "gauge",
+            "max_index_memory; writes of new keys past it are refused.",
+            max,
+        );
+        metric(
+            &mut out,
+            "kv_index_rejected_writes_total",
+            "counter",
+            "Writes of new keys refused because the index was full.",
+            stats.index.rejected_writes,
+        );
+    }
+    metric(
+        &mut out,
+        "kv_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        state.uptime().as_secs(),
+    );
+    metric(
+        &mut out,
+        "kv_last_sequence",
+        "gauge",
+        "Sequence number of the last write.",
+        stats.replication.applied_seq,
+    );
+    if let Some(free) = stats.disk.free_bytes {
+        metric(
+            &mut out,
+            "kv_disk_free_bytes",
+            "gauge",
+            "Bytes free on the data file's file system.",
+            free,
+        );
+    }
+    if stats.disk.min_free_bytes.is_some() {
+        metric(
+            &mut out,
+            "kv_disk_low",
+            "gauge",
+            "1 while free space is below min_free_space and writes are refused.",
+            stats.disk.low() as u64,
+        );
+        metric(
+            &mut out,
+            "kv_disk_rejected_writes_total",
+            "counter",
+            "Writes refused for want of disk space.",
+            stats.disk.rejected_writes,
+        );
+    }
+
+    if let Some(replica) = replica.as_deref().filter(|replica| !replica.stopped()) {
+        let replication = stats.replication;
+        metric(
+            &mut out,
+            "kv_replication_primary_sequence",
+            "gauge",
+            "Newest sequence number the primary is known to have reached.",
+            replication.primary_seq,
+        );
+        metric(
+            &mut out,
+            "kv_replication_lag_sequences",
+            "gauge",
+            "Writes the primary has that are yet to be applied.",
+            replication.lag(),
+        );
+        metric(
+            &mut out,
+            "kv_replication_records_applied_total",
+            "counter",
+            "Records applied from the primary.",
+            replication.records_applied,
+        );
+        metric(
+            &mut out,
+            "kv_replication_bytes_applied_total",
+            "counter",
+            "Bytes of records applied from the primary.",
+            replication.bytes_applied,
+        );
+        metric(
+            &mut out,
+            "kv_replication_connected",
+            "gauge",
+            "1 while streaming from the primary.",
+            replica.connected() as u64,
+        );
+        metric(
+            &mut out,
+            "kv_replication_reconnects_total",
+            "counter",
+            "Connections to the primary after the first.",
+            replica.reconnects(),
+        );
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
+}
@@ -0,0 +1,49 @@
+pub mod access_log;
+pub mod acl;
+pub mod admin;
+pub mod append;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod binary;
+pub mod buckets;
+pub mod cas;
+pub mod changes;
+pub mod cluster;
+pub mod compress;
+pub mod config;
+pub mod cors;
+pub mod counter;
+pub mod encoding;
+pub mod graphql;
+pub mod grpc;
+pub mod health;
+pub mod history;
+pub mod idempotency;
+pub mod jwt;
+pub mod kafka;
+pub mod keys;
+pub mod kv;
+pub mod limits;
+pub mod lock;
+pub mod maintenance;
+pub mod memcached;
+pub mod metrics;
+pub mod mqtt;
+pub mod openapi;
+pub mod ratelimit;
+pub mod replica;
+pub mod replication;
+pub mod resp;
+pub mod router;
+pub mod s3_backup;
+pub mod scan;
+pub mod state;
+pub mod stats;
+pub mod stream;
+pub mod systemd;
+pub mod tags;
+pub mod timeout;
+pub mod tls;
+pub mod ttl;
+pub mod watch;
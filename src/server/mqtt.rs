@@ -0,0 +1,194 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use breakout1_kv_store::Engine;
+use breakout1_kv_store::types::{Change, ChangeKind};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use super::state::AppState;
+use super::watch;
+
+/// Publishes waiting for the event loop before `publish` blocks, and with it the bridge.
+const REQUEST_CAPACITY: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Pause before the event loop reconnects to a broker that failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// How often the bridge checks whether the engine has finished loading.
+const LOAD_POLL: Duration = Duration::from_millis(100);
+
+/// Change bridge settings. The bridge is off while `broker` is unset.
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    /// `host:port` of the broker.
+    pub broker: Option<String>,
+    pub topic: String,
+    /// Only writes to keys under it are published.
+    pub prefix: String,
+    /// Publish values themselves rather than their SHA-256 digest.
+    pub values: bool,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// JSON payload of one published change. Deletes carry neither value nor digest, and neither
+/// do sets of keys deleted again before they were read.
+#[derive(Serialize)]
+struct ChangeMessage {
+    seq: u64,
+    op: &'static str,
+    key: String,
+    /// Base64, with `mqtt_values` on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    /// Hex SHA-256 of the value, with `mqtt_values` off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+}
+
+impl MqttSettings {
+    pub fn enabled(&self) -> bool {
+        self.broker.is_some()
+    }
+
+    /// Fails on a broker address without a port.
+    fn options(&self, max_packet_size: usize) -> io::Result<MqttOptions> {
+        let broker = self.broker.as_deref().unwrap_or_default();
+        let (host, port) = broker
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("mqtt_broker {} is not host:port", broker),
+                )
+            })?;
+        let mut options = MqttOptions::new(&self.client_id, host, port);
+        options
+            .set_keep_alive(KEEP_ALIVE)
+            .set_max_packet_size(max_packet_size, max_packet_size);
+        if let Some(username) = &self.username {
+            options.set_credentials(username, self.password.as_deref().unwrap_or_default());
+        }
+        Ok(options)
+    }
+}
+
+/// Connects to the broker and publishes every write under the prefix, in sequence order, once
+/// the engine has loaded. Messages go out with QoS 1 and are limited to `max_packet_size`
+/// bytes. Falling behind the engine replays the missed changes from the log.
+pub fn spawn(
+    settings: MqttSettings,
+    state: web::Data<AppState>,
+    max_packet_size: usize,
+) -> io::Result<()> {
+    let (client, mut events) =
+        AsyncClient::new(settings.options(max_packet_size)?, REQUEST_CAPACITY);
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = events.poll().await {
+                warn!("MQTT connection failed: {}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let engine = loop {
+            match state.engine() {
+                Some(engine) => break engine.clone(),
+                None => tokio::time::sleep(LOAD_POLL).await,
+            }
+        };
+        info!(
+            "publishing changes to MQTT topic {} on {}",
+            settings.topic,
+            settings.broker.as_deref().unwrap_or_default()
+        );
+        bridge(&settings, &client, engine).await;
+    });
+    Ok(())
+}
+
+async fn bridge(settings: &MqttSettings, client: &AsyncClient, engine: Arc<Engine>) {
+    let prefix = settings.prefix.as_bytes().to_vec();
+    let mut last_seq = engine.last_sequence();
+    let mut subscription = engine.watch(&prefix);
+    loop {
+        let mut changes = watch::forward(subscription);
+        while let Some(change) = changes.recv().await {
+            last_seq = change.seq;
+            publish(settings, client, &engine, change).await;
+        }
+
+        warn!(
+            "MQTT bridge fell behind, replaying changes after {}",
+            last_seq
+        );
+        let (history, resumed) = loop {
+            let (replay, prefix) = (engine.clone(), prefix.clone());
+            match web::block(move || replay.watch_since(&prefix, last_seq)).await {
+                Ok(Ok(resumed)) => break resumed,
+                Ok(Err(e)) => warn!("failed to replay changes for MQTT: {}", e),
+                Err(e) => warn!("failed to replay changes for MQTT: {}", e),
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        };
+        for change in history {
+            last_seq = change.seq;
+            publish(settings, client, &engine, change).await;
+        }
+        subscription = resumed;
+    }
+}
+
+async fn publish(
+    settings: &MqttSettings,
+    client: &AsyncClient,
+    engine: &Arc<Engine>,
+    change: Change,
+) {
+    let value = match change.kind {
+        ChangeKind::Del => None,
+        // Read now, so a merge publishes the folded value.
+        ChangeKind::Set | ChangeKind::Merge => {
+            let (engine, key) = (engine.clone(), change.key.clone());
+            match web::block(move || engine.get(&key)).await {
+                Ok(Ok(value)) => value,
+                Ok(Err(e)) => {
+                    warn!("failed to read a changed key for MQTT: {}", e);
+                    None
+                }
+                Err(e) => {
+                    warn!("failed to read a changed key for MQTT: {}", e);
+                    None
+                }
+            }
+        }
+    };
+    let (value, digest) = match (value, settings.values) {
+        (None, _) => (None, None),
+        (Some(value), true) => (Some(STANDARD.encode(value)), None),
+        (Some(value), false) => (None, Some(format!("{:x}", Sha256::digest(value)))),
+    };
+    let message = ChangeMessage {
+        seq: change.seq,
+        op: watch::op(change.kind),
+        key: String::from_utf8_lossy(&change.key).into_owned(),
+        value,
+        digest,
+    };
+    let payload = serde_json::to_vec(&message).expect("change messages serialize");
+    if let Err(e) = client
+        .publish(&settings.topic, QoS::AtLeastOnce, false, payload)
+        .await
+    {
+        warn!("failed to publish change {} to MQTT: {}", change.seq, e);
+    }
+}
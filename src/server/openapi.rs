@@ -0,0 +1,34 @@
+use actix_web::HttpResponse;
+
+/// The OpenAPI 3.1 description of every route. Update it along with the routes in `main.rs`.
+const SPEC: &str = include_str!("openapi.json");
+
+/// Swagger UI, loaded from a CDN, pointed at `/openapi.json`.
+const DOCS_PAGE: &str = r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>breakout1 key-value store API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+pub async fn spec() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(SPEC)
+}
+
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(DOCS_PAGE)
+}
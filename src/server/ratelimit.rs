@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, web};
+
+use super::auth;
+
+/// Clients tracked before buckets that have refilled are dropped.
+const PRUNE_AT: usize = 10_000;
+
+/// Token buckets per client, shared with `limit` through app data.
+pub struct RateLimiter {
+    /// Tokens added per second.
+    rate: f64,
+    /// Most tokens a bucket holds, i.e. the largest burst allowed.
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        RateLimiter {
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, or returns how long until one is available.
+    fn acquire(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT && !buckets.contains_key(client) {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        if self.refill(bucket, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+/// Limits each client, identified by its credential or else its IP address, to the configured
+/// rate, except on `auth::PUBLIC_PATHS`. Requests over it get `429 Too Many Requests` with `Retry-After` in whole seconds.
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let Some(limiter) = limiter.filter(|_| !auth::PUBLIC_PATHS.contains(&req.path())) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let client = match auth::credential(req.headers()) {
+        Some(credential) => format!("key:{}", credential),
+        None => format!(
+            "ip:{}",
+            req.peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default()
+        ),
+    };
+
+    match limiter.acquire(&client) {
+        Ok(()) => Ok(next.call(req).await?.map_into_left_body()),
+        Err(wait) => {
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((
+                    header::RETRY_AFTER,
+                    wait.as_secs_f64().ceil().max(1.0) as u64,
+                ))
+                .body("rate limit exceeded");
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{HttpRequest, HttpResponse, error, web};
+use breakout1_kv_store::types::ReplicationStats;
+use breakout1_kv_store::{Engine, EngineOptions};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use super::admin;
+use super::auth::{self, Access};
+use super::state::{AppState, Db};
+
+/// Pause before reconnecting to the primary after the stream ends or fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Longer than the primary's heartbeat interval, so a stream that goes quiet counts as dead.
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Replica mode settings. The server is a primary while `primary` is unset.
+#[derive(Debug, Clone)]
+pub struct ReplicaSettings {
+    /// Base URL of the primary's HTTP API, such as `http://10.0.0.5:8080`.
+    pub primary: Option<String>,
+    /// Admin credential for the primary's `/replication` endpoints.
+    pub token: Option<String>,
+    /// Writes a replica may fall behind the primary before `/ready` fails.
+    pub max_lag: Option<u64>,
+}
+
+impl ReplicaSettings {
+    pub fn enabled(&self) -> bool {
+        self.primary.is_some()
+    }
+}
+
+/// The primary a replica copies, reached over its HTTP API.
+pub struct Primary {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Primary {
+    pub fn new(settings: &ReplicaSettings) -> Self {
+        Primary {
+            url: settings
+                .primary
+                .as_deref()
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            token: settings.token.clone(),
+            agent: ureq::AgentBuilder::new().timeout_read(READ_TIMEOUT).build(),
+        }
+    }
+
+    fn get(&self, path: &str) -> ureq::Request {
+        let request = self.agent.get(&format!("{}{}", self.url, path));
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Replaces the log at `path` with a snapshot of the primary's, written beside it first so
+    /// a failed download leaves the old log alone.
+    fn bootstrap(&self, path: &Path) -> io::Result<()> {
+        info!("bootstrapping {} from {}", path.display(), self.url);
+        let response = self
+            .get("/replication/snapshot")
+            .call()
+            .map_err(|e| io::Error::other(format!("fetching a snapshot: {}", e)))?;
+        let partial = path.with_extension("bootstrap");
+        let mut file = File::create(&partial)?;
+        let bytes = io::copy(&mut response.into_reader(), &mut file)?;
+        file.sync_all()?;
+        fs::rename(&partial, path)?;
+        info!("downloaded a {} byte snapshot", bytes);
+        Ok(())
+    }
+
+    /// The primary's records after `since`, or `None` if it no longer holds them all.
+    fn stream(&self, since: u64) -> io::Result<Option<Box<dyn Read + Send + Sync>>> {
+        match self
+            .get("/replication/stream")
+            .query("since_seq", &since.to_string())
+            .call()
+        {
+            Ok(response) => Ok(Some(response.into_reader())),
+            Err(ureq::Error::Status(410, _)) => Ok(None),
+            Err(e) => Err(io::Error::other(format!("streaming records: {}", e))),
+        }
+    }
+}
+
+/// How far a replica has got, shared between the thread following the primary and the
+/// `/replication/status` and `/admin/promote` handlers.
+pub struct ReplicaStatus {
+    primary: String,
+    max_lag: Option<u64>,
+    connected: AtomicBool,
+    /// Connections to the primary after the first.
+    reconnects: AtomicU64,
+    /// When the replica last held every write the primary had reported.
+    caught_up_at: Mutex<Option<Instant>>,
+    stopped: AtomicBool,
+    /// Held by the following thread while it runs, so `stop` can wait for it to finish.
+    following: Mutex<()>,
+}
+
+impl ReplicaStatus {
+    pub fn new(primary: &Primary, settings: &ReplicaSettings) -> Self {
+        ReplicaStatus {
+            primary: primary.url.clone(),
+            max_lag: settings.max_lag,
+            connected: AtomicBool::new(false),
+            reconnects: AtomicU64::new(0),
+            caught_up_at: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            following: Mutex::new(()),
+        }
+    }
+
+    fn heartbeat(&self, primary_seq: u64, applied: u64) {
+        if applied >= primary_seq {
+            *self.caught_up_at.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    /// Writes the primary has that the replica has yet to apply, as far as it knows, and how
+    /// many seconds ago it last had them all, if it ever did.
+    fn lag(&self, stats: &ReplicationStats) -> (u64, Option<u64>) {
+        let seqs = stats.lag();
+        if seqs == 0 && self.connected() {
+            return (0, Some(0));
+        }
+        let secs = self
+            .caught_up_at
+            .lock()
+            .unwrap()
+            .map(|at| at.elapsed().as_secs());
+        (seqs, secs)
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::SeqCst)
+    }
+
+    /// Whether the replica, until it is promoted, is further behind the primary than
+    /// `max_lag` allows.
+    pub fn lagging(&self, stats: &ReplicationStats) -> bool {
+        !self.stopped() && self.max_lag.is_some_and(|max_lag| stats.lag() > max_lag)
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Stops following the primary and waits until the records already received are applied.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        drop(self.following.lock().unwrap());
+    }
+}
+
+/// Fails the next read once replication is stopped, ending `replicate_from` after the batch it
+/// is on.
+struct Stoppable<'a, R> {
+    inner: R,
+    status: &'a ReplicaStatus,
+}
+
+impl<R: Read> Read for Stoppable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.status.stopped() {
+            return Err(io::Error::other("replication stopped"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Loads a replica's engine read-only, first bootstrapping its log from a snapshot of the
+/// primary's if it is empty, or if the primary has compacted away writes it is missing.
+pub fn load(primary: &Primary, path: &Path, options: EngineOptions) -> io::Result<Engine> {
+    if !fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+        primary.bootstrap(path)?;
+    }
+    let engine = Engine::load_with_options(path, options.clone())?;
+    if primary.stream(engine.last_sequence())?.is_some() {
+        engine.set_read_only(true);
+        return Ok(engine);
+    }
+    warn!(
+        "{} no longer holds every write after sequence {}",
+        primary.url,
+        engine.last_sequence()
+    );
+    drop(engine);
+    primary.bootstrap(path)?;
+    let engine = Engine::load_with_options(path, options)?;
+    engine.set_read_only(true);
+    Ok(engine)
+}
+
+/// Applies the primary's records to `engine` as they arrive, reconnecting whenever the stream
+/// ends or fails, from the engine's own `last_sequence`, until `status` is stopped. Also stops
+/// if the primary compacts away writes the replica has yet to apply; the replica then has to be
+/// restarted to bootstrap again.
+pub fn follow(primary: Primary, engine: Arc<Engine>, status: &ReplicaStatus) {
+    let _following = status.following.lock().unwrap();
+    info!("replicating from {}", primary.url);
+    let mut attempts = 0u64;
+    while !status.stopped() {
+        if attempts > 0 {
+            status.reconnects.fetch_add(1, Ordering::SeqCst);
+        }
+        attempts += 1;
+        let since = engine.last_sequence();
+        match primary.stream(since) {
+            Ok(Some(records)) => {
+                status.connected.store(true, Ordering::SeqCst);
+                let records = Stoppable {
+                    inner: records,
+                    status,
+                };
+                let replicated = engine.replicate_from(records, |primary_seq| {
+                    status.heartbeat(primary_seq, engine.last_sequence())
+                });
+                status.connected.store(false, Ordering::SeqCst);
+                match replicated {
+                    _ if status.stopped() => break,
+                    Ok(_) => warn!("replication stream from {} ended", primary.url),
+                    Err(e) => warn!("replication from {} failed: {}", primary.url, e),
+                }
+            }
+            Ok(None) => {
+                error!(
+                    "{} compacted away writes after sequence {}; restart this replica to \
+                     bootstrap it again",
+                    primary.url, since
+                );
+                return;
+            }
+            Err(e) => warn!("replication from {} failed: {}", primary.url, e),
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+    info!("stopped replicating from {}", primary.url);
+}
+
+#[derive(Serialize)]
+struct StatusResponse<'a> {
+    /// `primary`, or `replica` until it is promoted.
+    role: &'static str,
+    last_seq: u64,
+    #[serde(flatten)]
+    replica: Option<ReplicaLag<'a>>,
+}
+
+#[derive(Serialize)]
+struct ReplicaLag<'a> {
+    primary: &'a str,
+    connected: bool,
+    primary_seq: u64,
+    lag_seqs: u64,
+    /// `None` until the replica first catches up.
+    lag_secs: Option<u64>,
+    reconnects: u64,
+}
+
+#[derive(Serialize)]
+struct PromoteResponse {
+    last_seq: u64,
+}
+
+/// Whether this server is a primary or a replica, and on a replica how far behind its primary it
+/// is: in writes, going by the newest sequence number the primary has sent in a record or
+/// heartbeat, and in seconds since it last held all of them. Needs admin credentials.
+pub async fn status(
+    req: HttpRequest,
+    engine: Db,
+    state: web::Data<AppState>,
+    replica: Option<web::Data<ReplicaStatus>>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    let stats = engine.replication_stats();
+    let last_seq = stats.applied_seq;
+    let replica = replica.as_deref().filter(|replica| !replica.stopped());
+    let lag = replica.map(|replica| {
+        let (lag_seqs, lag_secs) = replica.lag(&stats);
+        ReplicaLag {
+            primary: &replica.primary,
+            connected: replica.connected(),
+            primary_seq: stats.primary_seq,
+            lag_seqs,
+            lag_secs,
+            reconnects: replica.reconnects(),
+        }
+    });
+    HttpResponse::Ok().json(StatusResponse {
+        role: if lag.is_some() { "replica" } else { "primary" },
+        last_seq,
+        replica: lag,
+    })
+}
+
+/// Turns a replica into a primary for a failover: stops following the primary, waits for the
+/// records already received to be applied, then accepts writes. The old primary must not take
+/// writes afterwards, or the two logs diverge. Needs admin credentials.
+pub async fn promote(
+    req: HttpRequest,
+    engine: Db,
+    state: web::Data<AppState>,
+    replica: Option<web::Data<ReplicaStatus>>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    let Some(replica) = replica else {
+        return HttpResponse::NotFound().body("this server is not a replica");
+    };
+    let promoted = web::block(move || {
+        replica.stop();
+        engine.set_read_only(false);
+        engine.last_sequence()
+    })
+    .await;
+    match promoted {
+        Ok(last_seq) => {
+            info!("promoted to primary at sequence {}", last_seq);
+            HttpResponse::Ok().json(PromoteResponse { last_seq })
+        }
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Answers requests that need write access with `403` while the server is a read-only replica;
+/// they have to go to the primary.
+pub async fn reject_writes(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let read_only = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.engine())
+        .is_some_and(|engine| engine.is_read_only());
+    if read_only && auth::required_access(&req) == Access::Write {
+        return Err(error::ErrorForbidden(
+            "this server is a read-only replica; send writes to the primary",
+        ));
+    }
+    next.call(req).await
+}
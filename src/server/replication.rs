@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use breakout1_kv_store::codec;
+use breakout1_kv_store::types::{Change, ReplicationCursor};
+use futures_util::stream;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use super::admin;
+use super::state::{AppState, Db};
+use super::stream::ReaderBody;
+use super::watch;
+
+/// How long the stream goes without records before sending a heartbeat.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Roughly how many bytes of records go into one chunk of the response.
+const READ_BYTES: usize = 256 * 1024;
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    #[serde(default)]
+    since_seq: u64,
+}
+
+/// What the stream holds between chunks.
+struct Tail {
+    engine: Db,
+    cursor: ReplicationCursor,
+    /// Wakes the stream when something is written.
+    writes: mpsc::Receiver<Change>,
+}
+
+/// A consistent snapshot of the whole log, which loads like any log file, for a new replica to
+/// start from before streaming what was written since. Needs admin credentials.
+pub async fn snapshot(req: HttpRequest, engine: Db, state: web::Data<AppState>) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    match web::block(move || engine.snapshot()).await {
+        Ok(Ok((len, snapshot))) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(ReaderBody::new(len, snapshot)),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Streams the log records written after `since_seq` to a replica, as they are framed in the
+/// log, then new ones as they are written, with a heartbeat carrying the newest sequence number
+/// whenever nothing has been written for a while. Needs admin credentials, since it carries
+/// every key. Answers `410 Gone` if compaction has dropped records the replica is missing; it
+/// then has to start over from a backup. The stream ends if that happens while it runs.
+pub async fn stream(
+    req: HttpRequest,
+    query: web::Query<StreamQuery>,
+    engine: Db,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    // Subscribed first, so nothing written after the cursor is taken goes unnoticed.
+    let writes = watch::forward(engine.watch(b""));
+    let since = query.since_seq;
+    let cursor = {
+        let engine = engine.clone();
+        match web::block(move || engine.replication_cursor(since)).await {
+            Ok(Ok(Some(cursor))) => cursor,
+            Ok(Ok(None)) => {
+                return HttpResponse::Gone()
+                    .body("the log no longer holds every write since since_seq");
+            }
+            Ok(Err(e)) => return HttpResponse::InternalServerError().body(e.to_string()),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        }
+    };
+
+    let tail = Tail {
+        engine,
+        cursor,
+        writes,
+    };
+    let chunks = stream::unfold(tail, |mut tail| async move {
+        loop {
+            // One read catches up on every write that woke the stream so far.
+            while tail.writes.try_recv().is_ok() {}
+            let engine = tail.engine.clone();
+            let mut cursor = tail.cursor.clone();
+            let read = web::block(move || {
+                engine
+                    .read_replication(&mut cursor, READ_BYTES)
+                    .map(|frames| frames.map(|frames| (frames, cursor)))
+            })
+            .await;
+            let frames = match read {
+                Ok(Ok(Some((frames, cursor)))) => {
+                    tail.cursor = cursor;
+                    frames
+                }
+                Ok(Ok(None)) | Ok(Err(_)) | Err(_) => return None,
+            };
+            if !frames.is_empty() {
+                return Some((Ok::<_, actix_web::Error>(Bytes::from(frames)), tail));
+            }
+            match tokio::time::timeout(HEARTBEAT_INTERVAL, tail.writes.recv()).await {
+                Ok(Some(_)) => {}
+                // The subscription fell behind; the log has every write it missed.
+                Ok(None) => tail.writes = watch::forward(tail.engine.watch(b"")),
+                Err(_) => {
+                    let heartbeat = codec::encode_heartbeat(tail.engine.last_sequence());
+                    return Some((Ok(Bytes::from(heartbeat)), tail));
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Compression would hold records back until a block fills up.
+        .insert_header((
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        ))
+        .streaming(chunks)
+}
@@ -0,0 +1,479 @@
+use std::io;
+use std::time::Duration;
+
+use actix_web::web;
+use breakout1_kv_store::Engine;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use super::auth::{self, Access, Grant};
+use super::state::AppState;
+
+/// Longest inline command or bulk-string header line.
+const MAX_LINE: u64 = 64 * 1024;
+/// Most arguments in one command.
+const MAX_ARGS: usize = 1024 * 1024;
+/// Keys returned by a `SCAN` without `COUNT`.
+const DEFAULT_SCAN_COUNT: usize = 10;
+/// Keys read from the engine per page while `KEYS` walks the keyspace.
+const KEYS_PAGE: usize = 1000;
+
+/// A RESP2 reply.
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(text) => out.extend_from_slice(format!("+{}\r\n", text).as_bytes()),
+            Reply::Error(text) => out.extend_from_slice(format!("-{}\r\n", text).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(bytes)) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write(out);
+                }
+            }
+        }
+    }
+
+    fn err(text: impl Into<String>) -> Self {
+        Reply::Error(format!("ERR {}", text.into()))
+    }
+
+    fn wrong_args(command: &str) -> Self {
+        Reply::err(format!(
+            "wrong number of arguments for '{}' command",
+            command.to_ascii_lowercase()
+        ))
+    }
+}
+
+/// Accepts RESP connections until the server stops. Each connection is served on the current
+/// worker; engine calls run on the blocking pool like HTTP handlers'. Commands whose arguments
+/// add up to more than `max_command_size` bytes close the connection.
+pub async fn serve(listener: TcpListener, state: web::Data<AppState>, max_command_size: usize) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = connection(stream, state, max_command_size).await {
+                        debug!(%peer, "resp connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept resp connection: {}", e),
+        }
+    }
+}
+
+/// Reads commands and answers them in order until the client hangs up or sends `QUIT`.
+async fn connection(
+    stream: TcpStream,
+    state: web::Data<AppState>,
+    max_command_size: usize,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    // Without required credentials every connection may do anything.
+    let mut grant: Option<Grant> = None;
+    let mut out = Vec::new();
+
+    loop {
+        let args = match read_command(&mut reader, max_command_size).await {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            // Past a malformed command there is no telling where the next one starts.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                writer
+                    .write_all(format!("-ERR Protocol error: {}\r\n", e).as_bytes())
+                    .await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(name) = args.first() else {
+            continue;
+        };
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        let reply = match name.as_str() {
+            "QUIT" => {
+                writer.write_all(b"+OK\r\n").await?;
+                return Ok(());
+            }
+            "PING" => match &args[1..] {
+                [] => Reply::Simple("PONG"),
+                [message] => Reply::Bulk(Some(message.clone())),
+                _ => Reply::wrong_args(&name),
+            },
+            "AUTH" => match &args[1..] {
+                // `AUTH <user> <credential>` from Redis 6 clients; the user name is ignored.
+                [credential] | [_, credential] => {
+                    match state.auth().grant(&String::from_utf8_lossy(credential)) {
+                        Some(granted) => {
+                            grant = Some(granted);
+                            Reply::Simple("OK")
+                        }
+                        None => Reply::Error("WRONGPASS invalid credentials".to_string()),
+                    }
+                }
+                _ => Reply::wrong_args(&name),
+            },
+            _ if state.auth().required() && grant.is_none() => {
+                Reply::Error("NOAUTH Authentication required.".to_string())
+            }
+            _ if state.in_maintenance() => Reply::err("server is in maintenance mode"),
+            _ => match state.engine() {
+                None => Reply::Error("LOADING engine is not ready".to_string()),
+                Some(engine) => {
+                    let engine = engine.clone();
+                    let grant = grant.clone();
+                    web::block(move || execute(&engine, grant.as_ref(), &name, &args[1..]))
+                        .await
+                        .unwrap_or_else(|e| Reply::err(e.to_string()))
+                }
+            },
+        };
+        out.clear();
+        reply.write(&mut out);
+        writer.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+/// The next command as its arguments, from a RESP array of bulk strings or an inline command
+/// line. `None` once the client hangs up. Nothing is allocated ahead of the data arriving, since
+/// clients send commands before they authenticate.
+async fn read_command(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    max_command_size: usize,
+) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let Some(count) = line.strip_prefix(b"*") else {
+        return Ok(Some(
+            line.split(u8::is_ascii_whitespace)
+                .filter(|arg| !arg.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect(),
+        ));
+    };
+    let count = parse_len(count, MAX_ARGS)?;
+    let mut args = Vec::with_capacity(count.min(64));
+    let mut left = max_command_size;
+    for _ in 0..count {
+        let header = read_line(reader)
+            .await?
+            .ok_or_else(|| protocol_error("connection closed within a command"))?;
+        let len = header
+            .strip_prefix(b"$")
+            .ok_or_else(|| protocol_error("expected a bulk string"))?;
+        let len = parse_len(len, usize::MAX)?;
+        left = left
+            .checked_sub(len)
+            .ok_or_else(|| protocol_error("command is larger than max_body_size"))?;
+        let mut arg = Vec::new();
+        (&mut *reader)
+            .take(len as u64 + 2)
+            .read_to_end(&mut arg)
+            .await?;
+        if arg.len() < len + 2 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string is not terminated by CRLF"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// One line without its line ending, or `None` at the end of the stream.
+pub async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("line too long"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_len(text: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(text)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .filter(|&len| len <= max)
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Runs one data command against the engine, on a blocking thread.
+fn execute(engine: &Engine, grant: Option<&Grant>, name: &str, args: &[Vec<u8>]) -> Reply {
+    let args: Vec<&[u8]> = args.iter().map(Vec::as_slice).collect();
+
+    // Arity and access are checked before anything runs.
+    let (access, keys) = match (name, args.as_slice()) {
+        ("GET" | "TTL", [key]) => (Access::Read, vec![*key]),
+        ("EXISTS", [_, ..]) => (Access::Read, args.clone()),
+        ("SET", [key, _, ..]) | ("INCR", [key]) | ("EXPIRE", [key, _]) => {
+            (Access::Write, vec![*key])
+        }
+        ("DEL", [_, ..]) => (Access::Write, args.clone()),
+        ("KEYS", [pattern]) => (Access::Read, vec![literal_prefix(pattern)]),
+        ("SCAN", [_, options @ ..]) => match parse_scan_options(options) {
+            Ok((pattern, _)) => (Access::Read, vec![literal_prefix(pattern)]),
+            Err(reply) => return reply,
+        },
+        ("GET" | "TTL" | "EXISTS" | "SET" | "INCR" | "EXPIRE" | "DEL" | "KEYS" | "SCAN", _) => {
+            return Reply::wrong_args(name);
+        }
+        _ => {
+            return Reply::err(format!("unknown command '{}'", name.to_ascii_lowercase()));
+        }
+    };
+    // No grant means credentials are not required.
+    if !grant.is_none_or(|grant| grant.allows(access, &keys)) {
+        return Reply::Error("NOPERM credentials do not allow this command".to_string());
+    }
+    if access == Access::Write && !auth::writable(&keys) {
+        return Reply::err(auth::RESERVED_KEY);
+    }
+
+    let result = match (name, args.as_slice()) {
+        ("GET", [key]) => engine.get(key).map(Reply::Bulk),
+        ("SET", [key, value, options @ ..]) => match parse_expiry(options) {
+            Ok(Some(ttl)) => engine.set_with_ttl(key, value, ttl),
+            Ok(None) => engine.set(key, value),
+            Err(reply) => return reply,
+        }
+        .map(|_| Reply::Simple("OK")),
+        ("DEL", keys) => {
+            let mut deleted = 0;
+            keys.iter()
+                .try_for_each(|key| {
+                    if engine.contains_key(key) {
+                        deleted += 1;
+                        engine.del(key)?;
+                    }
+                    Ok(())
+                })
+                .map(|()| Reply::Integer(deleted))
+        }
+        ("EXISTS", keys) => Ok(Reply::Integer(
+            keys.iter().filter(|key| engine.contains_key(key)).count() as i64,
+        )),
+        ("INCR", [key]) => engine.incr(key, 1).map(Reply::Integer),
+        ("EXPIRE", [key, secs]) => match parse_number(secs) {
+            None => return Reply::err("value is not an integer or out of range"),
+            // Redis deletes keys given a TTL that has already passed.
+            Some(0) => {
+                let existed = engine.contains_key(key);
+                engine.del(key).map(|()| Reply::Integer(existed as i64))
+            }
+            Some(secs) => engine
+                .expire(key, Duration::from_secs(secs))
+                .map(|set| Reply::Integer(set as i64)),
+        },
+        ("TTL", [key]) => Ok(Reply::Integer(match engine.ttl(key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(left)) => left.as_secs_f64().round() as i64,
+        })),
+        ("KEYS", [pattern]) => Ok(Reply::Array(
+            matching_keys(engine, pattern)
+                .into_iter()
+                .map(|key| Reply::Bulk(Some(key)))
+                .collect(),
+        )),
+        ("SCAN", [cursor, options @ ..]) => {
+            let (Some(offset), Ok((pattern, count))) =
+                (parse_number(cursor), parse_scan_options(options))
+            else {
+                return Reply::err("invalid cursor");
+            };
+            Ok(scan(engine, pattern, offset as usize, count))
+        }
+        _ => unreachable!("arity is checked above"),
+    };
+    result.unwrap_or_else(|e| match e.kind() {
+        io::ErrorKind::InvalidData => Reply::err("value is not an integer or out of range"),
+        _ => Reply::err(e.to_string()),
+    })
+}
+
+/// The `EX <seconds>` or `PX <milliseconds>` option of `SET`.
+fn parse_expiry(options: &[&[u8]]) -> Result<Option<Duration>, Reply> {
+    match options {
+        [] => Ok(None),
+        [unit, amount] => {
+            let amount = parse_number(amount)
+                .filter(|&amount| amount > 0)
+                .ok_or_else(|| Reply::err("invalid expire time in 'set' command"))?;
+            match unit.to_ascii_uppercase().as_slice() {
+                b"EX" => Ok(Some(Duration::from_secs(amount))),
+                b"PX" => Ok(Some(Duration::from_millis(amount))),
+                _ => Err(Reply::err("syntax error")),
+            }
+        }
+        _ => Err(Reply::err("syntax error")),
+    }
+}
+
+/// The `MATCH` pattern (default `*`) and `COUNT` of a `SCAN`.
+fn parse_scan_options<'a>(mut options: &[&'a [u8]]) -> Result<(&'a [u8], usize), Reply> {
+    let mut pattern: &[u8] = b"*";
+    let mut count = DEFAULT_SCAN_COUNT;
+    while let [option, value, rest @ ..] = options {
+        match option.to_ascii_uppercase().as_slice() {
+            b"MATCH" => pattern = value,
+            b"COUNT" => {
+                count = parse_number(value)
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| Reply::err("value is not an integer or out of range"))?
+                    as usize;
+            }
+            _ => return Err(Reply::err("syntax error")),
+        }
+        options = rest;
+    }
+    match options {
+        [] => Ok((pattern, count)),
+        _ => Err(Reply::err("syntax error")),
+    }
+}
+
+fn parse_number(text: &[u8]) -> Option<u64> {
+    std::str::from_utf8(text).ok()?.parse().ok()
+}
+
+/// One `SCAN` step. The cursor counts the keys under the pattern's literal prefix already
+/// visited, so clients that treat it as an integer work; `0` starts and ends the iteration.
+fn scan(engine: &Engine, pattern: &[u8], offset: usize, count: usize) -> Reply {
+    let prefix = literal_prefix(pattern);
+    let page = engine.keys(prefix, None, offset.saturating_add(count));
+    let visited = page.len();
+    let next = if visited < offset.saturating_add(count) {
+        0
+    } else {
+        visited
+    };
+    let keys = page
+        .into_iter()
+        .skip(offset)
+        .filter(|key| glob_match(pattern, key))
+        .map(|key| Reply::Bulk(Some(key)))
+        .collect();
+    Reply::Array(vec![
+        Reply::Bulk(Some(next.to_string().into_bytes())),
+        Reply::Array(keys),
+    ])
+}
+
+/// Every key matching `pattern`, walking only the keys under its literal prefix.
+fn matching_keys(engine: &Engine, pattern: &[u8]) -> Vec<Vec<u8>> {
+    let prefix = literal_prefix(pattern);
+    let mut matches = Vec::new();
+    let mut after: Option<Vec<u8>> = None;
+    loop {
+        let page = engine.keys(prefix, after.as_deref(), KEYS_PAGE);
+        let done = page.len() < KEYS_PAGE;
+        after = page.last().cloned();
+        matches.extend(page.into_iter().filter(|key| glob_match(pattern, key)));
+        if done {
+            return matches;
+        }
+    }
+}
+
+/// The part of a glob pattern before its first special character.
+fn literal_prefix(pattern: &[u8]) -> &[u8] {
+    let end = pattern
+        .iter()
+        .position(|c| matches!(c, b'*' | b'?' | b'[' | b'\\'))
+        .unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Redis-style glob matching: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes. A mismatch only goes
+/// back to the last `*`, so matching takes at most pattern length × key length steps.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the pattern resumes after the last `*`, and where the text it swallows ends so far.
+    let mut star = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some(next) = match_one(pattern, p, text[t]) {
+            p = next;
+            t += 1;
+        } else if let Some((resume, swallowed)) = star {
+            p = resume;
+            t = swallowed + 1;
+            star = Some((resume, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Whether the pattern element at `p`, which is not `*`, matches `c`, and if so where the next
+/// element starts. `None` past the end of the pattern.
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    let rest = pattern.get(p + 1..)?;
+    match pattern[p] {
+        b'?' => Some(p + 1),
+        b'[' => {
+            let Some(close) = rest.iter().skip(1).position(|&b| b == b']') else {
+                // An unterminated class is a literal `[`.
+                return (c == b'[').then_some(p + 1);
+            };
+            let (negated, class) = match rest[..close + 1].split_first() {
+                Some((b'^', class)) => (true, class),
+                _ => (false, &rest[..close + 1]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            (matched != negated).then_some(p + close + 3)
+        }
+        b'\\' if !rest.is_empty() => (rest[0] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
@@ -0,0 +1,408 @@
+use std::io;
+
+use actix_web::body::SizedStream;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{HttpRequest, HttpResponse, error, web};
+use base64::DecodeError;
+use futures_util::{TryStreamExt, future};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::batch;
+use super::encoding::{Encoding, EncodingQuery};
+use super::health;
+
+/// Headers that describe one connection or one encoding of the body, so they are not passed
+/// between the client and a backend. The router compresses its own responses.
+const HOP_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::ACCEPT_ENCODING,
+];
+
+/// Routes that address one key in their path; the router sends them on whole.
+const KEY_ROUTES: &[&str] = &[
+    "/get/{key}",
+    "/del/{key}",
+    "/undelete/{key}",
+    "/ttl/{key}",
+    "/incr/{key}",
+    "/decr/{key}",
+    "/append/{key}",
+    "/cas/{key}",
+    "/lock/{key}",
+    "/exists/{key}",
+    "/kv/{key}",
+    "/history/{key}",
+];
+
+/// Router mode settings. The server serves its own data while `backends` is empty.
+#[derive(Debug, Clone)]
+pub struct RouterSettings {
+    /// Base URLs of the nodes that hold the data.
+    pub backends: Vec<String>,
+    /// Points each backend gets on the hash ring.
+    pub vnodes: u32,
+}
+
+impl RouterSettings {
+    pub fn enabled(&self) -> bool {
+        !self.backends.is_empty()
+    }
+
+    /// The router to serve with, or `None` when router mode is off.
+    pub fn router(&self) -> io::Result<Option<Router>> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        if self.vnodes == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "router_vnodes must be at least 1",
+            ));
+        }
+        Ok(Some(Router::new(&self.backends, self.vnodes)))
+    }
+}
+
+/// Backends placed on a consistent-hashing ring. A key belongs to the backend owning the first
+/// point at or after the key's hash, so adding or removing a backend only moves the keys
+/// between it and its neighbours.
+pub struct Router {
+    backends: Vec<String>,
+    /// `(point, backend)` pairs sorted by point.
+    ring: Vec<(u64, usize)>,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct Keyed {
+    key: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SetItem {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DelResult {
+    deleted: usize,
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let digest = Sha256::digest(bytes);
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+impl Router {
+    /// Places `vnodes` points per backend, hashed from its URL, so every router given the same
+    /// backends sends each key to the same one.
+    pub fn new(backends: &[String], vnodes: u32) -> Self {
+        let backends: Vec<String> = backends
+            .iter()
+            .map(|backend| backend.trim_end_matches('/').to_string())
+            .collect();
+        let mut ring = Vec::with_capacity(backends.len() * vnodes as usize);
+        for (i, backend) in backends.iter().enumerate() {
+            for vnode in 0..vnodes {
+                ring.push((hash(format!("{}#{}", backend, vnode).as_bytes()), i));
+            }
+        }
+        ring.sort_unstable();
+        Router {
+            backends,
+            ring,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn backend_for(&self, key: &[u8]) -> usize {
+        let point = hash(key);
+        let i = self.ring.partition_point(|&(p, _)| p < point);
+        self.ring[i % self.ring.len()].1
+    }
+
+    /// The positions of `keys` that belong to each backend, indexed by backend.
+    fn split<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a str>,
+        encoding: Encoding,
+    ) -> Result<Vec<Vec<usize>>, DecodeError> {
+        let mut parts = vec![Vec::new(); self.backends.len()];
+        for (position, key) in keys.enumerate() {
+            parts[self.backend_for(&encoding.decode(key)?)].push(position);
+        }
+        Ok(parts)
+    }
+
+    /// Sends `body` to `backend` with the method, path, query and headers of `req`, or as JSON
+    /// with `json`.
+    async fn send(
+        &self,
+        backend: usize,
+        req: &HttpRequest,
+        body: Vec<u8>,
+        json: bool,
+    ) -> reqwest::Result<reqwest::Response> {
+        let content_type = json.then_some("application/json");
+        outgoing(&self.http, &self.backends[backend], req, body, content_type)
+            .send()
+            .await
+    }
+
+    /// Sends `req` to `backend` and streams back whatever it answers.
+    async fn forward(&self, backend: usize, req: &HttpRequest, body: web::Bytes) -> HttpResponse {
+        match self.send(backend, req, body.to_vec(), false).await {
+            Ok(response) => relay(response),
+            Err(e) => unreachable_backend(&self.backends[backend], e),
+        }
+    }
+
+    /// Sends `items` to `backend` as the JSON body of `req`, and parses its answer. A backend
+    /// that fails answers for the whole request.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        backend: usize,
+        req: &HttpRequest,
+        items: &impl Serialize,
+    ) -> Result<T, HttpResponse> {
+        let body = serde_json::to_vec(items)
+            .map_err(|e| HttpResponse::InternalServerError().body(e.to_string()))?;
+        let response = self
+            .send(backend, req, body, true)
+            .await
+            .map_err(|e| unreachable_backend(&self.backends[backend], e))?;
+        if !response.status().is_success() {
+            return Err(relay(response));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| unreachable_backend(&self.backends[backend], e))
+    }
+
+    /// Sends each backend's share of a batch to it at once, as `part` builds it from the
+    /// positions of its items, and returns the answers with the positions.
+    async fn fan_out<T: DeserializeOwned>(
+        &self,
+        req: &HttpRequest,
+        parts: Vec<Vec<usize>>,
+        part: impl Fn(&[usize]) -> Value,
+    ) -> Result<Vec<(Vec<usize>, T)>, HttpResponse> {
+        let calls = parts
+            .into_iter()
+            .enumerate()
+            .filter(|(_, positions)| !positions.is_empty())
+            .map(|(backend, positions)| {
+                let items = part(&positions);
+                async move {
+                    let answer = self.call(backend, req, &items).await?;
+                    Ok((positions, answer))
+                }
+            });
+        future::try_join_all(calls).await
+    }
+}
+
+/// A request to the node at `base` with the method, path, query and headers of `req`, carrying
+/// `body`, of `content_type` if given.
+pub fn outgoing(
+    http: &reqwest::Client,
+    base: &str,
+    req: &HttpRequest,
+    body: Vec<u8>,
+    content_type: Option<&str>,
+) -> reqwest::RequestBuilder {
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes())
+        .unwrap_or(reqwest::Method::GET);
+    let mut request = http.request(method, format!("{}{}", base, path)).body(body);
+    for (name, value) in req.headers() {
+        let replaced = content_type.is_some() && name == header::CONTENT_TYPE;
+        if !HOP_HEADERS.contains(name) && !replaced {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+    if let Some(content_type) = content_type {
+        request = request.header("content-type", content_type);
+    }
+    request
+}
+
+/// Passes a backend's answer on to the client, streaming its body.
+pub fn relay(response: reqwest::Response) -> HttpResponse {
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut builder = HttpResponse::build(status);
+    for (name, value) in response.headers() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_str().as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) && !HOP_HEADERS.contains(&name)
+        {
+            builder.append_header((name, value));
+        }
+    }
+    // Read from the header, which also holds for answers to HEAD.
+    let len = response
+        .headers()
+        .get("content-length")
+        .and_then(|len| len.to_str().ok()?.parse().ok());
+    let body = response.bytes_stream().map_err(error::ErrorBadGateway);
+    match len {
+        Some(len) => builder.body(SizedStream::new(len, body)),
+        None => builder.streaming(body),
+    }
+}
+
+fn unreachable_backend(backend: &str, e: reqwest::Error) -> HttpResponse {
+    HttpResponse::BadGateway().body(format!("{}: {}", backend, e))
+}
+
+fn bad_encoding(e: DecodeError) -> HttpResponse {
+    HttpResponse::BadRequest().body(e.to_string())
+}
+
+/// Registers the routes a router serves in place of the usual ones. Everything it does not
+/// forward answers `404`.
+pub fn configure(cfg: &mut web::ServiceConfig, router: web::Data<Router>) {
+    cfg.app_data(router)
+        .route("/health", web::get().to(health::health))
+        .route("/ready", web::get().to(ready))
+        .route("/set", web::post().to(set))
+        .route("/batch/get", web::post().to(batch_get))
+        .route("/batch/set", web::post().to(batch_set))
+        .route("/batch/del", web::post().to(batch_del));
+    for path in KEY_ROUTES {
+        cfg.route(path, web::route().to(key));
+    }
+    cfg.route("/{path:.*}", web::route().to(not_routed));
+}
+
+/// A router is ready as soon as it starts; a backend that is down fails the requests for its
+/// keys with `502`.
+async fn ready() -> HttpResponse {
+    HttpResponse::Ok().body("ready")
+}
+
+async fn not_routed() -> HttpResponse {
+    HttpResponse::NotFound().body("a router only forwards single-key requests and batches")
+}
+
+/// Forwards a request for the key in its path to the backend that owns it.
+async fn key(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    body: web::Bytes,
+    router: web::Data<Router>,
+) -> HttpResponse {
+    let key = match query.encoding.decode(req.match_info().query("key")) {
+        Ok(key) => key,
+        Err(e) => return bad_encoding(e),
+    };
+    router.forward(router.backend_for(&key), &req, body).await
+}
+
+/// Forwards `POST /set` by the `key` in its body.
+async fn set(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    body: web::Bytes,
+    router: web::Data<Router>,
+) -> HttpResponse {
+    let keyed: Keyed = match serde_json::from_slice(&body) {
+        Ok(keyed) => keyed,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let key = match query.encoding.decode(&keyed.key) {
+        Ok(key) => key,
+        Err(e) => return bad_encoding(e),
+    };
+    router.forward(router.backend_for(&key), &req, body).await
+}
+
+async fn batch_get(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    keys: web::Json<Vec<String>>,
+    router: web::Data<Router>,
+) -> HttpResponse {
+    let parts = match router.split(keys.iter().map(String::as_str), query.encoding) {
+        Ok(parts) => parts,
+        Err(e) => return bad_encoding(e),
+    };
+    let part = |positions: &[usize]| positions.iter().map(|&i| keys[i].as_str()).collect();
+    let answers = match router
+        .fan_out::<Vec<Option<String>>>(&req, parts, part)
+        .await
+    {
+        Ok(answers) => answers,
+        Err(response) => return response,
+    };
+    let mut values = vec![None; keys.len()];
+    for (positions, part) in answers {
+        for (i, value) in positions.into_iter().zip(part) {
+            values[i] = value;
+        }
+    }
+    HttpResponse::Ok().json(values)
+}
+
+async fn batch_set(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    body: web::Bytes,
+    router: web::Data<Router>,
+) -> HttpResponse {
+    let items: Vec<SetItem> = match batch::parse_items(&req, &body) {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let parts = match router.split(items.iter().map(|item| item.key.as_str()), query.encoding) {
+        Ok(parts) => parts,
+        Err(e) => return bad_encoding(e),
+    };
+    let part = |positions: &[usize]| {
+        positions
+            .iter()
+            .map(|&i| serde_json::to_value(&items[i]).unwrap_or_default())
+            .collect()
+    };
+    let answers = match router.fan_out::<Vec<Value>>(&req, parts, part).await {
+        Ok(answers) => answers,
+        Err(response) => return response,
+    };
+    let mut results = vec![Value::Null; items.len()];
+    for (positions, part) in answers {
+        for (i, result) in positions.into_iter().zip(part) {
+            results[i] = result;
+        }
+    }
+    HttpResponse::Ok().json(results)
+}
+
+async fn batch_del(
+    req: HttpRequest,
+    query: web::Query<EncodingQuery>,
+    keys: web::Json<Vec<String>>,
+    router: web::Data<Router>,
+) -> HttpResponse {
+    let parts = match router.split(keys.iter().map(String::as_str), query.encoding) {
+        Ok(parts) => parts,
+        Err(e) => return bad_encoding(e),
+    };
+    let part = |positions: &[usize]| positions.iter().map(|&i| keys[i].as_str()).collect();
+    match router.fan_out::<DelResult>(&req, parts, part).await {
+        Ok(answers) => HttpResponse::Ok().json(DelResult {
+            deleted: answers.iter().map(|(_, result)| result.deleted).sum(),
+        }),
+        Err(response) => response,
+    }
+}
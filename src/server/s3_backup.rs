@@ -0,0 +1,277 @@
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use breakout1_kv_store::types::BackupPoint;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::admin;
+use super::state::{AppState, Db};
+
+const MANIFEST: &str = "manifest.json";
+
+/// Remote backup settings. Backups to S3 are off while `bucket` is unset.
+#[derive(Debug, Clone)]
+pub struct S3Settings {
+    /// Base URL of the S3-compatible service, such as `https://s3.us-east-1.amazonaws.com`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// Object key prefix the backup sets are stored under.
+    pub prefix: String,
+    /// Backup sets kept; older ones are deleted after each full backup.
+    pub retain: usize,
+}
+
+impl S3Settings {
+    /// The backup target, or `None` when no bucket is configured.
+    pub fn target(&self) -> io::Result<Option<S3Backups>> {
+        let Some(name) = &self.bucket else {
+            return Ok(None);
+        };
+        let credentials = Credentials::new(
+            self.access_key.as_deref(),
+            self.secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .map_err(io::Error::other)?;
+        let region = Region::Custom {
+            region: self.region.clone(),
+            endpoint: self.endpoint.clone(),
+        };
+        let bucket = Bucket::new(name, region, credentials)
+            .map_err(io::Error::other)?
+            .with_path_style();
+        Ok(Some(S3Backups {
+            bucket,
+            prefix: self.prefix.trim_end_matches('/').to_string(),
+            retain: self.retain.max(1),
+            running: Mutex::new(()),
+        }))
+    }
+}
+
+/// Backup sets in a bucket. A set is the objects under `<prefix>/<unix millis>/`: a full copy
+/// of the log as `000000.db`, the increments taken after it as `000001.db` and on, and a
+/// `manifest.json` recording where the last part ends. Concatenating the parts in order
+/// restores the log.
+pub struct S3Backups {
+    bucket: Box<Bucket>,
+    prefix: String,
+    retain: usize,
+    /// Held by the backup in progress, so two never add to one set at once.
+    running: Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    /// Number of parts in the set.
+    parts: u32,
+    /// Length of the log the parts add up to.
+    len: u64,
+    /// The log's first bytes, base64-encoded, to tell whether it was compacted since.
+    head: String,
+}
+
+#[derive(Deserialize)]
+pub struct S3BackupRequest {
+    /// Upload only what was written since the newest set's last part. A full backup starting
+    /// a new set is taken instead when there is no set yet or the log was compacted since.
+    #[serde(default)]
+    incremental: bool,
+}
+
+#[derive(Serialize)]
+struct S3BackupResponse {
+    set: String,
+    object: String,
+    incremental: bool,
+    bytes: u64,
+    /// Sets deleted by retention pruning.
+    pruned: Vec<String>,
+}
+
+/// Uploads a full or incremental backup of the main log to the configured bucket, then, after
+/// a full one, deletes the oldest sets beyond `s3_retain`.
+pub async fn backup(
+    req: HttpRequest,
+    body: web::Json<S3BackupRequest>,
+    engine: Db,
+    state: web::Data<AppState>,
+    target: Option<web::Data<S3Backups>>,
+) -> HttpResponse {
+    if let Err(response) = admin::authorize(&req, &state) {
+        return response;
+    }
+    let Some(target) = target else {
+        return HttpResponse::NotFound().body("no S3 backup target is configured");
+    };
+    match target.backup(engine, body.incremental).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+impl S3Backups {
+    async fn backup(&self, engine: Db, incremental: bool) -> io::Result<S3BackupResponse> {
+        let _running = self.running.lock().await;
+        let base = match incremental {
+            true => self.newest_set().await?,
+            false => None,
+        };
+        let since = match &base {
+            Some((_, manifest)) => Some(BackupPoint {
+                len: manifest.len,
+                head: STANDARD
+                    .decode(&manifest.head)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            }),
+            None => None,
+        };
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let staging = std::env::temp_dir().join(format!("kv-s3-backup-{}.db", millis));
+        let path = staging.clone();
+        let copied = match web::block(move || -> io::Result<(BackupPoint, bool)> {
+            let mut file = std::fs::File::create(&path)?;
+            if let Some(point) = engine.backup_since(since.as_ref(), &mut file)? {
+                return Ok((point, since.is_some()));
+            }
+            // Compacted since the newest set: start a new one.
+            let mut file = std::fs::File::create(&path)?;
+            let point = engine
+                .backup_since(None, &mut file)?
+                .expect("full copies always succeed");
+            Ok((point, false))
+        })
+        .await
+        {
+            Ok(copied) => copied,
+            Err(e) => Err(io::Error::other(e)),
+        };
+        let uploaded = match copied {
+            Ok((point, incremental)) => {
+                self.upload(&staging, point, incremental, base, millis)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&staging).await;
+        let mut response = uploaded?;
+        if !response.incremental {
+            response.pruned = self.prune().await?;
+        }
+        Ok(response)
+    }
+
+    async fn upload(
+        &self,
+        staging: &Path,
+        point: BackupPoint,
+        incremental: bool,
+        base: Option<(String, Manifest)>,
+        millis: u128,
+    ) -> io::Result<S3BackupResponse> {
+        let (set, part, from) = match (incremental, base) {
+            (true, Some((set, manifest))) => (set, manifest.parts, manifest.len),
+            _ => (format!("{:013}", millis), 0, 0),
+        };
+        let object = format!("{}/{}/{:06}.db", self.prefix, set, part);
+        let mut file = tokio::fs::File::open(staging).await?;
+        self.bucket
+            .put_object_stream(&mut file, &object)
+            .await
+            .map_err(io::Error::other)?;
+        let manifest = Manifest {
+            parts: part + 1,
+            len: point.len,
+            head: STANDARD.encode(&point.head),
+        };
+        self.bucket
+            .put_object(
+                format!("{}/{}/{}", self.prefix, set, MANIFEST),
+                &serde_json::to_vec(&manifest)?,
+            )
+            .await
+            .map_err(io::Error::other)?;
+        Ok(S3BackupResponse {
+            set,
+            object,
+            incremental,
+            bytes: point.len - from,
+            pruned: Vec::new(),
+        })
+    }
+
+    /// Set names, oldest first.
+    async fn sets(&self) -> io::Result<Vec<String>> {
+        let pages = self
+            .bucket
+            .list(format!("{}/", self.prefix), Some("/".to_string()))
+            .await
+            .map_err(io::Error::other)?;
+        let mut sets: Vec<String> = pages
+            .into_iter()
+            .flat_map(|page| page.common_prefixes.unwrap_or_default())
+            .filter_map(|common| {
+                let set = common.prefix.strip_prefix(&format!("{}/", self.prefix))?;
+                Some(set.trim_end_matches('/').to_string())
+            })
+            .collect();
+        sets.sort();
+        Ok(sets)
+    }
+
+    /// The newest set with its manifest, if it has one.
+    async fn newest_set(&self) -> io::Result<Option<(String, Manifest)>> {
+        let Some(set) = self.sets().await?.pop() else {
+            return Ok(None);
+        };
+        let manifest = match self
+            .bucket
+            .get_object(format!("{}/{}/{}", self.prefix, set, MANIFEST))
+            .await
+        {
+            Ok(response) => serde_json::from_slice(response.bytes())?,
+            // Its first upload did not finish.
+            Err(S3Error::HttpFailWithBody(404, _)) => return Ok(None),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        Ok(Some((set, manifest)))
+    }
+
+    /// Deletes every object of the sets beyond the newest `retain`.
+    async fn prune(&self) -> io::Result<Vec<String>> {
+        let mut sets = self.sets().await?;
+        let keep = sets.len().saturating_sub(self.retain);
+        let old: Vec<String> = sets.drain(..keep).collect();
+        for set in &old {
+            let pages = self
+                .bucket
+                .list(format!("{}/{}/", self.prefix, set), None)
+                .await
+                .map_err(io::Error::other)?;
+            for object in pages.into_iter().flat_map(|page| page.contents) {
+                self.bucket
+                    .delete_object(&object.key)
+                    .await
+                    .map_err(io::Error::other)?;
+            }
+        }
+        Ok(old)
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::ops::Bound;
+
+use actix_web::error::BlockingError;
+use actix_web::{HttpResponse, web};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::state::Db;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ScanQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+}
+
+/// One scanned pair. Values that are not valid UTF-8 are sent base64-encoded in `value_base64`
+/// instead of `value`.
+#[derive(Serialize)]
+struct ScanEntry {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_base64: Option<String>,
+}
+
+impl ScanEntry {
+    fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
+        let key = String::from_utf8_lossy(&key).into_owned();
+        match String::from_utf8(value) {
+            Ok(value) => Self {
+                key,
+                value: Some(value),
+                value_base64: None,
+            },
+            Err(e) => Self {
+                key,
+                value: None,
+                value_base64: Some(STANDARD.encode(e.as_bytes())),
+            },
+        }
+    }
+}
+
+/// Returns the key/value pairs under `prefix` as a JSON array in key order.
+pub async fn scan(query: web::Query<ScanQuery>, engine: Db, scope: Scope) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let prefix = query.into_inner().prefix;
+    if let Err(response) = scope.read(prefix.as_bytes()) {
+        return response;
+    }
+
+    respond(web::block(move || engine.scan(prefix.as_bytes(), limit)).await)
+}
+
+#[derive(Deserialize)]
+pub struct RangeQuery {
+    start: Option<String>,
+    after: Option<String>,
+    end: Option<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    reverse: bool,
+}
+
+/// Returns the pairs with keys from `start` (inclusive) or `after` (exclusive) up to `end`
+/// (exclusive). With `reverse=true` the walk starts at `end` and goes down, so the next page is
+/// requested with the last key returned as `end`; going forward it is passed as `after`.
+pub async fn range(query: web::Query<RangeQuery>, engine: Db, scope: Scope) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let RangeQuery {
+        start,
+        after,
+        end,
+        reverse,
+        ..
+    } = query.into_inner();
+    let (lower, upper) = bounds(&start, &after, &end);
+    if let Err(response) = scope.read_range(lower, upper) {
+        return response;
+    }
+
+    respond(
+        web::block(move || {
+            let (lower, upper) = bounds(&start, &after, &end);
+            engine.range(lower, upper, limit, reverse)
+        })
+        .await,
+    )
+}
+
+fn bounds<'a>(
+    start: &'a Option<String>,
+    after: &'a Option<String>,
+    end: &'a Option<String>,
+) -> (Bound<&'a [u8]>, Bound<&'a [u8]>) {
+    let lower = match (after, start) {
+        (Some(after), _) => Bound::Excluded(after.as_bytes()),
+        (None, Some(start)) => Bound::Included(start.as_bytes()),
+        (None, None) => Bound::Unbounded,
+    };
+    let upper = match end {
+        Some(end) => Bound::Excluded(end.as_bytes()),
+        None => Bound::Unbounded,
+    };
+    (lower, upper)
+}
+
+fn respond(result: Result<io::Result<Vec<(Vec<u8>, Vec<u8>)>>, BlockingError>) -> HttpResponse {
+    match result {
+        Ok(Ok(pairs)) => HttpResponse::Ok().json(
+            pairs
+                .into_iter()
+                .map(|(key, value)| ScanEntry::new(key, value))
+                .collect::<Vec<_>>(),
+        ),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
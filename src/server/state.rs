@@ -0,0 +1,118 @@
+use std::future::{Ready, ready};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest, error, web};
+use breakout1_kv_store::{Buckets, Engine};
+
+use super::auth::Auth;
+use super::buckets;
+
+/// Shared server state. The engine is loaded in the background after the server starts
+/// listening, so health checks answer while a large log is still being indexed.
+pub struct AppState {
+    engine: OnceLock<Arc<Engine>>,
+    /// Set before the engine when `bucket_dir` is configured.
+    buckets: OnceLock<Buckets>,
+    started: Instant,
+    auth: Auth,
+    maintenance: AtomicBool,
+}
+
+impl AppState {
+    pub fn new(auth: Auth) -> Self {
+        Self {
+            engine: OnceLock::new(),
+            buckets: OnceLock::new(),
+            started: Instant::now(),
+            auth,
+            maintenance: AtomicBool::new(false),
+        }
+    }
+
+    /// Credentials accepted by `auth::authenticate` and the `/admin` endpoints.
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn engine(&self) -> Option<&Arc<Engine>> {
+        self.engine.get()
+    }
+
+    pub fn set_engine(&self, engine: Engine) {
+        if self.engine.set(Arc::new(engine)).is_err() {
+            panic!("engine loaded twice");
+        }
+    }
+
+    /// `None` while loading or if buckets are not configured.
+    pub fn buckets(&self) -> Option<&Buckets> {
+        self.buckets.get()
+    }
+
+    pub fn set_buckets(&self, buckets: Buckets) {
+        if self.buckets.set(buckets).is_err() {
+            panic!("buckets loaded twice");
+        }
+    }
+
+    /// Whether requests can be served.
+    pub fn is_ready(&self) -> bool {
+        self.engine().is_some()
+    }
+
+    /// Whether only `/admin` requests are served, set through `/admin/maintenance`.
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    pub fn set_maintenance(&self, on: bool) {
+        self.maintenance.store(on, Ordering::SeqCst);
+    }
+}
+
+/// Extracts the loaded engine, or under `/b/{bucket}` that bucket's, answering `503 Service
+/// Unavailable` until it is ready and, outside `/admin`, while the server is in maintenance mode.
+/// Unknown buckets answer `404`.
+#[derive(Clone)]
+pub struct Db(Arc<Engine>);
+
+impl FromRequest for Db {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(state) = req.app_data::<web::Data<AppState>>() else {
+            return ready(Err(error::ErrorServiceUnavailable("engine is not ready")));
+        };
+        if state.in_maintenance() && !buckets::route_path(req.path()).starts_with("/admin/") {
+            return ready(Err(error::ErrorServiceUnavailable(
+                "server is in maintenance mode",
+            )));
+        }
+        ready(match (state.engine(), req.match_info().get("bucket")) {
+            (None, _) => Err(error::ErrorServiceUnavailable("engine is not ready")),
+            (Some(engine), None) => Ok(Db(engine.clone())),
+            (Some(_), Some(name)) => state
+                .buckets()
+                .and_then(|buckets| buckets.get(name))
+                .map(Db)
+                .ok_or_else(|| error::ErrorNotFound("bucket not found")),
+        })
+    }
+}
+
+impl Deref for Db {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        &self.0
+    }
+}
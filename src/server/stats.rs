@@ -0,0 +1,120 @@
+use actix_web::{HttpResponse, Responder, web};
+use breakout1_kv_store::types::{CompactionReport, ReplicationStats};
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::replica::ReplicaStatus;
+use super::state::{AppState, Db};
+
+#[derive(Serialize)]
+struct StatsResponse {
+    keys: usize,
+    file_size: u64,
+    live_bytes: u64,
+    dead_bytes: u64,
+    /// Estimated memory used by the in-memory index.
+    index_bytes: u64,
+    uptime_secs: u64,
+    last_compaction: Option<CompactionSummary>,
+    /// Only on a replica, until it is promoted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replication: Option<ReplicationSummary>,
+}
+
+/// JSON shape of a replica's `ReplicationStats`.
+#[derive(Serialize)]
+struct ReplicationSummary {
+    applied_seq: u64,
+    primary_seq: u64,
+    lag_seqs: u64,
+    records_applied: u64,
+    bytes_applied: u64,
+    connected: bool,
+    reconnects: u64,
+}
+
+impl ReplicationSummary {
+    fn new(stats: ReplicationStats, replica: &ReplicaStatus) -> Self {
+        Self {
+            applied_seq: stats.applied_seq,
+            primary_seq: stats.primary_seq,
+            lag_seqs: stats.lag(),
+            records_applied: stats.records_applied,
+            bytes_applied: stats.bytes_applied,
+            connected: replica.connected(),
+            reconnects: replica.reconnects(),
+        }
+    }
+}
+
+/// JSON shape of a `CompactionReport`.
+#[derive(Serialize)]
+pub struct CompactionSummary {
+    finished_at: i64,
+    duration_ms: u64,
+    bytes_before: u64,
+    bytes_after: u64,
+    keys: usize,
+}
+
+impl From<CompactionReport> for CompactionSummary {
+    fn from(report: CompactionReport) -> Self {
+        Self {
+            finished_at: report.finished_at,
+            duration_ms: report.duration.as_millis() as u64,
+            bytes_before: report.bytes_before,
+            bytes_after: report.bytes_after,
+            keys: report.keys,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Serialize)]
+struct UsageResponse {
+    prefix: String,
+    keys: usize,
+    bytes: u64,
+}
+
+/// Live keys under `prefix` and the bytes of the log they take up. Dead bytes belong to no key,
+/// so they only show up in `/stats`.
+pub async fn du(query: web::Query<UsageQuery>, engine: Db, scope: Scope) -> HttpResponse {
+    let prefix = query.into_inner().prefix;
+    if let Err(response) = scope.read(prefix.as_bytes()) {
+        return response;
+    }
+    let lookup = prefix.clone();
+    match web::block(move || engine.usage(lookup.as_bytes())).await {
+        Ok(usage) => HttpResponse::Ok().json(UsageResponse {
+            prefix,
+            keys: usage.keys,
+            bytes: usage.bytes,
+        }),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+pub async fn stats(
+    engine: Db,
+    state: web::Data<AppState>,
+    replica: Option<web::Data<ReplicaStatus>>,
+) -> impl Responder {
+    let stats = engine.stats();
+    let replica = replica.as_deref().filter(|replica| !replica.stopped());
+    HttpResponse::Ok().json(StatsResponse {
+        keys: stats.keys,
+        file_size: stats.file_size,
+        live_bytes: stats.live_bytes,
+        dead_bytes: stats.dead_bytes,
+        index_bytes: stats.index.bytes,
+        uptime_secs: state.uptime().as_secs(),
+        last_compaction: stats.last_compaction.map(CompactionSummary::from),
+        replication: replica.map(|replica| ReplicationSummary::new(stats.replication, replica)),
+    })
+}
@@ -0,0 +1,102 @@
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::rt::task;
+use actix_web::web::{self, Bytes};
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+
+/// Bytes read from the engine per chunk of a streamed response.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks buffered between the request or response and the blocking engine call.
+const CHANNEL_DEPTH: usize = 8;
+
+/// A response body of `len` bytes fed by a blocking thread that drains a reader, so a large value
+/// goes out a chunk at a time instead of being loaded whole.
+pub struct ReaderBody {
+    len: u64,
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+}
+
+impl ReaderBody {
+    pub fn new(len: u64, mut reader: impl Read + Send + 'static) -> Self {
+        let (tx, chunks) = mpsc::channel(CHANNEL_DEPTH);
+        task::spawn_blocking(move || {
+            let mut buf = vec![0u8; CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => Ok(Bytes::copy_from_slice(&buf[..n])),
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // A send error means the client went away.
+                if tx.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        ReaderBody { len, chunks }
+    }
+}
+
+impl MessageBody for ReaderBody {
+    type Error = io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.len)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().chunks.poll_recv(cx)
+    }
+}
+
+/// A blocking reader over a request body, fed chunk by chunk by `forward`. A body that fails
+/// part way through reads as an error rather than an early end, so a partial value is never
+/// stored.
+pub struct PayloadReader {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current[..n]);
+        self.current = self.current.slice(n..);
+        Ok(n)
+    }
+}
+
+/// Splits a request body into a `PayloadReader` for a blocking engine call and the future that
+/// feeds it. Run the engine call with `web::block` first, then await the future.
+pub fn payload_reader(mut payload: web::Payload) -> (PayloadReader, impl Future<Output = ()>) {
+    let (tx, chunks) = mpsc::channel(CHANNEL_DEPTH);
+    let forward = async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| io::Error::other(e.to_string()));
+            let failed = chunk.is_err();
+            // A send error means the engine stopped reading, e.g. on a failed precondition.
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    };
+    let reader = PayloadReader {
+        chunks,
+        current: Bytes::new(),
+    };
+    (reader, forward)
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+/// A listening socket bound by systemd and passed to the server.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// The sockets passed through socket activation (`LISTEN_FDS`), empty if there are none.
+#[cfg(unix)]
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    use std::env;
+    use std::process;
+
+    // Passed sockets start at this descriptor, after stdin, stdout and stderr.
+    const FIRST_FD: i32 = 3;
+
+    // `LISTEN_PID` keeps a child that inherited the variables from taking the sockets too.
+    let for_us = env::var("LISTEN_PID").is_ok_and(|pid| pid == process::id().to_string());
+    let count = match env::var("LISTEN_FDS") {
+        Ok(count) if for_us => count.parse::<i32>().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("LISTEN_FDS is not a number: {count}"),
+            )
+        })?,
+        _ => return Ok(Vec::new()),
+    };
+    (FIRST_FD..FIRST_FD + count).map(listener).collect()
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    Ok(Vec::new())
+}
+
+#[cfg(unix)]
+fn listener(fd: i32) -> io::Result<Listener> {
+    use std::mem::{self, MaybeUninit};
+    use std::os::fd::FromRawFd;
+
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket {fd} passed by systemd is {what}"),
+        )
+    };
+    let mut kind: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `kind` and `len` describe a buffer the size of the option's value.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            (&mut kind as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if kind != libc::SOCK_STREAM {
+        return Err(invalid("not a stream socket"));
+    }
+    let mut addr = MaybeUninit::<libc::sockaddr_storage>::zeroed();
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // SAFETY: `sockaddr_storage` is large enough for any address.
+    if unsafe { libc::getsockname(fd, addr.as_mut_ptr().cast(), &mut len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: zeroed, then filled in by `getsockname`.
+    let family = i32::from(unsafe { addr.assume_init() }.ss_family);
+    // SAFETY: systemd hands each descriptor to this process alone, and it is wrapped only once.
+    let listener = match family {
+        libc::AF_INET | libc::AF_INET6 => Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+        libc::AF_UNIX => Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+        _ => return Err(invalid("neither TCP nor a unix socket")),
+    };
+    match &listener {
+        Listener::Tcp(listener) => listener.set_nonblocking(true)?,
+        Listener::Unix(listener) => listener.set_nonblocking(true)?,
+    }
+    Ok(listener)
+}
+
+/// Tells systemd about the server's state, such as `READY=1`, when it runs as a `Type=notify`
+/// service; does nothing without `NOTIFY_SOCKET`.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = std::env::var_os("NOTIFY_SOCKET")
+        && let Err(e) = send(&path, state)
+    {
+        tracing::warn!("failed to notify systemd of {}: {}", state, e);
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    // A leading `@` names a socket in Linux's abstract namespace.
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)?
+        }
+        _ => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::io;
+
+use actix_web::{HttpResponse, web};
+use breakout1_kv_store::types::BatchOp;
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::EncodingQuery;
+use super::limits;
+use super::state::Db;
+
+#[derive(Deserialize)]
+pub struct TagPath {
+    tag: String,
+    key: String,
+}
+
+#[derive(Serialize)]
+struct Tagged {
+    keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Deleted {
+    deleted: usize,
+}
+
+/// Adds `key` to `tag` (`204`), whether or not it was in it already.
+pub async fn tag(
+    path: web::Path<TagPath>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let TagPath { tag, key } = path.into_inner();
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+
+    match web::block(move || engine.tag(&key, &tag)).await {
+        Ok(Ok(_)) => HttpResponse::NoContent().finish(),
+        Ok(Err(e)) => tag_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Takes `key` out of `tag` (`204`), or `404` if it was not in it.
+pub async fn untag(
+    path: web::Path<TagPath>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let TagPath { tag, key } = path.into_inner();
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+
+    match web::block(move || engine.untag(&key, &tag)).await {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => HttpResponse::NotFound().body("Key is not tagged"),
+        Ok(Err(e)) => tag_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The live keys tagged `tag`, in ascending order. Every key must be readable with the caller's
+/// credentials, since the tag may group keys from several prefixes.
+pub async fn keys(
+    tag: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let keys = match engine.keys_by_tag(&tag) {
+        Ok(keys) => keys,
+        Err(e) => return tag_error(e),
+    };
+    if let Err(response) = scope.read_all(&keys) {
+        return response;
+    }
+    HttpResponse::Ok().json(Tagged {
+        keys: keys.iter().map(|key| query.encoding.encode(key)).collect(),
+    })
+}
+
+/// Deletes every live key tagged `tag` in one atomic batch and returns how many there were. The
+/// tags stay, so keys written again are back in the group.
+pub async fn delete(tag: web::Path<String>, engine: Db, scope: Scope) -> HttpResponse {
+    let keys = match engine.keys_by_tag(&tag) {
+        Ok(keys) => keys,
+        Err(e) => return tag_error(e),
+    };
+    if let Err(response) = scope.write_all(&keys) {
+        return response;
+    }
+    // The keys the credentials were checked for, rather than `delete_by_tag`, so a key tagged in
+    // the meantime cannot slip past the check.
+    let ops: Vec<_> = keys.into_iter().map(|key| BatchOp::Del { key }).collect();
+    let result = web::block(move || {
+        if ops.is_empty() {
+            Ok(Vec::new())
+        } else {
+            engine.write_batch(&ops)
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Ok(existed)) => HttpResponse::Ok().json(Deleted {
+            deleted: existed.into_iter().filter(|&existed| existed).count(),
+        }),
+        Ok(Err(e)) => limits::write_error(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// `400` for a tag the engine refuses, otherwise as for any write.
+fn tag_error(e: io::Error) -> HttpResponse {
+    match e.kind() {
+        io::ErrorKind::InvalidInput => HttpResponse::BadRequest().body(e.to_string()),
+        _ => limits::write_error(e),
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{error, web};
+use serde::Deserialize;
+use tokio::time;
+use tracing::warn;
+
+use super::buckets;
+
+/// Routes whose work grows with the number of keys they touch.
+const BULK_ROUTES: &[&str] = &[
+    "/scan",
+    "/range",
+    "/keys",
+    "/batch/",
+    "/history/",
+    "/graphql",
+];
+/// Long-lived streams, bodies of any size and admin jobs, which no timeout applies to.
+const UNLIMITED_ROUTES: &[&str] = &[
+    "/watch",
+    "/changes",
+    "/replication/stream",
+    "/kv/",
+    "/admin/",
+];
+
+/// Time limits and the slow-request threshold, shared with `enforce` through app data. `None`
+/// turns each off.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub request: Option<Duration>,
+    /// Limit for scans, listings and batches.
+    pub bulk: Option<Duration>,
+    /// Requests taking at least this long are logged.
+    pub slow: Option<Duration>,
+}
+
+impl Timeouts {
+    fn limit(&self, path: &str) -> Option<Duration> {
+        let path = buckets::route_path(path);
+        if UNLIMITED_ROUTES.iter().any(|route| path.starts_with(route)) {
+            None
+        } else if BULK_ROUTES.iter().any(|route| path.starts_with(route)) {
+            self.bulk
+        } else {
+            self.request
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PrefixQuery {
+    prefix: Option<String>,
+}
+
+/// Answers `503` when a request runs past its route's limit, dropping the handler; engine work
+/// already handed to a blocking thread still finishes there. Requests that time out or exceed
+/// the slow threshold are logged with their path, which holds the key, and any `prefix`.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(timeouts) = req.app_data::<web::Data<Timeouts>>().map(|t| **t) else {
+        return next.call(req).await;
+    };
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let prefix = web::Query::<PrefixQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().prefix);
+
+    let result = match timeouts.limit(&path) {
+        Some(limit) => match time::timeout(limit, next.call(req)).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    method = %method,
+                    path = %path,
+                    prefix = prefix.as_deref(),
+                    limit_ms = limit.as_millis() as u64,
+                    "request timed out"
+                );
+                return Err(error::ErrorServiceUnavailable("request timed out"));
+            }
+        },
+        None => next.call(req).await,
+    };
+
+    let elapsed = started.elapsed();
+    if timeouts.slow.is_some_and(|slow| elapsed >= slow) {
+        warn!(
+            method = %method,
+            path = %path,
+            prefix = prefix.as_deref(),
+            elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+            "slow request"
+        );
+    }
+    result
+}
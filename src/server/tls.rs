@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::pki_types::CertificateDer;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+/// HTTPS settings. The server speaks plain HTTP while `cert` and `key` are unset.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM certificate chain, leaf first.
+    pub cert: Option<PathBuf>,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: Option<PathBuf>,
+    /// PEM CA certificates that client certificates must chain to. When set, clients without a
+    /// valid certificate are refused during the handshake.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    /// The rustls configuration, or `None` for plain HTTP.
+    pub fn server_config(&self) -> io::Result<Option<ServerConfig>> {
+        let (cert, key) = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) if self.client_ca.is_none() => return Ok(None),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "tls_cert and tls_key must be set together, and tls_client_ca needs both",
+                ));
+            }
+        };
+
+        let chain = read_certs(cert)?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+            .ok_or_else(|| invalid(format!("no private key in {}", key.display())))?;
+
+        let builder = ServerConfig::builder();
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(client_ca)? {
+                    roots.add(cert).map_err(io::Error::other)?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .map_err(io::Error::other)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        builder
+            .with_single_cert(chain, key)
+            .map(Some)
+            .map_err(io::Error::other)
+    }
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificates in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
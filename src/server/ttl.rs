@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use actix_web::{HttpResponse, web};
+use serde::{Deserialize, Serialize};
+
+use super::acl::Scope;
+use super::encoding::EncodingQuery;
+use super::state::Db;
+
+#[derive(Deserialize)]
+pub struct ExpireRequest {
+    ttl_secs: u64,
+}
+
+#[derive(Serialize)]
+struct TtlResponse {
+    /// Whole seconds left, rounded up; `null` if the key never expires.
+    ttl_secs: Option<u64>,
+}
+
+/// Makes an existing key expire `ttl_secs` from now. `404` if the key does not exist.
+pub async fn expire(
+    key: web::Path<String>,
+    req: web::Json<ExpireRequest>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.write(&key) {
+        return response;
+    }
+    let ttl = Duration::from_secs(req.ttl_secs);
+
+    match web::block(move || engine.expire(&key, ttl)).await {
+        Ok(Ok(true)) => HttpResponse::Ok().body("OK"),
+        Ok(Ok(false)) => HttpResponse::NotFound().body("Key is not found"),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Time left before a key expires. `404` if the key does not exist.
+pub async fn ttl(
+    key: web::Path<String>,
+    query: web::Query<EncodingQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let key = match query.encoding.decode(&key) {
+        Ok(key) => key,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&key) {
+        return response;
+    }
+
+    match engine.ttl(&key) {
+        Some(ttl) => HttpResponse::Ok().json(TtlResponse {
+            ttl_secs: ttl.map(|ttl| ttl.as_millis().div_ceil(1000) as u64),
+        }),
+        None => HttpResponse::NotFound().body("Key is not found"),
+    }
+}
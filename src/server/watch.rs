@@ -0,0 +1,135 @@
+use std::sync::mpsc::{self as std_mpsc, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse, rt, web};
+use actix_ws::{CloseCode, CloseReason, Message};
+use breakout1_kv_store::types::{Change, ChangeKind};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use super::acl::Scope;
+use super::encoding::Encoding;
+use super::state::Db;
+
+/// Changes buffered between the forwarding thread and the connection.
+const CHANNEL_DEPTH: usize = 64;
+/// How often an idle forwarding thread checks whether its connection is gone.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct WatchQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// JSON shape of a `Change`.
+#[derive(Serialize)]
+pub struct ChangeEvent {
+    seq: u64,
+    op: &'static str,
+    key: String,
+}
+
+impl ChangeEvent {
+    pub fn new(change: Change, encoding: Encoding) -> Self {
+        Self {
+            seq: change.seq,
+            op: op(change.kind),
+            key: encoding.encode(&change.key),
+        }
+    }
+}
+
+/// The `op` field naming a kind of write.
+pub fn op(kind: ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Set => "set",
+        ChangeKind::Del => "del",
+        ChangeKind::Merge => "merge",
+    }
+}
+
+/// Upgrades to a WebSocket that sends a JSON text frame, `{"seq", "op", "key"}`, for every write
+/// to a key under `prefix`. A client that falls too far behind is disconnected with close code
+/// 1013 (try again later).
+pub async fn watch(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<WatchQuery>,
+    engine: Db,
+    scope: Scope,
+) -> HttpResponse {
+    let encoding = query.encoding;
+    let prefix = match encoding.decode(&query.prefix) {
+        Ok(prefix) => prefix,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(response) = scope.read(&prefix) {
+        return response;
+    }
+    let (response, mut session, mut messages) = match actix_ws::handle(&req, body) {
+        Ok(parts) => parts,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+    let mut changes = forward(engine.watch(&prefix));
+
+    rt::spawn(async move {
+        loop {
+            tokio::select! {
+                change = changes.recv() => {
+                    let Some(change) = change else {
+                        let reason = CloseReason {
+                            code: CloseCode::Again,
+                            description: Some("fell too far behind".to_string()),
+                        };
+                        let _ = session.close(Some(reason)).await;
+                        return;
+                    };
+                    let frame = serde_json::to_string(&ChangeEvent::new(change, encoding))
+                        .expect("change events serialize");
+                    if session.text(frame).await.is_err() {
+                        return;
+                    }
+                }
+                message = messages.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        let _ = session.close(None).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    });
+    response
+}
+
+/// Moves changes from an engine subscription onto an async channel, from a thread of its own
+/// since the subscription blocks. The channel closes if the subscription is dropped for falling
+/// behind, and the thread ends once the channel's receiver is gone.
+pub fn forward(subscription: std_mpsc::Receiver<Change>) -> mpsc::Receiver<Change> {
+    let (tx, changes) = mpsc::channel(CHANNEL_DEPTH);
+    thread::spawn(move || {
+        loop {
+            match subscription.recv_timeout(POLL_INTERVAL) {
+                Ok(change) => {
+                    if tx.blocking_send(change).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) if !tx.is_closed() => {}
+                Err(_) => break,
+            }
+        }
+    });
+    changes
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+
+use tracing::warn;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use web_time::Instant;
+
+use crate::constants::SLOW_LOG_CAPACITY;
+use crate::engine::now_millis;
+use crate::types::{SlowOp, SlowOpKind};
+
+/// The most recent `SLOW_LOG_CAPACITY` operations that took at least the threshold, oldest
+/// first. Each is also logged as a tracing event at warn level.
+#[derive(Debug)]
+pub struct SlowLog {
+    threshold: Duration,
+    ops: Mutex<VecDeque<SlowOp>>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            ops: Mutex::new(VecDeque::with_capacity(SLOW_LOG_CAPACITY)),
+        }
+    }
+
+    /// Keeps the operation `timer` has been timing if it took at least the threshold.
+    pub fn record(&self, timer: OpTimer, key_len: Option<usize>, value_len: Option<u64>) {
+        let Some(started) = timer.started else {
+            return;
+        };
+        let duration = started.elapsed();
+        if duration < self.threshold {
+            return;
+        }
+        let op = SlowOp {
+            kind: timer.kind,
+            at: now_millis(),
+            key_len,
+            value_len,
+            duration,
+            phases: timer.phases,
+        };
+        warn!(
+            op = ?op.kind,
+            key_len = op.key_len,
+            value_len = op.value_len,
+            duration_us = op.duration.as_micros() as u64,
+            phases = ?op.phases,
+            "slow engine operation"
+        );
+
+        let mut ops = self.ops.lock().unwrap();
+        if ops.len() == SLOW_LOG_CAPACITY {
+            ops.pop_front();
+        }
+        ops.push_back(op);
+    }
+
+    pub fn ops(&self) -> Vec<SlowOp> {
+        self.ops.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.ops.lock().unwrap().clear();
+    }
+}
+
+/// Times one operation phase by phase. Inert, and never reads the clock, when the engine keeps
+/// no slow log and has no observer.
+#[derive(Debug)]
+pub struct OpTimer {
+    kind: SlowOpKind,
+    started: Option<Instant>,
+    /// End of the last phase.
+    mark: Option<Instant>,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl OpTimer {
+    pub fn start(kind: SlowOpKind, enabled: bool) -> Self {
+        let now = enabled.then(Instant::now);
+        Self {
+            kind,
+            started: now,
+            mark: now,
+            phases: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> SlowOpKind {
+        self.kind
+    }
+
+    /// Time since the operation started; zero for an inert timer.
+    pub fn elapsed(&self) -> Duration {
+        self.started
+            .map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    /// Ends a phase called `name`, adding to an earlier one of the same name.
+    pub fn phase(&mut self, name: &'static str) {
+        let Some(mark) = self.mark else {
+            return;
+        };
+        let now = Instant::now();
+        let took = now - mark;
+        self.mark = Some(now);
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += took,
+            None => self.phases.push((name, took)),
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// An open handle on a log or on compaction's scratch log. Handles opened for appending write at
+/// the end whatever their position, like a file opened with `O_APPEND`.
+pub trait LogFile: Read + Write + Seek + Send + Sync {
+    /// Current length in bytes.
+    fn size(&self) -> io::Result<u64>;
+    /// Truncates to `len` bytes, dropping a partly written record.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+    /// Forces everything written so far to stable storage.
+    fn sync_all(&self) -> io::Result<()>;
+    /// Like `sync_all`, but may skip metadata that is not needed to read the data back.
+    fn sync_data(&self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Where an engine keeps its log. The engine holds one appending handle, opens read handles as
+/// it needs them, and compacts by writing a scratch log and swapping it in.
+pub trait Storage: Send + Sync {
+    /// Opens the log for reading and appending, creating it empty if it does not exist.
+    fn open_log(&self) -> io::Result<Box<dyn LogFile>>;
+    /// Opens a read handle on the log. It keeps reading the log it opened after `replace_log`.
+    fn open_reader(&self) -> io::Result<Box<dyn LogFile>>;
+    /// Creates an empty scratch log for compaction, replacing any left by an earlier attempt.
+    fn create_temp(&self) -> io::Result<Box<dyn LogFile>>;
+    /// Atomically makes the scratch log the log.
+    fn replace_log(&self) -> io::Result<()>;
+    /// Bytes free on the file system holding the log, or `None` if there is none to ask.
+    fn free_space(&self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+impl LogFile for File {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// A log in a file on disk, compacted through a `.tmp` file next to it. What `Engine::load`
+/// uses.
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileStorage {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.path.with_extension("tmp")
+    }
+}
+
+impl Storage for FileStorage {
+    fn open_log(&self) -> io::Result<Box<dyn LogFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        Ok(Box::new(file))
+    }
+
+    fn open_reader(&self) -> io::Result<Box<dyn LogFile>> {
+        Ok(Box::new(File::open(&self.path)?))
+    }
+
+    fn create_temp(&self) -> io::Result<Box<dyn LogFile>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.temp_path())?;
+        Ok(Box::new(file))
+    }
+
+    fn replace_log(&self) -> io::Result<()> {
+        fs::rename(self.temp_path(), &self.path)
+    }
+
+    // The widths of the `statvfs` fields differ between platforms.
+    #[cfg(unix)]
+    #[allow(clippy::unnecessary_cast)]
+    fn free_space(&self) -> io::Result<Option<u64>> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(self.path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // `path` is NUL-terminated and `stat` is only read once `statvfs` has filled it in.
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stat = unsafe { stat.assume_init() };
+        // Blocks available to unprivileged users, so space reserved for root does not count.
+        Ok(Some(
+            (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64),
+        ))
+    }
+}
+
+/// A log held in memory, for tests, browsers and other places without a file system. Clones
+/// share the same log, so one can be kept to read the bytes back with `contents`, for example
+/// to persist them to IndexedDB, and a later engine loaded from them with `from_bytes`.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    logs: Arc<Mutex<MemoryLogs>>,
+}
+
+#[derive(Default)]
+struct MemoryLogs {
+    log: Arc<RwLock<Vec<u8>>>,
+    temp: Option<Arc<RwLock<Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Storage whose log starts out as `bytes`, such as a copy of a log file.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        MemoryStorage {
+            logs: Arc::new(Mutex::new(MemoryLogs {
+                log: Arc::new(RwLock::new(bytes)),
+                temp: None,
+            })),
+        }
+    }
+
+    /// A copy of the log as it is now. It loads like any log file.
+    pub fn contents(&self) -> Vec<u8> {
+        self.logs.lock().unwrap().log.read().unwrap().clone()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn open_log(&self) -> io::Result<Box<dyn LogFile>> {
+        let log = self.logs.lock().unwrap().log.clone();
+        Ok(Box::new(MemoryFile::new(log, true)))
+    }
+
+    fn open_reader(&self) -> io::Result<Box<dyn LogFile>> {
+        let log = self.logs.lock().unwrap().log.clone();
+        Ok(Box::new(MemoryFile::new(log, false)))
+    }
+
+    fn create_temp(&self) -> io::Result<Box<dyn LogFile>> {
+        let temp = Arc::new(RwLock::new(Vec::new()));
+        self.logs.lock().unwrap().temp = Some(temp.clone());
+        Ok(Box::new(MemoryFile::new(temp, false)))
+    }
+
+    fn replace_log(&self) -> io::Result<()> {
+        let mut logs = self.logs.lock().unwrap();
+        logs.log = logs
+            .temp
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no scratch log to swap in"))?;
+        Ok(())
+    }
+}
+
+/// A handle on one in-memory log, with its own position.
+struct MemoryFile {
+    data: Arc<RwLock<Vec<u8>>>,
+    pos: u64,
+    append: bool,
+}
+
+impl MemoryFile {
+    fn new(data: Arc<RwLock<Vec<u8>>>, append: bool) -> Self {
+        MemoryFile {
+            data,
+            pos: 0,
+            append,
+        }
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.read().unwrap();
+        let start = (self.pos as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.write().unwrap();
+        if self.append {
+            self.pos = data.len() as u64;
+        }
+        let start = self.pos as usize;
+        if data.len() < start {
+            data.resize(start, 0);
+        }
+        let overlap = buf.len().min(data.len() - start);
+        data[start..start + overlap].copy_from_slice(&buf[..overlap]);
+        data.extend_from_slice(&buf[overlap..]);
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (0, n as i64),
+            SeekFrom::End(n) => (self.data.read().unwrap().len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        self.pos = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+impl LogFile for MemoryFile {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.data.write().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,14 +1,342 @@
-use wincode::{SchemaRead, SchemaWrite};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-#[derive(SchemaWrite, SchemaRead, Debug)]
+/// Small string-to-string map stored in a value's record, such as its content type.
+pub type Metadata = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Default)]
 pub struct DataFileEntry {
     pub tstamp: i64,
+    /// Position of the write in the engine's total order of writes. Starts at 1; 0 means the
+    /// record predates sequence numbers.
+    pub seq: u64,
     pub key: Vec<u8>,
     pub value: Option<Vec<u8>>,
+    /// The value is a merge operand to fold into the key's current value rather than replace it.
+    pub merge: bool,
+    pub chunk: ChunkRole,
+    /// Only stored on the record the index points at (the last piece of a chunked value).
+    pub meta: Metadata,
+    /// Milliseconds since the Unix epoch after which the value is gone. Stored like `meta`.
+    pub expires_at: Option<i64>,
+}
+
+/// Where a record sits within a value split across several records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChunkRole {
+    #[default]
+    Whole,
+    /// A leading piece; the value continues in later records for the same key.
+    Piece,
+    /// The final piece, preceded by this many `Piece` records.
+    Last(u64),
+}
+
+/// A value read together with its metadata and version, as returned by `Engine::get_versioned`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned {
+    pub value: Vec<u8>,
+    pub meta: Metadata,
+    /// Sequence number of the newest write to the key.
+    pub seq: u64,
+}
+
+/// What `Engine::value_info` reports about a value without reading it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueInfo {
+    /// Length of the value in bytes.
+    pub len: u64,
+    pub meta: Metadata,
+    /// Sequence number of the newest write to the key.
+    pub seq: u64,
+}
+
+/// A lock taken with `Engine::acquire`, as returned by `Engine::lease`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub owner: String,
+    /// Fencing token handed out when the lock was taken, kept across renewals.
+    pub token: u64,
+    /// Milliseconds since the Unix epoch.
+    pub expires_at: i64,
+}
+
+/// A write delivered to the subscribers of `Engine::watch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub seq: u64,
+    pub key: Vec<u8>,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Set,
+    Del,
+    /// A merge operand was appended; read the key for the folded value.
+    Merge,
+}
+
+/// One write to a key, as returned by `Engine::history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub tstamp: i64,
+    pub kind: ChangeKind,
+    /// The value set or the merge operand; `None` for a delete.
+    pub value: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Clone)]
+/// A point in the past for `Engine::get_as_of`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// After the write with this sequence number.
+    Seq(u64),
+    /// At this many milliseconds since the Unix epoch.
+    Time(i64),
+}
+
+/// A write made on another engine, for `Engine::apply_remote`. `seq` and `node` are its version:
+/// the sequence number it got where it was made, and that engine's `node_id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteEntry {
+    pub key: Vec<u8>,
+    /// `None` for a delete.
+    pub value: Option<Vec<u8>>,
+    pub seq: u64,
+    pub node: u64,
+}
+
+/// One write applied by `Engine::write_batch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Del { key: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LogIndex {
     pub pos: u64,
     pub len: u64,
 }
+
+/// A key's entry in the index, as returned by `Engine::index_entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedKey {
+    pub key: Vec<u8>,
+    /// The record reads start from: the value, or its last piece. `None` when the key only has
+    /// merge operands.
+    pub record: Option<LogIndex>,
+    /// Sequence number in the head of `record`.
+    pub seq: Option<u64>,
+    /// Leading pieces of a chunked value, in order.
+    pub pieces: Vec<LogIndex>,
+    /// Merge operands appended since the value, oldest first.
+    pub operands: Vec<LogIndex>,
+    /// Milliseconds since the Unix epoch; the key may already have expired.
+    pub expires_at: Option<i64>,
+}
+
+/// Approximate traffic for one key, as reported by `Engine::hot_keys`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotKey {
+    pub key: Vec<u8>,
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// An engine operation timed by the slow-operation log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SlowOpKind {
+    Get,
+    Set,
+    Del,
+    Compact,
+    /// Rebuilding the index while the engine loads.
+    Rebuild,
+}
+
+/// An operation that took at least `EngineOptions::slow_op_threshold`, from `Engine::slow_ops`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowOp {
+    pub kind: SlowOpKind,
+    /// Milliseconds since the Unix epoch when it finished.
+    pub at: i64,
+    /// `None` for compactions and rebuilds.
+    pub key_len: Option<usize>,
+    /// `None` for deletes, gets of missing keys, compactions and rebuilds.
+    pub value_len: Option<u64>,
+    pub duration: Duration,
+    /// Where the time went, in the order the phases first ran, such as `lock` (waiting for the
+    /// index or write lock) and `read` for a get. Phases repeated in a loop are added up.
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+/// Outcome of one compaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Milliseconds since the Unix epoch.
+    pub finished_at: i64,
+    pub duration: Duration,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Live keys written to the new log.
+    pub keys: usize,
+}
+
+/// What `Engine::verify` found checking the log and the index against each other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Records read and decoded.
+    pub records: u64,
+    /// Bytes of log up to the end of the last whole record.
+    pub bytes: u64,
+    /// Bytes after that: a write cut short by a crash, or the rest of the log after a corrupt
+    /// length prefix.
+    pub unread_bytes: u64,
+    pub corrupt: Vec<CorruptRecord>,
+    /// Index entries that disagree with the log.
+    pub orphaned: Vec<OrphanedEntry>,
+    /// Keys with a live value in the log that are missing from the index.
+    pub missing: Vec<Vec<u8>>,
+}
+
+impl VerifyReport {
+    /// Whether nothing is corrupt and the index matches the log. Unread bytes alone are not a
+    /// fault; loading leaves a torn write in place.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty() && self.orphaned.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// A record `Engine::verify` could not decode, or whose checksum does not match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRecord {
+    /// Log offset of the payload, or of the length prefix when that is what is corrupt.
+    pub pos: u64,
+    pub reason: String,
+}
+
+/// An index entry `Engine::verify` found disagreeing with the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanedEntry {
+    pub key: Vec<u8>,
+    pub reason: String,
+}
+
+/// Outcome of importing keys from another store, such as `Engine::import_redis`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Keys written.
+    pub keys: usize,
+    /// Keys left out because their TTL had already run out.
+    pub expired: usize,
+    /// Values, keys or commands the import cannot represent, such as non-string Redis values.
+    pub skipped: usize,
+}
+
+/// Where a copy of the log made by `Engine::backup_since` ends: its length and first bytes.
+/// Compaction always rewrites the first bytes, so while they match, the log still extends the
+/// copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupPoint {
+    pub len: u64,
+    /// The first `BACKUP_HEAD_LEN` bytes, or all of a shorter log.
+    pub head: Vec<u8>,
+}
+
+/// How far a replica has read a primary's log, from `Engine::replication_cursor` and moved on by
+/// `Engine::read_replication`. Like `BackupPoint`, it notices compaction by the log's first
+/// bytes changing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationCursor {
+    /// Sequence number of the last write read.
+    pub seq: u64,
+    /// Log offset of the next record to read.
+    pub pos: u64,
+    pub head: Vec<u8>,
+}
+
+/// Point-in-time figures returned by `Engine::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineStats {
+    pub keys: usize,
+    pub file_size: u64,
+    /// Bytes of the log still referenced by the index.
+    pub live_bytes: u64,
+    /// Bytes compaction would reclaim: overwritten values, tombstones and torn writes.
+    pub dead_bytes: u64,
+    pub last_compaction: Option<CompactionReport>,
+    pub replication: ReplicationStats,
+    pub disk: DiskStats,
+    pub index: IndexStats,
+}
+
+/// What an engine has applied from a primary with `Engine::apply_replicated` and
+/// `Engine::replicate_from` since it loaded. On a primary the counts are zero and both sequence
+/// numbers are its own `last_sequence`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicationStats {
+    /// Sequence number of the last write applied; the engine's `last_sequence`.
+    pub applied_seq: u64,
+    /// The newest sequence number the primary is known to have reached, from the records and
+    /// heartbeats received.
+    pub primary_seq: u64,
+    pub records_applied: u64,
+    /// Framed bytes of the records applied.
+    pub bytes_applied: u64,
+}
+
+impl ReplicationStats {
+    /// Writes the primary has that have yet to be applied, as far as is known.
+    pub fn lag(&self) -> u64 {
+        self.primary_seq.saturating_sub(self.applied_seq)
+    }
+}
+
+/// Free space for the log against `EngineOptions::min_free_space`, from `Engine::disk_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskStats {
+    /// `None` when the storage has no file system or it could not be asked.
+    pub free_bytes: Option<u64>,
+    /// `EngineOptions::min_free_space`.
+    pub min_free_bytes: Option<u64>,
+    /// Writes refused for want of space since the engine loaded.
+    pub rejected_writes: u64,
+}
+
+impl DiskStats {
+    /// Whether free space is below the minimum, so writes are being refused.
+    pub fn low(&self) -> bool {
+        matches!((self.free_bytes, self.min_free_bytes), (Some(free), Some(min)) if free < min)
+    }
+}
+
+/// Memory the in-memory index uses against `EngineOptions::max_index_memory`, from
+/// `Engine::index_stats`. The figure is an estimate from key lengths and entry counts, not from
+/// the allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    pub bytes: u64,
+    /// `EngineOptions::max_index_memory`.
+    pub max_bytes: Option<u64>,
+    /// Writes of new keys refused since the engine loaded because the index was full.
+    pub rejected_writes: u64,
+}
+
+/// Live keys under a prefix and the log bytes they take up; returned by `Engine::usage`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixUsage {
+    pub keys: usize,
+    /// Framed record bytes, including value pieces and pending merge operands.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub bytes_scanned: u64,
+    pub total_bytes: u64,
+    pub entries: u64,
+    pub done: bool,
+}
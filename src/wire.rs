@@ -0,0 +1,150 @@
+use std::io::{self, Read};
+
+/// Request op codes.
+pub const OP_AUTH: u8 = 0;
+pub const OP_GET: u8 = 1;
+pub const OP_SET: u8 = 2;
+pub const OP_DEL: u8 = 3;
+
+/// Response status codes.
+pub const STATUS_OK: u8 = 0;
+pub const STATUS_NOT_FOUND: u8 = 1;
+pub const STATUS_ERROR: u8 = 2;
+
+/// Bytes of the length prefix in front of every frame.
+pub const FRAME_PREFIX_SIZE: usize = 4;
+
+/// A request of the binary protocol served on `binary_bind`. Integers are big-endian.
+///
+/// Frame: `[u32 length][u8 op][u32 key length][key][value]`, where the length counts the bytes
+/// after itself and the value runs to the end of the frame. `Auth` carries the credential in
+/// the key, and only `Set` has a value. Responses come back in request order, so a client may
+/// send many requests before reading any response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Auth(Vec<u8>),
+    Get(Vec<u8>),
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Del(Vec<u8>),
+}
+
+/// A response of the binary protocol.
+///
+/// Frame: `[u32 length][u8 status][payload]`. The payload is the value of an `Ok` answer to
+/// `Get`, empty for other `Ok` answers and `NotFound`, and a UTF-8 message for `Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ok(Vec<u8>),
+    NotFound,
+    Error(String),
+}
+
+impl Request {
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let (op, key, value): (u8, &[u8], &[u8]) = match self {
+            Request::Auth(credential) => (OP_AUTH, credential, &[]),
+            Request::Get(key) => (OP_GET, key, &[]),
+            Request::Set { key, value } => (OP_SET, key, value),
+            Request::Del(key) => (OP_DEL, key, &[]),
+        };
+        let mut body = Vec::with_capacity(5 + key.len() + value.len());
+        body.push(op);
+        body.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        body.extend_from_slice(key);
+        body.extend_from_slice(value);
+        frame(body)
+    }
+
+    /// Parses a frame body, the bytes after the length prefix.
+    pub fn decode(body: &[u8]) -> io::Result<Self> {
+        let (&op, rest) = body.split_first().ok_or_else(|| invalid("empty frame"))?;
+        let (len, rest) = rest
+            .split_first_chunk::<4>()
+            .ok_or_else(|| invalid("frame too short for a key length"))?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(invalid("key runs past the end of the frame"));
+        }
+        let (key, value) = rest.split_at(len);
+        let no_value = |request: Request| match value.is_empty() {
+            true => Ok(request),
+            false => Err(invalid("only set carries a value")),
+        };
+        match op {
+            OP_AUTH => no_value(Request::Auth(key.to_vec())),
+            OP_GET => no_value(Request::Get(key.to_vec())),
+            OP_SET => Ok(Request::Set {
+                key: key.to_vec(),
+                value: value.to_vec(),
+            }),
+            OP_DEL => no_value(Request::Del(key.to_vec())),
+            op => Err(invalid(&format!("unknown op {}", op))),
+        }
+    }
+}
+
+impl Response {
+    /// The whole frame, length prefix included.
+    pub fn encode(&self) -> Vec<u8> {
+        let (status, payload): (u8, &[u8]) = match self {
+            Response::Ok(payload) => (STATUS_OK, payload),
+            Response::NotFound => (STATUS_NOT_FOUND, &[]),
+            Response::Error(message) => (STATUS_ERROR, message.as_bytes()),
+        };
+        let mut body = Vec::with_capacity(1 + payload.len());
+        body.push(status);
+        body.extend_from_slice(payload);
+        frame(body)
+    }
+
+    /// Parses a frame body, the bytes after the length prefix.
+    pub fn decode(body: &[u8]) -> io::Result<Self> {
+        match body.split_first() {
+            Some((&STATUS_OK, payload)) => Ok(Response::Ok(payload.to_vec())),
+            Some((&STATUS_NOT_FOUND, [])) => Ok(Response::NotFound),
+            Some((&STATUS_ERROR, message)) => Ok(Response::Error(
+                String::from_utf8_lossy(message).into_owned(),
+            )),
+            _ => Err(invalid("malformed response frame")),
+        }
+    }
+}
+
+/// Reads one frame body, or `None` if the stream ends before it starts. Frames longer than
+/// `max_len` are refused before their body is read.
+pub fn read_frame(reader: &mut impl Read, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; FRAME_PREFIX_SIZE];
+    match reader.read_exact(&mut prefix) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = frame_len(prefix, max_len)?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// The body length announced by a frame's prefix, if it is at most `max_len`.
+pub fn frame_len(prefix: [u8; FRAME_PREFIX_SIZE], max_len: usize) -> io::Result<usize> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!("frame of {} bytes is over the limit of {}", len, max_len),
+        ));
+    }
+    Ok(len)
+}
+
+fn frame(body: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_PREFIX_SIZE + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    frame
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
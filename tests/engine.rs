@@ -432,3 +432,2605 @@ fn test_concurrent_writes_and_deletes() {
     engine.set(b"final", b"test").unwrap();
     assert_eq!(engine.get(b"final").unwrap(), Some(b"test".to_vec()));
 }
+
+// ==================== On-disk Format Tests ====================
+
+fn v1_record(key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&0i64.to_le_bytes());
+    payload.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    payload.extend_from_slice(key);
+    match value {
+        Some(v) => {
+            payload.push(1);
+            payload.extend_from_slice(&(v.len() as u64).to_le_bytes());
+            payload.extend_from_slice(v);
+        }
+        None => payload.push(0),
+    }
+
+    let mut record = (payload.len() as u64).to_le_bytes().to_vec();
+    record.extend_from_slice(&payload);
+    record
+}
+
+#[test]
+fn test_small_entries_use_varint_framing() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+    let engine = Engine::load(&path).unwrap();
+
+    let before = fs::metadata(&path).unwrap().len();
+    engine.set(b"k", b"12345678").unwrap();
+    let record_size = fs::metadata(&path).unwrap().len() - before;
+
+    // length, flags, tstamp (at most 10 bytes), key length, key, sequence, value length, value
+    assert!(record_size <= 1 + 1 + 10 + 1 + 1 + 1 + 1 + 8);
+}
+
+#[test]
+fn test_v1_log_is_upgraded_on_load() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+
+    let mut legacy = Vec::new();
+    legacy.extend(v1_record(b"a", Some(b"1")));
+    legacy.extend(v1_record(b"b", Some(b"2")));
+    legacy.extend(v1_record(b"a", None));
+    legacy.extend(v1_record(b"c", Some(b"3")));
+    fs::write(&path, &legacy).unwrap();
+
+    {
+        let engine = Engine::load(&path).unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), None);
+        assert_eq!(engine.get(b"b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get(b"c").unwrap(), Some(b"3".to_vec()));
+        engine.set(b"d", b"4").unwrap();
+    }
+
+    let on_disk = fs::read(&path).unwrap();
+    assert!(on_disk.starts_with(breakout1_kv_store::constants::FORMAT_V2_MAGIC));
+
+    let engine = Engine::load(&path).unwrap();
+    assert_eq!(engine.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(engine.get(b"d").unwrap(), Some(b"4".to_vec()));
+}
+
+#[test]
+fn test_index_insert_get_remove() {
+    use breakout1_kv_store::index::Index;
+    use breakout1_kv_store::types::LogIndex;
+
+    let mut index = Index::new();
+    index.insert(b"a".to_vec(), LogIndex { pos: 8, len: 4 });
+    index.insert(&b"b"[..], LogIndex { pos: 12, len: 4 });
+    assert_eq!(index.len(), 2);
+    assert_eq!(index.get(b"a").unwrap().pos, 8);
+
+    index.remove(b"a");
+    assert!(index.get(b"a").is_none());
+    assert!(index.contains_key(b"b"));
+}
+
+#[test]
+fn test_parallel_rebuild_matches_log_order() {
+    use breakout1_kv_store::EngineOptions;
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+
+    {
+        let engine = Engine::load_with_threshold(&path, u64::MAX).unwrap();
+        for round in 0..3u32 {
+            for i in 0..500u32 {
+                engine
+                    .set(format!("key{}", i).as_bytes(), &round.to_le_bytes())
+                    .unwrap();
+            }
+        }
+        for i in (0..500u32).step_by(3) {
+            engine.del(format!("key{}", i).as_bytes()).unwrap();
+        }
+    }
+
+    let engine = Engine::load_with_options(
+        &path,
+        EngineOptions {
+            compact_threshold: u64::MAX,
+            rebuild_threads: 4,
+            ..EngineOptions::default()
+        },
+    )
+    .unwrap();
+
+    for i in 0..500u32 {
+        let expected = (i % 3 != 0).then(|| 2u32.to_le_bytes().to_vec());
+        assert_eq!(
+            engine.get(format!("key{}", i).as_bytes()).unwrap(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_load_progress_reports_completion() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::LoadProgress;
+    use std::sync::Mutex;
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+
+    {
+        let engine = Engine::load(&path).unwrap();
+        for i in 0..10u32 {
+            engine.set(format!("key{}", i).as_bytes(), b"v").unwrap();
+        }
+    }
+
+    let seen: Arc<Mutex<Vec<LoadProgress>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&seen);
+    let options = EngineOptions {
+        on_load_progress: Some(Arc::new(move |p: &LoadProgress| {
+            sink.lock().unwrap().push(*p)
+        })),
+        ..EngineOptions::default()
+    };
+    Engine::load_with_options(&path, options).unwrap();
+
+    let seen = seen.lock().unwrap();
+    let last = seen.last().unwrap();
+    assert!(last.done);
+    assert_eq!(last.entries, 10);
+    assert_eq!(last.bytes_scanned, last.total_bytes);
+}
+
+// ==================== Merge Operator Tests ====================
+
+fn counter_engine(path: &std::path::Path) -> Engine {
+    use breakout1_kv_store::EngineOptions;
+
+    let add = |_key: &[u8], existing: Option<&[u8]>, operand: &[u8]| {
+        let current = existing.map_or(0, |v| u64::from_le_bytes(v.try_into().unwrap()));
+        let delta = u64::from_le_bytes(operand.try_into().unwrap());
+        (current + delta).to_le_bytes().to_vec()
+    };
+    let options = EngineOptions {
+        merge_operator: Some(Arc::new(add)),
+        ..EngineOptions::default()
+    };
+    Engine::load_with_options(path, options).unwrap()
+}
+
+#[test]
+fn test_merge_folds_operands_on_read() {
+    let file = NamedTempFile::new().unwrap();
+    let engine = counter_engine(file.path());
+
+    engine.merge(b"hits", &2u64.to_le_bytes()).unwrap();
+    engine.merge(b"hits", &3u64.to_le_bytes()).unwrap();
+    assert_eq!(
+        engine.get(b"hits").unwrap(),
+        Some(5u64.to_le_bytes().to_vec())
+    );
+
+    engine.set(b"hits", &10u64.to_le_bytes()).unwrap();
+    engine.merge(b"hits", &1u64.to_le_bytes()).unwrap();
+    assert_eq!(
+        engine.get(b"hits").unwrap(),
+        Some(11u64.to_le_bytes().to_vec())
+    );
+
+    engine.del(b"hits").unwrap();
+    assert_eq!(engine.get(b"hits").unwrap(), None);
+}
+
+#[test]
+fn test_merge_survives_reload_and_compaction() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+
+    {
+        let engine = counter_engine(&path);
+        for _ in 0..10 {
+            engine.merge(b"hits", &1u64.to_le_bytes()).unwrap();
+        }
+    }
+
+    let engine = counter_engine(&path);
+    assert_eq!(
+        engine.get(b"hits").unwrap(),
+        Some(10u64.to_le_bytes().to_vec())
+    );
+
+    engine.compact().unwrap();
+    engine.merge(b"hits", &5u64.to_le_bytes()).unwrap();
+    assert_eq!(
+        engine.get(b"hits").unwrap(),
+        Some(15u64.to_le_bytes().to_vec())
+    );
+}
+
+#[test]
+fn test_merge_without_operator_is_rejected() {
+    let (engine, _f) = temp_engine();
+    assert!(engine.merge(b"k", b"x").is_err());
+}
+
+// ==================== Streaming Tests ====================
+
+#[test]
+fn test_set_from_reader_and_get_to_writer() {
+    let (engine, _f) = temp_engine();
+    let value: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+    engine
+        .set_from_reader(b"blob", &value[..], value.len() as u64)
+        .unwrap();
+
+    let mut out = Vec::new();
+    let written = engine.get_to_writer(b"blob", &mut out).unwrap();
+    assert_eq!(written, Some(value.len() as u64));
+    assert_eq!(out, value);
+    assert_eq!(engine.get(b"blob").unwrap(), Some(value));
+
+    let mut missing = Vec::new();
+    assert_eq!(engine.get_to_writer(b"nope", &mut missing).unwrap(), None);
+}
+
+#[test]
+fn test_set_from_short_reader_leaves_log_intact() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+    let engine = Engine::load(&path).unwrap();
+    engine.set(b"before", b"1").unwrap();
+
+    let short = [7u8; 10];
+    assert!(engine.set_from_reader(b"blob", &short[..], 100).is_err());
+    assert_eq!(engine.get(b"blob").unwrap(), None);
+
+    engine.set(b"after", b"2").unwrap();
+    drop(engine);
+
+    let engine = Engine::load(&path).unwrap();
+    assert_eq!(engine.get(b"before").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(engine.get(b"after").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(engine.get(b"blob").unwrap(), None);
+}
+
+#[test]
+fn test_chunked_values_round_trip_reload_and_compaction() {
+    use breakout1_kv_store::EngineOptions;
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+    let options = || EngineOptions {
+        compact_threshold: u64::MAX,
+        chunk_size: Some(1000),
+        ..EngineOptions::default()
+    };
+    let value: Vec<u8> = (0..4500u32).map(|i| (i % 251) as u8).collect();
+
+    {
+        let engine = Engine::load_with_options(&path, options()).unwrap();
+        engine.set(b"big", &value).unwrap();
+        engine.set(b"small", b"tiny").unwrap();
+        assert_eq!(engine.get(b"big").unwrap(), Some(value.clone()));
+    }
+
+    let engine = Engine::load_with_options(&path, options()).unwrap();
+    assert_eq!(engine.get(b"big").unwrap(), Some(value.clone()));
+
+    engine.compact().unwrap();
+    let mut out = Vec::new();
+    engine.get_to_writer(b"big", &mut out).unwrap();
+    assert_eq!(out, value);
+    assert_eq!(engine.get(b"small").unwrap(), Some(b"tiny".to_vec()));
+
+    engine.set(b"big", b"replaced").unwrap();
+    drop(engine);
+    let engine = Engine::load_with_options(&path, options()).unwrap();
+    assert_eq!(engine.get(b"big").unwrap(), Some(b"replaced".to_vec()));
+}
+
+#[test]
+fn test_hot_keys_ranks_busiest_first() {
+    use breakout1_kv_store::EngineOptions;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        track_hot_keys: true,
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+
+    engine.set(b"hot", b"1").unwrap();
+    engine.set(b"warm", b"1").unwrap();
+    engine.set(b"cold", b"1").unwrap();
+    for _ in 0..50 {
+        engine.get(b"hot").unwrap();
+    }
+    for _ in 0..10 {
+        engine.get(b"warm").unwrap();
+    }
+
+    let hot = engine.hot_keys(2);
+    assert_eq!(hot.len(), 2);
+    assert_eq!(hot[0].key, b"hot".to_vec());
+    assert!(hot[0].reads >= 50);
+    assert!(hot[0].writes >= 1);
+    assert_eq!(hot[1].key, b"warm".to_vec());
+
+    let (untracked, _f) = temp_engine();
+    untracked.get(b"x").unwrap();
+    assert!(untracked.hot_keys(10).is_empty());
+}
+
+// ==================== Sequence Number Tests ====================
+
+#[test]
+fn test_sequence_increases_with_every_write() {
+    let (engine, _f) = temp_engine();
+    assert_eq!(engine.last_sequence(), 0);
+
+    engine.set(b"a", b"1").unwrap();
+    assert_eq!(engine.last_sequence(), 1);
+    engine.set(b"a", b"2").unwrap();
+    engine.del(b"a").unwrap();
+    engine.del(b"missing").unwrap();
+    assert_eq!(engine.last_sequence(), 4);
+}
+
+#[test]
+fn test_sequence_survives_reload_and_compaction() {
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+
+    {
+        let engine = Engine::load(&path).unwrap();
+        engine.set(b"keep", b"1").unwrap();
+        for i in 0..5u32 {
+            engine.set(format!("tmp{}", i).as_bytes(), b"x").unwrap();
+            engine.del(format!("tmp{}", i).as_bytes()).unwrap();
+        }
+        assert_eq!(engine.last_sequence(), 11);
+    }
+
+    let engine = Engine::load(&path).unwrap();
+    assert_eq!(engine.last_sequence(), 11);
+
+    engine.compact().unwrap();
+    drop(engine);
+
+    let engine = Engine::load(&path).unwrap();
+    assert_eq!(engine.last_sequence(), 11);
+    assert_eq!(engine.get(b"keep").unwrap(), Some(b"1".to_vec()));
+    engine.set(b"next", b"2").unwrap();
+    assert_eq!(engine.last_sequence(), 12);
+}
+
+#[test]
+fn test_sync_always_persists_writes() {
+    use breakout1_kv_store::{EngineOptions, SyncPolicy};
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        sync: SyncPolicy::Always,
+        chunk_size: Some(4),
+        ..EngineOptions::default()
+    };
+
+    {
+        let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+        engine.set(b"a", b"1").unwrap();
+        engine.set(b"big", b"0123456789").unwrap();
+        engine.del(b"a").unwrap();
+        engine.compact().unwrap();
+        engine.sync().unwrap();
+    }
+
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    assert_eq!(engine.get(b"a").unwrap(), None);
+    assert_eq!(engine.get(b"big").unwrap(), Some(b"0123456789".to_vec()));
+}
+
+#[test]
+fn test_stats_track_dead_bytes_and_compaction() {
+    let (engine, _f) = temp_engine();
+    let fresh = engine.stats();
+    assert_eq!(fresh.keys, 0);
+    assert_eq!(fresh.dead_bytes, 0);
+    assert_eq!(fresh.last_compaction, None);
+
+    for i in 0..10u32 {
+        engine.set(b"counter", format!("{}", i).as_bytes()).unwrap();
+    }
+    engine.set(b"gone", b"x").unwrap();
+    engine.del(b"gone").unwrap();
+
+    let before = engine.stats();
+    assert_eq!(before.keys, 1);
+    assert_eq!(before.live_bytes + before.dead_bytes, before.file_size);
+    assert!(before.dead_bytes > 0);
+
+    let report = engine.compact().unwrap();
+    assert_eq!(report.keys, 1);
+    assert_eq!(report.bytes_before, before.file_size);
+
+    let after = engine.stats();
+    assert_eq!(after.file_size, report.bytes_after);
+    assert!(after.dead_bytes < before.dead_bytes);
+    assert_eq!(after.last_compaction, Some(report));
+}
+
+#[test]
+fn test_backup_is_a_loadable_snapshot() {
+    let (engine, _f) = temp_engine();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"b", b"2").unwrap();
+    engine.del(b"a").unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let backup_path = dir.path().join("backup.db");
+    let len = engine.backup(&backup_path).unwrap();
+    assert_eq!(len, fs::metadata(&backup_path).unwrap().len());
+
+    engine.set(b"c", b"3").unwrap();
+
+    let restored = Engine::load(&backup_path).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), None);
+    assert_eq!(restored.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(restored.get(b"c").unwrap(), None);
+    assert_eq!(restored.last_sequence(), 3);
+}
+
+#[test]
+fn test_keys_pages_through_prefix_in_order() {
+    let (engine, _f) = temp_engine();
+    for key in ["user:3", "user:1", "order:1", "user:2", "user:4", "userx"] {
+        engine.set(key.as_bytes(), b"v").unwrap();
+    }
+    engine.del(b"user:4").unwrap();
+
+    let page = engine.keys(b"user:", None, 2);
+    assert_eq!(page, vec![b"user:1".to_vec(), b"user:2".to_vec()]);
+
+    let page = engine.keys(b"user:", Some(b"user:2"), 2);
+    assert_eq!(page, vec![b"user:3".to_vec()]);
+
+    assert_eq!(engine.keys(b"", None, 10).len(), 5);
+    assert!(engine.keys(b"missing", None, 10).is_empty());
+}
+
+#[test]
+fn test_usage_counts_live_keys_and_bytes_under_prefix() {
+    use std::time::Duration;
+
+    let (engine, _f) = temp_engine();
+    engine.set(b"user:1", b"a").unwrap();
+    engine.set(b"user:2", b"bb").unwrap();
+    engine.set(b"user:2", b"ccc").unwrap();
+    engine.set(b"user:3", b"d").unwrap();
+    engine.del(b"user:3").unwrap();
+    engine
+        .set_with_ttl(b"user:4", b"e", Duration::from_millis(1))
+        .unwrap();
+    engine.set(b"order:1", b"f").unwrap();
+    thread::sleep(Duration::from_millis(5));
+
+    let users = engine.usage(b"user:");
+    assert_eq!(users.keys, 2);
+    let everything = engine.usage(b"");
+    assert_eq!(everything.keys, 3);
+    assert!(users.bytes > 0 && users.bytes < everything.bytes);
+    // Overwritten values are dead bytes, not usage.
+    assert!(everything.bytes <= engine.stats().live_bytes);
+    assert_eq!(engine.usage(b"missing").keys, 0);
+}
+
+#[test]
+fn test_delete_prefix_removes_only_keys_under_prefix() {
+    let (engine, file) = temp_engine();
+    for key in ["tenant:a:1", "tenant:a:2", "tenant:ab", "tenant:b:1"] {
+        engine.set(key.as_bytes(), b"v").unwrap();
+    }
+    engine.del(b"tenant:a:2").unwrap();
+
+    assert_eq!(engine.delete_prefix(b"tenant:a:").unwrap(), 1);
+    assert_eq!(engine.get(b"tenant:a:1").unwrap(), None);
+    assert_eq!(engine.get(b"tenant:ab").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(engine.get(b"tenant:b:1").unwrap(), Some(b"v".to_vec()));
+    assert_eq!(engine.delete_prefix(b"tenant:a:").unwrap(), 0);
+
+    drop(engine);
+    let engine = Engine::load(file.path()).unwrap();
+    assert_eq!(engine.keys(b"tenant:", None, 10).len(), 2);
+}
+
+#[test]
+fn test_history_and_as_of_read_past_versions_until_compaction() {
+    use breakout1_kv_store::types::{AsOf, ChangeKind};
+
+    let (engine, _f) = temp_engine();
+    engine.set(b"other", b"x").unwrap();
+    engine.set(b"doc", b"v1").unwrap();
+    let v1_seq = engine.get_versioned(b"doc").unwrap().unwrap().seq;
+    engine.set(b"doc", b"v2").unwrap();
+    engine.del(b"doc").unwrap();
+    let deleted_seq = engine.last_sequence();
+    engine.set(b"doc", b"v3").unwrap();
+
+    let history = engine.history(b"doc", 10).unwrap();
+    let versions: Vec<_> = history
+        .iter()
+        .map(|entry| (entry.kind, entry.value.clone()))
+        .collect();
+    assert_eq!(
+        versions,
+        vec![
+            (ChangeKind::Set, Some(b"v3".to_vec())),
+            (ChangeKind::Del, None),
+            (ChangeKind::Set, Some(b"v2".to_vec())),
+            (ChangeKind::Set, Some(b"v1".to_vec())),
+        ]
+    );
+    assert_eq!(engine.history(b"doc", 1).unwrap().len(), 1);
+
+    let as_of = |seq| {
+        engine
+            .get_as_of(b"doc", AsOf::Seq(seq))
+            .unwrap()
+            .map(|v| v.value)
+    };
+    assert_eq!(as_of(v1_seq - 1), None);
+    assert_eq!(as_of(v1_seq), Some(b"v1".to_vec()));
+    assert_eq!(as_of(v1_seq + 1), Some(b"v2".to_vec()));
+    assert_eq!(as_of(deleted_seq), None);
+    assert_eq!(as_of(u64::MAX), Some(b"v3".to_vec()));
+
+    engine.compact().unwrap();
+    assert_eq!(engine.history(b"doc", 10).unwrap().len(), 1);
+    assert_eq!(as_of(u64::MAX), Some(b"v3".to_vec()));
+}
+
+#[test]
+fn test_scan_returns_prefix_pairs_in_order() {
+    let file = NamedTempFile::new().unwrap();
+    let engine = counter_engine(file.path());
+    engine.set(b"user:42:name", b"alice").unwrap();
+    engine.set(b"user:42:email", b"a@example.com").unwrap();
+    engine
+        .merge(b"user:42:visits", &3u64.to_le_bytes())
+        .unwrap();
+    engine.set(b"user:43:name", b"bob").unwrap();
+    engine.set(b"user:42:old", b"x").unwrap();
+    engine.del(b"user:42:old").unwrap();
+
+    let pairs = engine.scan(b"user:42:", 10).unwrap();
+    assert_eq!(
+        pairs,
+        vec![
+            (b"user:42:email".to_vec(), b"a@example.com".to_vec()),
+            (b"user:42:name".to_vec(), b"alice".to_vec()),
+            (b"user:42:visits".to_vec(), 3u64.to_le_bytes().to_vec()),
+        ]
+    );
+    assert_eq!(engine.scan(b"user:", 2).unwrap().len(), 2);
+}
+
+#[test]
+fn test_range_walks_keys_both_ways() {
+    use std::ops::Bound;
+
+    let (engine, _f) = temp_engine();
+    for i in 1..=5u32 {
+        let key = format!("event:{:04}", i);
+        engine
+            .set(key.as_bytes(), i.to_string().as_bytes())
+            .unwrap();
+    }
+
+    let keys = |pairs: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+        pairs.into_iter().map(|(key, _)| key).collect()
+    };
+
+    let forward = engine
+        .range(
+            Bound::Included(b"event:0002"),
+            Bound::Excluded(b"event:0005"),
+            10,
+            false,
+        )
+        .unwrap();
+    assert_eq!(
+        keys(forward),
+        vec![
+            b"event:0002".to_vec(),
+            b"event:0003".to_vec(),
+            b"event:0004".to_vec()
+        ]
+    );
+
+    let last_two = engine
+        .range(Bound::Unbounded, Bound::Unbounded, 2, true)
+        .unwrap();
+    assert_eq!(
+        last_two,
+        vec![
+            (b"event:0005".to_vec(), b"5".to_vec()),
+            (b"event:0004".to_vec(), b"4".to_vec()),
+        ]
+    );
+
+    let empty = engine
+        .range(Bound::Included(b"z"), Bound::Excluded(b"a"), 10, false)
+        .unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn test_write_batch_applies_in_order_and_persists() {
+    use breakout1_kv_store::types::BatchOp;
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_owned();
+    {
+        let engine = Engine::load(&path).unwrap();
+        engine.set(b"old", b"0").unwrap();
+
+        let existed = engine
+            .write_batch(&[
+                BatchOp::Set {
+                    key: b"a".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                BatchOp::Set {
+                    key: b"a".to_vec(),
+                    value: b"2".to_vec(),
+                },
+                BatchOp::Del {
+                    key: b"old".to_vec(),
+                },
+                BatchOp::Del {
+                    key: b"missing".to_vec(),
+                },
+            ])
+            .unwrap();
+        assert_eq!(existed, vec![false, true, true, false]);
+        assert_eq!(engine.last_sequence(), 5);
+    }
+
+    let engine = Engine::load(&path).unwrap();
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(engine.get(b"old").unwrap(), None);
+    assert_eq!(engine.last_sequence(), 5);
+}
+
+#[test]
+fn test_set_many_if_absent_leaves_existing_keys_alone() {
+    use std::time::Duration;
+
+    let (engine, _f) = temp_engine();
+    engine.set(b"a", b"old").unwrap();
+    engine
+        .set_with_ttl(b"gone", b"old", Duration::from_millis(1))
+        .unwrap();
+    thread::sleep(Duration::from_millis(5));
+
+    let pairs = [
+        (b"a".to_vec(), b"new".to_vec()),
+        (b"b".to_vec(), b"1".to_vec()),
+        (b"b".to_vec(), b"2".to_vec()),
+        (b"gone".to_vec(), b"new".to_vec()),
+    ];
+    let written = engine.set_many_if_absent(&pairs).unwrap();
+    assert_eq!(written, vec![false, true, false, true]);
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"old".to_vec()));
+    assert_eq!(engine.get(b"b").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(engine.get(b"gone").unwrap(), Some(b"new".to_vec()));
+
+    let sequence = engine.last_sequence();
+    assert_eq!(engine.set_many_if_absent(&pairs[..1]).unwrap(), vec![false]);
+    assert_eq!(engine.last_sequence(), sequence);
+}
+
+#[test]
+fn test_get_many_keeps_request_order() {
+    let (engine, _f) = temp_engine();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"b", b"2").unwrap();
+
+    let values = engine.get_many(&[b"b", b"missing", b"a", b"b"]).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            Some(b"2".to_vec()),
+            None,
+            Some(b"1".to_vec()),
+            Some(b"2".to_vec())
+        ]
+    );
+}
+
+#[test]
+fn test_metadata_round_trips_through_reload_and_compaction() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::Metadata;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        chunk_size: Some(4),
+        ..EngineOptions::default()
+    };
+    let meta = Metadata::from([("content-type".to_string(), "image/png".to_string())]);
+    {
+        let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+        engine.set_with_metadata(b"small", b"png", &meta).unwrap();
+        engine
+            .set_with_metadata(b"big", b"0123456789", &meta)
+            .unwrap();
+        engine.set(b"plain", b"x").unwrap();
+    }
+
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.compact().unwrap();
+    assert_eq!(
+        engine.get_with_metadata(b"small").unwrap(),
+        Some((b"png".to_vec(), meta.clone()))
+    );
+    assert_eq!(
+        engine.get_with_metadata(b"big").unwrap(),
+        Some((b"0123456789".to_vec(), meta))
+    );
+    assert_eq!(
+        engine.get_with_metadata(b"plain").unwrap(),
+        Some((b"x".to_vec(), Metadata::new()))
+    );
+
+    engine.set(b"small", b"replaced").unwrap();
+    assert_eq!(
+        engine.get_with_metadata(b"small").unwrap(),
+        Some((b"replaced".to_vec(), Metadata::new()))
+    );
+}
+
+#[test]
+fn test_compare_and_set_checks_version() {
+    use breakout1_kv_store::types::Metadata;
+
+    let (engine, _f) = temp_engine();
+    let meta = Metadata::new();
+
+    assert_eq!(
+        engine.compare_and_set(b"k", None, b"1", &meta).unwrap(),
+        Ok(1)
+    );
+    assert_eq!(
+        engine.compare_and_set(b"k", None, b"x", &meta).unwrap(),
+        Err(Some(1))
+    );
+
+    let current = engine.get_versioned(b"k").unwrap().unwrap();
+    assert_eq!(current.value, b"1".to_vec());
+    assert_eq!(engine.version(b"k").unwrap(), Some(current.seq));
+
+    engine.set(b"k", b"2").unwrap();
+    assert_eq!(
+        engine
+            .compare_and_set(b"k", Some(current.seq), b"x", &meta)
+            .unwrap(),
+        Err(Some(2))
+    );
+    assert_eq!(
+        engine.compare_and_set(b"k", Some(2), b"3", &meta).unwrap(),
+        Ok(3)
+    );
+
+    assert_eq!(engine.compare_and_del(b"k", 2).unwrap(), Err(Some(3)));
+    assert_eq!(engine.compare_and_del(b"k", 3).unwrap(), Ok(()));
+    assert_eq!(engine.get(b"k").unwrap(), None);
+    assert_eq!(engine.compare_and_del(b"k", 3).unwrap(), Err(None));
+}
+
+#[test]
+fn test_set_nx_only_creates() {
+    let (engine, _f) = temp_engine();
+
+    assert!(engine.set_nx(b"k", b"first").unwrap());
+    assert!(!engine.set_nx(b"k", b"second").unwrap());
+    assert_eq!(engine.get(b"k").unwrap(), Some(b"first".to_vec()));
+
+    engine.del(b"k").unwrap();
+    assert!(engine.set_nx(b"k", b"third").unwrap());
+    assert_eq!(engine.get(b"k").unwrap(), Some(b"third".to_vec()));
+}
+
+#[test]
+fn test_ttl_expires_keys_across_reload_and_compaction() {
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    {
+        let engine = Engine::load(file.path()).unwrap();
+        engine
+            .set_with_ttl(b"session", b"s", Duration::from_millis(100))
+            .unwrap();
+        engine.set(b"keep", b"k").unwrap();
+        assert!(engine.expire(b"keep", Duration::from_secs(3600)).unwrap());
+        assert!(!engine.expire(b"ghost", Duration::from_secs(1)).unwrap());
+        engine.set(b"plain", b"p").unwrap();
+    }
+
+    let engine = Engine::load(file.path()).unwrap();
+    assert_eq!(engine.get(b"session").unwrap(), Some(b"s".to_vec()));
+    assert!(engine.ttl(b"keep").unwrap().unwrap() > Duration::from_secs(3500));
+    assert_eq!(engine.ttl(b"plain"), Some(None));
+    assert_eq!(engine.ttl(b"ghost"), None);
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(engine.get(b"session").unwrap(), None);
+    assert_eq!(engine.ttl(b"session"), None);
+    assert_eq!(
+        engine.keys(b"", None, 10),
+        vec![b"keep".to_vec(), b"plain".to_vec()]
+    );
+
+    engine.compact().unwrap();
+    assert_eq!(engine.stats().keys, 2);
+    assert_eq!(engine.get(b"keep").unwrap(), Some(b"k".to_vec()));
+    assert!(engine.ttl(b"keep").unwrap().is_some());
+
+    engine.set(b"keep", b"again").unwrap();
+    assert_eq!(engine.ttl(b"keep"), Some(None));
+}
+
+#[test]
+fn test_incr_counts_in_decimal_and_keeps_ttl() {
+    use std::time::Duration;
+
+    let (engine, _f) = temp_engine();
+
+    assert_eq!(engine.incr(b"hits", 1).unwrap(), 1);
+    assert_eq!(engine.incr(b"hits", 5).unwrap(), 6);
+    assert_eq!(engine.incr(b"hits", -10).unwrap(), -4);
+    assert_eq!(engine.get(b"hits").unwrap(), Some(b"-4".to_vec()));
+
+    engine.set(b"name", b"alice").unwrap();
+    let err = engine.incr(b"name", 1).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(engine.get(b"name").unwrap(), Some(b"alice".to_vec()));
+
+    engine.set(b"max", i64::MAX.to_string().as_bytes()).unwrap();
+    assert!(engine.incr(b"max", 1).is_err());
+
+    engine
+        .set_with_ttl(b"window", b"0", Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(engine.incr(b"window", 1).unwrap(), 1);
+    assert!(engine.ttl(b"window").unwrap().is_some());
+}
+
+#[test]
+fn test_incr_with_floor_clamps_in_one_write() {
+    let (engine, _f) = temp_engine();
+    engine.set(b"stock", b"3").unwrap();
+    let changes = engine.watch(b"stock");
+
+    assert_eq!(engine.incr_with_floor(b"stock", -5, 0).unwrap(), 0);
+    assert_eq!(engine.incr_with_floor(b"stock", i64::MIN, 0).unwrap(), 0);
+    assert_eq!(engine.incr_with_floor(b"stock", 2, 0).unwrap(), 2);
+    assert_eq!(engine.get(b"stock").unwrap(), Some(b"2".to_vec()));
+    // One change per call, with no negative value in between.
+    assert_eq!(changes.try_iter().count(), 3);
+}
+
+#[test]
+fn test_set_with_metadata_and_ttl_keeps_both() {
+    use breakout1_kv_store::types::Metadata;
+    use std::time::Duration;
+
+    let (engine, _f) = temp_engine();
+    let meta = Metadata::from([("flags".to_string(), "42".to_string())]);
+    engine
+        .set_with_metadata_and_ttl(b"item", b"v", &meta, Duration::from_secs(60))
+        .unwrap();
+
+    assert_eq!(
+        engine.get_with_metadata(b"item").unwrap(),
+        Some((b"v".to_vec(), meta))
+    );
+    assert!(engine.ttl(b"item").unwrap().is_some());
+}
+
+#[test]
+fn test_metadata_is_read_without_the_value() {
+    use breakout1_kv_store::types::Metadata;
+
+    let (engine, _f) = temp_engine();
+    let meta = Metadata::from([
+        ("content-type".to_string(), "application/json".to_string()),
+        ("schema-version".to_string(), "3".to_string()),
+    ]);
+    engine.set_with_metadata(b"doc", b"{}", &meta).unwrap();
+    engine.set(b"plain", b"x").unwrap();
+
+    assert_eq!(engine.metadata(b"doc").unwrap(), Some(meta));
+    assert_eq!(engine.metadata(b"plain").unwrap(), Some(Metadata::new()));
+    assert_eq!(engine.metadata(b"missing").unwrap(), None);
+
+    engine.set(b"doc", b"[]").unwrap();
+    assert_eq!(engine.metadata(b"doc").unwrap(), Some(Metadata::new()));
+}
+
+#[test]
+fn test_set_idempotent_ignores_retries_across_reloads() {
+    use breakout1_kv_store::EngineOptions;
+    use std::thread;
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        idempotency_window: Duration::from_millis(200),
+        ..EngineOptions::default()
+    };
+    {
+        let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+        let first = engine
+            .set_idempotent(b"order", b"1", "req-1")
+            .unwrap()
+            .unwrap();
+        let second = engine
+            .set_idempotent(b"order", b"2", "req-2")
+            .unwrap()
+            .unwrap();
+        assert!(second > first);
+        assert_eq!(
+            engine.set_idempotent(b"order", b"1", "req-1").unwrap(),
+            Err(first)
+        );
+        assert_eq!(engine.get(b"order").unwrap(), Some(b"2".to_vec()));
+    }
+
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    assert!(
+        engine
+            .set_idempotent(b"order", b"1", "req-1")
+            .unwrap()
+            .is_err()
+    );
+    assert_eq!(engine.get(b"order").unwrap(), Some(b"2".to_vec()));
+
+    thread::sleep(Duration::from_millis(250));
+    assert!(
+        engine
+            .set_idempotent(b"order", b"1", "req-1")
+            .unwrap()
+            .is_ok()
+    );
+    assert_eq!(engine.get(b"order").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn test_set_idempotent_refuses_an_id_sent_for_another_key() {
+    use breakout1_kv_store::engine::is_reserved;
+    use std::io::ErrorKind;
+
+    let (engine, _f) = temp_engine();
+    let first = engine
+        .set_idempotent(b"order", b"1", "req-1")
+        .unwrap()
+        .unwrap();
+    let err = engine
+        .set_idempotent(b"invoice", b"1", "req-1")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(engine.get(b"invoice").unwrap(), None);
+
+    // Clearing every key leaves the record of the id, and the retry stays a no-op.
+    assert_eq!(engine.delete_prefix(b"").unwrap(), 1);
+    assert!(is_reserved(b"__idempotency:req-1"));
+    assert_eq!(
+        engine.set_idempotent(b"order", b"1", "req-1").unwrap(),
+        Err(first)
+    );
+    assert_eq!(engine.get(b"order").unwrap(), None);
+}
+
+#[test]
+fn test_set_returns_the_seq_read_back_with_the_value() {
+    use breakout1_kv_store::types::Metadata;
+
+    let (engine, _f) = temp_engine();
+    let first = engine.set(b"k", b"1").unwrap();
+    let second = engine.set(b"k", b"2").unwrap();
+    assert!(second > first);
+    assert_eq!(
+        engine.get_with_seq(b"k").unwrap(),
+        Some((b"2".to_vec(), second))
+    );
+    assert_eq!(engine.get_with_seq(b"missing").unwrap(), None);
+
+    assert_eq!(
+        engine
+            .compare_and_set(b"k", Some(first), b"3", &Metadata::new())
+            .unwrap(),
+        Err(Some(second))
+    );
+    let third = engine
+        .compare_and_set(b"k", Some(second), b"3", &Metadata::new())
+        .unwrap();
+    assert_eq!(
+        engine.get_with_seq(b"k").unwrap(),
+        Some((b"3".to_vec(), third.unwrap()))
+    );
+}
+
+#[test]
+fn test_locks_hand_out_increasing_fencing_tokens() {
+    use std::thread;
+    use std::time::Duration;
+
+    let (engine, _f) = temp_engine();
+    let ttl = Duration::from_millis(200);
+    let token = engine.acquire(b"jobs", "a", ttl).unwrap().unwrap();
+    assert_eq!(engine.acquire(b"jobs", "a", ttl).unwrap(), Ok(token));
+    let held = engine.acquire(b"jobs", "b", ttl).unwrap().unwrap_err();
+    assert_eq!((held.owner.as_str(), held.token), ("a", token));
+    assert_eq!(engine.lease(b"jobs").unwrap(), Some(held.clone()));
+
+    // Locks are reserved keys, which clearing every key leaves held.
+    assert!(breakout1_kv_store::engine::is_reserved(b"__lock:jobs"));
+    assert_eq!(engine.delete_prefix(b"").unwrap(), 0);
+    assert_eq!(engine.lease(b"jobs").unwrap(), Some(held));
+
+    assert!(
+        engine
+            .renew(b"jobs", token, Duration::from_secs(60))
+            .unwrap()
+    );
+    assert!(!engine.renew(b"jobs", token + 1, ttl).unwrap());
+    assert!(!engine.release(b"jobs", token + 1).unwrap());
+    assert!(engine.release(b"jobs", token).unwrap());
+    assert_eq!(engine.lease(b"jobs").unwrap(), None);
+
+    let next = engine.acquire(b"jobs", "b", ttl).unwrap().unwrap();
+    assert!(next > token);
+    thread::sleep(Duration::from_millis(250));
+    assert!(!engine.renew(b"jobs", next, ttl).unwrap());
+    let last = engine.acquire(b"jobs", "a", ttl).unwrap().unwrap();
+    assert!(last > next);
+}
+
+#[test]
+fn test_undelete_restores_within_trash_retention() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::Metadata;
+    use std::thread;
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        trash_retention: Some(Duration::from_millis(300)),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+    let meta = Metadata::from([("content-type".to_string(), "text/plain".to_string())]);
+    engine.set_with_metadata(b"doc", b"draft", &meta).unwrap();
+    engine.set(b"tmp", b"x").unwrap();
+    engine.del(b"doc").unwrap();
+    engine.del(b"tmp").unwrap();
+    assert_eq!(engine.get(b"doc").unwrap(), None);
+
+    assert!(engine.undelete(b"doc").unwrap());
+    assert_eq!(
+        engine.get_with_metadata(b"doc").unwrap(),
+        Some((b"draft".to_vec(), meta))
+    );
+    assert!(!engine.undelete(b"doc").unwrap());
+    assert!(!engine.undelete(b"missing").unwrap());
+
+    // A key written again since its delete keeps the new value.
+    engine.set(b"tmp", b"y").unwrap();
+    assert!(!engine.undelete(b"tmp").unwrap());
+    engine.set(b"gone", b"z").unwrap();
+    engine.del(b"tmp").unwrap();
+    engine.del(b"gone").unwrap();
+
+    // The trash survives compaction and reloads until the retention runs out.
+    engine.compact().unwrap();
+    drop(engine);
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    assert!(engine.undelete(b"tmp").unwrap());
+    assert_eq!(engine.get(b"tmp").unwrap(), Some(b"y".to_vec()));
+    thread::sleep(Duration::from_millis(350));
+    engine.compact().unwrap();
+    assert!(!engine.undelete(b"gone").unwrap());
+    assert_eq!(engine.get(b"gone").unwrap(), None);
+}
+
+#[test]
+fn test_batch_prefix_and_tag_deletes_go_through_the_trash() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::BatchOp;
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        trash_retention: Some(Duration::from_secs(60)),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"logs:1", b"x").unwrap();
+    engine.set(b"invoice:7", b"due").unwrap();
+    engine.tag(b"invoice:7", "billing").unwrap();
+
+    // A delete in a batch trashes the value it removes, even one set earlier in the batch.
+    let ops = [
+        BatchOp::Set {
+            key: b"b".to_vec(),
+            value: b"2".to_vec(),
+        },
+        BatchOp::Del { key: b"a".to_vec() },
+        BatchOp::Del { key: b"b".to_vec() },
+    ];
+    assert_eq!(engine.write_batch(&ops).unwrap(), vec![false, true, true]);
+    assert_eq!(engine.delete_prefix(b"logs:").unwrap(), 1);
+    assert_eq!(engine.delete_by_tag("billing").unwrap(), 1);
+
+    for (key, value) in [
+        (&b"a"[..], &b"1"[..]),
+        (b"b", b"2"),
+        (b"logs:1", b"x"),
+        (b"invoice:7", b"due"),
+    ] {
+        assert!(engine.undelete(key).unwrap());
+        assert_eq!(engine.get(key).unwrap(), Some(value.to_vec()));
+    }
+}
+
+#[test]
+fn test_batch_that_cannot_trash_every_value_writes_nothing() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::constants::TRASH_PREFIX;
+    use breakout1_kv_store::types::BatchOp;
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    let options = EngineOptions {
+        trash_retention: Some(Duration::from_secs(60)),
+        ..EngineOptions::default()
+    };
+    let dels = [
+        BatchOp::Del { key: b"a".to_vec() },
+        BatchOp::Del { key: b"b".to_vec() },
+    ];
+
+    // Room for both keys and one trash copy, measured on an engine without a limit.
+    let (room, file) = {
+        let file = NamedTempFile::new().unwrap();
+        let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+        engine.set(b"a", b"1").unwrap();
+        let one = engine.index_stats().bytes;
+        engine.set(b"b", b"2").unwrap();
+        let two = engine.index_stats().bytes;
+        engine.write_batch(&dels[..1]).unwrap();
+        let trashed = engine.index_stats().bytes;
+        engine.undelete(b"a").unwrap();
+        (two + trashed - one, file)
+    };
+
+    let options = EngineOptions {
+        max_index_memory: Some(room),
+        ..options
+    };
+    let engine = Engine::load_with_options(file.path(), options.clone()).unwrap();
+    assert_eq!(
+        engine.write_batch(&dels).unwrap_err().kind(),
+        ErrorKind::OutOfMemory
+    );
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(engine.get(&[TRASH_PREFIX, b"a"].concat()).unwrap(), None);
+    drop(engine);
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    assert_eq!(engine.get(&[TRASH_PREFIX, b"a"].concat()).unwrap(), None);
+    assert_eq!(engine.get(b"b").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn test_tags_group_keys_across_prefixes() {
+    use std::io::ErrorKind;
+
+    let (engine, _f) = temp_engine();
+    engine.set(b"invoice:1", b"10").unwrap();
+    engine.set(b"invoice:2", b"20").unwrap();
+    engine.set(b"user:7", b"ann").unwrap();
+    assert!(engine.tag(b"invoice:2", "billing").unwrap());
+    assert!(engine.tag(b"user:7", "billing").unwrap());
+    assert!(engine.tag(b"user:7", "vip").unwrap());
+    assert!(!engine.tag(b"user:7", "vip").unwrap());
+    // A tag that is a prefix of another keeps its own members.
+    assert!(engine.tag(b"invoice:1", "bill").unwrap());
+
+    assert_eq!(
+        engine.keys_by_tag("billing").unwrap(),
+        vec![b"invoice:2".to_vec(), b"user:7".to_vec()]
+    );
+    assert_eq!(
+        engine.keys_by_tag("bill").unwrap(),
+        vec![b"invoice:1".to_vec()]
+    );
+    assert_eq!(
+        engine.get_by_tag("vip").unwrap(),
+        vec![(b"user:7".to_vec(), b"ann".to_vec())]
+    );
+    assert_eq!(
+        engine.tag(b"k", "a\0b").unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+
+    // Membership records are the engine's own and stay out of listings.
+    let keys = vec![
+        b"invoice:1".to_vec(),
+        b"invoice:2".to_vec(),
+        b"user:7".to_vec(),
+    ];
+    assert_eq!(engine.keys(b"", None, 100), keys);
+    assert_eq!(engine.scan(b"__", 100).unwrap(), vec![]);
+    assert_eq!(engine.usage(b"").keys, 3);
+
+    assert!(engine.untag(b"user:7", "vip").unwrap());
+    assert!(!engine.untag(b"user:7", "vip").unwrap());
+    assert!(engine.keys_by_tag("vip").unwrap().is_empty());
+
+    assert_eq!(engine.delete_by_tag("billing").unwrap(), 2);
+    assert_eq!(engine.get(b"user:7").unwrap(), None);
+    assert_eq!(engine.get(b"invoice:1").unwrap(), Some(b"10".to_vec()));
+    assert!(engine.keys_by_tag("billing").unwrap().is_empty());
+
+    // Tags belong to the name, so a key written again is back in its groups.
+    engine.set(b"user:7", b"bob").unwrap();
+    assert_eq!(
+        engine.keys_by_tag("billing").unwrap(),
+        vec![b"user:7".to_vec()]
+    );
+}
+
+#[test]
+fn test_append_extends_value() {
+    let (engine, _f) = temp_engine();
+
+    assert_eq!(engine.append(b"log", b"a").unwrap(), 1);
+    assert_eq!(engine.append(b"log", b"bc").unwrap(), 3);
+    assert_eq!(engine.append(b"log", b"").unwrap(), 3);
+    assert_eq!(engine.get(b"log").unwrap(), Some(b"abc".to_vec()));
+}
+
+#[test]
+fn test_value_info_reads_heads_only() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::Metadata;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load_with_options(
+        file.path(),
+        EngineOptions {
+            chunk_size: Some(4),
+            ..EngineOptions::default()
+        },
+    )
+    .unwrap();
+    let meta = Metadata::from([("content-type".to_string(), "text/plain".to_string())]);
+    engine
+        .set_with_metadata(b"big", b"0123456789", &meta)
+        .unwrap();
+
+    let info = engine.value_info(b"big").unwrap().unwrap();
+    assert_eq!(info.len, 10);
+    assert_eq!(info.meta, meta);
+    assert_eq!(Some(info.seq), engine.version(b"big").unwrap());
+
+    assert!(engine.contains_key(b"big"));
+    assert!(!engine.contains_key(b"ghost"));
+    assert_eq!(engine.value_info(b"ghost").unwrap(), None);
+}
+
+#[test]
+fn test_streams_values_of_unknown_length_in_and_out() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::Metadata;
+    use std::io::Read;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load_with_options(
+        file.path(),
+        EngineOptions {
+            chunk_size: Some(4),
+            ..EngineOptions::default()
+        },
+    )
+    .unwrap();
+    let meta = Metadata::from([("content-type".to_string(), "text/plain".to_string())]);
+
+    for value in [&b""[..], b"abc", b"abcd", b"abcdefgh", b"0123456789"] {
+        engine
+            .set_from_reader_with_metadata(b"k", value, None, &meta)
+            .unwrap();
+        assert_eq!(engine.get(b"k").unwrap(), Some(value.to_vec()));
+
+        let (info, mut reader) = engine.open_value(b"k").unwrap().unwrap();
+        assert_eq!(info.len, value.len() as u64);
+        assert_eq!(info.meta, meta);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, value);
+    }
+
+    // An open reader keeps the value it was opened on.
+    let (_, mut reader) = engine.open_value(b"k").unwrap().unwrap();
+    engine.set(b"k", b"new").unwrap();
+    engine.compact().unwrap();
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, b"0123456789");
+
+    let version = engine.version(b"k").unwrap();
+    let mut body: &[u8] = b"unread";
+    assert_eq!(
+        engine
+            .compare_and_set_from_reader(b"k", None, &mut body, None, &meta)
+            .unwrap(),
+        Err(version)
+    );
+    assert_eq!(body, b"unread");
+    assert!(
+        engine
+            .compare_and_set_from_reader(b"k", version, &mut body, Some(6), &meta)
+            .unwrap()
+            .is_ok()
+    );
+    assert_eq!(engine.get(b"k").unwrap(), Some(b"unread".to_vec()));
+}
+
+#[test]
+fn test_max_value_size_rejects_larger_values() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::{BatchOp, Metadata};
+    use std::io::ErrorKind;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load_with_options(
+        file.path(),
+        EngineOptions {
+            chunk_size: Some(4),
+            max_value_size: Some(8),
+            ..EngineOptions::default()
+        },
+    )
+    .unwrap();
+    engine.set(b"k", b"12345678").unwrap();
+    let size = engine.stats().file_size;
+
+    let too_large =
+        |result: std::io::Result<()>| result.unwrap_err().kind() == ErrorKind::FileTooLarge;
+    assert!(too_large(engine.set(b"k", b"123456789").map(drop)));
+    assert!(too_large(
+        engine.set_from_reader(b"k", &b"123456789"[..], 9).map(drop)
+    ));
+    assert!(too_large(
+        engine
+            .set_from_reader_with_metadata(b"k", &b"123456789"[..], None, &Metadata::new())
+            .map(drop)
+    ));
+    assert!(too_large(
+        engine
+            .write_batch(&[BatchOp::Set {
+                key: b"other".to_vec(),
+                value: b"123456789".to_vec(),
+            }])
+            .map(drop)
+    ));
+    assert!(too_large(engine.append(b"k", b"9").map(drop)));
+
+    // Rejected writes leave nothing behind, even the pieces of an unsized value.
+    assert_eq!(engine.stats().file_size, size);
+    assert_eq!(engine.get(b"k").unwrap(), Some(b"12345678".to_vec()));
+    assert_eq!(engine.get(b"other").unwrap(), None);
+}
+
+#[test]
+fn test_watch_streams_changes_under_prefix() {
+    use breakout1_kv_store::types::{BatchOp, ChangeKind};
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    engine.set(b"user:0", b"before").unwrap();
+    let changes = engine.watch(b"user:");
+
+    engine.set(b"user:1", b"a").unwrap();
+    engine.set(b"other", b"b").unwrap();
+    engine.del(b"user:1").unwrap();
+    engine
+        .write_batch(&[
+            BatchOp::Set {
+                key: b"user:2".to_vec(),
+                value: b"c".to_vec(),
+            },
+            BatchOp::Del {
+                key: b"other".to_vec(),
+            },
+        ])
+        .unwrap();
+
+    let seen: Vec<_> = changes
+        .try_iter()
+        .map(|change| (change.key, change.kind))
+        .collect();
+    assert_eq!(
+        seen,
+        vec![
+            (b"user:1".to_vec(), ChangeKind::Set),
+            (b"user:1".to_vec(), ChangeKind::Del),
+            (b"user:2".to_vec(), ChangeKind::Set),
+        ]
+    );
+
+    // Dropping the receiver unsubscribes.
+    drop(changes);
+    engine.set(b"user:3", b"d").unwrap();
+}
+
+#[test]
+fn test_watch_since_replays_history_then_tails() {
+    use breakout1_kv_store::types::ChangeKind;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    engine.set(b"user:1", b"a").unwrap();
+    let after = engine.last_sequence();
+    engine.set(b"user:2", b"b").unwrap();
+    engine.set(b"other", b"c").unwrap();
+    engine.del(b"user:1").unwrap();
+
+    let (history, changes) = engine.watch_since(b"user:", after).unwrap();
+    let replayed: Vec<_> = history
+        .iter()
+        .map(|change| (change.key.clone(), change.kind))
+        .collect();
+    assert_eq!(
+        replayed,
+        vec![
+            (b"user:2".to_vec(), ChangeKind::Set),
+            (b"user:1".to_vec(), ChangeKind::Del),
+        ]
+    );
+
+    engine.set(b"user:3", b"d").unwrap();
+    let tailed = changes.try_recv().unwrap();
+    assert_eq!(tailed.key, b"user:3");
+    assert_eq!(tailed.seq, history.last().unwrap().seq + 1);
+
+    // After compaction only the surviving keys remain, in sequence order.
+    engine.set(b"user:0", b"e").unwrap();
+    engine.compact().unwrap();
+    let (history, _) = engine.watch_since(b"user:", 0).unwrap();
+    let keys: Vec<_> = history.iter().map(|change| change.key.clone()).collect();
+    assert_eq!(
+        keys,
+        vec![b"user:2".to_vec(), b"user:3".to_vec(), b"user:0".to_vec()]
+    );
+}
+
+#[test]
+fn test_buckets_are_separate_and_survive_reopen() {
+    use breakout1_kv_store::{Buckets, EngineOptions};
+
+    let dir = tempfile::tempdir().unwrap();
+    let buckets = Buckets::open(dir.path(), EngineOptions::default()).unwrap();
+    let orders = buckets.create("orders").unwrap();
+    let users = buckets.create("users").unwrap();
+    orders.set(b"k", b"order").unwrap();
+    users.set(b"k", b"user").unwrap();
+
+    assert!(matches!(
+        buckets.create("users"),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists
+    ));
+    assert!(matches!(
+        buckets.create("../escape"),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput
+    ));
+    drop((orders, users));
+    drop(buckets);
+
+    let buckets = Buckets::open(dir.path(), EngineOptions::default()).unwrap();
+    assert_eq!(buckets.names(), vec!["orders", "users"]);
+    assert_eq!(
+        buckets.get("orders").unwrap().get(b"k").unwrap(),
+        Some(b"order".to_vec())
+    );
+
+    assert!(buckets.delete("orders").unwrap());
+    assert!(!buckets.delete("orders").unwrap());
+    assert!(buckets.get("orders").is_none());
+    assert_eq!(buckets.names(), vec!["users"]);
+    assert!(!dir.path().join("orders.db").exists());
+}
+
+#[test]
+fn test_wire_frames_roundtrip_and_respect_the_limit() {
+    use breakout1_kv_store::wire::{self, Request, Response};
+    use std::io::Cursor;
+
+    let requests = [
+        Request::Auth(b"secret".to_vec()),
+        Request::Get(b"k".to_vec()),
+        Request::Set {
+            key: b"k".to_vec(),
+            value: b"v".to_vec(),
+        },
+        Request::Del(b"k".to_vec()),
+    ];
+    let stream: Vec<u8> = requests.iter().flat_map(Request::encode).collect();
+    let mut reader = Cursor::new(&stream);
+    for request in &requests {
+        let body = wire::read_frame(&mut reader, 1024).unwrap().unwrap();
+        assert_eq!(&Request::decode(&body).unwrap(), request);
+    }
+    assert!(wire::read_frame(&mut reader, 1024).unwrap().is_none());
+
+    for response in [
+        Response::Ok(b"v".to_vec()),
+        Response::NotFound,
+        Response::Error("nope".to_string()),
+    ] {
+        let frame = response.encode();
+        let body = wire::read_frame(&mut Cursor::new(&frame), 1024)
+            .unwrap()
+            .unwrap();
+        assert_eq!(Response::decode(&body).unwrap(), response);
+    }
+
+    assert!(matches!(
+        wire::read_frame(&mut Cursor::new(&stream), 4),
+        Err(e) if e.kind() == std::io::ErrorKind::FileTooLarge
+    ));
+    // A get frame with trailing value bytes is malformed.
+    let mut frame = Request::Get(b"k".to_vec()).encode();
+    frame.push(b'x');
+    assert!(Request::decode(&frame[wire::FRAME_PREFIX_SIZE..]).is_err());
+}
+
+#[test]
+fn test_incremental_backups_concatenate_until_compaction() {
+    let (engine, _f) = temp_engine();
+    engine.set(b"a", b"1").unwrap();
+
+    let mut log = Vec::new();
+    let full = engine.backup_since(None, &mut log).unwrap().unwrap();
+    assert_eq!(full.len, log.len() as u64);
+
+    engine.set(b"b", b"2").unwrap();
+    engine.del(b"a").unwrap();
+    let point = engine.backup_since(Some(&full), &mut log).unwrap().unwrap();
+    assert_eq!(point.len, log.len() as u64);
+    // Nothing new, nothing copied.
+    let empty = engine
+        .backup_since(Some(&point), &mut log)
+        .unwrap()
+        .unwrap();
+    assert_eq!(empty, point);
+
+    let dir = tempfile::tempdir().unwrap();
+    let restored_path = dir.path().join("restored.db");
+    fs::write(&restored_path, &log).unwrap();
+    let restored = Engine::load(&restored_path).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), None);
+    assert_eq!(restored.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(restored.last_sequence(), engine.last_sequence());
+
+    engine.compact().unwrap();
+    let mut rest = Vec::new();
+    assert!(
+        engine
+            .backup_since(Some(&point), &mut rest)
+            .unwrap()
+            .is_none()
+    );
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_memory_storage_compacts_and_reloads() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::storage::MemoryStorage;
+    use std::io::Read;
+
+    let storage = MemoryStorage::new();
+    let engine = Engine::load_with_storage(storage.clone(), EngineOptions::default()).unwrap();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"a", b"2").unwrap();
+    engine.set(b"b", b"3").unwrap();
+    engine.del(b"b").unwrap();
+    let before = storage.contents().len();
+
+    // A reader opened before compaction keeps reading the old log.
+    let (_, mut reader) = engine.open_value(b"a").unwrap().unwrap();
+    engine.compact().unwrap();
+    assert!(storage.contents().len() < before);
+    let mut value = Vec::new();
+    reader.read_to_end(&mut value).unwrap();
+    assert_eq!(value, b"2");
+
+    engine.set(b"c", b"4").unwrap();
+    drop(engine);
+    let restored = MemoryStorage::from_bytes(storage.contents());
+    let engine = Engine::load_with_storage(restored, EngineOptions::default()).unwrap();
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(engine.get(b"b").unwrap(), None);
+    assert_eq!(engine.get(b"c").unwrap(), Some(b"4".to_vec()));
+}
+
+#[test]
+fn test_import_redis_reads_rdb_preamble_and_aof_commands() {
+    let far = (i64::MAX / 2).to_le_bytes();
+    let past = 1000i64.to_le_bytes();
+    let mut dump = b"REDIS0011".to_vec();
+    // aux field, select db 0, resize hint
+    dump.extend_from_slice(b"\xfa\x09redis-ver\x057.2.0\xfe\x00\xfb\x05\x01");
+    // a = 1 as an 8-bit integer
+    dump.extend_from_slice(b"\x00\x01a\xc0\x01");
+    // ttl = "x", expiring far in the future
+    dump.push(0xfc);
+    dump.extend_from_slice(&far);
+    dump.extend_from_slice(b"\x00\x03ttl\x01x");
+    // old = "v", already expired
+    dump.push(0xfc);
+    dump.extend_from_slice(&past);
+    dump.extend_from_slice(b"\x00\x03old\x01v");
+    // lzf = "abcabc", LZF-compressed
+    dump.extend_from_slice(b"\x00\x03lzf\xc3\x06\x06\x02abc\x20\x02");
+    // a list, which is skipped
+    dump.extend_from_slice(b"\x01\x04list\x02\x01x\x01y");
+    dump.extend_from_slice(b"\xff\x00\x00\x00\x00\x00\x00\x00\x00");
+    // AOF commands after the preamble, the last one cut off
+    dump.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n");
+    dump.extend_from_slice(b"*2\r\n$4\r\nINCR\r\n$1\r\nb\r\n");
+    dump.extend_from_slice(b"*3\r\n$5\r\nRPUSH\r\n$4\r\nlist\r\n$1\r\nz\r\n");
+    dump.extend_from_slice(b"*2\r\n$3\r\nDEL\r\n$3\r\nlzf\r\n");
+    dump.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$5\r\nhel");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+    fs::write(&path, &dump).unwrap();
+    let (engine, _f) = temp_engine();
+    engine.set(b"lzf", b"kept").unwrap();
+    let report = engine.import_redis(&path).unwrap();
+
+    assert_eq!((report.keys, report.expired, report.skipped), (3, 1, 2));
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(engine.get(b"b").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(engine.get(b"ttl").unwrap(), Some(b"x".to_vec()));
+    assert!(engine.ttl(b"ttl").unwrap().is_some());
+    assert_eq!(engine.get(b"old").unwrap(), None);
+    assert_eq!(engine.get(b"list").unwrap(), None);
+    assert_eq!(engine.get(b"c").unwrap(), None);
+    // Deleted in Redis, so the store's own value is left alone.
+    assert_eq!(engine.get(b"lzf").unwrap(), Some(b"kept".to_vec()));
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn test_sled_import_and_export_round_trip() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let source = db.open_tree("source").unwrap();
+    for i in 0..2500u32 {
+        source
+            .insert(format!("key{i:04}").as_bytes(), &i.to_be_bytes())
+            .unwrap();
+    }
+    let (engine, _f) = temp_engine();
+    engine.set(b"key0000", b"old").unwrap();
+    let report = engine.import_sled(&source).unwrap();
+    assert_eq!(report.keys, 2500);
+    assert_eq!(
+        engine.get(b"key0000").unwrap(),
+        Some(0u32.to_be_bytes().to_vec())
+    );
+    assert_eq!(
+        engine.get(b"key2499").unwrap(),
+        Some(2499u32.to_be_bytes().to_vec())
+    );
+
+    engine.set(b"extra", b"x").unwrap();
+    engine.del(b"key0001").unwrap();
+    let target = db.open_tree("target").unwrap();
+    assert_eq!(engine.export_sled(&target).unwrap(), 2500);
+    assert_eq!(target.len(), 2500);
+    assert_eq!(target.get(b"extra").unwrap().as_deref(), Some(&b"x"[..]));
+    assert_eq!(target.get(b"key0001").unwrap(), None);
+}
+
+#[test]
+fn test_replica_follows_primary_log_and_resumes() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::codec;
+
+    let options = || EngineOptions {
+        chunk_size: Some(4),
+        ..EngineOptions::default()
+    };
+    let primary_file = NamedTempFile::new().unwrap();
+    let primary = Engine::load_with_options(primary_file.path(), options()).unwrap();
+    let replica_file = NamedTempFile::new().unwrap();
+    let replica = Engine::load_with_options(replica_file.path(), options()).unwrap();
+
+    primary.set(b"a", b"1").unwrap();
+    primary
+        .set(b"chunked", b"a value in several pieces")
+        .unwrap();
+    primary.set(b"b", b"2").unwrap();
+    primary.del(b"a").unwrap();
+
+    let mut cursor = primary.replication_cursor(0).unwrap().unwrap();
+    // Small reads still never split the chunked value.
+    loop {
+        let frames = primary.read_replication(&mut cursor, 8).unwrap().unwrap();
+        if frames.is_empty() {
+            break;
+        }
+        replica.apply_replicated(&frames).unwrap();
+    }
+    assert_eq!(replica.last_sequence(), primary.last_sequence());
+    assert_eq!(replica.get(b"a").unwrap(), None);
+    assert_eq!(
+        replica.get(b"chunked").unwrap(),
+        Some(b"a value in several pieces".to_vec())
+    );
+    assert_eq!(replica.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+    // The replica's applied sequence survives a restart, and re-applied records are skipped.
+    drop(replica);
+    let replica = Engine::load_with_options(replica_file.path(), options()).unwrap();
+    let applied = replica.last_sequence();
+    assert_eq!(applied, primary.last_sequence());
+    let mut from_start = primary.replication_cursor(0).unwrap().unwrap();
+    let all = primary
+        .read_replication(&mut from_start, usize::MAX)
+        .unwrap()
+        .unwrap();
+    assert_eq!(replica.apply_replicated(&all).unwrap(), applied);
+
+    // Compaction is fine for a replica that is caught up...
+    primary.compact().unwrap();
+    primary.set(b"c", b"3").unwrap();
+    let frames = primary
+        .read_replication(&mut cursor, usize::MAX)
+        .unwrap()
+        .unwrap();
+    let mut stream = codec::encode_heartbeat(primary.last_sequence() - 1);
+    stream.extend_from_slice(&frames);
+    stream.extend_from_slice(&codec::encode_heartbeat(primary.last_sequence()));
+    let mut heartbeats = Vec::new();
+    replica
+        .replicate_from(stream.as_slice(), |seq| heartbeats.push(seq))
+        .unwrap();
+    assert_eq!(
+        heartbeats,
+        [primary.last_sequence() - 1, primary.last_sequence()]
+    );
+    assert_eq!(replica.get(b"c").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(replica.last_sequence(), primary.last_sequence());
+
+    // ...but one that missed a delete compaction dropped has to start over.
+    let mut behind = primary
+        .replication_cursor(replica.last_sequence())
+        .unwrap()
+        .unwrap();
+    primary.del(b"b").unwrap();
+    primary.compact().unwrap();
+    assert_eq!(
+        primary.read_replication(&mut behind, usize::MAX).unwrap(),
+        None
+    );
+    assert!(primary.replication_cursor(applied).unwrap().is_none());
+}
+
+#[test]
+fn test_replication_stats_track_what_the_replica_applied() {
+    use breakout1_kv_store::codec;
+    use breakout1_kv_store::types::ReplicationStats;
+
+    let (primary, _primary_file) = temp_engine();
+    let (replica, _replica_file) = temp_engine();
+    assert_eq!(replica.stats().replication, ReplicationStats::default());
+
+    primary.set(b"a", b"1").unwrap();
+    primary.set(b"b", b"2").unwrap();
+    let mut cursor = primary.replication_cursor(0).unwrap().unwrap();
+    let frames = primary
+        .read_replication(&mut cursor, usize::MAX)
+        .unwrap()
+        .unwrap();
+    let mut stream = frames.clone();
+    // The primary has moved on to writes the replica has yet to receive.
+    stream.extend_from_slice(&codec::encode_heartbeat(primary.last_sequence() + 3));
+    replica.replicate_from(stream.as_slice(), |_| {}).unwrap();
+
+    let stats = replica.replication_stats();
+    assert_eq!(stats.applied_seq, primary.last_sequence());
+    assert_eq!(stats.primary_seq, primary.last_sequence() + 3);
+    assert_eq!(stats.lag(), 3);
+    assert_eq!(stats.records_applied, 2);
+    assert_eq!(stats.bytes_applied, frames.len() as u64);
+
+    // Records it already holds are not counted again.
+    replica.apply_replicated(&frames).unwrap();
+    assert_eq!(replica.replication_stats().records_applied, 2);
+    assert_eq!(primary.replication_stats().lag(), 0);
+}
+
+#[test]
+fn test_archive_keeps_every_record_through_compaction_and_restarts() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::archive;
+    use std::fs::File;
+
+    let dir = tempfile::tempdir().unwrap();
+    let archive_dir = dir.path().join("archive");
+    let path = dir.path().join("data.db");
+    let options = || EngineOptions {
+        archive_dir: Some(archive_dir.clone()),
+        ..EngineOptions::default()
+    };
+
+    let engine = Engine::load_with_options(&path, options()).unwrap();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"a", b"2").unwrap();
+    engine.del(b"a").unwrap();
+    engine.compact().unwrap();
+    engine.set(b"b", b"3").unwrap();
+    let last = engine.last_sequence();
+    drop(engine);
+
+    // Reopening neither copies records again nor skips any.
+    let engine = Engine::load_with_options(&path, options()).unwrap();
+    engine.set(b"c", b"4").unwrap();
+
+    let files = archive::files(&archive_dir).unwrap();
+    assert!(!files.is_empty());
+    let replay_file = tempfile::NamedTempFile::new().unwrap();
+    let replay = Engine::load(replay_file.path()).unwrap();
+    for file in &files {
+        replay
+            .replicate_from(File::open(file).unwrap(), |_| {})
+            .unwrap();
+    }
+    assert_eq!(replay.last_sequence(), last + 1);
+    assert_eq!(replay.get(b"a").unwrap(), None);
+    assert_eq!(replay.get(b"b").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(replay.get(b"c").unwrap(), Some(b"4".to_vec()));
+    // The writes compaction dropped are still in the archive.
+    assert_eq!(replay.history(b"a", 10).unwrap().len(), 3);
+}
+
+#[test]
+fn test_read_only_engine_rejects_writes_but_applies_replicated_records() {
+    use breakout1_kv_store::types::BatchOp;
+    use std::io::{ErrorKind, Read};
+
+    let (primary, _p) = temp_engine();
+    primary.set(b"a", b"1").unwrap();
+    let (replica, _r) = temp_engine();
+    replica.set_read_only(true);
+
+    let read_only = |e: std::io::Error| assert_eq!(e.kind(), ErrorKind::ReadOnlyFilesystem);
+    read_only(replica.set(b"a", b"2").unwrap_err());
+    read_only(replica.del(b"a").unwrap_err());
+    read_only(replica.incr(b"n", 1).unwrap_err());
+    read_only(replica.set_from_reader(b"a", &b"2"[..], 1).unwrap_err());
+    let ops = [BatchOp::Del { key: b"a".to_vec() }];
+    read_only(replica.write_batch(&ops).unwrap_err());
+
+    let (len, mut snapshot) = primary.snapshot().unwrap();
+    let mut log = Vec::new();
+    snapshot.read_to_end(&mut log).unwrap();
+    assert_eq!(log.len() as u64, len);
+
+    let mut cursor = primary.replication_cursor(0).unwrap().unwrap();
+    let frames = primary
+        .read_replication(&mut cursor, usize::MAX)
+        .unwrap()
+        .unwrap();
+    replica.apply_replicated(&frames).unwrap();
+    assert_eq!(replica.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(replica.last_sequence(), 1);
+
+    replica.set_read_only(false);
+    replica.set(b"a", b"2").unwrap();
+    assert_eq!(replica.get(b"a").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn test_apply_remote_keeps_the_last_writer_on_every_site() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::options::ConflictResolver;
+    use breakout1_kv_store::types::RemoteEntry;
+
+    let site = |options: EngineOptions| {
+        let file = NamedTempFile::new().unwrap();
+        (
+            Engine::load_with_options(file.path(), options).unwrap(),
+            file,
+        )
+    };
+    let node = |node_id| EngineOptions {
+        node_id,
+        ..EngineOptions::default()
+    };
+    let (a, _a) = site(node(1));
+    let (b, _b) = site(node(2));
+    let latest = |engine: &Engine, node, key: &[u8]| RemoteEntry {
+        key: key.to_vec(),
+        value: engine.get(key).unwrap(),
+        seq: engine.last_sequence(),
+        node,
+    };
+
+    // Concurrent writes with the same sequence number: the higher node id wins on both sites.
+    a.set(b"k", b"from a").unwrap();
+    b.set(b"k", b"from b").unwrap();
+    let (from_a, from_b) = (latest(&a, 1, b"k"), latest(&b, 2, b"k"));
+    assert!(a.apply_remote(&from_b).unwrap());
+    assert!(!b.apply_remote(&from_a).unwrap());
+    assert_eq!(a.get(b"k").unwrap(), Some(b"from b".to_vec()));
+    assert_eq!(b.get(b"k").unwrap(), Some(b"from b".to_vec()));
+
+    // A write made after seeing the peer's wins, and replaying an older one changes nothing.
+    a.set(b"k", b"later").unwrap();
+    let later = latest(&a, 1, b"k");
+    assert!(later.seq > from_b.seq);
+    assert!(b.apply_remote(&later).unwrap());
+    assert!(!b.apply_remote(&from_b).unwrap());
+    assert_eq!(b.get(b"k").unwrap(), Some(b"later".to_vec()));
+
+    // A delete is a write too: an older set arriving after it does not bring the key back.
+    b.del(b"k").unwrap();
+    let deleted = latest(&b, 2, b"k");
+    assert_eq!(deleted.value, None);
+    assert!(a.apply_remote(&deleted).unwrap());
+    assert!(!a.apply_remote(&later).unwrap());
+    assert_eq!(a.get(b"k").unwrap(), None);
+
+    let len = |entry: &RemoteEntry| entry.value.as_ref().map_or(0, Vec::len);
+    let longest: ConflictResolver = Arc::new(move |current, incoming| len(incoming) > len(current));
+    let (c, _c) = site(EngineOptions {
+        conflict_resolver: Some(longest),
+        ..EngineOptions::default()
+    });
+    c.set(b"k", b"medium").unwrap();
+    let remote = |value: &[u8], seq| RemoteEntry {
+        key: b"k".to_vec(),
+        value: Some(value.to_vec()),
+        seq,
+        node: 9,
+    };
+    assert!(!c.apply_remote(&remote(b"short", 100)).unwrap());
+    assert!(c.apply_remote(&remote(b"much longer", 1)).unwrap());
+    assert_eq!(c.get(b"k").unwrap(), Some(b"much longer".to_vec()));
+}
+
+#[test]
+fn test_verify_reports_corrupt_records_and_index_disagreements() {
+    use breakout1_kv_store::EngineOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        chunk_size: Some(4),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"chunked", b"split into pieces").unwrap();
+    engine
+        .set_with_ttl(b"ttl", b"2", Duration::from_secs(60))
+        .unwrap();
+    engine.set(b"gone", b"3").unwrap();
+    engine.del(b"gone").unwrap();
+    engine.compact().unwrap();
+    engine.set(b"a", b"4").unwrap();
+    let report = engine.verify().unwrap();
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.unread_bytes, 0);
+    assert_eq!(report.bytes, fs::metadata(file.path()).unwrap().len());
+
+    // Unknown flags on the record for "b" stop it decoding, so the index entry for it has
+    // nothing behind it in the log.
+    let start = fs::metadata(file.path()).unwrap().len();
+    engine.set(b"b", b"5").unwrap();
+    let mut log = fs::OpenOptions::new()
+        .write(true)
+        .open(file.path())
+        .unwrap();
+    log.seek(SeekFrom::Start(start + 1)).unwrap();
+    log.write_all(&[0xff]).unwrap();
+    // A length prefix cut short, as a crash mid-write leaves.
+    log.seek(SeekFrom::End(0)).unwrap();
+    log.write_all(&[0x80]).unwrap();
+
+    let report = engine.verify().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.corrupt.len(), 1);
+    assert_eq!(report.corrupt[0].pos, start + 1);
+    assert_eq!(report.orphaned.len(), 1);
+    assert_eq!(report.orphaned[0].key, b"b");
+    assert!(report.missing.is_empty());
+    assert_eq!(report.unread_bytes, 1);
+}
+
+#[test]
+fn test_verify_catches_a_changed_value_byte_by_its_checksum() {
+    use std::io::{ErrorKind, Seek, SeekFrom, Write};
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    engine.set(b"a", b"1").unwrap();
+    let start = fs::metadata(file.path()).unwrap().len();
+    engine.set(b"b", b"value").unwrap();
+    let end = fs::metadata(file.path()).unwrap().len();
+    assert!(engine.verify().unwrap().is_ok());
+
+    // The last value byte sits just before the 4-byte checksum. The record still decodes.
+    let mut log = fs::OpenOptions::new()
+        .write(true)
+        .open(file.path())
+        .unwrap();
+    log.seek(SeekFrom::Start(end - 5)).unwrap();
+    log.write_all(b"E").unwrap();
+
+    let report = engine.verify().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.corrupt.len(), 1);
+    assert_eq!(report.corrupt[0].pos, start + 1);
+    assert!(report.corrupt[0].reason.contains("checksum"));
+    assert_eq!(engine.get(b"b").unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+    drop(engine);
+    let err = Engine::load(file.path()).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains(&format!("offset {}", start + 1)));
+}
+
+#[test]
+fn test_index_entries_show_where_each_key_points() {
+    use breakout1_kv_store::EngineOptions;
+
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        chunk_size: Some(4),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"user:1", b"old").unwrap();
+    engine.set(b"user:1", b"new").unwrap();
+    engine.set(b"user:2", b"split into pieces").unwrap();
+    engine.set(b"other", b"x").unwrap();
+
+    let entries = engine.index_entries(b"user:", None, 10).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].key, b"user:1");
+    assert_eq!(entries[0].seq, engine.version(b"user:1").unwrap());
+    assert!(entries[0].pieces.is_empty());
+    assert_eq!(entries[1].key, b"user:2");
+    assert_eq!(entries[1].pieces.len(), 4);
+    assert!(
+        entries[1]
+            .pieces
+            .iter()
+            .all(|piece| { piece.pos < entries[1].record.as_ref().unwrap().pos })
+    );
+
+    let next = engine.index_entries(b"user:", Some(b"user:1"), 10).unwrap();
+    assert_eq!(next.len(), 1);
+    assert_eq!(next[0].key, b"user:2");
+}
+
+#[test]
+fn test_writes_fail_while_free_space_is_below_the_minimum() {
+    use breakout1_kv_store::EngineOptions;
+    use std::io::ErrorKind;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    engine.set(b"kept", b"1").unwrap();
+    drop(engine);
+
+    // No file system has this much free, so every write is refused.
+    let options = EngineOptions {
+        min_free_space: Some(u64::MAX),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    let size = fs::metadata(file.path()).unwrap().len();
+    assert_eq!(
+        engine.set(b"new", b"2").unwrap_err().kind(),
+        ErrorKind::StorageFull
+    );
+    assert_eq!(
+        engine.del(b"kept").unwrap_err().kind(),
+        ErrorKind::StorageFull
+    );
+    assert_eq!(fs::metadata(file.path()).unwrap().len(), size);
+    assert_eq!(engine.get(b"kept").unwrap(), Some(b"1".to_vec()));
+    engine.compact().unwrap();
+
+    let disk = engine.disk_stats();
+    assert!(disk.low());
+    assert!(disk.free_bytes.is_some());
+    assert_eq!(disk.rejected_writes, 2);
+
+    let options = EngineOptions {
+        min_free_space: Some(1),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"new", b"2").unwrap();
+    assert!(!engine.disk_stats().low());
+}
+
+#[test]
+fn test_new_keys_fail_once_the_index_reaches_its_memory_limit() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::types::BatchOp;
+    use std::io::ErrorKind;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    assert_eq!(engine.index_stats().bytes, 0);
+    engine.set(b"a", b"1").unwrap();
+    engine.set(b"b", b"2").unwrap();
+    let full = engine.index_stats().bytes;
+    assert!(full > 0);
+    engine.del(b"b").unwrap();
+    let one = engine.index_stats().bytes;
+    assert!(one < full);
+    engine.set(b"b", b"2").unwrap();
+    assert_eq!(engine.index_stats().bytes, full);
+    drop(engine);
+
+    let options = EngineOptions {
+        max_index_memory: Some(full),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    assert_eq!(engine.index_stats().bytes, full);
+    assert_eq!(
+        engine.set(b"c", b"3").unwrap_err().kind(),
+        ErrorKind::OutOfMemory
+    );
+    let batch = [BatchOp::Set {
+        key: b"c".to_vec(),
+        value: b"3".to_vec(),
+    }];
+    assert_eq!(
+        engine.write_batch(&batch).unwrap_err().kind(),
+        ErrorKind::OutOfMemory
+    );
+    assert_eq!(engine.get(b"c").unwrap(), None);
+
+    // Existing keys can still be overwritten and deleted, which makes room.
+    engine.set(b"a", b"10").unwrap();
+    engine.incr(b"b", 1).unwrap();
+    engine.del(b"b").unwrap();
+    engine.set(b"c", b"3").unwrap();
+    assert_eq!(engine.get(b"c").unwrap(), Some(b"3".to_vec()));
+
+    let stats = engine.stats().index;
+    assert_eq!(stats.bytes, full);
+    assert_eq!(stats.max_bytes, Some(full));
+    assert_eq!(stats.rejected_writes, 2);
+}
+
+#[test]
+fn test_slow_ops_are_kept_with_their_sizes_and_phases() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::constants::SLOW_LOG_CAPACITY;
+    use breakout1_kv_store::types::SlowOpKind;
+    use std::time::Duration;
+
+    let file = NamedTempFile::new().unwrap();
+    let engine = Engine::load(file.path()).unwrap();
+    engine.set(b"key", b"value").unwrap();
+    engine.get(b"key").unwrap();
+    assert!(engine.slow_ops().is_empty());
+    drop(engine);
+
+    // Every operation takes at least no time at all.
+    let options = EngineOptions {
+        slow_op_threshold: Some(Duration::ZERO),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"key", b"longer value").unwrap();
+    engine.get(b"key").unwrap();
+    engine.get(b"missing").unwrap();
+    engine.del(b"key").unwrap();
+    engine.compact().unwrap();
+
+    let ops = engine.slow_ops();
+    let kinds: Vec<_> = ops.iter().map(|op| op.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            SlowOpKind::Rebuild,
+            SlowOpKind::Set,
+            SlowOpKind::Get,
+            SlowOpKind::Get,
+            SlowOpKind::Del,
+            SlowOpKind::Compact,
+        ]
+    );
+    assert_eq!((ops[1].key_len, ops[1].value_len), (Some(3), Some(12)));
+    assert_eq!((ops[2].key_len, ops[2].value_len), (Some(3), Some(12)));
+    assert_eq!((ops[3].key_len, ops[3].value_len), (Some(7), None));
+    assert_eq!((ops[5].key_len, ops[5].value_len), (None, None));
+    let phases: Vec<_> = ops[2].phases.iter().map(|(name, _)| *name).collect();
+    assert_eq!(phases, ["lock", "read"]);
+    for op in &ops {
+        assert!(op.phases.iter().map(|(_, took)| *took).sum::<Duration>() <= op.duration);
+    }
+
+    for _ in 0..SLOW_LOG_CAPACITY {
+        engine.get(b"key").unwrap();
+    }
+    let ops = engine.slow_ops();
+    assert_eq!(ops.len(), SLOW_LOG_CAPACITY);
+    assert!(ops.iter().all(|op| op.kind == SlowOpKind::Get));
+
+    engine.clear_slow_ops();
+    assert!(engine.slow_ops().is_empty());
+}
+
+#[test]
+fn test_observer_sees_operations_compactions_and_corruption() {
+    use breakout1_kv_store::types::CompactionReport;
+    use breakout1_kv_store::{EngineObserver, EngineOptions};
+    use std::io::{Seek, SeekFrom, Write};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl EngineObserver for Recorder {
+        fn on_set(&self, key: &[u8], value_len: u64, _: Duration) {
+            let key = String::from_utf8_lossy(key);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("set {} {}", key, value_len));
+        }
+        fn on_get(&self, key: &[u8], value_len: Option<u64>, _: Duration) {
+            let key = String::from_utf8_lossy(key);
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("get {} {:?}", key, value_len));
+        }
+        fn on_del(&self, key: &[u8], _: Duration) {
+            let key = String::from_utf8_lossy(key);
+            self.0.lock().unwrap().push(format!("del {}", key));
+        }
+        fn on_compact_start(&self, _: u64) {
+            self.0.lock().unwrap().push("compact start".to_string());
+        }
+        fn on_compact_end(&self, report: &CompactionReport) {
+            let line = format!("compact end {}", report.keys);
+            self.0.lock().unwrap().push(line);
+        }
+        fn on_corruption(&self, pos: u64, _: &str) {
+            self.0.lock().unwrap().push(format!("corrupt {}", pos));
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    let file = NamedTempFile::new().unwrap();
+    let options = EngineOptions {
+        observer: Some(recorder.clone()),
+        ..EngineOptions::default()
+    };
+    let engine = Engine::load_with_options(file.path(), options).unwrap();
+    engine.set(b"a", b"123").unwrap();
+    engine.get(b"a").unwrap();
+    engine.get(b"missing").unwrap();
+    engine.del(b"a").unwrap();
+    // Only the set family, get and del are reported one by one.
+    engine.incr(b"counter", 1).unwrap();
+    engine.compact().unwrap();
+
+    let start = fs::metadata(file.path()).unwrap().len();
+    engine.set(b"b", b"5").unwrap();
+    let mut log = fs::OpenOptions::new()
+        .write(true)
+        .open(file.path())
+        .unwrap();
+    log.seek(SeekFrom::Start(start + 1)).unwrap();
+    log.write_all(&[0xff]).unwrap();
+    assert_eq!(
+        engine.get(b"b").unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [
+            "set a 3".to_string(),
+            "get a Some(3)".to_string(),
+            "get missing None".to_string(),
+            "del a".to_string(),
+            "compact start".to_string(),
+            "compact end 1".to_string(),
+            "set b 1".to_string(),
+            format!("corrupt {}", start + 1),
+        ]
+    );
+}
+
+struct RaftNode {
+    raft: breakout1_kv_store::raft::Raft,
+    store: Engine,
+    _files: (NamedTempFile, NamedTempFile),
+}
+
+fn raft_timing() -> breakout1_kv_store::raft::Timing {
+    breakout1_kv_store::raft::Timing {
+        election_timeout: std::time::Duration::from_millis(100),
+        heartbeat: std::time::Duration::from_millis(20),
+    }
+}
+
+/// Three followers, with ids 1 to 3, that have not heard from each other yet.
+fn raft_cluster(now: std::time::Instant) -> Vec<RaftNode> {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::raft::{self, Raft, RaftLog};
+
+    (1..=3)
+        .map(|id| {
+            let (store, store_file) = temp_engine();
+            let log_file = NamedTempFile::new().unwrap();
+            let log = RaftLog::open(log_file.path(), EngineOptions::default()).unwrap();
+            let peers = (1..=3).filter(|&peer| peer != id).collect();
+            let applied = raft::applied_index(&store).unwrap();
+            let raft = Raft::open(id, peers, log, raft_timing(), applied, now).unwrap();
+            RaftNode {
+                raft,
+                store,
+                _files: (store_file, log_file),
+            }
+        })
+        .collect()
+}
+
+/// Sends `from`'s next request to `to` and the response back, both through their encodings.
+/// Returns whether there was a request to send.
+fn raft_exchange(nodes: &mut [RaftNode], from: usize, to: usize, now: std::time::Instant) -> bool {
+    use breakout1_kv_store::raft::{
+        AppendRequest, AppendResponse, Request, Response, VoteRequest, VoteResponse,
+    };
+
+    let peer = nodes[to].raft.id();
+    let Some(request) = nodes[from].raft.next_request(peer, now).unwrap() else {
+        return false;
+    };
+    let request = match request {
+        Request::Vote(request) => Request::Vote(VoteRequest::decode(&request.encode()).unwrap()),
+        Request::Append(request) => {
+            Request::Append(AppendRequest::decode(&request.encode()).unwrap())
+        }
+    };
+    let response = match nodes[to].raft.handle(&request, now).unwrap() {
+        Response::Vote(response) => {
+            Response::Vote(VoteResponse::decode(&response.encode()).unwrap())
+        }
+        Response::Append(response) => {
+            Response::Append(AppendResponse::decode(&response.encode()).unwrap())
+        }
+    };
+    nodes[from].raft.on_response(peer, &response, now).unwrap();
+    true
+}
+
+fn raft_apply(node: &mut RaftNode) {
+    for (index, entry) in node.raft.committed(usize::MAX).unwrap() {
+        breakout1_kv_store::raft::apply(&node.store, index, entry).unwrap();
+        node.raft.applied_to(index).unwrap();
+    }
+}
+
+#[test]
+fn test_raft_commits_a_write_once_a_majority_holds_it() {
+    use breakout1_kv_store::raft::{self, Role};
+    use breakout1_kv_store::types::BatchOp;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut nodes = raft_cluster(start);
+    // Past every randomized election timeout.
+    let now = start + Duration::from_secs(1);
+    nodes[0].raft.tick(now).unwrap();
+    assert_eq!(nodes[0].raft.role(), Role::Candidate);
+    assert!(raft_exchange(&mut nodes, 0, 1, now));
+    assert_eq!(nodes[0].raft.role(), Role::Leader);
+    assert_eq!(nodes[0].raft.term(), 1);
+    assert_eq!(nodes[1].raft.propose(Vec::new()).unwrap(), None);
+
+    let set = BatchOp::Set {
+        key: b"a".to_vec(),
+        value: b"1".to_vec(),
+    };
+    // The entry opening the term is at index 1.
+    assert_eq!(nodes[0].raft.propose(vec![set]).unwrap(), Some((2, 1)));
+    assert_eq!(nodes[0].raft.commit_index(), 0);
+    assert!(raft_exchange(&mut nodes, 0, 1, now));
+    assert_eq!(nodes[1].raft.leader(), Some(1));
+    assert_eq!(nodes[0].raft.commit_index(), 2);
+    // Node 3 never heard of the election, and still has nothing.
+    assert_eq!(nodes[2].raft.last_index(), 0);
+
+    raft_apply(&mut nodes[0]);
+    assert_eq!(nodes[0].store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(raft::applied_index(&nodes[0].store).unwrap(), 2);
+    // The bookkeeping key is not the user's.
+    assert_eq!(nodes[0].store.scan(b"", 10).unwrap().len(), 1);
+
+    let del = BatchOp::Del { key: b"a".to_vec() };
+    assert_eq!(nodes[0].raft.propose(vec![del]).unwrap(), Some((3, 1)));
+    assert!(raft_exchange(&mut nodes, 0, 2, now));
+    assert_eq!(nodes[0].raft.commit_index(), 3);
+    assert_eq!(nodes[2].raft.last_index(), 3);
+    assert_eq!(nodes[2].raft.commit_index(), 2);
+    // Followers learn of the commit with the next request.
+    let later = now + Duration::from_millis(50);
+    assert!(raft_exchange(&mut nodes, 0, 1, later));
+    assert!(raft_exchange(&mut nodes, 0, 2, later));
+    for node in &mut nodes {
+        raft_apply(node);
+        assert_eq!(node.raft.applied_index(), 3);
+        assert_eq!(node.store.get(b"a").unwrap(), None);
+    }
+}
+
+#[test]
+fn test_raft_reads_wait_for_a_majority_to_confirm_the_leader() {
+    use breakout1_kv_store::raft::Role;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut nodes = raft_cluster(start);
+    let now = start + Duration::from_secs(1);
+    nodes[0].raft.tick(now).unwrap();
+    raft_exchange(&mut nodes, 0, 1, now);
+    raft_exchange(&mut nodes, 0, 2, now);
+    assert_eq!(nodes[1].raft.read_index(), None);
+
+    let ticket = nodes[0].raft.read_index().unwrap();
+    // Not before the entry opening the term is committed.
+    assert_eq!(ticket.index, 1);
+    assert!(!nodes[0].raft.confirmed(&ticket));
+    // The round is answered by a request sent after the ticket, heartbeat or not.
+    assert!(raft_exchange(&mut nodes, 0, 1, now));
+    assert!(nodes[0].raft.confirmed(&ticket));
+
+    // Node 2 loses touch with the leader and wins an election with node 3.
+    let later = now + Duration::from_secs(1);
+    nodes[1].raft.tick(later).unwrap();
+    assert!(raft_exchange(&mut nodes, 1, 2, later));
+    assert_eq!(nodes[1].raft.role(), Role::Leader);
+    assert_eq!(nodes[1].raft.term(), 2);
+
+    // The old leader does not know yet, but cannot confirm a read.
+    let stale = nodes[0].raft.read_index().unwrap();
+    raft_exchange(&mut nodes, 0, 2, later);
+    assert!(!nodes[0].raft.confirmed(&stale));
+    assert_eq!(nodes[0].raft.role(), Role::Follower);
+    assert_eq!(nodes[0].raft.term(), 2);
+}
+
+#[test]
+fn test_raft_log_survives_restarts_and_discards_what_every_node_applied() {
+    use breakout1_kv_store::EngineOptions;
+    use breakout1_kv_store::raft::{self, Raft, RaftLog, Role};
+    use breakout1_kv_store::types::BatchOp;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut nodes = raft_cluster(start);
+    let mut now = start + Duration::from_secs(1);
+    nodes[0].raft.tick(now).unwrap();
+    raft_exchange(&mut nodes, 0, 1, now);
+    for i in 0..5u8 {
+        let set = BatchOp::Set {
+            key: vec![b'k', i],
+            value: vec![i],
+        };
+        nodes[0].raft.propose(vec![set]).unwrap();
+    }
+    // Twice around: once for the entries, then for the commit index.
+    for _ in 0..2 {
+        now += Duration::from_millis(50);
+        raft_exchange(&mut nodes, 0, 1, now);
+        raft_exchange(&mut nodes, 0, 2, now);
+        for node in &mut nodes {
+            raft_apply(node);
+        }
+    }
+    assert_eq!(nodes[2].raft.applied_index(), 6);
+    assert_eq!(nodes[2].store.get(&[b'k', 4]).unwrap(), Some(vec![4]));
+    // Followers discard once the leader says every node holds the entries.
+    now += Duration::from_millis(50);
+    raft_exchange(&mut nodes, 0, 2, now);
+    raft_apply(&mut nodes[2]);
+    nodes[2].raft.applied_to(6).unwrap();
+
+    let node = nodes.pop().unwrap();
+    let (store_file, log_file) = node._files;
+    drop(node.raft);
+    let log = RaftLog::open(log_file.path(), EngineOptions::default()).unwrap();
+    assert_eq!(log.first_index(), 6);
+    assert_eq!(log.last_index(), 6);
+    let applied = raft::applied_index(&node.store).unwrap();
+    let restarted = Raft::open(3, vec![1, 2], log, raft_timing(), applied, now).unwrap();
+    assert_eq!(restarted.term(), 1);
+    assert_eq!(restarted.role(), Role::Follower);
+    assert_eq!(restarted.last_index(), 6);
+    drop(restarted);
+
+    // A node whose store was lost cannot replay the entries it discarded.
+    let log = RaftLog::open(log_file.path(), EngineOptions::default()).unwrap();
+    let err = Raft::open(3, vec![1, 2], log, raft_timing(), 0, now)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    drop(store_file);
+}
+
+#[test]
+fn test_raft_new_leader_overwrites_entries_that_never_committed() {
+    use breakout1_kv_store::raft::Role;
+    use breakout1_kv_store::types::BatchOp;
+    use std::time::{Duration, Instant};
+
+    let set = |value: &[u8]| {
+        vec![BatchOp::Set {
+            key: b"k".to_vec(),
+            value: value.to_vec(),
+        }]
+    };
+    let start = Instant::now();
+    let mut nodes = raft_cluster(start);
+    let now = start + Duration::from_secs(1);
+    nodes[0].raft.tick(now).unwrap();
+    // The vote, then the entry opening the term.
+    raft_exchange(&mut nodes, 0, 1, now);
+    raft_exchange(&mut nodes, 0, 1, now);
+    raft_exchange(&mut nodes, 0, 2, now);
+    // Node 1 takes a write and is cut off before sending it.
+    assert_eq!(nodes[0].raft.propose(set(b"lost")).unwrap(), Some((2, 1)));
+
+    let later = now + Duration::from_secs(1);
+    nodes[1].raft.tick(later).unwrap();
+    assert!(raft_exchange(&mut nodes, 1, 2, later));
+    assert_eq!(nodes[1].raft.role(), Role::Leader);
+    assert_eq!(nodes[1].raft.propose(set(b"kept")).unwrap(), Some((3, 2)));
+    // Until node 1 has the new leader's entries, it is told to go further back.
+    while raft_exchange(&mut nodes, 1, 0, later) {}
+    raft_exchange(&mut nodes, 1, 2, later);
+    raft_exchange(&mut nodes, 1, 0, later + Duration::from_millis(50));
+    assert_eq!(nodes[0].raft.role(), Role::Follower);
+    assert_eq!(nodes[0].raft.last_index(), 3);
+    assert_eq!(nodes[0].raft.commit_index(), 3);
+    raft_apply(&mut nodes[0]);
+    assert_eq!(nodes[0].store.get(b"k").unwrap(), Some(b"kept".to_vec()));
+}